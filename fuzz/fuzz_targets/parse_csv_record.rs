@@ -0,0 +1,18 @@
+#![no_main]
+
+use csv::ByteRecord;
+use libfuzzer_sys::fuzz_target;
+use payments_engine::io::TransactionReader;
+use payments_engine::transactions::Transaction;
+
+fuzz_target!(|data: &[u8]| {
+    // A single record, split on commas without any CSV unquoting
+    let record = ByteRecord::from(data.split(|byte| *byte == b',').collect::<Vec<_>>());
+    let _ = Transaction::parse_csv_record(&record);
+
+    // The same bytes as a whole input, through the reader that skips
+    // malformed rows
+    if let Ok(reader) = TransactionReader::new(data) {
+        for _ in reader {}
+    }
+});
@@ -0,0 +1,132 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::currency::{RoundingMode, DEFAULT_DECIMALS};
+use crate::processor::ClientDb;
+use crate::transactions::{AdjustmentReason, Transaction, TransactionType};
+
+/// Interest rates by balance tier, read from a JSON file such as
+///
+/// ```json
+/// {"days": 30, "tiers": [{"from": 0, "rate": 0.01}, {"from": 10000, "rate": 0.02}]}
+/// ```
+///
+/// `rate` is yearly. A balance earns the rate of the highest tier it
+/// reaches, on the whole balance; balances under the lowest tier earn
+/// nothing.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct RateTable {
+    /// Days of interest each accrual pays.
+    pub days: u32,
+    /// Days in the year the rates are quoted over.
+    #[serde(default = "default_day_count")]
+    pub day_count: u32,
+    pub tiers: Vec<RateTier>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
+pub struct RateTier {
+    pub from: f64,
+    pub rate: f64,
+}
+
+fn default_day_count() -> u32 {
+    365
+}
+
+impl RateTable {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut table: RateTable = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if table.day_count == 0 {
+            return Err("day_count must be positive".into());
+        }
+        table
+            .tiers
+            .sort_unstable_by(|a, b| a.from.total_cmp(&b.from));
+        Ok(table)
+    }
+
+    /// Yearly rate a balance of `balance` earns, if any.
+    pub fn rate(&self, balance: f64) -> Option<f64> {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|tier| balance >= tier.from)
+            .map(|tier| tier.rate)
+    }
+
+    /// Interest on `balance` over the table's period, rounded to four
+    /// decimals with `rounding`.
+    pub fn interest(&self, balance: f64, rounding: RoundingMode) -> f64 {
+        let rate = match self.rate(balance) {
+            Some(rate) if balance > 0.0 => rate,
+            _ => return 0.0,
+        };
+        let interest = balance * rate * self.days as f64 / self.day_count as f64;
+        rounding.round(interest, DEFAULT_DECIMALS)
+    }
+}
+
+/// The interest credits for every eligible client: open, unlocked, and
+/// with an available balance that earns a non-zero amount. Credits are
+/// ordered by client and take transaction ids counting down from
+/// `u32::MAX`, one per client, so they stay clear of input ids.
+pub(crate) fn postings(
+    clients_db: &ClientDb,
+    rates: &RateTable,
+    rounding: RoundingMode,
+) -> Vec<Transaction> {
+    let mut postings: Vec<Transaction> = clients_db
+        .iter()
+        .filter(|client| !client.locked && !client.closed)
+        .filter_map(|client| {
+            let interest = rates.interest(client.available, rounding);
+            (interest > 0.0).then(|| Transaction {
+                tx_type: TransactionType::Credit,
+                client_id: client.id,
+                tx_id: u32::MAX - client.id as u32,
+                amount: Some(interest),
+                reason: Some(AdjustmentReason::Interest),
+                dispute_reason: None,
+            })
+        })
+        .collect();
+    postings.sort_unstable_by_key(|tx| tx.client_id);
+    postings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_balances_earn_the_rate_of_their_tier() {
+        let rates = RateTable {
+            days: 30,
+            day_count: 360,
+            tiers: vec![
+                RateTier {
+                    from: 100.0,
+                    rate: 0.012,
+                },
+                RateTier {
+                    from: 10_000.0,
+                    rate: 0.024,
+                },
+            ],
+        };
+
+        let interest = |balance| rates.interest(balance, RoundingMode::HalfEven);
+        assert_eq!(interest(50.0), 0.0);
+        assert_eq!(interest(1_000.0), 1.0);
+        assert_eq!(interest(12_000.0), 24.0);
+        assert_eq!(interest(-500.0), 0.0);
+        assert_eq!(interest(100.01), 0.1);
+        assert_eq!(rates.interest(100.01, RoundingMode::Truncate), 0.1);
+        assert_eq!(rates.interest(100.06, RoundingMode::HalfUp), 0.1001);
+    }
+}
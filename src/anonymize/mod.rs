@@ -0,0 +1,218 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+use hmac_sha256::HMAC;
+
+use crate::currency::Precision;
+use crate::engine::{EngineBuilder, PaymentsEngine};
+use crate::io::TransactionReader;
+use crate::outcome::TransactionOutcome;
+use crate::transactions::Transaction;
+
+/// Strips real identifiers from the engine's outputs so they can be shared
+/// for analytics.
+///
+/// Client and transaction ids are replaced by pseudonyms: the first 8 bytes
+/// of their HMAC-SHA256 under a secret key, in hex. The same key always
+/// gives the same pseudonyms, so exports can be joined with each other,
+/// while without the key they can't be traced back. There are only 65536
+/// client ids, so anyone holding the key can reverse them by trying every
+/// one: the key must stay with whoever produces the exports. Amounts can
+/// also be rounded down to buckets, hiding exact values.
+#[derive(Clone, Debug)]
+pub struct Anonymizer {
+    key: Vec<u8>,
+    bucket: Option<f64>,
+}
+
+impl Anonymizer {
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            key: key.as_ref().to_vec(),
+            bucket: None,
+        }
+    }
+
+    /// Rounds every amount down to a multiple of `width`, e.g. 100 to
+    /// write a deposit of 250 as 200.
+    pub fn bucket_amounts(mut self, width: f64) -> Self {
+        self.bucket = Some(width);
+        self
+    }
+
+    pub fn client(&self, id: u16) -> String {
+        self.pseudonym(b"client", &id.to_be_bytes())
+    }
+
+    pub fn transaction(&self, id: u32) -> String {
+        self.pseudonym(b"tx", &id.to_be_bytes())
+    }
+
+    /// `amount` rounded down to its bucket, or as is without buckets.
+    pub fn amount(&self, amount: f64) -> f64 {
+        match self.bucket {
+            Some(width) => (amount / width).floor() * width,
+            None => amount,
+        }
+    }
+
+    /// Client and transaction ids are hashed apart, so a client and a
+    /// transaction with the same number get unrelated pseudonyms.
+    fn pseudonym(&self, domain: &[u8], id: &[u8]) -> String {
+        let mut mac = HMAC::new(&self.key);
+        mac.update(domain);
+        mac.update(id);
+        mac.finalize()[..8]
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// Writes every transaction and what became of it as
+    /// `type,client,tx,amount,outcome,reason` CSV, in the order given.
+    pub fn write_transactions<W: Write>(
+        &self,
+        processed: &[(Transaction, TransactionOutcome)],
+        precision: Precision,
+        destination: W,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(destination);
+        writer.write_record(["type", "client", "tx", "amount", "outcome", "reason"])?;
+        for (tx, outcome) in processed {
+            let (outcome, reason) = match outcome {
+                TransactionOutcome::Applied { .. } => ("applied", String::new()),
+                TransactionOutcome::Held { .. } => ("held", String::new()),
+                TransactionOutcome::Rejected { reason } => ("rejected", reason.to_string()),
+                TransactionOutcome::Ignored { reason } => ("ignored", reason.to_string()),
+            };
+            writer.write_record([
+                tx.tx_type.as_str(),
+                &self.client(tx.client_id),
+                &self.transaction(tx.tx_id),
+                &tx.amount.map_or_else(String::new, |amount| {
+                    precision.format(self.amount(amount)).to_string()
+                }),
+                outcome,
+                &reason,
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the client report as `client,available,held,total,locked`
+    /// CSV, ordered by pseudonym so the order doesn't hint at the ids.
+    pub fn write_report<W: Write>(
+        &self,
+        engine: &PaymentsEngine,
+        precision: Precision,
+        destination: W,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut rows: Vec<(String, [f64; 3], bool)> = engine
+            .clients()
+            .iter()
+            .map(|client| {
+                (
+                    self.client(client.id),
+                    [client.available, client.held, client.total],
+                    client.locked,
+                )
+            })
+            .collect();
+        rows.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut writer = csv::Writer::from_writer(destination);
+        writer.write_record(["client", "available", "held", "total", "locked"])?;
+        for (client, [available, held, total], locked) in rows {
+            writer.write_record([
+                client.as_str(),
+                &precision.format(self.amount(available)).to_string(),
+                &precision.format(self.amount(held)).to_string(),
+                &precision.format(self.amount(total)).to_string(),
+                if locked { "true" } else { "false" },
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Applies `input` in file order on a fresh engine built by `builder`, and
+/// writes the anonymized transactions and report to `transactions.csv` and
+/// `clients.csv` in `dir`.
+pub fn export(
+    input: &Path,
+    builder: &EngineBuilder,
+    anonymizer: &Anonymizer,
+    precision: Precision,
+    dir: &Path,
+) -> Result<(), Box<dyn Error>> {
+    let engine = builder.clone().build();
+    let mut processed = vec![];
+    for tx in TransactionReader::new(File::open(input)?)? {
+        let tx = tx?;
+        processed.push((tx, engine.apply_transaction(tx)));
+    }
+
+    fs::create_dir_all(dir)?;
+    anonymizer.write_transactions(
+        &processed,
+        precision,
+        File::create(dir.join("transactions.csv"))?,
+    )?;
+    anonymizer.write_report(&engine, precision, File::create(dir.join("clients.csv"))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_pseudonymized_and_amounts_bucketed() {
+        let anonymizer = Anonymizer::new("secret").bucket_amounts(100.0);
+        let engine = PaymentsEngine::new();
+        let processed: Vec<_> = [
+            Transaction::new_deposit(7, 1, 250.0),
+            Transaction::new_withdrawal(7, 2, 900.0),
+        ]
+        .iter()
+        .map(|tx| (*tx, engine.apply_transaction(*tx)))
+        .collect();
+
+        let pseudonym = anonymizer.client(7);
+        assert_eq!(pseudonym.len(), 16);
+        assert_eq!(pseudonym, Anonymizer::new("secret").client(7));
+        assert_ne!(pseudonym, Anonymizer::new("other").client(7));
+        assert_ne!(pseudonym, anonymizer.transaction(7));
+
+        let mut transactions = vec![];
+        anonymizer
+            .write_transactions(&processed, Precision::default(), &mut transactions)
+            .unwrap();
+        let transactions = String::from_utf8(transactions).unwrap();
+        let rows: Vec<&str> = transactions.lines().collect();
+        assert_eq!(
+            rows[1],
+            format!(
+                "deposit,{},{},200.0000,applied,",
+                pseudonym,
+                anonymizer.transaction(1)
+            )
+        );
+        assert!(rows[2].ends_with(",900.0000,rejected,insufficient available funds"));
+
+        let mut report = vec![];
+        anonymizer
+            .write_report(&engine, Precision::default(), &mut report)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            format!(
+                "client,available,held,total,locked\n{},200.0000,0.0000,200.0000,false\n",
+                pseudonym
+            )
+        );
+    }
+}
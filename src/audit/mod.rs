@@ -0,0 +1,300 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::File;
+use std::io::BufRead;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::conformance::replay;
+use crate::engine::EngineBuilder;
+use crate::events::{open_journal, parse_journal, ClientEventKind};
+use crate::report::{Report, ReportRow, RowDiff};
+use crate::transactions::TransactionType;
+
+/// One line of an `--events` journal: a transaction's outcome or a client
+/// lifecycle change. Only the fields the balances depend on are read.
+#[derive(Deserialize)]
+struct JournalLine {
+    seq: u64,
+    client: u16,
+    #[serde(rename = "type")]
+    tx_type: Option<TransactionType>,
+    tx: Option<u32>,
+    amount: Option<f64>,
+    outcome: Option<String>,
+    lifecycle: Option<ClientEventKind>,
+    /// The client a merged account went to.
+    into: Option<u16>,
+    /// Balances after an applied transaction, read for custom types only,
+    /// whose effect can't be told from the amount.
+    available: Option<f64>,
+    held: Option<f64>,
+    total: Option<f64>,
+    locked: Option<bool>,
+}
+
+#[derive(Default)]
+struct Account {
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+}
+
+/// Balances folded from applied events alone, without the processor.
+#[derive(Default)]
+struct Ledger {
+    accounts: BTreeMap<u16, Account>,
+    /// Types and amounts of applied deposits and withdrawals, by
+    /// transaction id.
+    amounts: HashMap<u32, (TransactionType, f64)>,
+    /// Amounts currently held by disputes, by transaction id.
+    disputed: HashMap<u32, f64>,
+}
+
+impl Ledger {
+    fn apply(&mut self, line: &JournalLine) -> Result<(), String> {
+        match line.lifecycle {
+            Some(ClientEventKind::Created) => {
+                self.accounts.entry(line.client).or_default();
+            }
+            // The client's earlier lines may or may not have been removed
+            // with it, so only its absence from now on counts
+            Some(ClientEventKind::Erased) => {
+                self.accounts.remove(&line.client);
+            }
+            Some(ClientEventKind::Merged) => {
+                let into = line.into.ok_or_else(|| {
+                    format!(
+                        "event {}: merge of client {} without a target",
+                        line.seq, line.client
+                    )
+                })?;
+                let from = self.accounts.remove(&line.client).unwrap_or_default();
+                let account = self.accounts.entry(into).or_default();
+                account.available += from.available;
+                account.held += from.held;
+                account.total += from.total;
+                account.locked |= from.locked;
+            }
+            _ => {}
+        }
+        let (Some(tx_type), Some(tx)) = (line.tx_type, line.tx) else {
+            return Ok(());
+        };
+        // A deposit or withdrawal held for review moves its amount to held
+        // instead
+        let held = match line.outcome.as_deref() {
+            Some("applied") => false,
+            Some("held") => true,
+            _ => return Ok(()),
+        };
+
+        let missing = || format!("event {}: {:?} {} without an amount", line.seq, tx_type, tx);
+        let amounts = &mut self.amounts;
+        let account = self.accounts.entry(line.client).or_default();
+        match tx_type {
+            TransactionType::Deposit | TransactionType::Credit => {
+                let amount = line.amount.ok_or_else(missing)?;
+                match held {
+                    true => account.held += amount,
+                    false => account.available += amount,
+                }
+                account.total += amount;
+                if tx_type == TransactionType::Deposit {
+                    amounts.insert(tx, (tx_type, amount));
+                }
+            }
+            TransactionType::Withdrawal | TransactionType::Debit => {
+                let amount = line.amount.ok_or_else(missing)?;
+                account.available -= amount;
+                match held {
+                    true => account.held += amount,
+                    false => account.total -= amount,
+                }
+                if tx_type == TransactionType::Withdrawal {
+                    amounts.insert(tx, (tx_type, amount));
+                }
+            }
+            TransactionType::Dispute => {
+                let amount = line
+                    .amount
+                    .or_else(|| amounts.get(&tx).map(|&(_, amount)| amount))
+                    .ok_or_else(missing)?;
+                account.available -= amount;
+                account.held += amount;
+                *self.disputed.entry(tx).or_default() += amount;
+            }
+            TransactionType::Resolve | TransactionType::Chargeback => {
+                let disputed = self.disputed.entry(tx).or_default();
+                let amount = line.amount.unwrap_or(*disputed);
+                *disputed -= amount;
+                account.held -= amount;
+                if tx_type == TransactionType::Resolve {
+                    account.available += amount;
+                } else {
+                    account.total -= amount;
+                    account.locked = true;
+                }
+            }
+            TransactionType::Approve | TransactionType::Deny => {
+                let (reviewed, amount) = amounts.get(&tx).copied().ok_or_else(|| {
+                    format!(
+                        "event {}: {:?} of unknown transaction {}",
+                        line.seq, tx_type, tx
+                    )
+                })?;
+                account.held -= amount;
+                match (tx_type, reviewed) {
+                    (TransactionType::Approve, TransactionType::Deposit)
+                    | (TransactionType::Deny, TransactionType::Withdrawal) => {
+                        account.available += amount
+                    }
+                    _ => account.total -= amount,
+                }
+            }
+            TransactionType::Close => {}
+            // What a custom type does is up to its handler, so the balances
+            // it left are taken as recorded
+            TransactionType::Custom(_) => {
+                let balances = (line.available, line.held, line.total, line.locked);
+                let (Some(available), Some(held), Some(total), Some(locked)) = balances else {
+                    return Err(format!(
+                        "event {}: {:?} {} without balances",
+                        line.seq, tx_type, tx
+                    ));
+                };
+                *account = Account {
+                    available,
+                    held,
+                    total,
+                    locked,
+                };
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Recomputes every client's balances from an `--events` journal by
+/// folding the applied transactions' amounts, independently of the
+/// processor that decided them.
+///
+/// Events are taken in sequence order. Amounts only add up, so the order
+/// doesn't change the result except for resolves and chargebacks without
+/// an amount, which settle whatever their dispute holds at that point.
+pub fn replay_journal<R: BufRead>(reader: R) -> Result<Report, Box<dyn Error>> {
+    let mut lines: Vec<JournalLine> = parse_journal(reader)?;
+    lines.sort_by_key(|line| line.seq);
+
+    let mut ledger = Ledger::default();
+    for line in &lines {
+        ledger.apply(line)?;
+    }
+    Ok(ledger
+        .accounts
+        .iter()
+        .map(|(client, account)| {
+            ReportRow::new(
+                *client,
+                account.available,
+                account.held,
+                account.total,
+                account.locked,
+            )
+        })
+        .collect())
+}
+
+/// Compares the balances recomputed from the journal at `events`, as the
+/// expected ones, with the report at `report`. The journal may be gzip or
+/// zstd compressed, according to its extension.
+pub fn audit_journal(events: &Path, report: &Path) -> Result<Vec<RowDiff>, Box<dyn Error>> {
+    let journal = open_journal(events)?;
+    let expected =
+        replay_journal(journal).map_err(|err| format!("{}: {}", events.display(), err))?;
+    Ok(expected.diff(&read_report(report)?))
+}
+
+/// Compares the balances of applying `input` in file order on a fresh
+/// engine, as the expected ones, with the report at `report`.
+pub fn audit_input(
+    input: &Path,
+    builder: &EngineBuilder,
+    report: &Path,
+) -> Result<Vec<RowDiff>, Box<dyn Error>> {
+    let expected = Report::from_engine(&replay(input, builder)?);
+    Ok(expected.diff(&read_report(report)?))
+}
+
+fn read_report(path: &Path) -> Result<Report, Box<dyn Error>> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Report::parse(file).map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::events::JsonlSink;
+    use crate::transactions::{AdjustmentReason, Transaction};
+    use std::sync::{Arc, Mutex};
+
+    /// Collects what a `JsonlSink` writes, to read it back.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_journal_replays_to_the_engine_balances() {
+        let journal = Shared::default();
+        let engine = PaymentsEngine::builder()
+            .event_sink(JsonlSink::new(journal.clone()))
+            .allow_adjustments(true)
+            .build();
+        for tx in [
+            Transaction::new_deposit(1, 1, 5.0),
+            Transaction::new_withdrawal(1, 2, 1.5),
+            Transaction::new_withdrawal(2, 3, 1.0),
+            Transaction::new_deposit(2, 4, 2.0),
+            Transaction::new_dispute(2, 4),
+            Transaction::new_chargeback(2, 4),
+            Transaction {
+                amount: Some(1.0),
+                ..Transaction::new_dispute(1, 1)
+            },
+            Transaction::new_resolve(1, 1),
+            Transaction::new_debit(3, 5, 0.25, AdjustmentReason::Fee),
+        ] {
+            engine.apply_transaction(tx);
+        }
+        engine.flush_events().unwrap();
+
+        let replayed = replay_journal(journal.0.lock().unwrap().as_slice()).unwrap();
+        assert_eq!(replayed.diff(&Report::from_engine(&engine)), []);
+
+        engine.merge_clients(3, 2).unwrap();
+        engine.flush_events().unwrap();
+        let replayed = replay_journal(journal.0.lock().unwrap().as_slice()).unwrap();
+        assert_eq!(replayed.diff(&Report::from_engine(&engine)), []);
+
+        engine.clients().get_mut(&1).unwrap().available = 4.0;
+        assert_eq!(
+            replayed.diff(&Report::from_engine(&engine)),
+            [RowDiff::Changed {
+                expected: ReportRow::new(1, 3.5, 0.0, 3.5, false),
+                actual: ReportRow::new(1, 4.0, 0.0, 3.5, false),
+            }]
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use std::io::{self, Write};
+use std::sync::OnceLock;
+
+use log::{LevelFilter, Log, Metadata, Record};
+use payments_engine::engine::RunId;
+
+/// Writes log records to stderr, one per line, so diagnostics never mix
+/// into a report printed on stdout.
+struct StderrLogger;
+
+/// Run id prefixed to every line, if one was given.
+static RUN_ID: OnceLock<RunId> = OnceLock::new();
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let mut stderr = io::stderr().lock();
+            // Nowhere left to report a failing stderr
+            let _ = match RUN_ID.get() {
+                Some(run_id) => writeln!(stderr, "[{}] {}", run_id, record.args()),
+                None => writeln!(stderr, "{}", record.args()),
+            };
+        }
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Sends log records up to `level` to stderr, prefixed with `run_id` if
+/// given. Call once at startup.
+pub fn init(level: LevelFilter, run_id: Option<RunId>) {
+    if let Some(run_id) = run_id {
+        let _ = RUN_ID.set(run_id);
+    }
+    // Only fails if a logger was already set
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+}
@@ -0,0 +1,1395 @@
+mod logger;
+
+pub use logger::init as init_logging;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, ArgMatches, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use log::LevelFilter;
+use payments_engine::currency::{Currency, Precision, RoundingMode, DEFAULT_DECIMALS};
+use payments_engine::engine::{
+    ClientLocking, EngineBuilder, EngineHasher, PaymentsEngine, RunId, RuntimeConfig,
+};
+use payments_engine::events::{FanoutSink, JsonlSink};
+use payments_engine::import::{AccountMap, ImportFormat};
+use payments_engine::invariants::{InvariantChecks, OnViolation};
+use payments_engine::io::actors::ActorConfig;
+use payments_engine::io::chaos::{Chaos, ChaosConfig};
+use payments_engine::io::{
+    Compression, CsvReport, FixedWidthLayout, JsonReport, ReportColumns, ReportLayout, ReportSink,
+    ShardBy, TableReport,
+};
+use payments_engine::notify::{AlertType, FileNotifier, Notifier};
+use payments_engine::policy::{AmountLimits, ClientLimits, DuplicatePolicy};
+use payments_engine::remap::ClientIdMap;
+use payments_engine::screening::ClientBlocklist;
+use payments_engine::statement::StatementDate;
+use payments_engine::transactions::TransactionType;
+use payments_engine::webhook::{WebhookConfig, WebhookSink, WebhookUrl};
+use tokio::runtime::Runtime;
+
+/// Applies a CSV file of deposits, withdrawals, disputes, resolves and
+/// chargebacks and prints the resulting client balances
+#[derive(Parser)]
+#[command(version, about, arg_required_else_help = true)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// CSV file with the transactions to process, or fixed-width records
+    /// with --fixed-width
+    pub input: Option<String>,
+
+    /// Read the input as fixed-width records, one per line, with the
+    /// fields where this JSON layout file puts them
+    #[arg(
+        long,
+        value_name = "LAYOUT",
+        value_parser = parse_fixed_width_layout,
+        conflicts_with = "partitions"
+    )]
+    pub fixed_width: Option<FixedWidthLayout>,
+
+    /// Import the input as a bank file of this format instead of CSV:
+    /// iso20022 for pain.001 and camt.053 XML, with the iso20022 feature,
+    /// or ofx or qif for bank statement exports
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        requires = "accounts",
+        conflicts_with_all = ["fixed_width", "partitions", "skip", "limit", "checkpoint", "resume"]
+    )]
+    pub import: Option<ImportFormat>,
+
+    /// CSV file with account and client columns, giving the client of each
+    /// bank account in an --import
+    #[arg(long, value_name = "PATH", value_parser = parse_accounts, requires = "import")]
+    pub accounts: Option<AccountMap>,
+
+    /// Id of the first transaction of an --import, numbered in file order
+    #[arg(long, value_name = "ID", default_value_t = 1, requires = "import")]
+    pub first_tx: u32,
+
+    /// Split the input into this many byte ranges parsed in parallel, with
+    /// each client's transactions applied in file order on its own shard
+    #[arg(long, value_name = "N")]
+    pub partitions: Option<usize>,
+
+    /// Pass over this many data rows before processing
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 0,
+        conflicts_with = "partitions"
+    )]
+    pub skip: u64,
+
+    /// Stop after processing this many data rows
+    #[arg(long, value_name = "N", conflicts_with = "partitions")]
+    pub limit: Option<u64>,
+
+    /// When the run ends, including on ctrl-c, save the engine's state and
+    /// the number of rows read to this file for --resume
+    #[arg(long, value_name = "PATH", conflicts_with = "partitions")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Continue from a --checkpoint file after the rows it covers. The input
+    /// must be the same file, unchanged
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["partitions", "skip"])]
+    pub resume: Option<PathBuf>,
+
+    /// Keep the ids of every deposit and withdrawal processed across runs
+    /// in this file, created if missing, and ignore those repeating one,
+    /// e.g. when replaying daily files that overlap. Updated once the
+    /// report has been printed
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_TX_ID_INDEX")]
+    pub tx_id_index: Option<PathBuf>,
+
+    /// Write a JSON manifest of the run to this file: the input's
+    /// fingerprint, row and rejection counts, engine version, settings and
+    /// start and end times
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_MANIFEST")]
+    pub manifest: Option<PathBuf>,
+
+    /// Write the balances owed to closed accounts to this file as
+    /// `client,amount` CSV, compressed if it ends in .gz or .zst
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_PAYOUTS")]
+    pub payouts: Option<PathBuf>,
+
+    /// Write every dispute, resolve and chargeback to this file as CSV,
+    /// grouped by transaction, compressed if it ends in .gz or .zst
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_DISPUTES")]
+    pub disputes: Option<PathBuf>,
+
+    /// Write every transaction rejected by --blocklist to this file as CSV,
+    /// compressed if it ends in .gz or .zst
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_SCREENING")]
+    pub screening: Option<PathBuf>,
+
+    /// Credit interest from the JSON rate table in this file to every open,
+    /// unlocked client once the whole input has been applied
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_INTEREST_RATES")]
+    pub interest_rates: Option<PathBuf>,
+
+    /// Only report these clients, e.g. --client 7,12
+    #[arg(long = "client", value_name = "IDS", value_delimiter = ',')]
+    pub clients: Vec<u16>,
+
+    /// With --client, don't apply other clients' transactions at all
+    #[arg(long, requires = "clients")]
+    pub skip_other_clients: bool,
+
+    /// Exit with a distinct status when rows were malformed, transactions
+    /// were rejected, processing was interrupted or an output couldn't be
+    /// written: 3 rejected, 4 interrupted, 5 unreadable input or malformed
+    /// rows, 6 output error
+    #[arg(long, env = "PAYMENTS_ENGINE_STRICT_EXIT", value_parser = BoolishValueParser::new())]
+    pub strict_exit: bool,
+
+    /// Only print errors on stderr, not warnings such as skipped rows
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print more on stderr: -v adds each rejected transaction and
+    /// per-file progress, -vv every transaction's outcome
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    #[command(flatten)]
+    pub report: ReportOptions,
+
+    #[command(flatten)]
+    pub engine: EngineOptions,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Arguments that don't change what a run produces, left out of its
+/// recorded settings.
+const UNRECORDED_ARGS: [&str; 5] = ["input", "manifest", "quiet", "run_id", "verbose"];
+
+impl Cli {
+    /// Every setting of a batch run by name, whether given on the command
+    /// line, through the environment or left at its default, for the run
+    /// manifest.
+    pub fn effective_config(matches: &ArgMatches) -> BTreeMap<String, String> {
+        let command = Cli::command();
+        command
+            .get_arguments()
+            .map(|arg| arg.get_id().as_str())
+            .filter(|id| !UNRECORDED_ARGS.contains(id))
+            .filter_map(|id| {
+                let values = matches.try_get_raw(id).ok()??;
+                let values: Vec<_> = values.map(|value| value.to_string_lossy()).collect();
+                Some((id.to_string(), values.join(",")))
+            })
+            .collect()
+    }
+
+    /// Most detailed level of diagnostics to print.
+    pub fn log_level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+
+    /// Engine settings of whichever mode was selected.
+    pub fn engine_options(&self) -> &EngineOptions {
+        match &self.command {
+            Some(Command::Watch { engine, .. })
+            | Some(Command::Tail { engine, .. })
+            | Some(Command::Simulate { engine, .. })
+            | Some(Command::Conformance { engine, .. })
+            | Some(Command::Soak { engine, .. })
+            | Some(Command::Fix { engine, .. })
+            | Some(Command::Verify { engine, .. })
+            | Some(Command::AuditBalances { engine, .. })
+            | Some(Command::Export { engine, .. }) => engine,
+            Some(Command::Statement { .. })
+            | Some(Command::Rollup { .. })
+            | Some(Command::EraseClient { .. })
+            | Some(Command::MergeClients { .. })
+            | Some(Command::Completions { .. })
+            | Some(Command::Man { .. })
+            | None => &self.engine,
+        }
+    }
+}
+
+/// How a batch run ended, from least to most severe. With `--strict-exit`
+/// the process exits with the most severe status that applies.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ExitStatus {
+    Success = 0,
+    /// Some transactions were rejected, e.g. for insufficient funds.
+    Rejected = 3,
+    /// Reading stopped early on ctrl-c; the report covers what was read.
+    Interrupted = 4,
+    /// The input couldn't be read, or malformed rows in it were skipped.
+    InvalidInput = 5,
+    /// The report, its schema or the events couldn't be written.
+    OutputError = 6,
+    /// A transaction broke an invariant checked by --check-invariants.
+    InvariantViolation = 7,
+}
+
+impl ExitStatus {
+    /// Process exit code for a run that ended in `result`, where an `Err`
+    /// stopped the run early. Without `strict`, only stopping early fails.
+    pub fn code(result: Result<ExitStatus, ExitStatus>, strict: bool) -> i32 {
+        match result {
+            Ok(status) | Err(status) if strict => status as i32,
+            Ok(_) => 0,
+            Err(_) => 1,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Watch a drop directory, processing CSV files as they appear
+    Watch {
+        /// Directory to watch; processed/ and failed/ are created inside it
+        dir: PathBuf,
+
+        /// Rewrite the client report at this path after every processed file
+        #[arg(long, env = "PAYMENTS_ENGINE_REPORT")]
+        report: Option<PathBuf>,
+
+        /// Write only the clients changed since the previous delta to a new
+        /// numbered file in this directory after every processed file
+        #[arg(long, value_name = "DIR", env = "PAYMENTS_ENGINE_DELTA_DIR")]
+        delta_dir: Option<PathBuf>,
+
+        /// How often to poll the directory, in milliseconds
+        #[arg(long, default_value_t = 1000, env = "PAYMENTS_ENGINE_POLL_MS")]
+        poll_ms: u64,
+
+        #[command(flatten)]
+        report_options: ReportOptions,
+
+        #[command(flatten)]
+        chaos: ChaosOptions,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Follow a growing CSV file, applying rows as they are appended
+    Tail {
+        /// CSV file to follow; it may not exist yet
+        input: PathBuf,
+
+        /// Rewrite the client report at this path after every poll that
+        /// read new rows
+        #[arg(long, env = "PAYMENTS_ENGINE_REPORT")]
+        report: Option<PathBuf>,
+
+        /// Write only the clients changed since the previous delta to a new
+        /// numbered file in this directory after every poll that read new
+        /// rows
+        #[arg(long, value_name = "DIR", env = "PAYMENTS_ENGINE_DELTA_DIR")]
+        delta_dir: Option<PathBuf>,
+
+        /// How often to check the file for new rows, in milliseconds
+        #[arg(long, default_value_t = 1000, env = "PAYMENTS_ENGINE_POLL_MS")]
+        poll_ms: u64,
+
+        #[command(flatten)]
+        report_options: ReportOptions,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Replay a CSV file in a seeded, reproducible interleaving
+    Simulate {
+        /// CSV file with the transactions to replay
+        input: PathBuf,
+
+        /// Seed driving the interleaving; the same seed replays the same order
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        /// Number of transactions in flight that may be reordered
+        #[arg(long, default_value_t = 8)]
+        window: usize,
+
+        /// Instead of printing a report, try this many seeds starting at
+        /// --seed and list those whose balances differ from in-order processing
+        #[arg(long, value_name = "N")]
+        seeds: Option<u64>,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Process an endless synthetic feed, periodically logging memory, map
+    /// sizes and throughput
+    Soak {
+        /// Stop after this many seconds instead of at ctrl-c
+        #[arg(long, value_name = "SECONDS")]
+        duration_secs: Option<u64>,
+
+        /// How often to log resource usage, in seconds
+        #[arg(long, default_value_t = 10)]
+        report_every_secs: u64,
+
+        /// Number of distinct clients in the feed
+        #[arg(long, default_value_t = 1000)]
+        clients: u16,
+
+        /// Seed of the synthetic feed
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Accept a FIX session from a trading venue and apply its trades as
+    /// deposits and withdrawals until ctrl-c, then print the report
+    Fix {
+        /// Address to accept the venue's connections on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:9878")]
+        listen: String,
+
+        /// The engine's CompID, the venue's TargetCompID
+        #[arg(long, value_name = "ID")]
+        sender_comp_id: String,
+
+        /// The venue's CompID
+        #[arg(long, value_name = "ID")]
+        target_comp_id: String,
+
+        /// Protocol version the venue speaks
+        #[arg(long, default_value = "FIX.4.4")]
+        begin_string: String,
+
+        /// Id of the first trade's transaction, numbering the rest in
+        /// order; keep clear of the ids of other inputs
+        #[arg(long, value_name = "ID", default_value_t = 1)]
+        first_tx: u32,
+
+        #[command(flatten)]
+        report_options: ReportOptions,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Compare two reports, or several runs of one input, and list any
+    /// divergence
+    Verify {
+        /// Two report files to compare, e.g. written by two versions
+        #[arg(
+            num_args = 2,
+            value_names = ["FIRST", "SECOND"],
+            required_unless_present = "input",
+            conflicts_with = "input"
+        )]
+        reports: Vec<PathBuf>,
+
+        /// Instead, process this CSV file several times and compare every
+        /// run with in-order processing
+        #[arg(long)]
+        input: Option<String>,
+
+        /// Number of runs of --input
+        #[arg(long, default_value_t = 2)]
+        runs: usize,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Recompute balances from an event journal or the input, and list
+    /// where a stored report disagrees with them
+    AuditBalances {
+        /// Report file to check, e.g. written by the run that wrote the
+        /// journal
+        report: PathBuf,
+
+        /// Event journal written with --events, optionally gzip or zstd
+        /// compressed
+        #[arg(
+            long,
+            value_name = "PATH",
+            required_unless_present = "input",
+            conflicts_with = "input"
+        )]
+        journal: Option<PathBuf>,
+
+        /// Instead, process this CSV file in order on a fresh engine
+        #[arg(long, value_name = "PATH")]
+        input: Option<PathBuf>,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Print account statements from an event journal: the opening
+    /// balance, every transaction with the balances after it, and the
+    /// closing balance
+    Statement {
+        /// Event journal written with --events, optionally gzip or zstd
+        /// compressed
+        journal: PathBuf,
+
+        /// Clients to print statements for, e.g. --client 1,7; every client
+        /// by default
+        #[arg(long, value_name = "ID", value_delimiter = ',')]
+        client: Vec<u16>,
+
+        /// First journal sequence number to cover
+        #[arg(long, value_name = "SEQ", default_value_t = 1)]
+        from: u64,
+
+        /// Last journal sequence number to cover; the end of the journal by
+        /// default
+        #[arg(long, value_name = "SEQ")]
+        to: Option<u64>,
+
+        /// Write one HTML page instead of plain text
+        #[arg(long)]
+        html: bool,
+
+        /// Write SWIFT MT940 statements in --currency instead of plain text
+        #[arg(long, conflicts_with = "html", requires = "currency")]
+        mt940: bool,
+
+        /// Date of MT940 statements and their entries, as YYYY-MM-DD;
+        /// today in UTC by default
+        #[arg(long, value_name = "DATE", requires = "mt940")]
+        date: Option<StatementDate>,
+
+        /// Number of MT940 statements in the account holders' series
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        statement_number: u32,
+
+        #[command(flatten)]
+        amounts: AmountOptions,
+    },
+    /// Add up each client's activity per period of an event journal, as
+    /// CSV for finance
+    Rollup {
+        /// Event journal written with --events, optionally gzip or zstd
+        /// compressed
+        journal: PathBuf,
+
+        /// Number of journal sequence numbers each period spans
+        #[arg(long, value_name = "EVENTS", value_parser = clap::value_parser!(u64).range(1..))]
+        period: u64,
+
+        /// Write the rollups to this file instead of stdout, compressed if
+        /// it ends in .gz or .zst
+        #[arg(long, value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        amounts: AmountOptions,
+    },
+    /// Process a CSV file and write its transactions and report with
+    /// pseudonymized client and transaction ids, for sharing with analytics
+    Export {
+        /// CSV file to process, in file order
+        input: PathBuf,
+
+        /// Directory to write transactions.csv and clients.csv to
+        #[arg(long, value_name = "DIR")]
+        out: PathBuf,
+
+        /// File holding the secret key the pseudonyms are derived from
+        #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_EXPORT_KEY_FILE")]
+        key_file: PathBuf,
+
+        /// Round amounts down to multiples of this amount
+        #[arg(long, value_name = "AMOUNT", value_parser = parse_bucket)]
+        bucket: Option<f64>,
+
+        #[command(flatten)]
+        amounts: AmountOptions,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Erase a client's trail from event journals and checkpoints, leaving
+    /// an erasure certificate in each journal
+    #[command(group(clap::ArgGroup::new("stores").required(true).multiple(true)))]
+    EraseClient {
+        /// Client to erase
+        client: u16,
+
+        /// Event journal written with --events to erase the client from,
+        /// optionally gzip or zstd compressed; may be repeated
+        #[arg(long, value_name = "PATH", group = "stores")]
+        journal: Vec<PathBuf>,
+
+        /// Checkpoint written with --checkpoint to erase the client from;
+        /// may be repeated
+        #[arg(long, value_name = "PATH", group = "stores")]
+        checkpoint: Vec<PathBuf>,
+    },
+    /// Merge one client's account into another's in checkpoints, e.g. once
+    /// upstream systems found them to be the same customer
+    MergeClients {
+        /// Client whose account is merged and removed
+        from: u16,
+
+        /// Client taking over the balances and transactions
+        into: u16,
+
+        /// Checkpoint written with --checkpoint to merge the clients in;
+        /// may be repeated
+        #[arg(long, value_name = "PATH", required = true)]
+        checkpoint: Vec<PathBuf>,
+    },
+    /// Run a directory of NAME.csv / NAME.expected.csv pairs and report diffs
+    Conformance {
+        /// Directory holding the suite
+        dir: PathBuf,
+
+        #[command(flatten)]
+        engine: EngineOptions,
+    },
+    /// Print a shell completion script, e.g. to source from ~/.bashrc
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+    /// Print the man page, or write one page per subcommand to a directory
+    Man {
+        #[arg(long, value_name = "DIR")]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+/// Settings shared by every mode that runs an engine.
+#[derive(Args)]
+pub struct EngineOptions {
+    /// Slow ingestion down once the engine is estimated to use this many bytes
+    #[arg(long, value_name = "BYTES", env = "PAYMENTS_ENGINE_MEMORY_WATERMARK")]
+    pub memory_watermark: Option<usize>,
+
+    /// Apply transactions through one actor per client, in order, instead
+    /// of a task per transaction. Ignores --memory-watermark
+    #[arg(
+        long,
+        env = "PAYMENTS_ENGINE_CLIENT_ACTORS",
+        value_parser = BoolishValueParser::new()
+    )]
+    pub client_actors: bool,
+
+    /// With --client-actors, let a client's actor hibernate after this many
+    /// milliseconds without transactions
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 1000,
+        requires = "client_actors",
+        env = "PAYMENTS_ENGINE_ACTOR_IDLE_MS"
+    )]
+    pub actor_idle_ms: u64,
+
+    /// With --client-actors, transactions a client's mailbox holds before
+    /// reading input waits for its actor
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1024,
+        requires = "client_actors",
+        env = "PAYMENTS_ENGINE_ACTOR_MAILBOX"
+    )]
+    pub actor_mailbox: usize,
+
+    /// Most transactions spawned and not yet applied at once; reading input
+    /// waits for the oldest beyond it
+    #[arg(long, value_name = "N", env = "PAYMENTS_ENGINE_MAX_IN_FLIGHT")]
+    pub max_in_flight: Option<usize>,
+
+    /// Number of shards in the client and transaction maps (a power of two)
+    #[arg(long, value_parser = parse_shard_amount, env = "PAYMENTS_ENGINE_SHARDS")]
+    pub shards: Option<usize>,
+
+    /// Hash function for the maps: sip (DoS-resistant) or fx (faster)
+    #[arg(long, default_value = "sip", env = "PAYMENTS_ENGINE_HASHER")]
+    pub hasher: EngineHasher,
+
+    /// How transactions lock client accounts: entries (each access locks
+    /// the account's shard of the map) or shard-mutexes (a transaction
+    /// holds its shard from start to end, for traffic dominated by a few
+    /// clients)
+    #[arg(
+        long,
+        value_name = "STRATEGY",
+        default_value = "entries",
+        env = "PAYMENTS_ENGINE_CLIENT_LOCKING"
+    )]
+    pub client_locking: ClientLocking,
+
+    /// Measure how often transactions wait for their client's lock and log
+    /// the hottest clients at the end of the run
+    #[arg(
+        long,
+        env = "PAYMENTS_ENGINE_LOCK_STATS",
+        value_parser = BoolishValueParser::new()
+    )]
+    pub lock_stats: bool,
+
+    /// Put a Bloom filter sized for this many transactions in front of the
+    /// transaction store to speed up duplicate checks
+    #[arg(
+        long,
+        value_name = "EXPECTED_TRANSACTIONS",
+        env = "PAYMENTS_ENGINE_TX_ID_FILTER"
+    )]
+    pub tx_id_filter: Option<usize>,
+
+    /// Pre-allocate the client map for this many clients
+    #[arg(long, value_name = "N", env = "PAYMENTS_ENGINE_EXPECTED_CLIENTS")]
+    pub expected_clients: Option<usize>,
+
+    /// Pre-allocate the transaction map and ingestion buffers for this many
+    /// transactions
+    #[arg(long, value_name = "N", env = "PAYMENTS_ENGINE_EXPECTED_TRANSACTIONS")]
+    pub expected_transactions: Option<usize>,
+
+    /// Number of runtime worker threads, defaults to the number of CPUs
+    #[arg(long, env = "PAYMENTS_ENGINE_WORKER_THREADS")]
+    pub worker_threads: Option<usize>,
+
+    /// Most runtime threads for blocking work such as file reads, 512 by
+    /// default
+    #[arg(long, value_name = "N", env = "PAYMENTS_ENGINE_MAX_BLOCKING_THREADS")]
+    pub max_blocking_threads: Option<usize>,
+
+    /// Scheduler ticks between polls for I/O, 61 by default; lower favours
+    /// latency of socket input, higher throughput
+    #[arg(long, value_name = "TICKS", env = "PAYMENTS_ENGINE_EVENT_INTERVAL")]
+    pub event_interval: Option<u32>,
+
+    /// Scheduler ticks between checks of the runtime's shared task queue,
+    /// 31 by default
+    #[arg(
+        long,
+        value_name = "TICKS",
+        env = "PAYMENTS_ENGINE_GLOBAL_QUEUE_INTERVAL"
+    )]
+    pub global_queue_interval: Option<u32>,
+
+    /// Id of this run, up to 32 hex digits, recorded in the manifest and
+    /// every outcome event, e.g. to tell the instances of a deployment
+    /// apart. Generated if not given; a given id also prefixes every line
+    /// logged on stderr
+    #[arg(long, value_name = "HEX", env = "PAYMENTS_ENGINE_RUN_ID")]
+    pub run_id: Option<RunId>,
+
+    /// Write the outcome of every transaction to this file as JSON lines,
+    /// compressed if it ends in .gz or .zst
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_EVENTS")]
+    pub events: Option<PathBuf>,
+
+    /// Post the events of applied chargebacks, or of --webhook-types, to
+    /// this http:// URL, retrying failed deliveries
+    #[arg(
+        long,
+        value_name = "URL",
+        env = "PAYMENTS_ENGINE_WEBHOOK",
+        requires_all = ["webhook_outbox", "webhook_dead_letter"]
+    )]
+    pub webhook: Option<WebhookUrl>,
+
+    /// Log of webhook alerts and delivery attempts; alerts it shows pending
+    /// are retried when the next run opens it
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "PAYMENTS_ENGINE_WEBHOOK_OUTBOX",
+        requires = "webhook"
+    )]
+    pub webhook_outbox: Option<PathBuf>,
+
+    /// File webhook alerts go to once every delivery attempt failed
+    #[arg(
+        long,
+        value_name = "PATH",
+        env = "PAYMENTS_ENGINE_WEBHOOK_DEAD_LETTER",
+        requires = "webhook"
+    )]
+    pub webhook_dead_letter: Option<PathBuf>,
+
+    /// Alerts posted to the webhook: transaction types whose applied
+    /// outcomes are posted, or account changes (created, locked, closed,
+    /// erased, merged)
+    #[arg(
+        long,
+        value_name = "TYPE",
+        value_delimiter = ',',
+        default_value = "chargeback",
+        env = "PAYMENTS_ENGINE_WEBHOOK_TYPES"
+    )]
+    pub webhook_types: Vec<AlertType>,
+
+    /// Append alerts of a type to a file as JSON lines, e.g.
+    /// --notify locked=locks.jsonl; repeat, or separate with commas, for
+    /// more types or files
+    #[arg(
+        long,
+        value_name = "TYPE=PATH",
+        value_parser = parse_notify_route,
+        value_delimiter = ',',
+        env = "PAYMENTS_ENGINE_NOTIFY"
+    )]
+    pub notify: Vec<(AlertType, PathBuf)>,
+
+    /// Delivery attempts per webhook alert before it is dead-lettered
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 8,
+        value_parser = clap::value_parser!(u32).range(1..),
+        env = "PAYMENTS_ENGINE_WEBHOOK_MAX_ATTEMPTS"
+    )]
+    pub webhook_max_attempts: u32,
+
+    /// Wait before retrying a failed webhook delivery, in milliseconds,
+    /// doubled after every further failure up to five minutes
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 1000,
+        env = "PAYMENTS_ENGINE_WEBHOOK_BACKOFF_MS"
+    )]
+    pub webhook_backoff_ms: u64,
+
+    /// Reject every transaction of the clients listed in this file, one id
+    /// per line, e.g. from a sanctions list
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = parse_blocklist,
+        env = "PAYMENTS_ENGINE_BLOCKLIST"
+    )]
+    pub blocklist: Option<ClientBlocklist>,
+
+    /// Load per-client limits from this CSV file, with a client column and
+    /// any of max_withdrawal, overdraft_limit and minimum_balance. They
+    /// take precedence over the other limit options, and watch mode
+    /// reloads the file when it changes
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = parse_client_limits,
+        env = "PAYMENTS_ENGINE_CLIENT_LIMITS"
+    )]
+    pub client_limits: Option<ClientLimitsFile>,
+
+    /// Map legacy client ids to current ones on the way in, from this CSV
+    /// file with old_client and new_client columns
+    #[arg(
+        long,
+        value_name = "PATH",
+        value_parser = parse_client_map,
+        env = "PAYMENTS_ENGINE_CLIENT_MAP"
+    )]
+    pub client_map: Option<ClientIdMap>,
+
+    /// Let withdrawals take the available balance down to minus this
+    /// amount instead of rejecting them below zero
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        value_parser = parse_limit,
+        env = "PAYMENTS_ENGINE_OVERDRAFT_LIMIT"
+    )]
+    pub overdraft_limit: Option<f64>,
+
+    /// Overdraft limit of one client, overriding --overdraft-limit, e.g.
+    /// --client-overdraft-limit 7=250,12=0
+    #[arg(
+        long,
+        value_name = "CLIENT=AMOUNT",
+        value_parser = parse_client_limit,
+        value_delimiter = ',',
+        env = "PAYMENTS_ENGINE_CLIENT_OVERDRAFT_LIMIT"
+    )]
+    pub client_overdraft_limit: Vec<(u16, f64)>,
+
+    /// Hold withdrawals above this amount until an approve or deny
+    /// transaction for them
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        value_parser = parse_limit,
+        env = "PAYMENTS_ENGINE_REVIEW_THRESHOLD"
+    )]
+    pub review_threshold: Option<f64>,
+
+    /// Deny a held withdrawal still pending this many seconds after it
+    /// was held
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        requires = "review_threshold",
+        env = "PAYMENTS_ENGINE_REVIEW_TIMEOUT_SECS"
+    )]
+    pub review_timeout_secs: Option<u64>,
+
+    /// Instead, deny it once this many more transactions were received,
+    /// whatever the time
+    #[arg(
+        long,
+        value_name = "TRANSACTIONS",
+        requires = "review_threshold",
+        conflicts_with = "review_timeout_secs",
+        env = "PAYMENTS_ENGINE_REVIEW_TIMEOUT_TRANSACTIONS"
+    )]
+    pub review_timeout_transactions: Option<u64>,
+
+    /// Reject withdrawals that would leave less than this amount available
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        value_parser = parse_limit,
+        env = "PAYMENTS_ENGINE_MINIMUM_BALANCE"
+    )]
+    pub minimum_balance: Option<f64>,
+
+    /// Minimum balance of one client, overriding --minimum-balance, e.g.
+    /// --client-minimum-balance 7=1000,12=0
+    #[arg(
+        long,
+        value_name = "CLIENT=AMOUNT",
+        value_parser = parse_client_limit,
+        value_delimiter = ',',
+        env = "PAYMENTS_ENGINE_CLIENT_MINIMUM_BALANCE"
+    )]
+    pub client_minimum_balance: Vec<(u16, f64)>,
+
+    /// Reject transactions with an amount below this
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        value_parser = parse_limit,
+        env = "PAYMENTS_ENGINE_MIN_AMOUNT"
+    )]
+    pub min_amount: Option<f64>,
+
+    /// Reject transactions with an amount above this
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        value_parser = parse_limit,
+        env = "PAYMENTS_ENGINE_MAX_AMOUNT"
+    )]
+    pub max_amount: Option<f64>,
+
+    /// Amount limits of one transaction type, replacing --min-amount and
+    /// --max-amount for it, e.g. --amount-limit deposit=..1000000,withdrawal=0.01..
+    #[arg(
+        long,
+        value_name = "TYPE=MIN..MAX",
+        value_parser = parse_type_amount_limits,
+        value_delimiter = ',',
+        env = "PAYMENTS_ENGINE_AMOUNT_LIMIT"
+    )]
+    pub amount_limit: Vec<(TransactionType, AmountLimits)>,
+
+    /// What to do with a deposit or withdrawal reusing an earlier
+    /// transaction id: ignore it, warn (log it), reject it and fail the
+    /// run, or replace the earlier one (last-write-wins)
+    #[arg(
+        long,
+        value_name = "POLICY",
+        default_value = "ignore",
+        env = "PAYMENTS_ENGINE_DUPLICATES"
+    )]
+    pub duplicates: DuplicatePolicy,
+
+    /// Apply manual credit and debit adjustments instead of rejecting them.
+    /// Debits may then overdraw the account
+    #[arg(
+        long,
+        env = "PAYMENTS_ENGINE_ALLOW_ADJUSTMENTS",
+        value_parser = BoolishValueParser::new()
+    )]
+    pub allow_adjustments: bool,
+
+    /// Check every client's balances after each applied transaction, and
+    /// on a violation alert (log an error) or abort
+    #[arg(long, value_name = "ACTION", env = "PAYMENTS_ENGINE_CHECK_INVARIANTS")]
+    pub check_invariants: Option<OnViolation>,
+
+    /// With --check-invariants, also require available funds to stay at or
+    /// above zero, or minus the overdraft limit
+    #[arg(
+        long,
+        requires = "check_invariants",
+        env = "PAYMENTS_ENGINE_STRICT_INVARIANTS",
+        value_parser = BoolishValueParser::new()
+    )]
+    pub strict_invariants: bool,
+}
+
+impl EngineOptions {
+    pub fn build(&self) -> PaymentsEngine {
+        self.builder().build()
+    }
+
+    /// The id given with --run-id, or else the one generated for this
+    /// process, the same for every engine it builds.
+    pub fn run_id(&self) -> RunId {
+        static GENERATED: OnceLock<RunId> = OnceLock::new();
+        self.run_id
+            .unwrap_or_else(|| *GENERATED.get_or_init(RunId::generate))
+    }
+
+    pub fn builder(&self) -> EngineBuilder {
+        let mut builder = PaymentsEngine::builder()
+            .run_id(self.run_id())
+            .hasher(self.hasher.clone())
+            .client_locking(self.client_locking)
+            .contention_stats(self.lock_stats);
+        if let Some(bytes) = self.memory_watermark {
+            builder = builder.memory_watermark(bytes);
+        }
+        if self.client_actors {
+            builder = builder.client_actors(ActorConfig {
+                idle_timeout: Duration::from_millis(self.actor_idle_ms),
+                mailbox_capacity: self.actor_mailbox,
+            });
+        }
+        if let Some(transactions) = self.max_in_flight {
+            builder = builder.max_in_flight(transactions);
+        }
+        builder = builder.runtime(self.runtime_config());
+        if let Some(shards) = self.shards {
+            builder = builder.shard_amount(shards);
+        }
+        if let Some(expected) = self.tx_id_filter {
+            builder = builder.tx_id_filter(expected);
+        }
+        if let Some(clients) = self.expected_clients {
+            builder = builder.expected_clients(clients);
+        }
+        if let Some(transactions) = self.expected_transactions {
+            builder = builder.expected_transactions(transactions);
+        }
+        if let Some(limit) = self.overdraft_limit {
+            builder = builder.overdraft_limit(limit);
+        }
+        for &(client, limit) in &self.client_overdraft_limit {
+            builder = builder.client_overdraft_limit(client, limit);
+        }
+        if let Some(blocklist) = &self.blocklist {
+            builder = builder.blocklist(blocklist.clone());
+        }
+        if let Some(file) = &self.client_limits {
+            builder = builder.client_limits(file.limits.clone());
+        }
+        if let Some(ids) = &self.client_map {
+            builder = builder.client_id_map(ids.clone());
+        }
+        if let Some(threshold) = self.review_threshold {
+            builder = builder.review_threshold(threshold);
+        }
+        if let Some(secs) = self.review_timeout_secs {
+            builder = builder.review_timeout(Duration::from_secs(secs));
+        }
+        if let Some(transactions) = self.review_timeout_transactions {
+            builder = builder.review_timeout_transactions(transactions);
+        }
+        if let Some(minimum) = self.minimum_balance {
+            builder = builder.minimum_balance(minimum);
+        }
+        for &(client, minimum) in &self.client_minimum_balance {
+            builder = builder.client_minimum_balance(client, minimum);
+        }
+        if self.min_amount.is_some() || self.max_amount.is_some() {
+            builder = builder.amount_limits(AmountLimits {
+                min: self.min_amount,
+                max: self.max_amount,
+            });
+        }
+        for &(tx_type, limits) in &self.amount_limit {
+            builder = builder.type_amount_limits(tx_type, limits);
+        }
+        builder = builder.duplicates(self.duplicates);
+        if self.allow_adjustments {
+            builder = builder.allow_adjustments(true);
+        }
+        if let Some(on_violation) = self.check_invariants {
+            builder = builder.check_invariants(InvariantChecks {
+                on_violation,
+                strict: self.strict_invariants,
+            });
+        }
+        let mut sinks = FanoutSink::new();
+        if let Some(path) = &self.events {
+            let file = File::create(path)
+                .and_then(|file| Compression::from_path(path).writer(file))
+                .expect("Error creating events file");
+            sinks = sinks.with(JsonlSink::new(file));
+        }
+        let mut notifier = Notifier::new();
+        if let (Some(url), Some(outbox), Some(dead_letter)) = (
+            &self.webhook,
+            &self.webhook_outbox,
+            &self.webhook_dead_letter,
+        ) {
+            let mut config = WebhookConfig::new(url.clone(), outbox, dead_letter);
+            config.max_attempts = self.webhook_max_attempts;
+            config.backoff = Duration::from_millis(self.webhook_backoff_ms);
+            notifier = notifier.route(
+                self.webhook_types.clone(),
+                WebhookSink::open(config).expect("Error opening webhook outbox"),
+            );
+        }
+        // One sink per file, however many types go to it
+        let mut files: Vec<(&PathBuf, Vec<AlertType>)> = vec![];
+        for (alert, path) in &self.notify {
+            match files.iter_mut().find(|(file, _)| *file == path) {
+                Some((_, types)) => types.push(*alert),
+                None => files.push((path, vec![*alert])),
+            }
+        }
+        for (path, types) in files {
+            let file = FileNotifier::open(path).expect("Error opening notification file");
+            notifier = notifier.route(types, file);
+        }
+        if !notifier.is_empty() {
+            sinks = sinks.with(notifier);
+        }
+        if !sinks.is_empty() {
+            builder = builder.event_sink(sinks);
+        }
+        builder
+    }
+
+    pub fn runtime_config(&self) -> RuntimeConfig {
+        RuntimeConfig {
+            worker_threads: self.worker_threads,
+            max_blocking_threads: self.max_blocking_threads,
+            event_interval: self.event_interval,
+            global_queue_interval: self.global_queue_interval,
+        }
+    }
+
+    pub fn runtime(&self) -> std::io::Result<Runtime> {
+        self.runtime_config().build()
+    }
+}
+
+/// How the client report is printed.
+#[derive(Args)]
+pub struct ReportOptions {
+    /// Add per-client activity columns to the report
+    #[arg(long, env = "PAYMENTS_ENGINE_EXTENDED", value_parser = BoolishValueParser::new())]
+    pub extended: bool,
+
+    /// Print the report as CSV, as a JSON array of clients, or as an aligned
+    /// table for reading in a terminal
+    #[arg(long, value_enum, default_value_t = ReportFormat::Csv, env = "PAYMENTS_ENGINE_FORMAT")]
+    pub format: ReportFormat,
+
+    /// Leave out the line naming the columns
+    #[arg(long, env = "PAYMENTS_ENGINE_NO_HEADER", value_parser = BoolishValueParser::new())]
+    pub no_header: bool,
+
+    /// Compress the report printed to stdout or written to --report-dir:
+    /// none, gzip or zstd. A report file is compressed according to its
+    /// .gz or .zst extension
+    #[arg(long, default_value = "none", env = "PAYMENTS_ENGINE_COMPRESS")]
+    pub compress: Compression,
+
+    /// Write the report as CSV files in this directory instead of printing
+    /// it, split by client into --report-shards files for parallel loading
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "format",
+        env = "PAYMENTS_ENGINE_REPORT_DIR"
+    )]
+    pub report_dir: Option<PathBuf>,
+
+    /// Number of files --report-dir splits the report into
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 16,
+        requires = "report_dir",
+        env = "PAYMENTS_ENGINE_REPORT_SHARDS"
+    )]
+    pub report_shards: usize,
+
+    /// How --report-dir assigns clients to files: range, by consecutive
+    /// client id ranges, or hash, by client id modulo the number of files
+    #[arg(
+        long,
+        default_value = "hash",
+        requires = "report_dir",
+        env = "PAYMENTS_ENGINE_SHARD_BY"
+    )]
+    pub shard_by: ShardBy,
+
+    /// Write a JSON description of the report's columns and schema version
+    /// to this file
+    #[arg(long, value_name = "PATH", env = "PAYMENTS_ENGINE_SCHEMA")]
+    pub schema: Option<PathBuf>,
+
+    /// Write amounts with the minor units of this ISO 4217 currency, e.g.
+    /// 0 places for JPY or 3 for BHD, instead of four
+    #[arg(long, value_name = "CODE", env = "PAYMENTS_ENGINE_CURRENCY")]
+    pub currency: Option<Currency>,
+
+    /// How amounts are rounded to fewer places, in outputs and in posted
+    /// interest: half-up, half-even (banker's) or truncate
+    #[arg(long, default_value = "half-up", env = "PAYMENTS_ENGINE_ROUNDING")]
+    pub rounding: RoundingMode,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Csv,
+    Json,
+    Table,
+}
+
+impl ReportFormat {
+    /// Report sink writing this format to `destination`. Only tables
+    /// highlight locked accounts.
+    pub fn sink<'a, W: Write + 'a>(
+        self,
+        destination: W,
+        highlight_locked: bool,
+    ) -> Box<dyn ReportSink + 'a> {
+        match self {
+            ReportFormat::Csv => Box::new(CsvReport::new(destination)),
+            ReportFormat::Json => Box::new(JsonReport::new(destination)),
+            ReportFormat::Table => {
+                Box::new(TableReport::new(destination).highlight_locked(highlight_locked))
+            }
+        }
+    }
+}
+
+impl ReportOptions {
+    pub fn layout(&self) -> ReportLayout {
+        ReportLayout {
+            columns: if self.extended {
+                ReportColumns::Extended
+            } else {
+                ReportColumns::Standard
+            },
+            header: !self.no_header,
+            precision: self.precision(),
+        }
+    }
+
+    /// Places and rounding of amounts in every output.
+    pub fn precision(&self) -> Precision {
+        Precision {
+            decimals: self
+                .currency
+                .map_or(DEFAULT_DECIMALS, |currency| currency.decimals()),
+            rounding: self.rounding,
+        }
+    }
+
+    /// Writes the schema descriptor, if one was asked for.
+    pub fn write_schema(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.schema {
+            let mut file = File::create(path)?;
+            serde_json::to_writer_pretty(&mut file, &self.layout().schema())?;
+            file.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Prints the engine's report to stdout, or writes it to --report-dir.
+    /// Tables highlight locked accounts when stdout is a terminal.
+    pub fn print(&self, engine: &PaymentsEngine) -> Result<(), Box<dyn Error>> {
+        if let Some(dir) = &self.report_dir {
+            engine.write_sharded_report(
+                dir,
+                self.report_shards,
+                self.shard_by,
+                self.layout(),
+                self.compress,
+            )?;
+            return Ok(());
+        }
+        let stdout = io::stdout();
+        let highlight = stdout.is_terminal() && self.compress == Compression::None;
+        let mut writer = self.compress.writer(stdout.lock())?;
+        engine.write_report_to(
+            self.format.sink(&mut writer, highlight).as_mut(),
+            self.layout(),
+        )?;
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Failure injection for checking that retry paths work.
+#[derive(Args)]
+pub struct ChaosOptions {
+    /// Fail this fraction of report writes (0 to 1)
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub chaos_write_errors: Option<f64>,
+
+    /// Drop the input connection on this fraction of reads (0 to 1)
+    #[arg(long, value_name = "RATE", value_parser = parse_rate)]
+    pub chaos_read_drops: Option<f64>,
+
+    /// Delay every report flush by this many milliseconds
+    #[arg(long, value_name = "MS")]
+    pub chaos_flush_delay_ms: Option<u64>,
+
+    /// Seed for the injected failures
+    #[arg(long, default_value_t = 0)]
+    pub chaos_seed: u64,
+}
+
+impl ChaosOptions {
+    /// The failure source, if any failure was asked for.
+    pub fn chaos(&self) -> Option<Chaos> {
+        if self.chaos_write_errors.is_none()
+            && self.chaos_read_drops.is_none()
+            && self.chaos_flush_delay_ms.is_none()
+        {
+            return None;
+        }
+
+        Some(Chaos::new(ChaosConfig {
+            seed: self.chaos_seed,
+            write_error_rate: self.chaos_write_errors.unwrap_or(0.0),
+            flush_delay: Duration::from_millis(self.chaos_flush_delay_ms.unwrap_or(0)),
+            read_drop_rate: self.chaos_read_drops.unwrap_or(0.0),
+        }))
+    }
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate: f64 = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if (0.0..=1.0).contains(&rate) {
+        Ok(rate)
+    } else {
+        Err("must be between 0 and 1".to_string())
+    }
+}
+
+/// How the commands reading a journal write amounts.
+#[derive(Args)]
+pub struct AmountOptions {
+    /// Write amounts with the minor units of this ISO 4217 currency
+    /// instead of four places
+    #[arg(long, value_name = "CODE")]
+    pub currency: Option<Currency>,
+
+    /// How amounts are rounded to fewer places: half-up, half-even or
+    /// truncate
+    #[arg(long, default_value = "half-up")]
+    pub rounding: RoundingMode,
+}
+
+impl AmountOptions {
+    pub fn precision(&self) -> Precision {
+        Precision {
+            decimals: self
+                .currency
+                .map_or(DEFAULT_DECIMALS, |currency| currency.decimals()),
+            rounding: self.rounding,
+        }
+    }
+}
+
+/// A client limits file and the limits it held when the options were
+/// parsed, kept together so watch mode knows what to reload.
+#[derive(Clone, Debug)]
+pub struct ClientLimitsFile {
+    pub path: PathBuf,
+    pub limits: ClientLimits,
+}
+
+fn parse_client_limits(s: &str) -> Result<ClientLimitsFile, String> {
+    let path = PathBuf::from(s);
+    let limits = ClientLimits::read(&path).map_err(|err| format!("{}: {}", s, err))?;
+    Ok(ClientLimitsFile { path, limits })
+}
+
+fn parse_accounts(s: &str) -> Result<AccountMap, String> {
+    AccountMap::read(Path::new(s)).map_err(|err| format!("{}: {}", s, err))
+}
+
+fn parse_fixed_width_layout(s: &str) -> Result<FixedWidthLayout, String> {
+    FixedWidthLayout::read(Path::new(s)).map_err(|err| format!("{}: {}", s, err))
+}
+
+fn parse_client_map(s: &str) -> Result<ClientIdMap, String> {
+    ClientIdMap::read(Path::new(s)).map_err(|err| format!("{}: {}", s, err))
+}
+
+fn parse_blocklist(s: &str) -> Result<ClientBlocklist, String> {
+    ClientBlocklist::read(Path::new(s)).map_err(|err| format!("{}: {}", s, err))
+}
+
+fn parse_bucket(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(width) if width.is_finite() && width > 0.0 => Ok(width),
+        _ => Err(format!("'{}' is not a positive amount", s)),
+    }
+}
+
+fn parse_limit(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(limit) if limit.is_finite() && limit >= 0.0 => Ok(limit),
+        _ => Err(format!("'{}' is not a non-negative amount", s)),
+    }
+}
+
+fn parse_client_limit(s: &str) -> Result<(u16, f64), String> {
+    let (client, limit) = s
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not CLIENT=AMOUNT", s))?;
+    let client = client
+        .parse()
+        .map_err(|_| format!("'{}' is not a client id", client))?;
+    Ok((client, parse_limit(limit)?))
+}
+
+fn parse_notify_route(s: &str) -> Result<(AlertType, PathBuf), String> {
+    let (alert, path) = s
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not TYPE=PATH", s))?;
+    Ok((alert.parse()?, PathBuf::from(path)))
+}
+
+fn parse_type_amount_limits(s: &str) -> Result<(TransactionType, AmountLimits), String> {
+    let invalid = || format!("'{}' is not TYPE=MIN..MAX", s);
+    let (tx_type, range) = s.split_once('=').ok_or_else(invalid)?;
+    let (min, max) = range.split_once("..").ok_or_else(invalid)?;
+    let bound = |bound: &str| match bound {
+        "" => Ok(None),
+        bound => parse_limit(bound).map(Some),
+    };
+    Ok((
+        tx_type.parse()?,
+        AmountLimits {
+            min: bound(min)?,
+            max: bound(max)?,
+        },
+    ))
+}
+
+fn parse_shard_amount(s: &str) -> Result<usize, String> {
+    let shards: usize = s.parse().map_err(|_| format!("'{}' is not a number", s))?;
+    if shards > 1 && shards.is_power_of_two() {
+        Ok(shards)
+    } else {
+        Err("must be a power of two greater than one".to_string())
+    }
+}
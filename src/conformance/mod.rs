@@ -0,0 +1,135 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::engine::{EngineBuilder, PaymentsEngine};
+use crate::io::TransactionReader;
+use crate::report::{Report, RowDiff};
+
+/// Suffix of the file holding the report a case's input must produce.
+const EXPECTED_SUFFIX: &str = ".expected.csv";
+
+/// Outcome of running one case of a conformance suite.
+#[derive(Debug)]
+pub struct CaseResult {
+    pub name: String,
+    pub outcome: CaseOutcome,
+}
+
+#[derive(Debug)]
+pub enum CaseOutcome {
+    Passed,
+    Failed(Vec<RowDiff>),
+    /// The case couldn't be run, e.g. its input or expected report didn't
+    /// parse.
+    Error(String),
+}
+
+/// Runs every case of a golden-file suite: each `<name>.csv` in `dir` is
+/// processed by a fresh engine and its report compared with
+/// `<name>.expected.csv`.
+///
+/// Transactions are applied in file order, so results don't depend on
+/// scheduling, and reports are compared by client at four decimals, so any
+/// implementation of the format can check itself against the same suite.
+pub fn run_suite(dir: &Path, builder: &EngineBuilder) -> io::Result<Vec<CaseResult>> {
+    let mut inputs = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        if path.is_file() && name.ends_with(".csv") && !name.ends_with(EXPECTED_SUFFIX) {
+            inputs.push(path);
+        }
+    }
+    inputs.sort();
+
+    Ok(inputs
+        .into_iter()
+        .map(|input| {
+            // Safe to unwrap, only files named *.csv were kept
+            let name = input.file_stem().unwrap().to_string_lossy().into_owned();
+            let outcome = match run_case(&input, &expected_path(&input), builder) {
+                Ok(diffs) if diffs.is_empty() => CaseOutcome::Passed,
+                Ok(diffs) => CaseOutcome::Failed(diffs),
+                Err(err) => CaseOutcome::Error(err.to_string()),
+            };
+            CaseResult { name, outcome }
+        })
+        .collect())
+}
+
+fn expected_path(input: &Path) -> PathBuf {
+    let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+    input.with_file_name(format!("{}{}", stem, EXPECTED_SUFFIX))
+}
+
+fn run_case(
+    input: &Path,
+    expected: &Path,
+    builder: &EngineBuilder,
+) -> Result<Vec<RowDiff>, Box<dyn Error>> {
+    let expected = File::open(expected)
+        .map_err(|err| format!("{}: {}", expected.display(), err))
+        .map_err(Box::<dyn Error>::from)
+        .and_then(Report::parse)?;
+
+    let engine = replay(input, builder)?;
+    Ok(expected.diff(&Report::from_engine(&engine)))
+}
+
+/// Applies a CSV file's transactions in file order on a fresh engine.
+pub fn replay(input: &Path, builder: &EngineBuilder) -> Result<PaymentsEngine, Box<dyn Error>> {
+    let engine = builder.clone().build();
+    for tx in TransactionReader::new(File::open(input)?)? {
+        engine.apply_transaction(tx?);
+    }
+    Ok(engine)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[test]
+    fn test_bundled_suite_passes() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("conformance");
+        let results = run_suite(&dir, &PaymentsEngine::builder()).unwrap();
+
+        assert!(!results.is_empty());
+        for result in results {
+            assert!(
+                matches!(result.outcome, CaseOutcome::Passed),
+                "{}: {:?}",
+                result.name,
+                result.outcome
+            );
+        }
+    }
+
+    #[test]
+    fn test_reports_diffs_and_missing_expectations() {
+        let dir = TempDir::new("conformance");
+        fs::write(
+            dir.join("a.csv"),
+            "type,client,tx,amount\ndeposit,1,1,2.0\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("a.expected.csv"),
+            "client,available,held,total,locked\n1,3.0,0,3.0,false\n",
+        )
+        .unwrap();
+        fs::write(dir.join("b.csv"), "type,client,tx,amount\n").unwrap();
+
+        let results = run_suite(dir.path(), &PaymentsEngine::builder()).unwrap();
+
+        assert_eq!(results.len(), 2);
+        assert!(matches!(&results[0].outcome, CaseOutcome::Failed(diffs) if diffs.len() == 1));
+        assert!(matches!(&results[1].outcome, CaseOutcome::Error(_)));
+    }
+}
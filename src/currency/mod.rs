@@ -0,0 +1,216 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Decimal places of amounts when no currency is given: the four the
+/// inputs carry.
+pub const DEFAULT_DECIMALS: usize = 4;
+
+/// An ISO 4217 currency and the number of minor units it is quoted in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Currency {
+    code: &'static str,
+    minor_units: u8,
+}
+
+/// Currencies outputs can be written in. Amounts are kept at four decimal
+/// places, so currencies with more minor units aren't listed.
+const REGISTRY: &[Currency] = &[
+    Currency::new("AUD", 2),
+    Currency::new("BHD", 3),
+    Currency::new("BRL", 2),
+    Currency::new("CAD", 2),
+    Currency::new("CHF", 2),
+    Currency::new("CLF", 4),
+    Currency::new("CLP", 0),
+    Currency::new("CNY", 2),
+    Currency::new("CZK", 2),
+    Currency::new("DKK", 2),
+    Currency::new("EUR", 2),
+    Currency::new("GBP", 2),
+    Currency::new("HKD", 2),
+    Currency::new("HUF", 2),
+    Currency::new("IDR", 2),
+    Currency::new("INR", 2),
+    Currency::new("IQD", 3),
+    Currency::new("ISK", 0),
+    Currency::new("JOD", 3),
+    Currency::new("JPY", 0),
+    Currency::new("KRW", 0),
+    Currency::new("KWD", 3),
+    Currency::new("LYD", 3),
+    Currency::new("MXN", 2),
+    Currency::new("NOK", 2),
+    Currency::new("NZD", 2),
+    Currency::new("OMR", 3),
+    Currency::new("PLN", 2),
+    Currency::new("SEK", 2),
+    Currency::new("SGD", 2),
+    Currency::new("TND", 3),
+    Currency::new("TRY", 2),
+    Currency::new("USD", 2),
+    Currency::new("UYW", 4),
+    Currency::new("VND", 0),
+    Currency::new("ZAR", 2),
+];
+
+impl Currency {
+    const fn new(code: &'static str, minor_units: u8) -> Self {
+        Self { code, minor_units }
+    }
+
+    /// The registered currency with this code, ignoring case.
+    pub fn lookup(code: &str) -> Option<Self> {
+        REGISTRY
+            .iter()
+            .find(|currency| currency.code.eq_ignore_ascii_case(code))
+            .copied()
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+
+    /// Decimal places amounts in this currency are written with.
+    pub fn decimals(&self) -> usize {
+        self.minor_units as usize
+    }
+}
+
+/// How amounts with more places than they are kept or written with are
+/// rounded, e.g. interest, or a balance written in a currency with fewer
+/// minor units.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RoundingMode {
+    /// To the nearest, ties to the even neighbour (banker's rounding).
+    HalfEven,
+    /// To the nearest, ties away from zero.
+    #[default]
+    HalfUp,
+    /// Towards zero.
+    Truncate,
+}
+
+impl RoundingMode {
+    /// `amount` rounded to `decimals` places.
+    pub fn round(self, amount: f64, decimals: usize) -> f64 {
+        let scale = 10f64.powi(decimals as i32);
+        // Most decimal amounts aren't exact in binary, e.g. 1.005 is stored
+        // just below; settle them at a millionth of the last place first so
+        // ties and whole numbers are seen as such.
+        let scaled = (amount * scale * 1e6).round() / 1e6;
+        let rounded = match self {
+            RoundingMode::HalfEven => scaled.round_ties_even(),
+            RoundingMode::HalfUp => scaled.round(),
+            RoundingMode::Truncate => scaled.trunc(),
+        };
+        rounded / scale
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "half-even" | "bankers" => Ok(RoundingMode::HalfEven),
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "truncate" => Ok(RoundingMode::Truncate),
+            _ => Err(format!(
+                "unknown rounding mode '{}', expected half-even, half-up or truncate",
+                mode
+            )),
+        }
+    }
+}
+
+/// The places amounts are written with and how they are rounded to them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Precision {
+    pub decimals: usize,
+    pub rounding: RoundingMode,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Self {
+            decimals: DEFAULT_DECIMALS,
+            rounding: RoundingMode::default(),
+        }
+    }
+}
+
+impl Precision {
+    pub fn round(&self, amount: f64) -> f64 {
+        self.rounding.round(amount, self.decimals)
+    }
+
+    /// Displays `amount` rounded, with exactly the precision's places.
+    pub fn format(&self, amount: f64) -> impl fmt::Display {
+        Formatted {
+            amount: self.round(amount),
+            decimals: self.decimals,
+        }
+    }
+}
+
+struct Formatted {
+    amount: f64,
+    decimals: usize,
+}
+
+impl fmt::Display for Formatted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The amount is already rounded, so this only pads the places
+        write!(f, "{:.*}", self.decimals, self.amount)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Currency::lookup(code).ok_or_else(|| format!("unknown currency '{}'", code))
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currencies_are_looked_up_by_code() {
+        assert_eq!("jpy".parse::<Currency>().unwrap().decimals(), 0);
+        assert_eq!("BHD".parse::<Currency>().unwrap().decimals(), 3);
+        assert_eq!(
+            "XYZ".parse::<Currency>(),
+            Err("unknown currency 'XYZ'".to_string())
+        );
+        assert!(REGISTRY.windows(2).all(|pair| pair[0].code < pair[1].code));
+    }
+
+    #[test]
+    fn test_rounding_modes_differ_on_ties_and_remainders() {
+        let round = |mode: RoundingMode, amount: f64| mode.round(amount, 2);
+
+        assert_eq!(round(RoundingMode::HalfEven, 1.005), 1.0);
+        assert_eq!(round(RoundingMode::HalfEven, 1.015), 1.02);
+        assert_eq!(round(RoundingMode::HalfUp, 1.005), 1.01);
+        assert_eq!(round(RoundingMode::HalfUp, -1.005), -1.01);
+        assert_eq!(round(RoundingMode::Truncate, 1.0099), 1.0);
+        assert_eq!(round(RoundingMode::Truncate, -1.0099), -1.0);
+        assert_eq!(round(RoundingMode::Truncate, 0.29), 0.29);
+
+        let jpy = Precision {
+            decimals: 0,
+            rounding: RoundingMode::HalfUp,
+        };
+        assert_eq!(jpy.format(2.5).to_string(), "3");
+        assert_eq!(Precision::default().format(0.1 + 0.2).to_string(), "0.3000");
+    }
+}
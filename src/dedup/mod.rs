@@ -0,0 +1,351 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use dashmap::DashMap;
+
+/// Identifies a transaction id index file; the last byte is the format
+/// version.
+const MAGIC: &[u8; 8] = b"PETXIDX\x01";
+
+/// Ids a chunk holds as a sorted array before it switches to a bitmap: at
+/// 4096 ids the array takes the bitmap's 8 KiB.
+const SPARSE_MAX: usize = 4096;
+
+/// Words of a chunk's bitmap, one bit for each of the 65536 ids it covers.
+const DENSE_WORDS: usize = 1024;
+
+/// The ids sharing their upper 16 bits, by their lower 16 bits.
+#[derive(Clone, Debug, PartialEq)]
+enum Chunk {
+    Sparse(Vec<u16>),
+    Dense(Box<[u64; DENSE_WORDS]>),
+}
+
+impl Chunk {
+    fn new() -> Self {
+        Chunk::Sparse(vec![])
+    }
+
+    fn contains(&self, low: u16) -> bool {
+        match self {
+            Chunk::Sparse(ids) => ids.binary_search(&low).is_ok(),
+            Chunk::Dense(words) => words[low as usize / 64] & (1 << (low % 64)) != 0,
+        }
+    }
+
+    /// Adds `low`, returning whether it was new.
+    fn insert(&mut self, low: u16) -> bool {
+        match self {
+            Chunk::Sparse(ids) => match ids.binary_search(&low) {
+                Ok(_) => false,
+                Err(at) if ids.len() < SPARSE_MAX => {
+                    ids.insert(at, low);
+                    true
+                }
+                Err(_) => {
+                    let mut words = Box::new([0u64; DENSE_WORDS]);
+                    for id in ids.iter().copied().chain([low]) {
+                        words[id as usize / 64] |= 1 << (id % 64);
+                    }
+                    *self = Chunk::Dense(words);
+                    true
+                }
+            },
+            Chunk::Dense(words) => {
+                let word = &mut words[low as usize / 64];
+                let bit = 1 << (low % 64);
+                let new = *word & bit == 0;
+                *word |= bit;
+                new
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Chunk::Sparse(ids) => ids.len(),
+            Chunk::Dense(words) => words.iter().map(|word| word.count_ones() as usize).sum(),
+        }
+    }
+
+    fn ids(&self) -> Vec<u16> {
+        match self {
+            Chunk::Sparse(ids) => ids.clone(),
+            Chunk::Dense(words) => (0..=u16::MAX)
+                .filter(|&low| words[low as usize / 64] & (1 << (low % 64)) != 0)
+                .collect(),
+        }
+    }
+
+    fn size_in_bytes(&self) -> usize {
+        match self {
+            Chunk::Sparse(ids) => ids.capacity() * 2,
+            Chunk::Dense(_) => DENSE_WORDS * 8,
+        }
+    }
+}
+
+/// A compact set of transaction ids, in the manner of a roaring bitmap:
+/// ids are grouped by their upper 16 bits, and each group is a sorted
+/// array while it has few ids and a bitmap once it has many. Scattered
+/// ids take about two bytes each and runs of consecutive ids an eighth of
+/// a byte each, so every id a feed numbering its transactions from one
+/// has ever used fits in a few hundred megabytes at worst.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TxIdSet {
+    chunks: BTreeMap<u16, Chunk>,
+}
+
+impl TxIdSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        let (high, low) = split(id);
+        self.chunks
+            .get(&high)
+            .is_some_and(|chunk| chunk.contains(low))
+    }
+
+    /// Adds `id`, returning whether it was new.
+    pub fn insert(&mut self, id: u32) -> bool {
+        let (high, low) = split(id);
+        self.chunks
+            .entry(high)
+            .or_insert_with(Chunk::new)
+            .insert(low)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.values().map(Chunk::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.chunks.values().map(Chunk::size_in_bytes).sum()
+    }
+
+    /// Reads a set written by [`TxIdSet::write_to`], checking its format
+    /// and checksum.
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, reason.to_string());
+        if bytes.len() < MAGIC.len() + 8 || !bytes.starts_with(&MAGIC[..7]) {
+            return Err(invalid("not a transaction id index"));
+        }
+        if bytes[7] != MAGIC[7] {
+            return Err(invalid(&format!(
+                "transaction id index version {} is not supported, expected {}",
+                bytes[7], MAGIC[7]
+            )));
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        if crc32fast::hash(body).to_le_bytes() != checksum {
+            return Err(invalid(
+                "transaction id index is corrupted, its checksum doesn't match",
+            ));
+        }
+
+        let mut rest = &body[MAGIC.len()..];
+        let mut take = |len: usize| -> io::Result<&[u8]> {
+            if rest.len() < len {
+                return Err(invalid("transaction id index is truncated"));
+            }
+            let (taken, tail) = rest.split_at(len);
+            rest = tail;
+            Ok(taken)
+        };
+        let mut set = TxIdSet::new();
+        let chunks = u32::from_le_bytes(take(4)?.try_into().unwrap());
+        for _ in 0..chunks {
+            let high = u16::from_le_bytes(take(2)?.try_into().unwrap());
+            let len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+            let chunk = if len <= SPARSE_MAX {
+                let ids: Vec<u16> = take(len * 2)?
+                    .chunks_exact(2)
+                    .map(|id| u16::from_le_bytes([id[0], id[1]]))
+                    .collect();
+                if ids.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(invalid("transaction id index has unsorted ids"));
+                }
+                Chunk::Sparse(ids)
+            } else {
+                let mut words = Box::new([0u64; DENSE_WORDS]);
+                for (word, bytes) in words.iter_mut().zip(take(DENSE_WORDS * 8)?.chunks_exact(8)) {
+                    *word = u64::from_le_bytes(bytes.try_into().unwrap());
+                }
+                Chunk::Dense(words)
+            };
+            set.chunks.insert(high, chunk);
+        }
+        Ok(set)
+    }
+
+    /// Writes the set in a little-endian binary format: a magic number and
+    /// version, the number of chunks, then each chunk's upper 16 bits and
+    /// id count followed by its ids or bitmap, and a CRC-32 of all of it.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = ChecksummedWriter {
+            inner: writer,
+            hasher: crc32fast::Hasher::new(),
+        };
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.chunks.len() as u32).to_le_bytes())?;
+        for (high, chunk) in &self.chunks {
+            writer.write_all(&high.to_le_bytes())?;
+            writer.write_all(&(chunk.len() as u32).to_le_bytes())?;
+            match chunk {
+                // Chunks turn dense past SPARSE_MAX ids and never back, so
+                // the count tells a reader which of the two follows
+                Chunk::Sparse(ids) => {
+                    for id in ids {
+                        writer.write_all(&id.to_le_bytes())?;
+                    }
+                }
+                Chunk::Dense(words) => {
+                    for word in words.iter() {
+                        writer.write_all(&word.to_le_bytes())?;
+                    }
+                }
+            }
+        }
+        let checksum = writer.hasher.clone().finalize();
+        writer.inner.write_all(&checksum.to_le_bytes())?;
+        writer.inner.flush()
+    }
+}
+
+fn split(id: u32) -> (u16, u16) {
+    ((id >> 16) as u16, id as u16)
+}
+
+struct ChecksummedWriter<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for ChecksummedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Every deposit and withdrawal id processed across runs, kept in a file,
+/// so replaying daily files that overlap never counts a transaction twice.
+///
+/// The ids loaded from the file are only read during a run, so checking
+/// them takes no lock. The ids of the run itself are recorded separately
+/// and never consulted: duplicates within a run are the engine's own
+/// business, under its [`DuplicatePolicy`](crate::policy::DuplicatePolicy),
+/// and leaving them out keeps partitioned runs, whose engines share one
+/// index, from depending on which partition got to an id first.
+pub struct TxIdIndex {
+    previous: TxIdSet,
+    current: DashMap<u16, Chunk>,
+}
+
+impl TxIdIndex {
+    /// An index of earlier runs' ids, e.g. for the first run.
+    pub fn new(previous: TxIdSet) -> Self {
+        Self {
+            previous,
+            current: DashMap::new(),
+        }
+    }
+
+    /// Loads the index written by earlier runs at `path`, or starts an
+    /// empty one if there's no file there yet.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let previous = match File::open(path) {
+            Ok(file) => TxIdSet::read_from(io::BufReader::new(file))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => TxIdSet::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(Self::new(previous))
+    }
+
+    /// Whether an earlier run processed `id`.
+    pub fn seen_before(&self, id: u32) -> bool {
+        self.previous.contains(id)
+    }
+
+    /// Adds `id` to the ids this run processed.
+    pub fn record(&self, id: u32) {
+        if !self.previous.contains(id) {
+            let (high, low) = split(id);
+            self.current
+                .entry(high)
+                .or_insert_with(Chunk::new)
+                .insert(low);
+        }
+    }
+
+    /// The ids of earlier runs and of this one.
+    pub fn merged(&self) -> TxIdSet {
+        let mut set = self.previous.clone();
+        for chunk in self.current.iter() {
+            for low in chunk.ids() {
+                set.insert((*chunk.key() as u32) << 16 | low as u32);
+            }
+        }
+        set
+    }
+
+    /// Writes the ids of earlier runs and of this one next to `path` and
+    /// renames the file into place, so an interrupted write leaves the
+    /// previous index as it was.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        self.merged().write_to(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_round_trips_and_only_answers_for_earlier_runs() {
+        let mut set = TxIdSet::new();
+        // Dense around 0, sparse further up
+        for id in (0..10_000).chain([70_000, 1 << 31, u32::MAX]) {
+            assert!(set.insert(id));
+        }
+        assert!(!set.insert(70_000));
+        assert_eq!(set.len(), 10_003);
+        assert!(set.size_in_bytes() < 10_000);
+
+        let mut bytes = vec![];
+        set.write_to(&mut bytes).unwrap();
+        assert_eq!(TxIdSet::read_from(&bytes[..]).unwrap(), set);
+        bytes[20] ^= 1;
+        assert!(TxIdSet::read_from(&bytes[..]).is_err());
+
+        let index = TxIdIndex::new(set);
+        assert!(index.seen_before(9_999));
+        assert!(!index.seen_before(10_000));
+        index.record(10_000);
+        index.record(5);
+        assert!(!index.seen_before(10_000));
+        let merged = index.merged();
+        assert_eq!(merged.len(), 10_004);
+        assert!(merged.contains(10_000));
+    }
+}
@@ -0,0 +1,432 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+use super::PaymentsEngine;
+use crate::erasure::ErasureCertificate;
+use crate::io::provenance::InputDigest;
+use crate::outcome::{Balances, RejectReason};
+use crate::processor::{Client, ClientStats};
+use crate::transactions::{
+    DisputeReason, DisputeStep, StoredTransaction, Transaction, TransactionStatus, TransactionType,
+};
+
+/// Version of the checkpoint format; checkpoints of another version are
+/// refused rather than misread.
+pub const CHECKPOINT_VERSION: u32 = 1;
+
+/// Engine state after the first `rows` data rows of an input, so a run can
+/// be resumed without applying them again.
+///
+/// Holds every client and retained transaction, so it is about as large as
+/// the engine's stores. Written as JSON.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub input: InputFingerprint,
+    /// Data rows of the input already processed, counted from the row after
+    /// the header with malformed ones included.
+    pub rows: u64,
+    received: u64,
+    rejected: u64,
+    // Absent from checkpoints written before duplicates were counted
+    #[serde(default)]
+    duplicates: u64,
+    malformed_rows: u64,
+    /// Digests of the input read up to the checkpoint.
+    // Absent from checkpoints written before inputs were hashed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub inputs: Vec<InputDigest>,
+    clients: Vec<ClientState>,
+    transactions: Vec<TransactionState>,
+}
+
+/// Identifies an input file by its length and CRC-32, to catch resuming
+/// against a file that was edited or replaced since the checkpoint.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputFingerprint {
+    pub len: u64,
+    pub crc32: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct ClientState {
+    client: u16,
+    available: f64,
+    held: f64,
+    total: f64,
+    locked: bool,
+    // Absent from checkpoints written before accounts could be closed
+    #[serde(default)]
+    closed: bool,
+    // Absent from checkpoints written before custom types could lock
+    // accounts
+    #[serde(default)]
+    frozen: bool,
+    transactions: u32,
+    disputes: u32,
+    deposited: f64,
+    withdrawn: f64,
+    #[serde(default)]
+    adjusted: f64,
+    last_activity: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct TransactionState {
+    tx: u32,
+    client: u16,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    amount: f64,
+    status: TransactionStatus,
+    // Absent from checkpoints written before partial disputes, where a
+    // disputed transaction is disputed in full
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    disputed: Option<f64>,
+    // Absent from checkpoints written before charged-back parts could be
+    // disputed again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    charged_back: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dispute_reason: Option<DisputeReason>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    disputes: Vec<DisputeStep>,
+}
+
+impl From<&Client> for ClientState {
+    fn from(client: &Client) -> Self {
+        let stats = &client.stats;
+        ClientState {
+            client: client.id,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.locked,
+            closed: client.closed,
+            frozen: client.frozen,
+            transactions: stats.transactions,
+            disputes: stats.disputes,
+            deposited: stats.deposited,
+            withdrawn: stats.withdrawn,
+            adjusted: stats.adjusted,
+            last_activity: stats.last_activity,
+        }
+    }
+}
+
+impl From<&ClientState> for Client {
+    fn from(state: &ClientState) -> Self {
+        Client {
+            id: state.client,
+            available: state.available,
+            held: state.held,
+            total: state.total,
+            locked: state.locked,
+            closed: state.closed,
+            frozen: state.frozen,
+            stats: ClientStats {
+                transactions: state.transactions,
+                disputes: state.disputes,
+                deposited: state.deposited,
+                withdrawn: state.withdrawn,
+                adjusted: state.adjusted,
+                last_activity: state.last_activity,
+            },
+        }
+    }
+}
+
+impl InputFingerprint {
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut buffer = vec![0; 64 * 1024];
+        let mut len = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            len += read as u64;
+        }
+        Ok(Self {
+            len,
+            crc32: hasher.finalize(),
+        })
+    }
+}
+
+impl Checkpoint {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "checkpoint version {} is not supported, expected {}",
+                checkpoint.version, CHECKPOINT_VERSION
+            )
+            .into());
+        }
+        Ok(checkpoint)
+    }
+
+    /// Writes the checkpoint next to `path` and renames it into place, so an
+    /// interrupted write never leaves a truncated checkpoint behind.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(tmp, path)
+    }
+
+    /// Fails unless the file at `path` is the input the checkpoint was
+    /// taken on.
+    pub fn check_input(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let fingerprint = InputFingerprint::of_file(path)?;
+        if fingerprint != self.input {
+            return Err(format!(
+                "{} doesn't match the checkpoint's input ({} bytes with CRC-32 {:08x}, \
+                 checkpoint has {} bytes with CRC-32 {:08x})",
+                path.display(),
+                fingerprint.len,
+                fingerprint.crc32,
+                self.input.len,
+                self.input.crc32
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Erases `client` and its transactions from the checkpoint, as
+    /// [`PaymentsEngine::erase_client`] does from a running engine.
+    pub fn erase_client(&mut self, client: u16) -> Result<ErasureCertificate, RejectReason> {
+        let index = self
+            .clients
+            .iter()
+            .position(|state| state.client == client)
+            .ok_or(RejectReason::UnknownClient)?;
+        if self.clients[index].held != 0.0 {
+            return Err(RejectReason::FundsHeld);
+        }
+        let state = self.clients.remove(index);
+        let before = self.transactions.len();
+        self.transactions.retain(|tx| tx.client != client);
+        Ok(ErasureCertificate {
+            client,
+            records: (before - self.transactions.len()) as u64,
+            available: state.available,
+            total: state.total,
+            locked: state.locked,
+        })
+    }
+
+    /// Merges client `from` into client `into` in the checkpoint, as
+    /// [`PaymentsEngine::merge_clients`] does in a running engine.
+    pub fn merge_clients(&mut self, from: u16, into: u16) -> Result<Balances, RejectReason> {
+        let position =
+            |clients: &[ClientState], id: u16| clients.iter().position(|state| state.client == id);
+        let source = position(&self.clients, from).ok_or(RejectReason::UnknownClient)?;
+        let source = Client::from(&self.clients[source]);
+        if from == into {
+            return Ok(Balances::from(&source));
+        }
+        let mut merged = match position(&self.clients, into) {
+            Some(target) => Client::from(&self.clients[target]),
+            None => Client::new(into),
+        };
+        merged.absorb(&source)?;
+        self.clients
+            .retain(|state| state.client != from && state.client != into);
+        self.clients.push(ClientState::from(&merged));
+        for tx in &mut self.transactions {
+            if tx.client == from {
+                tx.client = into;
+            }
+        }
+        Ok(Balances::from(&merged))
+    }
+}
+
+impl PaymentsEngine {
+    /// Captures the engine's state after `rows` data rows of `input`.
+    ///
+    /// Call it while no transaction is being applied, e.g. once reading has
+    /// stopped, so the state matches the rows exactly.
+    pub fn checkpoint(&self, input: InputFingerprint, rows: u64) -> Checkpoint {
+        let clients = self
+            .client_db
+            .iter()
+            .map(|client| ClientState::from(&*client))
+            .collect();
+        let transactions = self
+            .transactions_db
+            .ids()
+            .into_iter()
+            .filter_map(|tx_id| {
+                let tx = self.transactions_db.get(&tx_id)?;
+                Some(TransactionState {
+                    tx: tx_id,
+                    client: tx.client_id(),
+                    tx_type: tx.tx_type(),
+                    amount: tx.amount(),
+                    status: tx.status(),
+                    disputed: Some(tx.disputed()).filter(|disputed| *disputed != 0.0),
+                    charged_back: Some(tx.charged_back()).filter(|charged| *charged != 0.0),
+                    dispute_reason: tx.dispute_reason(),
+                    disputes: self.transactions_db.dispute_history(tx_id),
+                })
+            })
+            .collect();
+
+        Checkpoint {
+            version: CHECKPOINT_VERSION,
+            input,
+            rows,
+            received: self.received.load(Ordering::Relaxed),
+            rejected: self.rejected_count(),
+            duplicates: self.duplicate_count(),
+            malformed_rows: self.malformed_row_count(),
+            inputs: self.inputs(),
+            clients,
+            transactions,
+        }
+    }
+
+    /// Loads the state captured in `checkpoint`. Meant for an engine built
+    /// with the same settings that hasn't processed anything yet; clients
+    /// and transactions it already has are overwritten.
+    pub fn restore(&self, checkpoint: &Checkpoint) {
+        for state in &checkpoint.clients {
+            self.client_db.insert(state.client, Client::from(state));
+        }
+        for state in &checkpoint.transactions {
+            let tx = Transaction {
+                tx_type: state.tx_type,
+                client_id: state.client,
+                tx_id: state.tx,
+                amount: Some(state.amount),
+                reason: None,
+                dispute_reason: None,
+            };
+            let mut stored = StoredTransaction::new(&tx, state.amount);
+            stored.set_status(state.status);
+            stored.set_disputed(match (state.disputed, state.status) {
+                (Some(disputed), _) => disputed,
+                (None, TransactionStatus::Disputed) => state.amount,
+                (None, _) => 0.0,
+            });
+            // Older checkpoints didn't let a charged-back transaction be
+            // disputed again, so all of it not still disputed counts
+            stored.set_charged_back(match (state.charged_back, state.status) {
+                (Some(charged_back), _) => charged_back,
+                (None, TransactionStatus::Chargeback) => state.amount - stored.disputed(),
+                (None, _) => 0.0,
+            });
+            stored.set_dispute_reason(state.dispute_reason);
+            self.transactions_db.insert(state.tx, stored);
+            for step in &state.disputes {
+                self.transactions_db.record_dispute_step(state.tx, *step);
+            }
+        }
+        // Restored reviews time out counting from the checkpoint, or from
+        // the restore by the clock
+        let mut pending = self
+            .transactions_db
+            .ids_with_status(TransactionStatus::Pending);
+        pending.sort_unstable();
+        for tx_id in pending {
+            if let Some(tx) = self.transactions_db.get(&tx_id) {
+                self.hold_for_review(checkpoint.received, tx.client_id(), tx_id);
+            }
+        }
+        self.received.store(checkpoint.received, Ordering::Relaxed);
+        self.rejected.store(checkpoint.rejected, Ordering::Relaxed);
+        self.duplicates
+            .store(checkpoint.duplicates, Ordering::Relaxed);
+        self.malformed_rows
+            .store(checkpoint.malformed_rows, Ordering::Relaxed);
+        self.inputs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .extend(checkpoint.inputs.iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn apply(engine: &PaymentsEngine, transactions: &[Transaction]) {
+        for tx in transactions {
+            engine.apply_transaction(*tx);
+        }
+    }
+
+    #[test]
+    fn test_resuming_from_a_checkpoint_matches_an_uninterrupted_run() {
+        let transactions = [
+            Transaction::new_deposit(1, 1, 2.5),
+            Transaction::new_deposit(2, 2, 1.0),
+            Transaction::new_dispute(1, 1),
+            Transaction::new_withdrawal(2, 3, 7.0),
+            Transaction::new_resolve(1, 1),
+            Transaction::new_dispute(2, 2),
+            Transaction::new_chargeback(2, 2),
+        ];
+        let builder = || PaymentsEngine::builder().client_history(true);
+        let input = InputFingerprint { len: 1, crc32: 2 };
+
+        let uninterrupted = builder().build();
+        apply(&uninterrupted, &transactions);
+
+        let first = builder().build();
+        apply(&first, &transactions[..3]);
+        let json = serde_json::to_string(&first.checkpoint(input, 3)).unwrap();
+        let checkpoint: Checkpoint = serde_json::from_str(&json).unwrap();
+        let resumed = builder().build();
+        resumed.restore(&checkpoint);
+        apply(&resumed, &transactions[3..]);
+
+        let sorted = |engine: &PaymentsEngine| {
+            let mut checkpoint = engine.checkpoint(input, 7);
+            checkpoint.clients.sort_by_key(|client| client.client);
+            checkpoint.transactions.sort_by_key(|tx| tx.tx);
+            checkpoint
+        };
+        assert_eq!(checkpoint.rows, 3);
+        assert_eq!(sorted(&resumed), sorted(&uninterrupted));
+        assert_eq!(resumed.rejected_count(), 1);
+        assert_eq!(resumed.client_history(1), uninterrupted.client_history(1));
+    }
+
+    #[test]
+    fn test_checkpoint_is_tied_to_its_input_file() {
+        let dir = TempDir::new("checkpoint");
+        let (input, saved) = (dir.join("input.csv"), dir.join("checkpoint.json"));
+        fs::write(&input, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 1.0));
+        let fingerprint = InputFingerprint::of_file(&input).unwrap();
+        engine.checkpoint(fingerprint, 1).write(&saved).unwrap();
+        let checkpoint = Checkpoint::read(&saved).unwrap();
+
+        assert_eq!(checkpoint.rows, 1);
+        assert!(checkpoint.check_input(&input).is_ok());
+        fs::write(&input, "type,client,tx,amount\ndeposit,1,1,2.0\n").unwrap();
+        assert!(checkpoint
+            .check_input(&input)
+            .unwrap_err()
+            .to_string()
+            .contains("doesn't match the checkpoint's input"));
+    }
+}
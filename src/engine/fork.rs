@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+use dashmap::DashMap;
+
+use super::locking::ContentionStats;
+use super::{EngineHasher, PaymentsEngine};
+
+/// What a fork reads the transactions it hasn't copied yet from.
+pub(super) struct ForkBase {
+    parent: PaymentsEngine,
+    /// Transaction ids looked up in the parent so far, whether or not it
+    /// had them, so each is copied at most once.
+    copied: DashMap<u32, (), EngineHasher>,
+}
+
+impl PaymentsEngine {
+    /// A child engine starting from this one's current state, to apply
+    /// transactions to speculatively and then inspect or drop, e.g. to see
+    /// what a batch would do before committing to it.
+    ///
+    /// The fork is copied on write. Client accounts, at most 65536 of them,
+    /// are copied up front, so balance reports and snapshots of the fork
+    /// are complete. Retained transactions, which can run into the
+    /// millions, are copied from this engine only when the fork first
+    /// applies a transaction with their id, e.g. a dispute of an earlier
+    /// deposit. Queries over transactions, such as `search` and the dispute
+    /// report, therefore only cover the transactions the fork touched.
+    ///
+    /// Nothing the fork does reaches this engine: it publishes no events,
+    /// records no ids in the transaction id index, and has its own policy,
+    /// counters and review queue, starting from this engine's. Transactions
+    /// not copied yet are read from this engine as they are when the fork
+    /// needs them, so apply nothing to it while the fork is in use. Forks
+    /// can be forked in turn.
+    pub fn fork(&self) -> PaymentsEngine {
+        let paused = self.applying.write().unwrap_or_else(|err| err.into_inner());
+        let hasher = self.client_db.hasher().clone();
+        let client_db = DashMap::with_capacity_and_hasher(self.client_db.len(), hasher.clone());
+        for client in self.client_db.iter() {
+            client_db.insert(*client.key(), *client);
+        }
+        let reviews = self
+            .reviews
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        let screening_hits = self
+            .screening_hits
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        let counter = |count: &AtomicU64| Arc::new(AtomicU64::new(count.load(Ordering::Relaxed)));
+        let fork = PaymentsEngine {
+            client_db: Arc::new(client_db),
+            transactions_db: Arc::new(self.transactions_db.empty_like()),
+            memory_watermark: self.memory_watermark,
+            expected_transactions: self.expected_transactions,
+            client_actors: self.client_actors,
+            max_in_flight: self.max_in_flight,
+            clock: self.clock.clone(),
+            run_id: self.run_id,
+            events: None,
+            received: counter(&self.received),
+            rejected: counter(&self.rejected),
+            duplicates: counter(&self.duplicates),
+            malformed_rows: counter(&self.malformed_rows),
+            only_clients: self.only_clients.clone(),
+            client_ids: self.client_ids.clone(),
+            allow_adjustments: self.allow_adjustments,
+            policy: Arc::new(RwLock::new(self.policy())),
+            invariant_checks: self.invariant_checks,
+            risk_scorer: self.risk_scorer.clone(),
+            blocklist: self.blocklist.clone(),
+            custom_handlers: Arc::clone(&self.custom_handlers),
+            screening_hits: Arc::new(Mutex::new(screening_hits)),
+            violations: counter(&self.violations),
+            applying: Arc::new(RwLock::new(())),
+            shard_mutexes: self
+                .shard_mutexes
+                .as_ref()
+                .map(|mutexes| Arc::new(mutexes.empty_like())),
+            contention: self
+                .contention
+                .as_ref()
+                .map(|_| Arc::new(ContentionStats::new())),
+            reviews: Arc::new(Mutex::new(reviews.clone())),
+            tx_id_index: self.tx_id_index.clone(),
+            inputs: Arc::new(Mutex::new(self.inputs())),
+            fork_base: Some(Arc::new(ForkBase {
+                parent: self.clone(),
+                copied: DashMap::with_hasher(hasher),
+            })),
+        };
+        // The review timeout looks the held transactions up directly
+        for review in reviews {
+            fork.fault_in(review.tx_id);
+        }
+        drop(paused);
+        fork
+    }
+
+    /// Copies transaction `tx_id`, with its dispute steps, from the engine
+    /// this one was forked from, unless it was already looked up there.
+    pub(super) fn fault_in(&self, tx_id: u32) {
+        let Some(base) = &self.fork_base else {
+            return;
+        };
+        // Holding the entry makes a concurrent transaction with the same id
+        // wait for the copy
+        base.copied.entry(tx_id).or_insert_with(|| {
+            base.parent.fault_in(tx_id);
+            let stored = base.parent.transactions_db.get(&tx_id).map(|tx| *tx);
+            if let Some(stored) = stored {
+                self.transactions_db.insert(tx_id, stored);
+                for step in base.parent.transactions_db.dispute_history(tx_id) {
+                    self.transactions_db.record_dispute_step(tx_id, step);
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outcome::TransactionOutcome;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_forks_diverge_without_touching_the_parent() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 10.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 5.0));
+
+        let fork = engine.fork();
+        assert_eq!(fork.transaction_count(), 0);
+        fork.apply_transaction(Transaction::new_dispute(1, 1));
+        fork.apply_transaction(Transaction::new_chargeback(1, 1));
+        assert!(fork.clients().get(&1).unwrap().locked);
+        assert_eq!(fork.clients().get(&2).unwrap().available, 5.0);
+        assert_eq!(fork.transaction_count(), 1);
+        assert_eq!(fork.watermark(), 4);
+        // The deposit was copied, so repeating it is a duplicate
+        assert_eq!(
+            fork.apply_transaction(Transaction::new_deposit(1, 1, 10.0)),
+            engine.apply_transaction(Transaction::new_deposit(1, 1, 10.0))
+        );
+
+        let nested = fork.fork();
+        nested.apply_transaction(Transaction::new_withdrawal(2, 3, 5.0));
+        assert!(matches!(
+            nested.apply_transaction(Transaction::new_dispute(2, 2)),
+            TransactionOutcome::Applied { balances } if balances.held == 5.0
+        ));
+        assert_eq!(fork.clients().get(&2).unwrap().available, 5.0);
+        drop(nested);
+        drop(fork);
+
+        let client = *engine.clients().get(&1).unwrap();
+        assert!(!client.locked);
+        assert_eq!(client.available, 10.0);
+        assert_eq!(engine.watermark(), 3);
+        assert!(engine.dispute_history(1).is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+
+use rustc_hash::FxHasher;
+
+/// Hash function used by the client and transaction maps.
+///
+/// `Sip` is the standard library's DoS-resistant default. `Fx` is much
+/// cheaper on the small integer keys used here, at the cost of being
+/// predictable, which only matters if ids can be chosen by an attacker.
+#[derive(Clone, Debug)]
+pub enum EngineHasher {
+    Sip(RandomState),
+    Fx,
+}
+
+impl Default for EngineHasher {
+    fn default() -> Self {
+        EngineHasher::Sip(RandomState::new())
+    }
+}
+
+impl FromStr for EngineHasher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sip" => Ok(EngineHasher::default()),
+            "fx" => Ok(EngineHasher::Fx),
+            _ => Err(format!("unknown hasher '{}', expected sip or fx", s)),
+        }
+    }
+}
+
+impl BuildHasher for EngineHasher {
+    type Hasher = EngineHasherState;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        match self {
+            EngineHasher::Sip(state) => EngineHasherState::Sip(state.build_hasher()),
+            EngineHasher::Fx => EngineHasherState::Fx(FxHasher::default()),
+        }
+    }
+}
+
+pub enum EngineHasherState {
+    Sip(DefaultHasher),
+    Fx(FxHasher),
+}
+
+impl Hasher for EngineHasherState {
+    fn finish(&self) -> u64 {
+        match self {
+            EngineHasherState::Sip(hasher) => hasher.finish(),
+            EngineHasherState::Fx(hasher) => hasher.finish(),
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        match self {
+            EngineHasherState::Sip(hasher) => hasher.write(bytes),
+            EngineHasherState::Fx(hasher) => hasher.write(bytes),
+        }
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        match self {
+            EngineHasherState::Sip(hasher) => hasher.write_u16(i),
+            EngineHasherState::Fx(hasher) => hasher.write_u16(i),
+        }
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        match self {
+            EngineHasherState::Sip(hasher) => hasher.write_u32(i),
+            EngineHasherState::Fx(hasher) => hasher.write_u32(i),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+
+use dashmap::try_result::TryResult;
+
+use super::{PaymentsEngine, Snapshot};
+use crate::outcome::Balances;
+
+/// Shards of the client ids when the engine's maps keep dashmap's default.
+const DEFAULT_SHARDS: usize = 64;
+
+/// Transactions between republishing the snapshot that
+/// [`PaymentsEngine::balances`] reads with [`ClientLocking::ShardMutexes`].
+const REPUBLISH_EVERY: u64 = 1024;
+
+/// Clients listed by [`ContentionReport::hot_clients`].
+const HOT_CLIENTS: usize = 10;
+
+/// How transactions lock the client accounts they apply to.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ClientLocking {
+    /// Every access to an account locks its shard of the client map for as
+    /// long as the access lasts. Suits traffic spread over many clients.
+    #[default]
+    Entries,
+    /// Client ids are split into shards with a mutex each, which a
+    /// transaction holds from the first access to its account to the last.
+    /// Transactions of a hot client then queue on the mutex and each applies
+    /// undisturbed, instead of interleaving their accesses and passing the
+    /// map's shard lock back and forth. Point reads through
+    /// [`PaymentsEngine::balances`] are served from a snapshot republished
+    /// every 1024 transactions, so they don't wait for writers either.
+    /// Suits traffic dominated by a few clients.
+    ShardMutexes,
+}
+
+impl FromStr for ClientLocking {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "entries" => Ok(ClientLocking::Entries),
+            "shard-mutexes" => Ok(ClientLocking::ShardMutexes),
+            _ => Err(format!(
+                "unknown client locking '{}', expected entries or shard-mutexes",
+                s
+            )),
+        }
+    }
+}
+
+/// The per-shard mutexes and published snapshot of
+/// [`ClientLocking::ShardMutexes`].
+pub(super) struct ShardMutexes {
+    shards: Box<[Mutex<()>]>,
+    /// Transactions received by when the snapshot is due again.
+    republish_at: AtomicU64,
+    /// `None` until first read or due.
+    published: RwLock<Option<Arc<Snapshot>>>,
+}
+
+impl ShardMutexes {
+    pub(super) fn new(shards: Option<usize>) -> Self {
+        Self {
+            shards: (0..shards.unwrap_or(DEFAULT_SHARDS))
+                .map(|_| Mutex::new(()))
+                .collect(),
+            republish_at: AtomicU64::new(REPUBLISH_EVERY),
+            published: RwLock::new(None),
+        }
+    }
+
+    /// As many mutexes as this, with nothing published yet, for a fork.
+    pub(super) fn empty_like(&self) -> Self {
+        Self::new(Some(self.shards.len()))
+    }
+}
+
+/// How often transactions waited for their client's lock, see
+/// [`EngineBuilder::contention_stats`](super::EngineBuilder::contention_stats).
+pub(super) struct ContentionStats {
+    transactions: AtomicU64,
+    contended: AtomicU64,
+    waited_nanos: AtomicU64,
+    /// Contended transactions by client id.
+    by_client: Box<[AtomicU32]>,
+}
+
+impl ContentionStats {
+    pub(super) fn new() -> Self {
+        Self {
+            transactions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            waited_nanos: AtomicU64::new(0),
+            by_client: (0..=u16::MAX as usize).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    fn record(&self, client: u16, waited: Option<Duration>) {
+        self.transactions.fetch_add(1, Ordering::Relaxed);
+        if let Some(waited) = waited {
+            self.contended.fetch_add(1, Ordering::Relaxed);
+            self.waited_nanos
+                .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+            self.by_client[client as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Lock contention measured by an engine built with
+/// [`EngineBuilder::contention_stats`](super::EngineBuilder::contention_stats).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ContentionReport {
+    /// Transactions applied while measuring.
+    pub transactions: u64,
+    /// Transactions that found their client's lock taken: with
+    /// [`ClientLocking::Entries`] its shard of the client map, which other
+    /// clients share, with [`ClientLocking::ShardMutexes`] its shard mutex.
+    pub contended: u64,
+    /// Time the contended transactions waited for the lock, in total.
+    pub waited: Duration,
+    /// The clients whose transactions were contended most often, with how
+    /// often, most first; at most ten.
+    pub hot_clients: Vec<(u16, u32)>,
+}
+
+impl PaymentsEngine {
+    /// Locks `client` for a transaction as the engine's [`ClientLocking`]
+    /// asks, measuring the wait if contention stats are on. The guard is
+    /// `None` with [`ClientLocking::Entries`], where the client map locks
+    /// each access itself.
+    pub(super) fn lock_client(&self, client: u16) -> Option<MutexGuard<'_, ()>> {
+        match &self.shard_mutexes {
+            Some(mutexes) => {
+                let shard = &mutexes.shards[client as usize % mutexes.shards.len()];
+                let lock = || shard.lock().unwrap_or_else(|err| err.into_inner());
+                let Some(stats) = &self.contention else {
+                    return Some(lock());
+                };
+                if let Ok(guard) = shard.try_lock() {
+                    stats.record(client, None);
+                    return Some(guard);
+                }
+                let started = Instant::now();
+                let guard = lock();
+                stats.record(client, Some(started.elapsed()));
+                Some(guard)
+            }
+            None => {
+                if let Some(stats) = &self.contention {
+                    let waited = match self.client_db.try_get_mut(&client) {
+                        TryResult::Locked => {
+                            let started = Instant::now();
+                            drop(self.client_db.get(&client));
+                            Some(started.elapsed())
+                        }
+                        TryResult::Present(_) | TryResult::Absent => None,
+                    };
+                    stats.record(client, waited);
+                }
+                None
+            }
+        }
+    }
+
+    /// Republishes the snapshot read by [`balances`](Self::balances) once
+    /// it is due. Called with no transaction applying on this thread, as
+    /// taking the snapshot waits for all of them.
+    pub(super) fn republish(&self) {
+        let Some(mutexes) = &self.shard_mutexes else {
+            return;
+        };
+        let due = mutexes.republish_at.load(Ordering::Relaxed);
+        let received = self.received.load(Ordering::Relaxed);
+        // Only the one thread that moves the deadline on takes the snapshot
+        if received < due
+            || mutexes
+                .republish_at
+                .compare_exchange(
+                    due,
+                    received + REPUBLISH_EVERY,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+        self.publish_snapshot(mutexes);
+    }
+
+    fn publish_snapshot(&self, mutexes: &ShardMutexes) -> Arc<Snapshot> {
+        let snapshot = Arc::new(self.snapshot());
+        let mut published = mutexes
+            .published
+            .write()
+            .unwrap_or_else(|err| err.into_inner());
+        // A slower thread's older snapshot mustn't replace a newer one
+        match &*published {
+            Some(newer) if newer.watermark() > snapshot.watermark() => newer.clone(),
+            _ => {
+                *published = Some(snapshot.clone());
+                snapshot
+            }
+        }
+    }
+
+    /// A client's balances. With [`ClientLocking::ShardMutexes`] they come
+    /// from the last published snapshot, at most about 1024 transactions
+    /// old, and reading them only waits for the transactions being applied
+    /// the first time, when there is no snapshot yet.
+    pub fn balances(&self, client: u16) -> Option<Balances> {
+        match &self.shard_mutexes {
+            Some(mutexes) => {
+                let published = mutexes
+                    .published
+                    .read()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .clone();
+                published
+                    .unwrap_or_else(|| self.publish_snapshot(mutexes))
+                    .balances(client)
+            }
+            None => self
+                .client_db
+                .get(&client)
+                .map(|account| Balances::from(&*account)),
+        }
+    }
+
+    /// Lock contention so far, if the engine measures it.
+    pub fn contention(&self) -> Option<ContentionReport> {
+        let stats = self.contention.as_ref()?;
+        let mut hot_clients: Vec<(u16, u32)> = stats
+            .by_client
+            .iter()
+            .enumerate()
+            .map(|(client, count)| (client as u16, count.load(Ordering::Relaxed)))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        hot_clients.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot_clients.truncate(HOT_CLIENTS);
+        Some(ContentionReport {
+            transactions: stats.transactions.load(Ordering::Relaxed),
+            contended: stats.contended.load(Ordering::Relaxed),
+            waited: Duration::from_nanos(stats.waited_nanos.load(Ordering::Relaxed)),
+            hot_clients,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_shard_mutexes_serve_balances_from_a_published_snapshot() {
+        let engine = PaymentsEngine::builder()
+            .client_locking(ClientLocking::ShardMutexes)
+            .contention_stats(true)
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 0, 1.0));
+        // The first read publishes
+        assert_eq!(engine.balances(1).unwrap().available, 1.0);
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 1.0));
+        assert_eq!(engine.balances(1).unwrap().available, 1.0);
+
+        let threads: Vec<_> = (0..4u32)
+            .map(|thread| {
+                let engine = engine.clone();
+                std::thread::spawn(move || {
+                    for n in 0..1000 {
+                        let tx_id = 2 + thread * 1000 + n;
+                        engine.apply_transaction(Transaction::new_deposit(1, tx_id, 1.0));
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(engine.clients().get(&1).unwrap().available, 4002.0);
+        // Republished at most 1024 transactions before the last one
+        let published = engine.balances(1).unwrap().available;
+        assert!(published > 2977.0 && published <= 4002.0, "{}", published);
+        let report = engine.contention().unwrap();
+        assert_eq!(report.transactions, 4002);
+        match report.hot_clients.as_slice() {
+            [] => assert_eq!(report.contended, 0),
+            [(client, contended)] => {
+                assert_eq!(*client, 1);
+                assert_eq!(*contended as u64, report.contended);
+            }
+            hot => panic!("unexpected hot clients {:?}", hot),
+        }
+    }
+}
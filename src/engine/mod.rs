@@ -0,0 +1,2361 @@
+mod checkpoint;
+mod clock;
+mod fork;
+mod hasher;
+mod locking;
+mod query;
+mod run;
+mod runtime;
+mod snapshot;
+
+pub use checkpoint::{Checkpoint, InputFingerprint, CHECKPOINT_VERSION};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use hasher::EngineHasher;
+pub use locking::{ClientLocking, ContentionReport};
+pub use query::TransactionQuery;
+pub use run::RunId;
+pub use runtime::RuntimeConfig;
+pub use snapshot::Snapshot;
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::Write;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, SystemTime};
+
+use dashmap::DashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
+
+use crate::accrual::{self, RateTable};
+use crate::currency::{Precision, RoundingMode, DEFAULT_DECIMALS};
+use crate::dedup::TxIdIndex;
+use crate::erasure::ErasureCertificate;
+use crate::events::{
+    ClientEvent, ClientEventKind, ErasureEvent, Event, EventSink, InputEvent, MergeEvent,
+    OutcomeEvent,
+};
+use crate::invariants::{self, InvariantChecks, OnViolation};
+use crate::io::actors::ActorConfig;
+use crate::io::provenance::InputDigest;
+use crate::io::{
+    write_client_rows, write_csv_with, write_dispute_report, write_payouts, write_screening_report,
+    write_sharded_csv, Compression, ReportLayout, ReportSink, ShardBy,
+};
+use crate::outcome::{Balances, BatchOutcome, IgnoreReason, RejectReason, TransactionOutcome};
+use crate::plugin::{Change, CustomHandler, Recording, Replay};
+use crate::policy::{AmountLimits, ClientLimits, DuplicatePolicy, Policy, ReviewTimeout};
+use crate::processor::{self, Client, ClientDb, Lifecycle, Processed, TransactionsDb};
+use crate::remap::ClientIdMap;
+use crate::risk::{RiskAssessment, RiskDecision, RiskScorer};
+use crate::screening::{Blocklist, ScreeningHit};
+use crate::store::TransactionStore;
+use crate::transactions::{
+    CustomType, DisputeStep, HistoryEntry, Transaction, TransactionStatus, TransactionType,
+};
+
+/// Rough cost of a transaction that has been dispatched but not yet applied:
+/// the transaction itself plus the spawned task and its join handle.
+const IN_FLIGHT_TX_BYTES: usize = mem::size_of::<Transaction>() + 256;
+
+/// Owns the client and transaction stores so that several inputs (files,
+/// sockets, ...) can be applied to a single evolving state.
+///
+/// Cloning is cheap and yields a handle to the same underlying state.
+#[derive(Clone)]
+pub struct PaymentsEngine {
+    client_db: ClientDb,
+    transactions_db: TransactionsDb,
+    memory_watermark: Option<usize>,
+    expected_transactions: usize,
+    client_actors: Option<ActorConfig>,
+    max_in_flight: Option<usize>,
+    clock: Arc<dyn Clock>,
+    run_id: Option<RunId>,
+    events: Option<Arc<EventStream>>,
+    /// Transactions handed to the engine so far, numbering each one for the
+    /// clients' last activity.
+    received: Arc<AtomicU64>,
+    /// Transactions rejected so far.
+    rejected: Arc<AtomicU64>,
+    /// Deposits and withdrawals that repeated an earlier one so far.
+    duplicates: Arc<AtomicU64>,
+    /// Malformed input rows skipped so far by the readers feeding the
+    /// engine.
+    malformed_rows: Arc<AtomicU64>,
+    only_clients: Option<Arc<FxHashSet<u16>>>,
+    client_ids: Option<Arc<ClientIdMap>>,
+    allow_adjustments: bool,
+    /// Swapped whole when the client limits are reloaded, so a transaction
+    /// is applied under one policy from start to end.
+    policy: Arc<RwLock<Arc<Policy>>>,
+    invariant_checks: Option<InvariantChecks>,
+    /// Invariant violations found so far by the checks.
+    violations: Arc<AtomicU64>,
+    risk_scorer: Option<Arc<dyn RiskScorer>>,
+    blocklist: Option<Arc<dyn Blocklist>>,
+    custom_handlers: Arc<FxHashMap<CustomType, Arc<dyn CustomHandler>>>,
+    /// Transactions rejected by the blocklist so far, in the order they
+    /// were received.
+    screening_hits: Arc<Mutex<Vec<ScreeningHit>>>,
+    /// Held shared while transactions are applied, and exclusively while a
+    /// snapshot copies the balances or a batch is checked and applied, so
+    /// neither sees a transaction or batch half-applied.
+    applying: Arc<RwLock<()>>,
+    /// `None` with [`ClientLocking::Entries`].
+    shard_mutexes: Option<Arc<locking::ShardMutexes>>,
+    contention: Option<Arc<locking::ContentionStats>>,
+    /// Transactions held for review, oldest first, for the review timeout.
+    reviews: Arc<Mutex<VecDeque<HeldReview>>>,
+    tx_id_index: Option<Arc<TxIdIndex>>,
+    /// Digests of the input segments read into the engine so far, in the
+    /// order they were finished.
+    inputs: Arc<Mutex<Vec<InputDigest>>>,
+    /// The engine this one was forked from, with the transactions copied
+    /// from it so far; `None` unless built by [`PaymentsEngine::fork`].
+    fork_base: Option<Arc<fork::ForkBase>>,
+}
+
+/// What the blocklist, the risk scorer and a custom handler decided about
+/// a transaction, each `None` until asked. A batch leg keeps them from
+/// its check to its application, so none of them sees a leg twice or can
+/// decide differently the second time.
+#[derive(Clone, Debug, Default)]
+struct Decisions {
+    blocked: Option<bool>,
+    risk: Option<RiskAssessment>,
+    /// The changes the handler made to the account, if it accepted the
+    /// transaction.
+    custom: Option<Vec<Change>>,
+}
+
+/// A transaction held for review, as the position it was received at and
+/// the time it was held, its client and its id. Ordered by position.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct HeldReview {
+    sequence: u64,
+    held_at: SystemTime,
+    client_id: u16,
+    tx_id: u32,
+}
+
+/// Numbers outcome events and hands them to the sink. Shared by every
+/// engine built from the same builder, e.g. the shards of a partitioned
+/// run, so sequence numbers stay unique.
+struct EventStream {
+    sink: Box<dyn EventSink>,
+    sequence: AtomicU64,
+}
+
+impl EventStream {
+    fn publish(&self, transaction: Transaction, processed: Processed, run: Option<RunId>) {
+        let client = transaction.client_id;
+        if processed.lifecycle.created {
+            self.publish_client(client, ClientEventKind::Created);
+        }
+        if processed.lifecycle.locked {
+            self.publish_client(client, ClientEventKind::Locked);
+        }
+        if processed.lifecycle.closed {
+            self.publish_client(client, ClientEventKind::Closed);
+        }
+        self.sink.publish(&Event::Outcome(OutcomeEvent {
+            sequence: self.next_sequence(),
+            transaction,
+            outcome: processed.outcome,
+            dispute_reason: processed.dispute_reason,
+            risk_score: processed.risk_score,
+            run,
+        }));
+    }
+
+    fn publish_client(&self, client: u16, kind: ClientEventKind) {
+        self.sink.publish(&Event::Client(ClientEvent {
+            sequence: self.next_sequence(),
+            client,
+            kind,
+        }));
+    }
+
+    fn publish_erasure(&self, certificate: ErasureCertificate) {
+        self.sink.publish(&Event::Erasure(ErasureEvent {
+            sequence: self.next_sequence(),
+            certificate,
+        }));
+    }
+
+    fn publish_input(&self, digest: InputDigest) {
+        self.sink.publish(&Event::Input(InputEvent {
+            sequence: self.next_sequence(),
+            digest,
+        }));
+    }
+
+    fn publish_merge(&self, from: u16, into: u16) {
+        self.sink.publish(&Event::Merge(MergeEvent {
+            sequence: self.next_sequence(),
+            from,
+            into,
+        }));
+    }
+
+    fn next_sequence(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+impl PaymentsEngine {
+    pub fn new() -> Self {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::default()
+    }
+
+    pub async fn handle_transaction(&self, tx: Transaction) -> TransactionOutcome {
+        self.apply_transaction(tx)
+    }
+
+    /// Applies a transaction on the calling thread, for use outside of an
+    /// async runtime.
+    ///
+    /// The risk scorer, custom handlers and event sinks are called from
+    /// here, on whichever thread or task applies the transaction, and
+    /// custom handlers with the client's account locked. Whatever they take
+    /// holds up the transaction and the client's next ones, so they must be
+    /// cheap and must not block; anything slow, such as a network call,
+    /// belongs in front of the engine or on a background task.
+    pub fn apply_transaction(&self, tx: Transaction) -> TransactionOutcome {
+        let applying = self.applying();
+        let outcome = self.apply(self.admitted(tx), self.allow_adjustments);
+        drop(applying);
+        self.republish();
+        outcome
+    }
+
+    fn applying(&self) -> RwLockReadGuard<'_, ()> {
+        self.applying.read().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// `tx` as the engine applies it: its client id mapped, and its amount
+    /// rounded by the policy's [`RoundingMode`] to the four places balances
+    /// and retained transactions keep, so a dispute or chargeback moves
+    /// exactly what was credited.
+    fn admitted(&self, tx: Transaction) -> Transaction {
+        let mut tx = self.remapped(tx);
+        let rounding = self.policy().rounding();
+        tx.amount = tx
+            .amount
+            .map(|amount| rounding.round(amount, DEFAULT_DECIMALS));
+        tx
+    }
+
+    /// `tx` with its client id mapped to the current one, see
+    /// [`EngineBuilder::client_id_map`].
+    pub(crate) fn remapped(&self, mut tx: Transaction) -> Transaction {
+        if let Some(ids) = &self.client_ids {
+            tx.client_id = ids.map(tx.client_id);
+        }
+        tx
+    }
+
+    /// Credits interest on every eligible client's available balance, see
+    /// [`RateTable`]. The credits go through the engine like any other
+    /// transaction, whether or not it accepts adjustments from its input,
+    /// and are returned with their outcomes.
+    pub fn accrue_interest(&self, rates: &RateTable) -> Vec<(Transaction, TransactionOutcome)> {
+        let _applying = self.applying();
+        accrual::postings(&self.client_db, rates, self.policy().rounding())
+            .into_iter()
+            .map(|tx| (tx, self.apply(tx, true)))
+            .collect()
+    }
+
+    /// Applies the legs of a batch, e.g. a debit of one client, a credit of
+    /// another and a fee, all or nothing.
+    ///
+    /// The legs are first applied in order to a copy of the clients and
+    /// transactions they touch; if any of them wouldn't be applied, nothing
+    /// is and the batch is rejected naming that leg, each leg counting as
+    /// a rejected transaction. Otherwise they are applied to the engine and
+    /// published like single transactions. Nothing else is applied from the
+    /// check to the last leg, so no transaction arriving in between can
+    /// make a leg fail once the batch is found to be valid. The blocklist,
+    /// risk scorer and custom handlers are asked about each leg once, on
+    /// the check, and the legs are applied as they decided then.
+    pub fn apply_batch(&self, legs: &[Transaction]) -> BatchOutcome {
+        let outcome = self.apply_legs(legs);
+        self.republish();
+        outcome
+    }
+
+    fn apply_legs(&self, legs: &[Transaction]) -> BatchOutcome {
+        // Exclusive, so the legs apply to the state they were checked on
+        let _applying = self.applying.write().unwrap_or_else(|err| err.into_inner());
+        let legs: Vec<Transaction> = legs.iter().map(|leg| self.admitted(*leg)).collect();
+        for leg in &legs {
+            self.fault_in(leg.tx_id);
+        }
+
+        let clients: ClientDb = Arc::new(DashMap::with_hasher(self.client_db.hasher().clone()));
+        let transactions: TransactionsDb = Arc::new(TransactionStore::new(DashMap::with_hasher(
+            self.client_db.hasher().clone(),
+        )));
+        for leg in &legs {
+            if let Some(client) = self.client_db.get(&leg.client_id) {
+                clients.insert(leg.client_id, *client);
+            }
+            if let Some(tx) = self.transactions_db.get(&leg.tx_id) {
+                transactions.insert(leg.tx_id, *tx);
+            }
+        }
+        let mut decisions = vec![Decisions::default(); legs.len()];
+        for (index, leg) in legs.iter().enumerate() {
+            let outcome = match self.filtered(leg) {
+                Some(outcome) => outcome,
+                None => {
+                    self.process(
+                        *leg,
+                        0,
+                        self.allow_adjustments,
+                        &clients,
+                        &transactions,
+                        &mut decisions[index],
+                    )
+                    .outcome
+                }
+            };
+            if !matches!(
+                outcome,
+                TransactionOutcome::Applied { .. } | TransactionOutcome::Held { .. }
+            ) {
+                self.rejected
+                    .fetch_add(legs.len() as u64, Ordering::Relaxed);
+                log::debug!(
+                    "Rejected batch of {} legs, leg {} ({:?} {} of client {}): {}",
+                    legs.len(),
+                    index,
+                    leg.tx_type,
+                    leg.tx_id,
+                    leg.client_id,
+                    match outcome {
+                        TransactionOutcome::Rejected { reason } => {
+                            format!("{} {}", reason.code(), reason)
+                        }
+                        TransactionOutcome::Ignored { reason } => {
+                            format!("{} {}", reason.code(), reason)
+                        }
+                        outcome => format!("{:?}", outcome),
+                    }
+                );
+                return BatchOutcome::Rejected {
+                    leg: index,
+                    outcome,
+                };
+            }
+        }
+
+        // As checked: the blocklist, risk scorer and custom handlers were
+        // consulted once per leg, and their decisions are applied again
+        BatchOutcome::Applied {
+            outcomes: legs
+                .iter()
+                .zip(&mut decisions)
+                .map(|(leg, decisions)| self.apply_decided(*leg, self.allow_adjustments, decisions))
+                .collect(),
+        }
+    }
+
+    /// The outcome of a transaction for a client the engine doesn't
+    /// process, which is ignored before it is numbered.
+    fn filtered(&self, tx: &Transaction) -> Option<TransactionOutcome> {
+        match &self.only_clients {
+            Some(clients) if !clients.contains(&tx.client_id) => {
+                Some(TransactionOutcome::Ignored {
+                    reason: IgnoreReason::FilteredClient,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn process(
+        &self,
+        tx: Transaction,
+        sequence: u64,
+        allow_adjustments: bool,
+        client_db: &ClientDb,
+        tx_db: &TransactionsDb,
+        decisions: &mut Decisions,
+    ) -> Processed {
+        let blocked = *decisions.blocked.get_or_insert_with(|| {
+            self.blocklist
+                .as_ref()
+                .is_some_and(|blocklist| blocklist.is_blocked(tx.client_id))
+        });
+        if blocked {
+            return Processed {
+                outcome: TransactionOutcome::Rejected {
+                    reason: RejectReason::ClientBlocked,
+                },
+                lifecycle: Lifecycle::default(),
+                dispute_reason: None,
+                risk_score: None,
+                duplicate: false,
+            };
+        }
+        if tx.tx_type.is_adjustment() && !allow_adjustments {
+            return Processed {
+                outcome: TransactionOutcome::Rejected {
+                    reason: RejectReason::Unauthorized,
+                },
+                lifecycle: Lifecycle::default(),
+                dispute_reason: None,
+                risk_score: None,
+                duplicate: false,
+            };
+        }
+
+        let policy = self.policy();
+        let repeated = matches!(
+            tx.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        ) && self
+            .tx_id_index
+            .as_ref()
+            .is_some_and(|index| index.seen_before(tx.tx_id));
+        if repeated {
+            // The earlier transaction isn't retained, so it can't be replaced
+            let outcome = match policy.duplicates() {
+                DuplicatePolicy::Ignore | DuplicatePolicy::Warn => TransactionOutcome::Ignored {
+                    reason: IgnoreReason::Duplicate,
+                },
+                DuplicatePolicy::Reject | DuplicatePolicy::LastWriteWins => {
+                    TransactionOutcome::Rejected {
+                        reason: RejectReason::DuplicateTransaction,
+                    }
+                }
+            };
+            return Processed {
+                outcome,
+                lifecycle: Lifecycle::default(),
+                dispute_reason: None,
+                risk_score: None,
+                duplicate: true,
+            };
+        }
+        if let TransactionType::Custom(kind) = tx.tx_type {
+            if let Some(changes) = &decisions.custom {
+                let replay = Replay(changes);
+                return processor::apply_custom(
+                    tx,
+                    sequence,
+                    Some(&replay),
+                    client_db,
+                    tx_db,
+                    &policy,
+                );
+            }
+            let Some(handler) = self.custom_handlers.get(&kind) else {
+                return processor::apply_custom(tx, sequence, None, client_db, tx_db, &policy);
+            };
+            let recording = Recording {
+                handler: &**handler,
+                changes: Mutex::new(None),
+            };
+            let processed =
+                processor::apply_custom(tx, sequence, Some(&recording), client_db, tx_db, &policy);
+            decisions.custom = recording
+                .changes
+                .into_inner()
+                .unwrap_or_else(|err| err.into_inner());
+            return processed;
+        }
+        let scorer = match (&self.risk_scorer, tx.tx_type) {
+            (Some(scorer), TransactionType::Deposit | TransactionType::Withdrawal) => scorer,
+            _ => return processor::apply_transaction(tx, sequence, client_db, tx_db, &policy),
+        };
+        let assessment = *decisions.risk.get_or_insert_with(|| {
+            let balances = client_db
+                .get(&tx.client_id)
+                .map(|client| Balances::from(&*client));
+            scorer.score(&tx, balances.as_ref())
+        });
+        let hold = match assessment.decision {
+            RiskDecision::Approve => policy.needs_review(&tx),
+            RiskDecision::Hold => true,
+            RiskDecision::Reject => {
+                return Processed {
+                    outcome: TransactionOutcome::Rejected {
+                        reason: RejectReason::RiskRejected,
+                    },
+                    lifecycle: Lifecycle::default(),
+                    dispute_reason: None,
+                    risk_score: Some(assessment.score),
+                    duplicate: false,
+                };
+            }
+        };
+        Processed {
+            risk_score: Some(assessment.score),
+            ..processor::apply_reviewed(tx, sequence, hold, client_db, tx_db, &policy)
+        }
+    }
+
+    fn apply(&self, tx: Transaction, allow_adjustments: bool) -> TransactionOutcome {
+        self.apply_decided(tx, allow_adjustments, &mut Decisions::default())
+    }
+
+    /// Applies `tx` like [`apply`](Self::apply), with what `decisions`
+    /// already holds taken as decided rather than asked again.
+    fn apply_decided(
+        &self,
+        tx: Transaction,
+        allow_adjustments: bool,
+        decisions: &mut Decisions,
+    ) -> TransactionOutcome {
+        if let Some(outcome) = self.filtered(&tx) {
+            return outcome;
+        }
+
+        self.fault_in(tx.tx_id);
+        let sequence = self.received.fetch_add(1, Ordering::Relaxed) + 1;
+        let client = self.lock_client(tx.client_id);
+        let processed = self.process(
+            tx,
+            sequence,
+            allow_adjustments,
+            &self.client_db,
+            &self.transactions_db,
+            decisions,
+        );
+        // Expired reviews are applied below, possibly for the same shard
+        drop(client);
+        if processed.duplicate {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+            if self.policy().duplicates() == DuplicatePolicy::Warn {
+                log::warn!(
+                    "Ignored duplicate {:?} {} of client {} ({})",
+                    tx.tx_type,
+                    tx.tx_id,
+                    tx.client_id,
+                    IgnoreReason::Duplicate.code()
+                );
+            }
+        }
+        if let (
+            Some(index),
+            TransactionType::Deposit | TransactionType::Withdrawal,
+            TransactionOutcome::Applied { .. } | TransactionOutcome::Held { .. },
+        ) = (&self.tx_id_index, tx.tx_type, processed.outcome)
+        {
+            // What a fork applies is speculative
+            if self.fork_base.is_none() {
+                index.record(tx.tx_id);
+            }
+        }
+        match processed.outcome {
+            TransactionOutcome::Rejected {
+                reason: RejectReason::ClientBlocked,
+            } => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                log::warn!(
+                    "Rejected {:?} {} of blocked client {} ({})",
+                    tx.tx_type,
+                    tx.tx_id,
+                    tx.client_id,
+                    RejectReason::ClientBlocked.code()
+                );
+                let mut hits = self
+                    .screening_hits
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner());
+                hits.push(ScreeningHit {
+                    sequence,
+                    client_id: tx.client_id,
+                    tx_id: tx.tx_id,
+                    tx_type: tx.tx_type,
+                    amount: tx.amount,
+                });
+            }
+            TransactionOutcome::Rejected { reason } => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                log::debug!(
+                    "Rejected {:?} {} of client {}: {} {}",
+                    tx.tx_type,
+                    tx.tx_id,
+                    tx.client_id,
+                    reason.code(),
+                    reason
+                );
+            }
+            outcome => log::trace!(
+                "{:?} {} of client {}: {:?}",
+                tx.tx_type,
+                tx.tx_id,
+                tx.client_id,
+                outcome
+            ),
+        }
+        if let (
+            Some(checks),
+            TransactionOutcome::Applied { balances } | TransactionOutcome::Held { balances },
+        ) = (self.invariant_checks, processed.outcome)
+        {
+            self.check_invariants(checks, &tx, &balances);
+        }
+        self.publish(tx, processed);
+        if let TransactionOutcome::Held { .. } = processed.outcome {
+            self.hold_for_review(sequence, tx.client_id, tx.tx_id);
+        }
+        self.deny_expired_reviews(sequence);
+        processed.outcome
+    }
+
+    fn hold_for_review(&self, sequence: u64, client_id: u16, tx_id: u32) {
+        if self.policy().review_timeout().is_some() {
+            let mut reviews = self.reviews.lock().unwrap_or_else(|err| err.into_inner());
+            reviews.push_back(HeldReview {
+                sequence,
+                held_at: self.now(),
+                client_id,
+                tx_id,
+            });
+        }
+    }
+
+    /// Denies the transactions held for review whose timeout has passed,
+    /// e.g. on a poll of an input that brought no new transactions, and
+    /// returns how many. The timeout is otherwise only checked as
+    /// transactions are applied.
+    pub fn expire_reviews(&self) -> usize {
+        let applying = self.applying();
+        let denied = self.deny_expired_reviews(self.received.load(Ordering::Relaxed));
+        drop(applying);
+        self.republish();
+        denied
+    }
+
+    /// Denies the transactions still pending review once the review
+    /// timeout has passed since they were held, by the engine's clock or
+    /// in transactions received, with `sequence` the latest position.
+    /// The denials go through the engine like any other transaction.
+    /// Returns how many were denied.
+    fn deny_expired_reviews(&self, sequence: u64) -> usize {
+        let Some(timeout) = self.policy().review_timeout() else {
+            return 0;
+        };
+        let now = self.now();
+        let expired_at = |review: &HeldReview| match timeout {
+            ReviewTimeout::After(duration) => review
+                .held_at
+                .checked_add(duration)
+                .is_some_and(|deadline| deadline <= now),
+            ReviewTimeout::AfterTransactions(transactions) => {
+                review.sequence.saturating_add(transactions) <= sequence
+            }
+        };
+        let mut expired = vec![];
+        {
+            let mut reviews = self.reviews.lock().unwrap_or_else(|err| err.into_inner());
+            while let Some(review) = reviews.front() {
+                if !expired_at(review) {
+                    break;
+                }
+                expired.push((review.client_id, review.tx_id));
+                reviews.pop_front();
+            }
+        }
+        let mut denied = 0;
+        for (client_id, tx_id) in expired {
+            let pending = self
+                .transactions_db
+                .get(&tx_id)
+                .is_some_and(|tx| tx.status() == TransactionStatus::Pending);
+            if pending {
+                log::debug!("Review of transaction {} timed out, denying it", tx_id);
+                self.apply(
+                    Transaction {
+                        tx_type: TransactionType::Deny,
+                        client_id,
+                        tx_id,
+                        amount: None,
+                        reason: None,
+                        dispute_reason: None,
+                    },
+                    false,
+                );
+                denied += 1;
+            }
+        }
+        denied
+    }
+
+    fn check_invariants(&self, checks: InvariantChecks, tx: &Transaction, balances: &Balances) {
+        let floor = checks
+            .strict
+            .then(|| -self.policy().overdraft_limit(balances.client));
+        for violation in invariants::check_balances(balances, floor) {
+            match checks.on_violation {
+                OnViolation::Alert => {
+                    self.violations.fetch_add(1, Ordering::Relaxed);
+                    log::error!(
+                        "{:?} {} broke an invariant: {}",
+                        tx.tx_type,
+                        tx.tx_id,
+                        violation
+                    );
+                }
+                OnViolation::Abort => panic!(
+                    "{:?} {} broke an invariant: {}",
+                    tx.tx_type, tx.tx_id, violation
+                ),
+            }
+        }
+    }
+
+    fn publish(&self, tx: Transaction, processed: Processed) {
+        if let Some(events) = &self.events {
+            events.publish(tx, processed, self.run_id);
+        }
+    }
+
+    /// Records that the engine finished reading the input segment `digest`
+    /// describes, publishing it after the events of its transactions.
+    pub fn record_input(&self, digest: InputDigest) {
+        if let Some(events) = &self.events {
+            events.publish_input(digest.clone());
+        }
+        self.inputs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(digest);
+    }
+
+    /// Digests of the input segments read into the engine, including those
+    /// of a restored checkpoint, in the order they were finished.
+    pub fn inputs(&self) -> Vec<InputDigest> {
+        self.inputs
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Flushes the event sink, if any. Call once processing is done so
+    /// buffered events aren't lost.
+    pub fn flush_events(&self) -> std::io::Result<()> {
+        match &self.events {
+            Some(events) => events.sink.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves every client and transaction of `other` into this engine.
+    ///
+    /// Meant for merging engines that worked on disjoint sets of clients;
+    /// on a key collision the entry already in this engine is kept.
+    pub(crate) fn absorb(&self, other: PaymentsEngine) {
+        for client in other.client_db.iter() {
+            self.client_db.entry(*client.key()).or_insert(*client);
+        }
+        self.transactions_db.absorb(&other.transactions_db);
+        self.rejected
+            .fetch_add(other.rejected_count(), Ordering::Relaxed);
+        self.duplicates
+            .fetch_add(other.duplicate_count(), Ordering::Relaxed);
+        self.record_malformed_rows(other.malformed_row_count());
+    }
+
+    /// Number of invariant violations the engine's checks found so far;
+    /// always zero without [`EngineBuilder::check_invariants`].
+    pub fn invariant_violation_count(&self) -> u64 {
+        self.violations.load(Ordering::Relaxed)
+    }
+
+    /// Number of transactions rejected so far, e.g. for insufficient funds.
+    /// Ignored duplicates aren't counted.
+    pub fn rejected_count(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Number of deposits and withdrawals so far that repeated the type and
+    /// id of an earlier one, or an id of an earlier run, whether they were
+    /// ignored, rejected or replaced the earlier one, see
+    /// [`EngineBuilder::duplicates`] and [`EngineBuilder::tx_id_index`].
+    pub fn duplicate_count(&self) -> u64 {
+        self.duplicates.load(Ordering::Relaxed)
+    }
+
+    /// Number of malformed rows the readers skipped instead of handing them
+    /// to the engine.
+    pub fn malformed_row_count(&self) -> u64 {
+        self.malformed_rows.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn record_malformed_rows(&self, rows: u64) {
+        self.malformed_rows.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// The client's deposits and withdrawals in the order they were applied,
+    /// with their current status. Returns `None` unless the engine was built
+    /// with [`EngineBuilder::client_history`]; a client without any
+    /// transactions has an empty history.
+    pub fn client_history(&self, client_id: u16) -> Option<Vec<HistoryEntry>> {
+        let ids = self.transactions_db.client_history(client_id)?;
+        Some(
+            ids.into_iter()
+                .filter_map(|tx_id| {
+                    let tx = self.transactions_db.get(&tx_id)?;
+                    Some(tx.history_entry(tx_id))
+                })
+                .collect(),
+        )
+    }
+
+    /// Every dispute, resolve and chargeback applied to the transaction, in
+    /// order; empty if it was never disputed.
+    pub fn dispute_history(&self, tx_id: u32) -> Vec<DisputeStep> {
+        self.transactions_db.dispute_history(tx_id)
+    }
+
+    /// Retained transactions matching every criterion of `query`.
+    ///
+    /// A client criterion is answered from the history index when the
+    /// engine keeps one, and a disputed or charged-back status from the
+    /// status index; the other criteria are checked on those candidates, or
+    /// on every retained transaction when neither index applies. Results
+    /// for a client with history are in applied order, otherwise in
+    /// transaction id order.
+    pub fn search(&self, query: &TransactionQuery) -> Vec<HistoryEntry> {
+        let candidates = query
+            .client
+            .and_then(|client| self.transactions_db.client_history(client))
+            .or_else(|| {
+                let status = query.status?;
+                let mut ids = match status {
+                    TransactionStatus::Good => return None,
+                    _ => self.transactions_db.ids_with_status(status),
+                };
+                ids.sort_unstable();
+                Some(ids)
+            });
+
+        match candidates {
+            Some(ids) => ids
+                .into_iter()
+                .filter_map(|tx_id| {
+                    let tx = self.transactions_db.get(&tx_id)?;
+                    Some(tx.history_entry(tx_id))
+                })
+                .filter(|entry| query.matches(entry))
+                .collect(),
+            None => {
+                let mut entries: Vec<HistoryEntry> = self
+                    .transactions_db
+                    .iter()
+                    .map(|tx| tx.history_entry(*tx.key()))
+                    .filter(|entry| query.matches(entry))
+                    .collect();
+                entries.sort_unstable_by_key(|entry| entry.tx_id);
+                entries
+            }
+        }
+    }
+
+    /// The business rules the engine currently applies.
+    pub fn policy(&self) -> Arc<Policy> {
+        Arc::clone(&self.policy.read().unwrap_or_else(|err| err.into_inner()))
+    }
+
+    /// Replaces the per-client limits, e.g. when the file they were loaded
+    /// from changes. Transactions already being applied finish under the
+    /// old ones.
+    pub fn set_client_limits(&self, limits: ClientLimits) {
+        let mut policy = self.policy.write().unwrap_or_else(|err| err.into_inner());
+        let mut updated = Policy::clone(&policy);
+        updated.set_client_limits(limits);
+        *policy = Arc::new(updated);
+    }
+
+    /// Erases `client`'s trail, e.g. on a data-protection request: its
+    /// account and every transaction stored for it, with their dispute
+    /// steps and screening hits. The certificate is published as an event
+    /// and returned. A client with funds held by a dispute or review is
+    /// refused until they are settled.
+    ///
+    /// Transactions being applied are waited for, and new ones wait until
+    /// the erasure is done, so none can land between the check and the
+    /// removal. A later transaction of the client opens a new account.
+    pub fn erase_client(&self, client: u16) -> Result<ErasureCertificate, RejectReason> {
+        let _erasing = self.applying.write().unwrap_or_else(|err| err.into_inner());
+        let balances = match self.client_db.get(&client) {
+            Some(account) => Balances::from(&*account),
+            None => return Err(RejectReason::UnknownClient),
+        };
+        if balances.held != 0.0 {
+            return Err(RejectReason::FundsHeld);
+        }
+        self.client_db.remove(&client);
+        let certificate = ErasureCertificate {
+            client,
+            records: self.transactions_db.remove_client(client),
+            available: balances.available,
+            total: balances.total,
+            locked: balances.locked,
+        };
+        self.screening_hits
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain(|hit| hit.client_id != client);
+        if let Some(events) = &self.events {
+            events.publish_erasure(certificate);
+        }
+        Ok(certificate)
+    }
+
+    /// Merges client `from` into client `into`, e.g. once upstream systems
+    /// found them to be the same customer: `into` takes over `from`'s
+    /// balances, running totals, stored transactions and pending reviews,
+    /// and `from`'s account is removed. `into` is opened if it has no
+    /// account yet. The merged account is locked if either was; closed
+    /// accounts are refused. The merge is published as an event, and the
+    /// merged account's balances returned.
+    ///
+    /// Call it while neither client's transactions are being applied.
+    pub fn merge_clients(&self, from: u16, into: u16) -> Result<Balances, RejectReason> {
+        let _applying = self.applying();
+        let source = match self.client_db.get(&from) {
+            Some(account) => *account,
+            None => return Err(RejectReason::UnknownClient),
+        };
+        if from == into {
+            return Ok(Balances::from(&source));
+        }
+        let mut merged = match self.client_db.get(&into) {
+            Some(account) => *account,
+            None => Client::new(into),
+        };
+        merged.absorb(&source)?;
+        self.client_db.insert(into, merged);
+        self.client_db.remove(&from);
+        self.transactions_db.reassign_client(from, into);
+        for review in self
+            .reviews
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .iter_mut()
+            .filter(|review| review.client_id == from)
+        {
+            review.client_id = into;
+        }
+        if let Some(events) = &self.events {
+            events.publish_merge(from, into);
+        }
+        Ok(Balances::from(&merged))
+    }
+
+    /// Moves client `client` with its stored transactions and pending
+    /// reviews to `into`, which shouldn't have an account for it yet. Does
+    /// nothing for a client without an account.
+    ///
+    /// Call it while neither engine is applying the client's transactions.
+    pub(crate) fn hand_over(&self, client: u16, into: &PaymentsEngine) {
+        let Some((_, account)) = self.client_db.remove(&client) else {
+            return;
+        };
+        into.client_db.insert(client, account);
+        self.transactions_db
+            .move_client(client, &into.transactions_db);
+        let mut reviews = self.reviews.lock().unwrap_or_else(|err| err.into_inner());
+        let (moved, kept): (VecDeque<_>, VecDeque<_>) = reviews
+            .drain(..)
+            .partition(|review| review.client_id == client);
+        *reviews = kept;
+        let mut target = into.reviews.lock().unwrap_or_else(|err| err.into_inner());
+        target.extend(moved);
+        // Oldest first, as the review timeout expects
+        target.make_contiguous().sort_unstable();
+    }
+
+    /// Drops every client not in `clients`, e.g. to report on a few
+    /// clients of a large run. Their retained transactions are kept.
+    pub fn retain_clients(&self, clients: &[u16]) {
+        self.client_db.retain(|id, _| clients.contains(id));
+    }
+
+    /// Current time according to the engine's clock.
+    pub fn now(&self) -> SystemTime {
+        self.clock.now()
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.client_db.len()
+    }
+
+    /// Number of deposits and withdrawals retained for later disputes.
+    pub fn transaction_count(&self) -> usize {
+        self.transactions_db.len()
+    }
+
+    /// Estimates the memory held by the client and transaction stores.
+    ///
+    /// Only entries are counted, scaled by the hash table's maximum load
+    /// factor of 7/8; allocator overhead and empty shards are ignored.
+    pub fn approximate_memory_usage(&self) -> usize {
+        let clients =
+            self.client_db.len() * (mem::size_of::<u16>() + mem::size_of::<Client>()) * 8 / 7;
+
+        clients + self.transactions_db.approximate_memory_usage()
+    }
+
+    /// Whether ingestion should hold off given `in_flight` dispatched but
+    /// not yet applied transactions.
+    pub(crate) fn exceeds_memory_watermark(&self, in_flight: usize) -> bool {
+        match self.memory_watermark {
+            Some(watermark) => {
+                self.approximate_memory_usage() + in_flight * IN_FLIGHT_TX_BYTES > watermark
+            }
+            None => false,
+        }
+    }
+
+    /// How ingestion applies transactions: through one actor per client,
+    /// or with `None` a task per transaction.
+    pub(crate) fn client_actors(&self) -> Option<ActorConfig> {
+        self.client_actors
+    }
+
+    /// Id of the run the engine was built for, if given one, also carried by
+    /// its outcome events. Meant as a label for an embedding application's
+    /// metrics and logs, so they can be correlated with the journal.
+    pub fn run_id(&self) -> Option<RunId> {
+        self.run_id
+    }
+
+    /// Most transactions ingestion keeps in flight at once, if limited.
+    pub(crate) fn max_in_flight(&self) -> Option<usize> {
+        self.max_in_flight
+    }
+
+    /// Whether the stores alone are past the watermark, in which case
+    /// waiting for in-flight transactions can't bring usage back down.
+    pub(crate) fn stores_exceed_memory_watermark(&self) -> bool {
+        self.exceeds_memory_watermark(0)
+    }
+
+    /// Writes the current client balances as a CSV report.
+    pub fn write_report<W: Write>(&self, destination: W) -> Result<(), Box<dyn Error>> {
+        self.write_report_with(ReportLayout::default(), destination)
+    }
+
+    /// Writes the client report with the given columns and header.
+    pub fn write_report_with<W: Write>(
+        &self,
+        layout: ReportLayout,
+        destination: W,
+    ) -> Result<(), Box<dyn Error>> {
+        write_csv_with(&self.client_db, layout, destination)
+    }
+
+    /// Writes the residual balances of closed accounts, see
+    /// [`write_payouts`].
+    pub fn write_payouts<W: Write>(
+        &self,
+        precision: Precision,
+        destination: W,
+    ) -> Result<(), Box<dyn Error>> {
+        write_payouts(&self.client_db, precision, destination)
+    }
+
+    /// Writes the dispute history of every disputed transaction, see
+    /// [`write_dispute_report`].
+    pub fn write_dispute_report<W: Write>(
+        &self,
+        precision: Precision,
+        destination: W,
+    ) -> Result<(), Box<dyn Error>> {
+        write_dispute_report(&self.transactions_db, precision, destination)
+    }
+
+    /// Transactions rejected by the blocklist so far, in the order they
+    /// were received.
+    pub fn screening_hits(&self) -> Vec<ScreeningHit> {
+        let mut hits = self
+            .screening_hits
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        // Concurrent transactions may be recorded out of order
+        hits.sort_by_key(|hit| hit.sequence);
+        hits
+    }
+
+    /// Writes every transaction rejected by the blocklist, see
+    /// [`write_screening_report`].
+    pub fn write_screening_report<W: Write>(
+        &self,
+        precision: Precision,
+        destination: W,
+    ) -> Result<(), Box<dyn Error>> {
+        write_screening_report(&self.screening_hits(), precision, destination)
+    }
+
+    /// Number of transactions handed to the engine so far. Every client
+    /// changed by a later transaction has a higher `last_activity`.
+    pub fn watermark(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Writes a report of only the clients changed by transactions after
+    /// the `since` watermark, and returns the watermark to pass next time.
+    /// Starting from 0 reports every client.
+    ///
+    /// Call it while no transaction is being applied, e.g. between files in
+    /// watch mode: a transaction numbered before the returned watermark but
+    /// still being applied could otherwise be missed by both reports.
+    pub fn write_delta_report<W: Write>(
+        &self,
+        since: u64,
+        layout: ReportLayout,
+        destination: W,
+    ) -> Result<u64, Box<dyn Error>> {
+        let watermark = self.watermark();
+        let changed = self
+            .client_db
+            .iter()
+            .filter(|client| client.stats.last_activity > since)
+            .map(|client| *client);
+        write_client_rows(changed, layout, destination)?;
+        Ok(watermark)
+    }
+
+    /// Writes the client report split over `shards` CSV files in `dir`,
+    /// see [`write_sharded_csv`].
+    pub fn write_sharded_report(
+        &self,
+        dir: &Path,
+        shards: usize,
+        by: ShardBy,
+        layout: ReportLayout,
+        compression: Compression,
+    ) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        write_sharded_csv(&self.client_db, shards, by, layout, compression, dir)
+    }
+
+    /// Writes the client report through `sink`, in whichever format it
+    /// implements.
+    pub fn write_report_to(
+        &self,
+        sink: &mut dyn ReportSink,
+        layout: ReportLayout,
+    ) -> Result<(), Box<dyn Error>> {
+        sink.write_report(&self.client_db, layout)
+    }
+
+    /// Number of transactions the engine was sized for, used to pre-size
+    /// ingestion buffers.
+    pub(crate) fn expected_transactions(&self) -> usize {
+        self.expected_transactions
+    }
+
+    pub(crate) fn clients(&self) -> &ClientDb {
+        &self.client_db
+    }
+
+    pub(crate) fn transactions(&self) -> &TransactionsDb {
+        &self.transactions_db
+    }
+}
+
+impl Default for PaymentsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Tuning knobs for a [`PaymentsEngine`].
+///
+/// The defaults match DashMap's own: a shard count derived from the number
+/// of CPUs and the standard SipHash hasher.
+#[derive(Clone, Default)]
+pub struct EngineBuilder {
+    shard_amount: Option<usize>,
+    hasher: EngineHasher,
+    memory_watermark: Option<usize>,
+    client_actors: Option<ActorConfig>,
+    max_in_flight: Option<usize>,
+    runtime: RuntimeConfig,
+    client_locking: ClientLocking,
+    contention_stats: bool,
+    tx_id_filter: Option<usize>,
+    expected_clients: usize,
+    expected_transactions: usize,
+    client_history: bool,
+    only_clients: Option<Arc<FxHashSet<u16>>>,
+    client_ids: Option<Arc<ClientIdMap>>,
+    allow_adjustments: bool,
+    policy: Policy,
+    invariant_checks: Option<InvariantChecks>,
+    risk_scorer: Option<Arc<dyn RiskScorer>>,
+    blocklist: Option<Arc<dyn Blocklist>>,
+    custom_handlers: FxHashMap<CustomType, Arc<dyn CustomHandler>>,
+    clock: Option<Arc<dyn Clock>>,
+    run_id: Option<RunId>,
+    events: Option<Arc<EventStream>>,
+    tx_id_index: Option<Arc<TxIdIndex>>,
+}
+
+impl EngineBuilder {
+    /// Number of shards, i.e. independently locked sections, in each map.
+    /// Must be a power of two greater than one.
+    ///
+    /// More shards lower the odds of unrelated clients contending with a
+    /// very hot one, at the cost of some memory per map.
+    pub fn shard_amount(mut self, shard_amount: usize) -> Self {
+        self.shard_amount = Some(shard_amount);
+        self
+    }
+
+    pub fn hasher(mut self, hasher: EngineHasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Slows ingestion down once the stores and the transactions in flight
+    /// are estimated to take more than `bytes` of memory.
+    pub fn memory_watermark(mut self, bytes: usize) -> Self {
+        self.memory_watermark = Some(bytes);
+        self
+    }
+
+    /// Has the readers and channels feeding the engine apply transactions
+    /// through one actor per client rather than a task per transaction, so
+    /// each client's transactions are applied in order, see
+    /// [`ClientActors`](crate::io::actors::ClientActors). The memory
+    /// watermark then doesn't hold ingestion off; full mailboxes do.
+    pub fn client_actors(mut self, config: ActorConfig) -> Self {
+        self.client_actors = Some(config);
+        self
+    }
+
+    /// Keeps at most `transactions` spawned and not yet applied while
+    /// ingesting, waiting for the oldest before spawning more. A low limit
+    /// bounds how long a transaction waits behind others, a high one lets
+    /// more run in parallel. Unlimited by default, short of the memory
+    /// watermark.
+    pub fn max_in_flight(mut self, transactions: usize) -> Self {
+        self.max_in_flight = Some(transactions.max(1));
+        self
+    }
+
+    /// How transactions lock the client accounts they apply to,
+    /// [`ClientLocking::Entries`] by default.
+    pub fn client_locking(mut self, locking: ClientLocking) -> Self {
+        self.client_locking = locking;
+        self
+    }
+
+    /// Measures how often transactions wait for their client's lock and
+    /// for how long, see [`PaymentsEngine::contention`]. Measuring costs an
+    /// extra lock attempt per transaction, and 256 KiB of counters.
+    pub fn contention_stats(mut self, enabled: bool) -> Self {
+        self.contention_stats = enabled;
+        self
+    }
+
+    /// Settings of the runtime to drive the engine on, see
+    /// [`build_runtime`](Self::build_runtime).
+    pub fn runtime(mut self, config: RuntimeConfig) -> Self {
+        self.runtime = config;
+        self
+    }
+
+    /// A tokio runtime with the configured settings, for the engine's
+    /// ingestion tasks and actors to run on.
+    pub fn build_runtime(&self) -> std::io::Result<tokio::runtime::Runtime> {
+        self.runtime.build()
+    }
+
+    /// Puts a Bloom filter sized for `expected` transactions in front of the
+    /// transaction store, so ids that were never seen before skip the map
+    /// lookup. Costs about 1.2 bytes per expected transaction.
+    pub fn tx_id_filter(mut self, expected: usize) -> Self {
+        self.tx_id_filter = Some(expected);
+        self
+    }
+
+    /// Pre-allocates the client map for this many clients, so a large known
+    /// workload doesn't pause on repeated rehashing as the map grows.
+    pub fn expected_clients(mut self, clients: usize) -> Self {
+        self.expected_clients = clients;
+        self
+    }
+
+    /// Pre-allocates the transaction map and the ingestion buffers for this
+    /// many transactions.
+    pub fn expected_transactions(mut self, transactions: usize) -> Self {
+        self.expected_transactions = transactions;
+        self
+    }
+
+    /// Indexes transaction ids by client so [`PaymentsEngine::client_history`]
+    /// can list a client's transactions. Costs a few bytes per transaction.
+    pub fn client_history(mut self, enabled: bool) -> Self {
+        self.client_history = enabled;
+        self
+    }
+
+    /// Ignores every transaction of a client not in `clients`, without
+    /// storing or publishing anything for it. Since a client's balances only
+    /// depend on its own transactions, the listed clients end up as they
+    /// would without the filter, unless another client reused one of their
+    /// transaction ids first.
+    pub fn only_clients(mut self, clients: impl IntoIterator<Item = u16>) -> Self {
+        self.only_clients = Some(Arc::new(clients.into_iter().collect()));
+        self
+    }
+
+    /// Maps every incoming transaction's client id through `ids` before
+    /// anything else, so inputs using legacy client numbers land on the
+    /// current accounts. Reports, events and filters such as
+    /// [`only_clients`](Self::only_clients) all see the mapped ids.
+    pub fn client_id_map(mut self, ids: ClientIdMap) -> Self {
+        self.client_ids = Some(Arc::new(ids));
+        self
+    }
+
+    /// The client id `client` is processed under, after
+    /// [`client_id_map`](Self::client_id_map).
+    pub(crate) fn mapped_client(&self, client: u16) -> u16 {
+        self.client_ids
+            .as_ref()
+            .map_or(client, |ids| ids.map(client))
+    }
+
+    /// Applies manual `credit` and `debit` adjustments instead of rejecting
+    /// them as unauthorized. An allowed debit bypasses the available funds
+    /// check. Only enable it for inputs from a trusted back office.
+    pub fn allow_adjustments(mut self, enabled: bool) -> Self {
+        self.allow_adjustments = enabled;
+        self
+    }
+
+    /// Checks the client's balances after every applied transaction, see
+    /// [`invariants::check_balances`]. Off by default; the checks cost a
+    /// few comparisons per transaction.
+    pub fn check_invariants(mut self, checks: InvariantChecks) -> Self {
+        self.invariant_checks = Some(checks);
+        self
+    }
+
+    /// Lets withdrawals take every client's available balance down to
+    /// `-limit` instead of rejecting them below zero.
+    pub fn overdraft_limit(mut self, limit: f64) -> Self {
+        self.policy.set_overdraft_limit(limit);
+        self
+    }
+
+    /// Overdraft limit for one client, overriding
+    /// [`overdraft_limit`](Self::overdraft_limit).
+    pub fn client_overdraft_limit(mut self, client: u16, limit: f64) -> Self {
+        self.policy.set_client_overdraft_limit(client, limit);
+        self
+    }
+
+    /// Per-client limits, e.g. loaded with [`ClientLimits::read`], taking
+    /// precedence over the ones set one by one.
+    pub fn client_limits(mut self, limits: ClientLimits) -> Self {
+        self.policy.set_client_limits(limits);
+        self
+    }
+
+    /// Holds withdrawals above `threshold` for review: their amount is
+    /// held until an `approve` transaction lets it leave the account or a
+    /// `deny` returns it to the available balance.
+    pub fn review_threshold(mut self, threshold: f64) -> Self {
+        self.policy.set_review_threshold(threshold);
+        self
+    }
+
+    /// Denies a transaction held for review, by the threshold or a risk
+    /// scorer, that is still pending `timeout` after it was held, by the
+    /// engine's [`clock`](Self::clock). Checked as transactions are applied
+    /// and on [`PaymentsEngine::expire_reviews`].
+    pub fn review_timeout(mut self, timeout: Duration) -> Self {
+        self.policy
+            .set_review_timeout(ReviewTimeout::After(timeout));
+        self
+    }
+
+    /// Like [`review_timeout`](Self::review_timeout), but denies once the
+    /// engine has received `transactions` more transactions, whatever the
+    /// time, e.g. to replay an input and deny the same transactions.
+    pub fn review_timeout_transactions(mut self, transactions: u64) -> Self {
+        self.policy
+            .set_review_timeout(ReviewTimeout::AfterTransactions(transactions));
+        self
+    }
+
+    /// Rejects withdrawals that would leave a client with less than
+    /// `minimum` available, even within its overdraft limit.
+    pub fn minimum_balance(mut self, minimum: f64) -> Self {
+        self.policy.set_minimum_balance(minimum);
+        self
+    }
+
+    /// Minimum balance for one client, overriding
+    /// [`minimum_balance`](Self::minimum_balance).
+    pub fn client_minimum_balance(mut self, client: u16, minimum: f64) -> Self {
+        self.policy.set_client_minimum_balance(client, minimum);
+        self
+    }
+
+    /// Rejects transactions whose amount is outside `limits`, unless their
+    /// type has limits of its own.
+    pub fn amount_limits(mut self, limits: AmountLimits) -> Self {
+        self.policy.set_amount_limits(limits);
+        self
+    }
+
+    /// Amount limits for transactions of `tx_type`, replacing the global
+    /// ones for that type.
+    pub fn type_amount_limits(mut self, tx_type: TransactionType, limits: AmountLimits) -> Self {
+        self.policy.set_type_amount_limits(tx_type, limits);
+        self
+    }
+
+    /// How amounts with more than four places are rounded, both those the
+    /// engine receives and those it computes, such as interest. Half-up by
+    /// default.
+    pub fn rounding(mut self, rounding: RoundingMode) -> Self {
+        self.policy.set_rounding(rounding);
+        self
+    }
+
+    /// What happens to a deposit or withdrawal repeating the type and id
+    /// of an earlier one. Ignored by default.
+    pub fn duplicates(mut self, duplicates: DuplicatePolicy) -> Self {
+        self.policy.set_duplicates(duplicates);
+        self
+    }
+
+    /// Treats deposits and withdrawals whose ids `index` has from earlier
+    /// runs as duplicates, and records the ids of those this engine
+    /// applies or holds, for the index to be written after the run. An id
+    /// from an earlier run repeats it whatever the type, and can't be
+    /// replaced under last-write-wins, since the earlier transaction isn't
+    /// retained. Engines built from clones of the builder share the index.
+    pub fn tx_id_index(mut self, index: Arc<TxIdIndex>) -> Self {
+        self.tx_id_index = Some(index);
+        self
+    }
+
+    /// Clock the engine reads the time from, the system clock by default.
+    pub fn clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Consults `scorer` before applying every deposit and withdrawal, which
+    /// it may let through, hold for review or reject. Its score is
+    /// published with the transaction's outcome.
+    pub fn risk_scorer<S: RiskScorer + 'static>(mut self, scorer: S) -> Self {
+        self.risk_scorer = Some(Arc::new(scorer));
+        self
+    }
+
+    /// Rejects every transaction of a client `blocklist` blocks, and records
+    /// it for [`PaymentsEngine::write_screening_report`].
+    pub fn blocklist<B: Blocklist + 'static>(mut self, blocklist: B) -> Self {
+        self.blocklist = Some(Arc::new(blocklist));
+        self
+    }
+
+    /// Applies transactions of the custom type `kind` with `handler`, in
+    /// place of any handler set for it before. Transactions of a custom
+    /// type without a handler are rejected as `unsupported_type`.
+    pub fn custom_handler<H: CustomHandler + 'static>(
+        mut self,
+        kind: CustomType,
+        handler: H,
+    ) -> Self {
+        self.custom_handlers.insert(kind, Arc::new(handler));
+        self
+    }
+
+    /// Tags the engine with the id of the run it is built for, which every
+    /// outcome event then carries, see [`PaymentsEngine::run_id`].
+    pub fn run_id(mut self, id: RunId) -> Self {
+        self.run_id = Some(id);
+        self
+    }
+
+    /// Publishes the outcome of every transaction to `sink` as it is
+    /// applied, along with accounts being opened or locked. Every engine
+    /// built from this builder shares the sink and its sequence numbers.
+    pub fn event_sink<S: EventSink + 'static>(mut self, sink: S) -> Self {
+        self.events = Some(Arc::new(EventStream {
+            sink: Box::new(sink),
+            sequence: AtomicU64::new(0),
+        }));
+        self
+    }
+
+    /// Same settings, with the capacity hints divided evenly between
+    /// `shards` engines that each see a disjoint part of the workload.
+    pub(crate) fn split_capacity(&self, shards: usize) -> Self {
+        let shards = shards.max(1);
+        Self {
+            expected_clients: self.expected_clients.div_ceil(shards),
+            expected_transactions: self.expected_transactions.div_ceil(shards),
+            ..self.clone()
+        }
+    }
+
+    /// Builds the engine.
+    ///
+    /// Panics if the shard amount is not a power of two greater than one.
+    pub fn build(self) -> PaymentsEngine {
+        let clients = self.expected_clients;
+        let transactions = self.expected_transactions;
+        let (client_db, transactions_db) = match self.shard_amount {
+            Some(shards) => (
+                DashMap::with_capacity_and_hasher_and_shard_amount(
+                    clients,
+                    self.hasher.clone(),
+                    shards,
+                ),
+                DashMap::with_capacity_and_hasher_and_shard_amount(
+                    transactions,
+                    self.hasher.clone(),
+                    shards,
+                ),
+            ),
+            None => (
+                DashMap::with_capacity_and_hasher(clients, self.hasher.clone()),
+                DashMap::with_capacity_and_hasher(transactions, self.hasher.clone()),
+            ),
+        };
+        let hasher = self.hasher;
+        let shard_mutexes = match self.client_locking {
+            ClientLocking::Entries => None,
+            ClientLocking::ShardMutexes => {
+                Some(Arc::new(locking::ShardMutexes::new(self.shard_amount)))
+            }
+        };
+
+        let mut transactions_db = TransactionStore::new(transactions_db);
+        if self.client_history {
+            transactions_db = transactions_db.with_history(hasher);
+        }
+        if let Some(expected) = self.tx_id_filter {
+            transactions_db = transactions_db.with_filter(expected);
+        }
+
+        PaymentsEngine {
+            client_db: Arc::new(client_db),
+            transactions_db: Arc::new(transactions_db),
+            memory_watermark: self.memory_watermark,
+            expected_transactions: transactions,
+            client_actors: self.client_actors,
+            max_in_flight: self.max_in_flight,
+            clock: self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            run_id: self.run_id,
+            events: self.events,
+            received: Arc::new(AtomicU64::new(0)),
+            rejected: Arc::new(AtomicU64::new(0)),
+            duplicates: Arc::new(AtomicU64::new(0)),
+            malformed_rows: Arc::new(AtomicU64::new(0)),
+            only_clients: self.only_clients,
+            client_ids: self.client_ids,
+            allow_adjustments: self.allow_adjustments,
+            policy: Arc::new(RwLock::new(Arc::new(self.policy))),
+            invariant_checks: self.invariant_checks,
+            risk_scorer: self.risk_scorer,
+            blocklist: self.blocklist,
+            custom_handlers: Arc::new(self.custom_handlers),
+            screening_hits: Arc::new(Mutex::new(Vec::new())),
+            violations: Arc::new(AtomicU64::new(0)),
+            applying: Arc::new(RwLock::new(())),
+            shard_mutexes,
+            contention: self
+                .contention_stats
+                .then(|| Arc::new(locking::ContentionStats::new())),
+            reviews: Arc::new(Mutex::new(VecDeque::new())),
+            tx_id_index: self.tx_id_index,
+            inputs: Arc::new(Mutex::new(Vec::new())),
+            fork_base: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accrual::RateTier;
+    use crate::dedup::TxIdSet;
+    use crate::events::ChannelSink;
+    use crate::risk::RiskAssessment;
+    use crate::transactions::{AdjustmentReason, CustomType, TransactionType};
+
+    #[tokio::test]
+    async fn test_memory_usage_grows_with_the_stores() {
+        let engine = PaymentsEngine::new();
+        assert_eq!(engine.approximate_memory_usage(), 0);
+
+        engine
+            .handle_transaction(Transaction::new_deposit(1, 1, 1.0))
+            .await;
+
+        assert!(engine.approximate_memory_usage() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_watermark_accounts_for_in_flight_transactions() {
+        let engine = PaymentsEngine::builder()
+            .memory_watermark(IN_FLIGHT_TX_BYTES * 2)
+            .build();
+
+        assert!(!engine.exceeds_memory_watermark(2));
+        assert!(engine.exceeds_memory_watermark(3));
+        assert!(!engine.stores_exceed_memory_watermark());
+    }
+
+    #[tokio::test]
+    async fn test_tuned_engine_processes_transactions() {
+        let engine = PaymentsEngine::builder()
+            .shard_amount(64)
+            .hasher(EngineHasher::Fx)
+            .tx_id_filter(16)
+            .build();
+
+        engine
+            .handle_transaction(Transaction::new_deposit(1, 1, 2.0))
+            .await;
+        engine
+            .handle_transaction(Transaction::new_withdrawal(1, 2, 0.5))
+            .await;
+
+        assert_eq!(engine.clients().get(&1).unwrap().available, 1.5);
+    }
+
+    #[test]
+    fn test_capacity_hints_pre_allocate_the_maps() {
+        let engine = PaymentsEngine::builder()
+            .shard_amount(4)
+            .expected_clients(1_000)
+            .expected_transactions(10_000)
+            .build();
+
+        assert!(engine.clients().capacity() >= 1_000);
+        assert!(engine.transactions_db.capacity() >= 10_000);
+        assert_eq!(engine.approximate_memory_usage(), 0);
+    }
+
+    #[test]
+    fn test_engine_reads_time_from_its_clock() {
+        let clock = ManualClock::default();
+        let engine = PaymentsEngine::builder().clock(clock.clone()).build();
+        assert_eq!(engine.now(), SystemTime::UNIX_EPOCH);
+
+        clock.advance(std::time::Duration::from_secs(60));
+
+        assert_eq!(
+            engine.now(),
+            SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(60)
+        );
+        assert!(PaymentsEngine::new().now() > SystemTime::UNIX_EPOCH);
+    }
+
+    #[test]
+    fn test_split_capacity_divides_the_hints() {
+        let builder = PaymentsEngine::builder()
+            .expected_clients(10)
+            .expected_transactions(100)
+            .split_capacity(3);
+
+        assert_eq!(builder.expected_clients, 4);
+        assert_eq!(builder.expected_transactions, 34);
+    }
+
+    #[test]
+    fn test_rejected_transactions_are_counted() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0));
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 5.0));
+        assert_eq!(engine.rejected_count(), 1);
+
+        let merged = PaymentsEngine::new();
+        merged.apply_transaction(Transaction::new_dispute(2, 9));
+        merged.absorb(engine);
+        assert_eq!(merged.rejected_count(), 2);
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let apply = |policy: DuplicatePolicy| {
+            let engine = PaymentsEngine::builder().duplicates(policy).build();
+            engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0));
+            engine.apply_transaction(Transaction::new_withdrawal(1, 2, 0.5));
+            let outcome = engine.apply_transaction(Transaction::new_deposit(1, 1, 5.0));
+            assert_eq!(engine.duplicate_count(), 1);
+            (engine, outcome)
+        };
+
+        for policy in [DuplicatePolicy::Ignore, DuplicatePolicy::Warn] {
+            let (engine, outcome) = apply(policy);
+            assert_eq!(
+                outcome,
+                TransactionOutcome::Ignored {
+                    reason: IgnoreReason::Duplicate
+                }
+            );
+            assert_eq!(engine.clients().get(&1).unwrap().available, 1.5);
+        }
+
+        let (engine, outcome) = apply(DuplicatePolicy::Reject);
+        assert_eq!(
+            outcome,
+            TransactionOutcome::Rejected {
+                reason: RejectReason::DuplicateTransaction
+            }
+        );
+        assert_eq!(engine.rejected_count(), 1);
+
+        let (engine, outcome) = apply(DuplicatePolicy::LastWriteWins);
+        assert!(matches!(
+            outcome,
+            TransactionOutcome::Applied { balances } if balances.available == 4.5
+        ));
+        assert_eq!(engine.transactions().get(&1).unwrap().amount(), 5.0);
+        // A disputed deposit can't be replaced
+        engine.apply_transaction(Transaction::new_dispute(1, 1));
+        assert_eq!(
+            engine.apply_transaction(Transaction::new_deposit(1, 1, 1.0)),
+            TransactionOutcome::Rejected {
+                reason: RejectReason::DuplicateTransaction
+            }
+        );
+        assert_eq!(engine.clients().get(&1).unwrap().held, 5.0);
+    }
+
+    #[test]
+    fn test_ids_of_earlier_runs_are_duplicates() {
+        let mut earlier = TxIdSet::new();
+        earlier.insert(1);
+        let index = Arc::new(TxIdIndex::new(earlier));
+        let engine = PaymentsEngine::builder().tx_id_index(index.clone()).build();
+
+        assert_eq!(
+            engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0)),
+            TransactionOutcome::Ignored {
+                reason: IgnoreReason::Duplicate
+            }
+        );
+        engine.apply_transaction(Transaction::new_deposit(1, 2, 3.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 3, 9.0));
+        assert_eq!(engine.duplicate_count(), 1);
+        assert_eq!(engine.clients().get(&1).unwrap().available, 3.0);
+        // Only applied transactions are recorded for the next run
+        let merged = index.merged();
+        assert!(merged.contains(2));
+        assert!(!merged.contains(3));
+    }
+
+    #[test]
+    fn test_adjustments_are_rejected_unless_allowed() {
+        let credit = Transaction::new_credit(1, 1, 2.0, AdjustmentReason::Correction);
+        let engine = PaymentsEngine::new();
+
+        assert_eq!(
+            engine.apply_transaction(credit),
+            TransactionOutcome::Rejected {
+                reason: RejectReason::Unauthorized
+            }
+        );
+        assert_eq!(engine.rejected_count(), 1);
+        assert_eq!(engine.client_count(), 0);
+
+        let engine = PaymentsEngine::builder().allow_adjustments(true).build();
+        assert!(matches!(
+            engine.apply_transaction(credit),
+            TransactionOutcome::Applied { balances } if balances.available == 2.0
+        ));
+    }
+
+    #[test]
+    fn test_interest_is_credited_to_eligible_clients() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 3650.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 3650.0));
+        engine.apply_transaction(Transaction::new_dispute(2, 2));
+        engine.apply_transaction(Transaction::new_chargeback(2, 2));
+        let rates = RateTable {
+            days: 1,
+            day_count: 365,
+            tiers: vec![RateTier {
+                from: 0.0,
+                rate: 0.1,
+            }],
+        };
+
+        let posted = engine.accrue_interest(&rates);
+        assert_eq!(posted.len(), 1);
+        let (credit, outcome) = posted[0];
+        assert_eq!(credit.client_id, 1);
+        assert_eq!(credit.reason, Some(AdjustmentReason::Interest));
+        assert!(matches!(
+            outcome,
+            TransactionOutcome::Applied { balances } if balances.available == 3651.0
+        ));
+    }
+
+    #[test]
+    fn test_sub_scale_amounts_are_disputed_as_credited() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 0.00006));
+        assert_eq!(engine.transactions().get(&1).unwrap().amount(), 0.0001);
+        assert!(matches!(
+            engine.apply_transaction(Transaction::new_dispute(1, 1)),
+            TransactionOutcome::Applied { balances }
+                if balances.available == 0.0 && balances.held == 0.0001
+        ));
+        engine.apply_transaction(Transaction::new_chargeback(1, 1));
+
+        let client = *engine.clients().get(&1).unwrap();
+        assert!(client.locked);
+        for balance in [client.available, client.held, client.total] {
+            assert_eq!(balance, 0.0);
+            assert!(balance.is_sign_positive());
+        }
+    }
+
+    #[test]
+    fn test_rounding_mode_decides_what_is_credited() {
+        let credited = |rounding, amount| {
+            let engine = PaymentsEngine::builder().rounding(rounding).build();
+            engine.apply_transaction(Transaction::new_deposit(1, 1, amount));
+            let stored = engine.transactions().get(&1).unwrap().amount();
+            let available = engine.clients().get(&1).unwrap().available;
+            assert_eq!(stored, available);
+            available
+        };
+
+        assert_eq!(credited(RoundingMode::HalfUp, 0.00125), 0.0013);
+        assert_eq!(credited(RoundingMode::HalfEven, 0.00125), 0.0012);
+        assert_eq!(credited(RoundingMode::Truncate, 0.00129), 0.0012);
+    }
+
+    #[test]
+    fn test_reviews_time_out_into_denials() {
+        let engine = PaymentsEngine::builder()
+            .review_threshold(100.0)
+            .review_timeout_transactions(3)
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 500.0));
+        assert!(matches!(
+            engine.apply_transaction(Transaction::new_withdrawal(1, 2, 200.0)),
+            TransactionOutcome::Held { balances } if balances.held == 200.0
+        ));
+        assert!(matches!(
+            engine.apply_transaction(Transaction::new_withdrawal(1, 3, 150.0)),
+            TransactionOutcome::Held { .. }
+        ));
+        engine.apply_transaction(Transaction::new_approve(1, 3));
+        assert_eq!(engine.clients().get(&1).unwrap().held, 200.0);
+
+        engine.apply_transaction(Transaction::new_deposit(2, 4, 1.0));
+        let client = *engine.clients().get(&1).unwrap();
+        assert_eq!(client.available, 350.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(
+            engine.apply_transaction(Transaction::new_approve(1, 2)),
+            TransactionOutcome::Rejected {
+                reason: RejectReason::NotPending
+            }
+        );
+    }
+
+    #[test]
+    fn test_reviews_time_out_by_the_clock() {
+        let clock = ManualClock::default();
+        let engine = PaymentsEngine::builder()
+            .clock(clock.clone())
+            .review_threshold(100.0)
+            .review_timeout(Duration::from_secs(60))
+            .build();
+        let pending = |tx_id| {
+            engine.transactions().get(&tx_id).unwrap().status() == TransactionStatus::Pending
+        };
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 500.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 200.0));
+        clock.advance(Duration::from_secs(30));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 3, 150.0));
+
+        // However many transactions arrive meanwhile
+        for tx_id in 4..100 {
+            engine.apply_transaction(Transaction::new_deposit(2, tx_id, 1.0));
+        }
+        assert_eq!(engine.expire_reviews(), 0);
+        assert!(pending(2) && pending(3));
+
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(engine.expire_reviews(), 1);
+        assert!(!pending(2) && pending(3));
+        assert_eq!(engine.clients().get(&1).unwrap().held, 150.0);
+
+        // Also checked as transactions are applied
+        clock.advance(Duration::from_secs(30));
+        engine.apply_transaction(Transaction::new_deposit(2, 100, 1.0));
+        assert!(!pending(3));
+        let client = *engine.clients().get(&1).unwrap();
+        assert_eq!((client.available, client.held), (500.0, 0.0));
+    }
+
+    #[test]
+    fn test_risk_scorer_approves_holds_or_rejects() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let engine = PaymentsEngine::builder()
+            .event_sink(ChannelSink::new(sender))
+            .risk_scorer(|tx: &Transaction, balances: Option<&Balances>| {
+                // Large amounts are rejected from new clients, held otherwise
+                let (score, decision) = match (tx.amount, balances) {
+                    (Some(amount), None) if amount >= 1_000.0 => (0.99, RiskDecision::Reject),
+                    (Some(amount), Some(_)) if amount >= 1_000.0 => (0.5, RiskDecision::Hold),
+                    _ => (0.1, RiskDecision::Approve),
+                };
+                RiskAssessment { score, decision }
+            })
+            .build();
+
+        assert!(matches!(
+            engine.apply_transaction(Transaction::new_deposit(1, 1, 1_000.0)),
+            TransactionOutcome::Rejected {
+                reason: RejectReason::RiskRejected
+            }
+        ));
+        engine.apply_transaction(Transaction::new_deposit(1, 2, 100.0));
+        assert!(matches!(
+            engine.apply_transaction(Transaction::new_deposit(1, 3, 1_000.0)),
+            TransactionOutcome::Held { balances } if balances.held == 1_000.0
+        ));
+        engine.apply_transaction(Transaction::new_approve(1, 3));
+        assert_eq!(engine.clients().get(&1).unwrap().available, 1_100.0);
+
+        let scores: Vec<Option<f64>> = std::iter::from_fn(|| receiver.try_recv().ok())
+            .filter_map(|event| match event {
+                Event::Outcome(event) => Some(event.risk_score),
+                Event::Client(_) | Event::Erasure(_) | Event::Merge(_) | Event::Input(_) => None,
+            })
+            .collect();
+        assert_eq!(scores, [Some(0.99), Some(0.1), Some(0.5), None]);
+    }
+
+    #[test]
+    fn test_erasing_a_client_removes_its_trail() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let engine = PaymentsEngine::builder()
+            .event_sink(ChannelSink::new(sender))
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 5.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 1.5));
+        engine.apply_transaction(Transaction::new_deposit(2, 3, 2.0));
+        engine.apply_transaction(Transaction::new_dispute(2, 3));
+
+        assert_eq!(engine.erase_client(2), Err(RejectReason::FundsHeld));
+        assert_eq!(engine.erase_client(3), Err(RejectReason::UnknownClient));
+        let certificate = engine.erase_client(1).unwrap();
+        assert_eq!(
+            certificate,
+            ErasureCertificate {
+                client: 1,
+                records: 2,
+                available: 3.5,
+                total: 3.5,
+                locked: false,
+            }
+        );
+        assert_eq!(engine.client_count(), 1);
+        assert!(engine.transactions_db.get(&1).is_none());
+        assert!(engine.transactions_db.get(&3).is_some());
+
+        let last = std::iter::from_fn(|| receiver.try_recv().ok()).last();
+        assert!(matches!(last, Some(Event::Erasure(event)) if event.certificate == certificate));
+    }
+
+    #[test]
+    fn test_merging_clients_moves_balances_and_transactions() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let engine = PaymentsEngine::builder()
+            .event_sink(ChannelSink::new(sender))
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 5.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 2.0));
+        engine.apply_transaction(Transaction::new_dispute(2, 2));
+        engine.apply_transaction(Transaction::new_deposit(3, 3, 1.0));
+        engine.apply_transaction(Transaction::new_dispute(3, 3));
+        engine.apply_transaction(Transaction::new_chargeback(3, 3));
+
+        assert_eq!(engine.merge_clients(4, 1), Err(RejectReason::UnknownClient));
+        assert_eq!(
+            engine.merge_clients(2, 1),
+            Ok(Balances {
+                client: 1,
+                available: 5.0,
+                held: 2.0,
+                total: 7.0,
+                locked: false,
+            })
+        );
+        assert!(engine.clients().get(&2).is_none());
+        assert!(matches!(
+            engine.apply_transaction(Transaction::new_resolve(1, 2)),
+            TransactionOutcome::Applied { balances } if balances.available == 7.0
+        ));
+        let merged = engine.merge_clients(3, 1).unwrap();
+        assert!(merged.locked);
+        assert_eq!(merged.total, 7.0);
+
+        let last = std::iter::from_fn(|| receiver.try_recv().ok()).last();
+        assert!(matches!(
+            last,
+            Some(Event::Merge(MergeEvent {
+                from: 3,
+                into: 1,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_blocked_clients_are_rejected_and_reported() {
+        let engine = PaymentsEngine::builder()
+            .blocklist(|client: u16| client == 7)
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 5.0));
+        assert_eq!(
+            engine.apply_transaction(Transaction::new_deposit(7, 2, 2.5)),
+            TransactionOutcome::Rejected {
+                reason: RejectReason::ClientBlocked
+            }
+        );
+        engine.apply_transaction(Transaction::new_dispute(7, 2));
+        assert_eq!(engine.client_count(), 1);
+        assert_eq!(engine.rejected_count(), 2);
+
+        let mut report = vec![];
+        engine
+            .write_screening_report(Precision::default(), &mut report)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "seq,client,tx,type,amount\n2,7,2,deposit,2.5000\n3,7,2,dispute,\n"
+        );
+    }
+
+    #[test]
+    fn test_batches_apply_all_legs_or_none() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 10.0));
+        let transfer = |first: u32, amount: f64| {
+            [
+                Transaction::new_withdrawal(1, first, amount),
+                Transaction::new_deposit(2, first + 1, amount - 0.5),
+                Transaction::new_deposit(9, first + 2, 0.5),
+            ]
+        };
+
+        let outcome = engine.apply_batch(&transfer(10, 20.0));
+        assert_eq!(
+            outcome,
+            BatchOutcome::Rejected {
+                leg: 0,
+                outcome: TransactionOutcome::Rejected {
+                    reason: RejectReason::InsufficientFunds
+                }
+            }
+        );
+        assert_eq!(
+            outcome.to_string(),
+            "leg 0 rejected: insufficient available funds"
+        );
+        assert_eq!(engine.rejected_count(), 3);
+        assert_eq!(engine.client_count(), 1);
+
+        let legs = [
+            Transaction::new_deposit(2, 20, 5.0),
+            Transaction::new_withdrawal(2, 21, 5.0),
+            Transaction::new_withdrawal(2, 22, 0.1),
+        ];
+        assert!(matches!(
+            engine.apply_batch(&legs),
+            BatchOutcome::Rejected { leg: 2, .. }
+        ));
+        assert_eq!(engine.client_count(), 1);
+
+        match engine.apply_batch(&transfer(10, 4.0)) {
+            BatchOutcome::Applied { outcomes } => assert_eq!(outcomes.len(), 3),
+            outcome => panic!("batch not applied: {}", outcome),
+        }
+        assert_eq!(engine.client_count(), 3);
+        assert_eq!(engine.clients().get(&1).unwrap().available, 6.0);
+        assert_eq!(engine.clients().get(&2).unwrap().available, 3.5);
+    }
+
+    #[test]
+    fn test_batch_legs_are_scored_and_handled_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        let scored = Arc::new(AtomicUsize::new(0));
+        let handled = Arc::new(AtomicUsize::new(0));
+        let fee = CustomType::register("batch_fee").unwrap();
+        let engine = PaymentsEngine::builder()
+            .risk_scorer({
+                let scored = Arc::clone(&scored);
+                // A velocity check of sorts: a second look is one too many
+                move |_: &Transaction, _: Option<&Balances>| {
+                    let decision = match scored.fetch_add(1, Ordering::Relaxed) {
+                        0 => RiskDecision::Approve,
+                        _ => RiskDecision::Reject,
+                    };
+                    RiskAssessment {
+                        score: 0.1,
+                        decision,
+                    }
+                }
+            })
+            .custom_handler(fee, {
+                let handled = Arc::clone(&handled);
+                move |_: &Transaction, account: &mut crate::plugin::Account| {
+                    handled.fetch_add(1, Ordering::Relaxed);
+                    account.debit(1.0)
+                }
+            })
+            .build();
+        let legs = [
+            Transaction::new_deposit(1, 1, 5.0),
+            Transaction {
+                tx_type: TransactionType::Custom(fee),
+                client_id: 1,
+                tx_id: 2,
+                amount: None,
+                reason: None,
+                dispute_reason: None,
+            },
+        ];
+
+        match engine.apply_batch(&legs) {
+            BatchOutcome::Applied { outcomes } => assert!(outcomes
+                .iter()
+                .all(|outcome| matches!(outcome, TransactionOutcome::Applied { .. }))),
+            outcome => panic!("batch not applied: {}", outcome),
+        }
+        assert_eq!(scored.load(Ordering::Relaxed), 1);
+        assert_eq!(handled.load(Ordering::Relaxed), 1);
+        let client = *engine.clients().get(&1).unwrap();
+        assert_eq!((client.available, client.total), (4.0, 4.0));
+        assert_eq!(engine.rejected_count(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_transactions_cannot_break_a_valid_batch() {
+        let engine = PaymentsEngine::new();
+        // Another thread keeps taking the client's only funds and putting
+        // them back, which a batch checked in between would run into
+        let withdrawn = std::thread::scope(|scope| {
+            let withdrawals = scope.spawn(|| {
+                (1_000_000..1_010_000)
+                    .step_by(2)
+                    .filter(|&tx| {
+                        engine.apply_transaction(Transaction::new_deposit(1, tx, 1.0));
+                        let withdrawal = Transaction::new_withdrawal(1, tx + 1, 1.0);
+                        let outcome = engine.apply_transaction(withdrawal);
+                        matches!(outcome, TransactionOutcome::Applied { .. })
+                    })
+                    .count()
+            });
+            for tx in (1..10_000).step_by(2) {
+                let legs = [
+                    Transaction::new_withdrawal(1, tx, 1.0),
+                    Transaction::new_deposit(2, tx + 1, 1.0),
+                ];
+                if let BatchOutcome::Applied { outcomes } = engine.apply_batch(&legs) {
+                    assert!(outcomes
+                        .iter()
+                        .all(|outcome| matches!(outcome, TransactionOutcome::Applied { .. })));
+                }
+            }
+            withdrawals.join().unwrap()
+        });
+
+        let clients = engine.clients();
+        let total = |client| clients.get(&client).map_or(0.0, |client| client.total);
+        assert!(total(1) >= 0.0);
+        assert_eq!(total(1) + total(2), 5_000.0 - withdrawn as f64);
+    }
+
+    #[test]
+    fn test_strict_invariant_checks_flag_negative_available_funds() {
+        let checks = InvariantChecks {
+            on_violation: OnViolation::Alert,
+            strict: true,
+        };
+        let engine = PaymentsEngine::builder().check_invariants(checks).build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 1.5));
+        assert_eq!(engine.invariant_violation_count(), 0);
+
+        engine.apply_transaction(Transaction::new_dispute(1, 1));
+        assert_eq!(engine.invariant_violation_count(), 1);
+
+        let engine = PaymentsEngine::builder()
+            .check_invariants(checks)
+            .overdraft_limit(2.0)
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 3.5));
+        assert_eq!(engine.invariant_violation_count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Dispute 1 broke an invariant")]
+    fn test_invariant_checks_abort_on_violation() {
+        let engine = PaymentsEngine::builder()
+            .check_invariants(InvariantChecks {
+                on_violation: OnViolation::Abort,
+                strict: true,
+            })
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 1.5));
+        engine.apply_transaction(Transaction::new_dispute(1, 1));
+    }
+
+    #[test]
+    fn test_client_history_lists_transactions_with_their_status() {
+        let engine = PaymentsEngine::builder().client_history(true).build();
+        engine.apply_transaction(Transaction::new_deposit(1, 5, 2.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 6, 1.0));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 0.5));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 3, 9.0));
+        engine.apply_transaction(Transaction::new_dispute(1, 5));
+
+        assert_eq!(
+            engine.client_history(1).unwrap(),
+            [
+                HistoryEntry {
+                    tx_id: 5,
+                    client_id: 1,
+                    tx_type: TransactionType::Deposit,
+                    amount: 2.0,
+                    status: TransactionStatus::Disputed,
+                    dispute_reason: None,
+                },
+                HistoryEntry {
+                    tx_id: 2,
+                    client_id: 1,
+                    tx_type: TransactionType::Withdrawal,
+                    amount: 0.5,
+                    status: TransactionStatus::Good,
+                    dispute_reason: None,
+                },
+            ]
+        );
+        assert_eq!(engine.client_history(3), Some(vec![]));
+        assert_eq!(PaymentsEngine::new().client_history(1), None);
+    }
+
+    #[test]
+    fn test_delta_report_lists_clients_changed_since_the_watermark() {
+        let engine = PaymentsEngine::new();
+        let layout = ReportLayout {
+            header: false,
+            ..ReportLayout::default()
+        };
+        let delta = |since| {
+            let mut report = vec![];
+            let watermark = engine
+                .write_delta_report(since, layout, &mut report)
+                .unwrap();
+            let mut rows: Vec<String> = String::from_utf8(report)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            rows.sort();
+            (watermark, rows)
+        };
+
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 1.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 1.0));
+        let (watermark, rows) = delta(0);
+        assert_eq!(watermark, 2);
+        assert_eq!(rows.len(), 2);
+
+        engine.apply_transaction(Transaction::new_withdrawal(2, 3, 0.5));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 4, 5.0));
+        engine.apply_transaction(Transaction::new_withdrawal(3, 5, 5.0));
+        let (watermark, rows) = delta(watermark);
+        assert_eq!(watermark, 5);
+        assert_eq!(
+            rows,
+            [
+                "2,0.5000,0.0000,0.5000,false",
+                "3,0.0000,0.0000,0.0000,false"
+            ]
+        );
+
+        assert_eq!(delta(watermark), (5, vec![]));
+    }
+
+    #[test]
+    fn test_client_filters_limit_processing_and_reporting() {
+        let engine = PaymentsEngine::builder().only_clients([1, 2]).build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 1.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 1.0));
+        assert_eq!(
+            engine.apply_transaction(Transaction::new_deposit(3, 3, 1.0)),
+            TransactionOutcome::Ignored {
+                reason: IgnoreReason::FilteredClient
+            }
+        );
+        assert_eq!(engine.client_count(), 2);
+        assert_eq!(engine.transaction_count(), 2);
+
+        engine.retain_clients(&[2]);
+        let mut report = vec![];
+        engine.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked\n2,1.0000,0.0000,1.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn test_legacy_client_ids_are_mapped_on_the_way_in() {
+        let engine = PaymentsEngine::builder()
+            .client_id_map([(1001, 1), (1002, 1)].iter().copied().collect())
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1001, 1, 5.0));
+        engine.apply_transaction(Transaction::new_deposit(1, 2, 1.0));
+        assert!(matches!(
+            engine.apply_transaction(Transaction::new_dispute(1002, 1)),
+            TransactionOutcome::Applied { balances } if balances.client == 1 && balances.held == 5.0
+        ));
+        assert!(matches!(
+            engine.apply_batch(&[
+                Transaction::new_withdrawal(1002, 3, 0.5),
+                Transaction::new_deposit(2, 4, 0.5),
+            ]),
+            BatchOutcome::Applied { .. }
+        ));
+
+        assert_eq!(engine.client_count(), 2);
+        let client = *engine.clients().get(&1).unwrap();
+        assert_eq!((client.available, client.held), (0.5, 5.0));
+    }
+}
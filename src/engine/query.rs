@@ -0,0 +1,109 @@
+use std::ops::RangeInclusive;
+
+use crate::transactions::{HistoryEntry, TransactionStatus, TransactionType};
+
+/// Criteria for [`PaymentsEngine::search`](super::PaymentsEngine::search).
+/// Every criterion that is set must match; an empty query matches every
+/// retained transaction.
+///
+/// ```
+/// use payments_engine::engine::TransactionQuery;
+/// use payments_engine::transactions::{TransactionStatus, TransactionType};
+///
+/// let disputed = TransactionQuery::new().status(TransactionStatus::Disputed);
+/// let large_deposits_of_client_7 = TransactionQuery::new()
+///     .client(7)
+///     .tx_type(TransactionType::Deposit)
+///     .amounts(1_000.0..=f64::MAX);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TransactionQuery {
+    pub(super) client: Option<u16>,
+    pub(super) status: Option<TransactionStatus>,
+    tx_type: Option<TransactionType>,
+    amounts: Option<RangeInclusive<f64>>,
+}
+
+impl TransactionQuery {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn client(mut self, client_id: u16) -> Self {
+        self.client = Some(client_id);
+        self
+    }
+
+    pub fn status(mut self, status: TransactionStatus) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn tx_type(mut self, tx_type: TransactionType) -> Self {
+        self.tx_type = Some(tx_type);
+        self
+    }
+
+    /// Amounts within `range`, bounds included.
+    pub fn amounts(mut self, range: RangeInclusive<f64>) -> Self {
+        self.amounts = Some(range);
+        self
+    }
+
+    pub(super) fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.client.is_none_or(|client| entry.client_id == client)
+            && self.status.is_none_or(|status| entry.status == status)
+            && self.tx_type.is_none_or(|tx_type| entry.tx_type == tx_type)
+            && self
+                .amounts
+                .as_ref()
+                .is_none_or(|range| range.contains(&entry.amount))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::transactions::Transaction;
+
+    fn ids(entries: Vec<HistoryEntry>) -> Vec<u32> {
+        entries.iter().map(|entry| entry.tx_id).collect()
+    }
+
+    #[test]
+    fn test_search_combines_criteria() {
+        for history in [false, true] {
+            let engine = PaymentsEngine::builder().client_history(history).build();
+            engine.apply_transaction(Transaction::new_deposit(1, 4, 5.0));
+            engine.apply_transaction(Transaction::new_deposit(1, 2, 50.0));
+            engine.apply_transaction(Transaction::new_withdrawal(1, 3, 1.0));
+            engine.apply_transaction(Transaction::new_deposit(2, 1, 20.0));
+            engine.apply_transaction(Transaction::new_dispute(1, 2));
+            engine.apply_transaction(Transaction::new_dispute(2, 1));
+            engine.apply_transaction(Transaction::new_chargeback(2, 1));
+
+            let search = |query: TransactionQuery| ids(engine.search(&query));
+            let disputed = TransactionQuery::new().status(TransactionStatus::Disputed);
+            let client_1 = TransactionQuery::new().client(1);
+
+            assert_eq!(search(TransactionQuery::new()), [1, 2, 3, 4]);
+            assert_eq!(search(disputed.clone()), [2]);
+            assert_eq!(search(disputed.client(2)), Vec::<u32>::new());
+            assert_eq!(
+                search(TransactionQuery::new().status(TransactionStatus::Chargeback)),
+                [1]
+            );
+            assert_eq!(
+                search(TransactionQuery::new().status(TransactionStatus::Good)),
+                [3, 4]
+            );
+            assert_eq!(
+                search(client_1.clone().tx_type(TransactionType::Deposit)),
+                if history { [4, 2] } else { [2, 4] }
+            );
+            assert_eq!(search(client_1.amounts(1.0..=5.0)).len(), 2);
+            assert_eq!(search(TransactionQuery::new().amounts(10.0..=50.0)), [1, 2]);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::process;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Identifies one run of the engine, e.g. one instance of a deployment
+/// ingesting a day's file, so a balance in a report can be traced back to
+/// the run that produced it across the manifest, the events journal and
+/// the logs. Written as 32 hex digits.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RunId(u128);
+
+impl RunId {
+    /// A new id, unique in practice: a hash of the time, the process id and
+    /// the process's random hashing keys.
+    pub fn generate() -> Self {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut keyed = RandomState::new().build_hasher();
+        keyed.write_u128(since_epoch.as_nanos());
+
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&since_epoch.as_nanos().to_le_bytes());
+        hasher.update(&process::id().to_le_bytes());
+        hasher.update(&keyed.finish().to_le_bytes());
+        let mut id = [0; 16];
+        id.copy_from_slice(&hasher.finalize().as_bytes()[..16]);
+        Self(u128::from_be_bytes(id))
+    }
+}
+
+impl fmt::Display for RunId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+impl FromStr for RunId {
+    type Err = String;
+
+    /// Parses up to 32 hex digits, e.g. a UUID with its dashes removed.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match u128::from_str_radix(s, 16) {
+            Ok(id) if s.len() <= 32 && s.bytes().all(|b| b.is_ascii_hexdigit()) => Ok(RunId(id)),
+            _ => Err(format!("run id '{}' must be 1 to 32 hex digits", s)),
+        }
+    }
+}
+
+impl Serialize for RunId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for RunId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_ids_are_unique_and_round_trip() {
+        let id = RunId::generate();
+        assert_ne!(id, RunId::generate());
+        assert_eq!(id.to_string().len(), 32);
+        assert_eq!(id.to_string().parse(), Ok(id));
+        assert_eq!(
+            "0f".parse::<RunId>().unwrap().to_string(),
+            "0000000000000000000000000000000f"
+        );
+        assert!("+f".parse::<RunId>().is_err());
+        assert!("0".repeat(33).parse::<RunId>().is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::error::Error;
+use std::io::Write;
+
+use super::PaymentsEngine;
+use crate::io::{write_client_rows, ReportLayout};
+use crate::outcome::Balances;
+use crate::processor::Client;
+
+/// Every client's balances at one point in time, taken while ingestion
+/// carries on.
+///
+/// A snapshot waits for the transactions being applied to finish and holds
+/// off new ones while it copies the accounts, so it never shows a
+/// transaction, or a batch such as a transfer, half-applied. Client ids are
+/// 16 bits, so the copy is at most 65536 accounts and ingestion is paused
+/// for about as long as copying them takes.
+#[derive(Clone)]
+pub struct Snapshot {
+    watermark: u64,
+    clients: Vec<Client>,
+}
+
+impl Snapshot {
+    /// Number of transactions the engine had received when the snapshot
+    /// was taken; all of them are reflected in it.
+    pub fn watermark(&self) -> u64 {
+        self.watermark
+    }
+
+    pub fn balances(&self, client: u16) -> Option<Balances> {
+        self.clients
+            .binary_search_by_key(&client, |account| account.id)
+            .ok()
+            .map(|at| Balances::from(&self.clients[at]))
+    }
+
+    /// Balances of every client, in client order.
+    pub fn clients(&self) -> impl Iterator<Item = Balances> + '_ {
+        self.clients.iter().map(Balances::from)
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Writes the snapshot as a CSV report, in client order.
+    pub fn write_report<W: Write>(&self, destination: W) -> Result<(), Box<dyn Error>> {
+        self.write_report_with(ReportLayout::default(), destination)
+    }
+
+    /// Writes the snapshot as a report with the given columns and header.
+    pub fn write_report_with<W: Write>(
+        &self,
+        layout: ReportLayout,
+        destination: W,
+    ) -> Result<(), Box<dyn Error>> {
+        write_client_rows(self.clients.iter().copied(), layout, destination)
+    }
+}
+
+impl PaymentsEngine {
+    /// A consistent copy of every client's balances, see [`Snapshot`]. Safe
+    /// to call while transactions are being applied, e.g. to serve reports
+    /// from a long-running engine.
+    pub fn snapshot(&self) -> Snapshot {
+        let paused = self.applying.write().unwrap_or_else(|err| err.into_inner());
+        let mut clients: Vec<Client> = self.client_db.iter().map(|client| *client).collect();
+        let watermark = self.watermark();
+        drop(paused);
+
+        clients.sort_unstable_by_key(|client| client.id);
+        Snapshot { watermark, clients }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::Transaction;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_snapshots_never_see_half_applied_transfers() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 1000.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 1000.0));
+        let done = Arc::new(AtomicBool::new(false));
+
+        let transfers = {
+            let engine = engine.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                for n in 0..2000u32 {
+                    let (from, into) = if n % 2 == 0 { (1, 2) } else { (2, 1) };
+                    engine.apply_batch(&[
+                        Transaction::new_withdrawal(from, 10 + 2 * n, 1.5),
+                        Transaction::new_deposit(into, 11 + 2 * n, 1.5),
+                    ]);
+                }
+                done.store(true, Ordering::Relaxed);
+            })
+        };
+        let mut taken = 0;
+        while !done.load(Ordering::Relaxed) || taken == 0 {
+            let snapshot = engine.snapshot();
+            let total: f64 = snapshot.clients().map(|balances| balances.total).sum();
+            assert_eq!(total, 2000.0, "at watermark {}", snapshot.watermark());
+            assert_eq!(snapshot.watermark() % 2, 0);
+            taken += 1;
+        }
+        transfers.join().unwrap();
+
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot.watermark(), 4002);
+        assert_eq!(snapshot.balances(1).unwrap().available, 1000.0);
+        let mut report = vec![];
+        snapshot.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked\n\
+             1,1000.0000,0.0000,1000.0000,false\n\
+             2,1000.0000,0.0000,1000.0000,false\n"
+        );
+    }
+}
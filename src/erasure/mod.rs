@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::{
+    is_input_line, open_journal, ClientEventKind, ErasureEvent, Event, EventSink, JsonlSink,
+};
+use crate::io::Compression;
+
+/// Record of a client's trail being erased, e.g. on a data-protection
+/// request: which client, how many of its records were removed, and what
+/// it held at that point, so totals across clients still reconcile without
+/// its history.
+///
+/// Clients with funds held by a dispute or review are refused, so there is
+/// no held balance to record.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct ErasureCertificate {
+    pub client: u16,
+    /// Records of the client removed: stored transactions, or lines of a
+    /// journal.
+    pub records: u64,
+    pub available: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// The fields of a journal line erasure looks at.
+#[derive(Deserialize)]
+struct JournalLine {
+    seq: u64,
+    client: u16,
+    lifecycle: Option<ClientEventKind>,
+    outcome: Option<String>,
+    available: Option<f64>,
+    held: Option<f64>,
+    total: Option<f64>,
+    locked: Option<bool>,
+}
+
+/// Removes every line about `client` from the `--events` journal at `path`
+/// and appends an erasure event in their place, numbered after the last
+/// event, as a tombstone auditors can account for. The certificate's
+/// balances are those after the client's last applied transaction.
+/// Certificates of earlier erasures of the client are kept.
+///
+/// The journal is rewritten next to `path` and renamed into place, keeping
+/// its compression. A client without lines in the journal, or with funds
+/// still held at its end, is refused and the journal left as it was.
+pub fn erase_from_journal(path: &Path, client: u16) -> Result<ErasureCertificate, Box<dyn Error>> {
+    let mut kept = vec![];
+    let mut last_seq = 0;
+    let mut certificate = ErasureCertificate {
+        client,
+        records: 0,
+        available: 0.0,
+        total: 0.0,
+        locked: false,
+    };
+    let mut held = 0.0;
+    for (index, line) in open_journal(path)?.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if is_input_line(&line) {
+            kept.push(line);
+            continue;
+        }
+        let parsed: JournalLine = serde_json::from_str(&line)
+            .map_err(|err| format!("{}: line {}: {}", path.display(), index + 1, err))?;
+        last_seq = last_seq.max(parsed.seq);
+        // Earlier erasures' certificates stay, should the id have been
+        // reused since
+        if parsed.client != client || parsed.lifecycle == Some(ClientEventKind::Erased) {
+            kept.push(line);
+            continue;
+        }
+        certificate.records += 1;
+        if let Some("applied" | "held") = parsed.outcome.as_deref() {
+            certificate.available = parsed.available.unwrap_or_default();
+            certificate.total = parsed.total.unwrap_or_default();
+            certificate.locked = parsed.locked.unwrap_or_default();
+            held = parsed.held.unwrap_or_default();
+        }
+    }
+    if certificate.records == 0 {
+        return Err(format!("{}: client {} has no events", path.display(), client).into());
+    }
+    if held != 0.0 {
+        return Err(format!(
+            "{}: client {} still has funds held, settle them first",
+            path.display(),
+            client
+        )
+        .into());
+    }
+
+    let tmp = path.with_extension("tmp");
+    let mut writer = Compression::from_path(path).writer(File::create(&tmp)?)?;
+    for line in &kept {
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+    let sink = JsonlSink::new(&mut writer);
+    sink.publish(&Event::Erasure(ErasureEvent {
+        sequence: last_seq + 1,
+        certificate,
+    }));
+    sink.flush()?;
+    drop(sink);
+    writer.finish()?;
+    fs::rename(tmp, path)?;
+    Ok(certificate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audit::replay_journal;
+    use crate::report::ReportRow;
+    use crate::test_support::TempDir;
+    use std::io::BufReader;
+
+    #[test]
+    fn test_journal_keeps_a_tombstone_and_still_audits() {
+        let dir = TempDir::new("erasure");
+        let path = dir.join("journal.jsonl");
+        fs::write(
+            &path,
+            r#"{"seq":1,"client":1,"lifecycle":"created"}
+{"seq":2,"type":"deposit","client":1,"tx":1,"amount":5.0,"outcome":"applied","available":5.0,"held":0.0,"total":5.0,"locked":false}
+{"seq":3,"client":2,"lifecycle":"created"}
+{"seq":4,"type":"deposit","client":2,"tx":2,"amount":2.0,"outcome":"applied","available":2.0,"held":0.0,"total":2.0,"locked":false}
+{"seq":5,"type":"withdrawal","client":1,"tx":3,"amount":1.5,"outcome":"applied","available":3.5,"held":0.0,"total":3.5,"locked":false}
+"#,
+        )
+        .unwrap();
+
+        let certificate = erase_from_journal(&path, 1).unwrap();
+        assert_eq!(
+            certificate,
+            ErasureCertificate {
+                client: 1,
+                records: 3,
+                available: 3.5,
+                total: 3.5,
+                locked: false,
+            }
+        );
+        let journal = fs::read_to_string(&path).unwrap();
+        assert_eq!(journal.lines().count(), 3);
+        assert!(journal.ends_with(
+            "{\"seq\":6,\"client\":1,\"lifecycle\":\"erased\",\"records\":3,\"available\":3.5,\"total\":3.5,\"locked\":false}\n"
+        ));
+        let replayed = replay_journal(BufReader::new(File::open(&path).unwrap())).unwrap();
+        let rows: Vec<ReportRow> = replayed.rows().cloned().collect();
+        assert_eq!(rows, [ReportRow::new(2, 2.0, 0.0, 2.0, false)]);
+        assert!(erase_from_journal(&path, 1).is_err());
+    }
+}
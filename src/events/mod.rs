@@ -0,0 +1,629 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::engine::RunId;
+use crate::erasure::ErasureCertificate;
+use crate::io::provenance::InputDigest;
+use crate::io::Compression;
+use crate::outcome::{IgnoreReason, RejectReason, TransactionOutcome};
+use crate::transactions::{AdjustmentReason, DisputeReason, Transaction, TransactionType};
+
+/// The outcome of one transaction, published as processing proceeds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OutcomeEvent {
+    /// Position in the engine's stream of events, starting at 1.
+    pub sequence: u64,
+    pub transaction: Transaction,
+    pub outcome: TransactionOutcome,
+    /// Reason code of the dispute an applied dispute, resolve or chargeback
+    /// concerns, for consumers whose handling differs by reason.
+    pub dispute_reason: Option<DisputeReason>,
+    /// Score the engine's risk scorer gave a deposit or withdrawal, if the
+    /// engine has one.
+    pub risk_score: Option<f64>,
+    /// Id of the run that applied the transaction, if the engine was given
+    /// one.
+    pub run: Option<RunId>,
+}
+
+/// A change to a client's account as a whole, published just before the
+/// outcome of the transaction that caused it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClientEvent {
+    /// Position in the engine's stream of events, starting at 1.
+    pub sequence: u64,
+    pub client: u16,
+    pub kind: ClientEventKind,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientEventKind {
+    /// The client's first deposit or withdrawal opened the account.
+    Created,
+    /// A chargeback locked the account.
+    Locked,
+    /// A close transaction closed the account.
+    Closed,
+    /// The client's trail was erased, see [`ErasureEvent`].
+    Erased,
+    /// The account was merged into another one, see [`MergeEvent`].
+    Merged,
+}
+
+impl ClientEventKind {
+    pub const ALL: [ClientEventKind; 5] = [
+        ClientEventKind::Created,
+        ClientEventKind::Locked,
+        ClientEventKind::Closed,
+        ClientEventKind::Erased,
+        ClientEventKind::Merged,
+    ];
+
+    /// Name of the change as written in the journal, e.g. `locked`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ClientEventKind::Created => "created",
+            ClientEventKind::Locked => "locked",
+            ClientEventKind::Closed => "closed",
+            ClientEventKind::Erased => "erased",
+            ClientEventKind::Merged => "merged",
+        }
+    }
+}
+
+/// A client's account merged into another, which took over its balances
+/// and transactions.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MergeEvent {
+    pub sequence: u64,
+    pub from: u16,
+    pub into: u16,
+}
+
+/// A client erased from the engine or a journal, written to the journal
+/// as a lifecycle line with the certificate's fields.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ErasureEvent {
+    pub sequence: u64,
+    pub certificate: ErasureCertificate,
+}
+
+/// A segment of input the engine finished reading, published after the
+/// events of the transactions read from it. Written to the journal as a
+/// line with an `input` field in place of a client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputEvent {
+    pub sequence: u64,
+    pub digest: InputDigest,
+}
+
+/// Everything an engine publishes, in one numbered stream.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Outcome(OutcomeEvent),
+    Client(ClientEvent),
+    Erasure(ErasureEvent),
+    Merge(MergeEvent),
+    Input(InputEvent),
+}
+
+impl Event {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            Event::Outcome(event) => event.sequence,
+            Event::Client(event) => event.sequence,
+            Event::Erasure(event) => event.sequence,
+            Event::Merge(event) => event.sequence,
+            Event::Input(event) => event.sequence,
+        }
+    }
+}
+
+/// Destination of events, e.g. a file, a channel or a message broker.
+///
+/// Events are published while applying the transaction, see
+/// [`PaymentsEngine::apply_transaction`](crate::engine::PaymentsEngine::apply_transaction)
+/// for what that asks of implementations. Two
+/// transactions of the same client applied concurrently may be published
+/// in either order; the balances in each event are exact for its
+/// transaction.
+pub trait EventSink: Send + Sync {
+    fn publish(&self, event: &Event);
+
+    /// Pushes out anything buffered, e.g. at the end of a run.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes every event as one line of JSON. Client events are told apart
+/// from transaction outcomes by their `lifecycle` field.
+///
+/// Write errors are reported on stderr once and further events dropped, so
+/// a full disk can't stop processing.
+pub struct JsonlSink<W: Write + Send> {
+    writer: Mutex<JsonlWriter<W>>,
+}
+
+struct JsonlWriter<W: Write> {
+    writer: BufWriter<W>,
+    failed: bool,
+}
+
+impl<W: Write + Send> JsonlSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(JsonlWriter {
+                writer: BufWriter::new(writer),
+                failed: false,
+            }),
+        }
+    }
+}
+
+impl<W: Write + Send> EventSink for JsonlSink<W> {
+    fn publish(&self, event: &Event) {
+        let mut writer = self.writer.lock().unwrap();
+        if writer.failed {
+            return;
+        }
+
+        let result = write_event(&mut writer.writer, event)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.writer.write_all(b"\n"));
+        if let Err(err) = result {
+            log::error!(
+                "Failed to write outcome event, dropping further events: {}",
+                err
+            );
+            writer.failed = true;
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.writer.lock().unwrap().writer.flush()
+    }
+}
+
+/// Writes `event` as the JSON object [`JsonlSink`] puts on its line.
+pub fn write_event<W: Write>(writer: W, event: &Event) -> serde_json::Result<()> {
+    match event {
+        Event::Outcome(event) => serde_json::to_writer(writer, &EventRecord::from(event)),
+        Event::Client(event) => serde_json::to_writer(writer, &ClientRecord::from(event)),
+        Event::Erasure(event) => serde_json::to_writer(writer, &ErasureRecord::from(event)),
+        Event::Merge(event) => serde_json::to_writer(writer, &MergeRecord::from(event)),
+        Event::Input(event) => serde_json::to_writer(writer, &InputRecord::from(event)),
+    }
+}
+
+/// Publishes every event to several sinks in turn, e.g. a journal and a
+/// webhook.
+#[derive(Default)]
+pub struct FanoutSink {
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl FanoutSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<S: EventSink + 'static>(mut self, sink: S) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+}
+
+impl EventSink for FanoutSink {
+    fn publish(&self, event: &Event) {
+        for sink in &self.sinks {
+            sink.publish(event);
+        }
+    }
+
+    /// Flushes every sink, even after one fails, returning the first
+    /// error.
+    fn flush(&self) -> io::Result<()> {
+        let mut result = Ok(());
+        for sink in &self.sinks {
+            let flushed = sink.flush();
+            result = result.and(flushed);
+        }
+        result
+    }
+}
+
+/// Sends every event into a channel, for consumers in the same process.
+///
+/// The channel is unbounded so that publishing never blocks a processing
+/// task; a consumer that falls behind makes it grow. Events are dropped
+/// once the receiver is gone.
+pub struct ChannelSink {
+    sender: UnboundedSender<Event>,
+}
+
+impl ChannelSink {
+    pub fn new(sender: UnboundedSender<Event>) -> Self {
+        Self { sender }
+    }
+}
+
+impl EventSink for ChannelSink {
+    fn publish(&self, event: &Event) {
+        let _ = self.sender.send(event.clone());
+    }
+}
+
+/// Flat JSON shape of an event, with the balances or the reason depending
+/// on the outcome.
+#[derive(Serialize)]
+struct EventRecord {
+    seq: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    run: Option<RunId>,
+    #[serde(rename = "type")]
+    tx_type: TransactionType,
+    client: u16,
+    tx: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    amount: Option<f64>,
+    /// Reason code of a manual credit or debit, flagging it for audit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    adjustment: Option<AdjustmentReason>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dispute_reason: Option<DisputeReason>,
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<Reason>,
+    /// Stable code of the reason, see [`RejectReason::code`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    risk_score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    held: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locked: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct ClientRecord {
+    seq: u64,
+    client: u16,
+    lifecycle: ClientEventKind,
+}
+
+impl From<&ClientEvent> for ClientRecord {
+    fn from(event: &ClientEvent) -> Self {
+        ClientRecord {
+            seq: event.sequence,
+            client: event.client,
+            lifecycle: event.kind,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErasureRecord {
+    seq: u64,
+    client: u16,
+    lifecycle: ClientEventKind,
+    records: u64,
+    available: f64,
+    total: f64,
+    locked: bool,
+}
+
+impl From<&ErasureEvent> for ErasureRecord {
+    fn from(event: &ErasureEvent) -> Self {
+        let certificate = &event.certificate;
+        ErasureRecord {
+            seq: event.sequence,
+            client: certificate.client,
+            lifecycle: ClientEventKind::Erased,
+            records: certificate.records,
+            available: certificate.available,
+            total: certificate.total,
+            locked: certificate.locked,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MergeRecord {
+    seq: u64,
+    client: u16,
+    lifecycle: ClientEventKind,
+    into: u16,
+}
+
+impl From<&MergeEvent> for MergeRecord {
+    fn from(event: &MergeEvent) -> Self {
+        MergeRecord {
+            seq: event.sequence,
+            client: event.from,
+            lifecycle: ClientEventKind::Merged,
+            into: event.into,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct InputRecord<'a> {
+    seq: u64,
+    input: &'a str,
+    offset: u64,
+    len: u64,
+    blake3: &'a str,
+}
+
+impl<'a> From<&'a InputEvent> for InputRecord<'a> {
+    fn from(event: &'a InputEvent) -> Self {
+        InputRecord {
+            seq: event.sequence,
+            input: &event.digest.source,
+            offset: event.digest.offset,
+            len: event.digest.len,
+            blake3: &event.digest.blake3,
+        }
+    }
+}
+
+/// Just enough of a journal line to tell an [`InputEvent`]'s apart from
+/// those of clients.
+#[derive(Deserialize)]
+struct InputProbe {
+    input: Option<IgnoredAny>,
+}
+
+/// Whether a journal line records an input segment rather than something
+/// that happened to a client, so readers of client events can skip it.
+pub(crate) fn is_input_line(line: &str) -> bool {
+    serde_json::from_str::<InputProbe>(line).is_ok_and(|probe| probe.input.is_some())
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Reason {
+    Rejected(RejectReason),
+    Ignored(IgnoreReason),
+}
+
+impl From<&OutcomeEvent> for EventRecord {
+    fn from(event: &OutcomeEvent) -> Self {
+        let tx = &event.transaction;
+        let mut record = EventRecord {
+            seq: event.sequence,
+            run: event.run,
+            tx_type: tx.tx_type,
+            client: tx.client_id,
+            tx: tx.tx_id,
+            amount: tx.amount,
+            adjustment: tx.reason.filter(|_| tx.tx_type.is_adjustment()),
+            dispute_reason: event.dispute_reason,
+            outcome: "",
+            reason: None,
+            code: event.outcome.code(),
+            risk_score: event.risk_score,
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        };
+
+        match event.outcome {
+            TransactionOutcome::Applied { balances } | TransactionOutcome::Held { balances } => {
+                record.outcome = match event.outcome {
+                    TransactionOutcome::Held { .. } => "held",
+                    _ => "applied",
+                };
+                record.available = Some(balances.available);
+                record.held = Some(balances.held);
+                record.total = Some(balances.total);
+                record.locked = Some(balances.locked);
+            }
+            TransactionOutcome::Rejected { reason } => {
+                record.outcome = "rejected";
+                record.reason = Some(Reason::Rejected(reason));
+            }
+            TransactionOutcome::Ignored { reason } => {
+                record.outcome = "ignored";
+                record.reason = Some(Reason::Ignored(reason));
+            }
+        }
+        record
+    }
+}
+
+/// Opens the `--events` journal at `path`, decompressing it according to
+/// its extension.
+pub(crate) fn open_journal(path: &Path) -> Result<impl BufRead, Box<dyn Error>> {
+    let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    Ok(BufReader::new(Compression::from_path(path).reader(file)?))
+}
+
+/// Parses every line of a journal into `T`, which reads the fields it
+/// needs, skipping blank lines and those of input segments.
+pub(crate) fn parse_journal<T: DeserializeOwned, R: BufRead>(
+    journal: R,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let mut lines = vec![];
+    for (index, line) in journal.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || is_input_line(&line) {
+            continue;
+        }
+        let parsed =
+            serde_json::from_str(&line).map_err(|err| format!("line {}: {}", index + 1, err))?;
+        lines.push(parsed);
+    }
+    Ok(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use std::sync::Arc;
+
+    /// Lets a test read back what a sink wrote.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_jsonl_sink_writes_one_line_per_event() {
+        let buffer = SharedBuffer::default();
+        let engine = PaymentsEngine::builder()
+            .event_sink(JsonlSink::new(buffer.clone()))
+            .build();
+
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.5));
+        engine.apply_transaction(Transaction::new_withdrawal(1, 2, 5.0));
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.5));
+        engine.flush_events().unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"seq":1,"client":1,"lifecycle":"created"}"#,
+                r#"{"seq":2,"type":"deposit","client":1,"tx":1,"amount":2.5,"outcome":"applied","available":2.5,"held":0.0,"total":2.5,"locked":false}"#,
+                r#"{"seq":3,"type":"withdrawal","client":1,"tx":2,"amount":5.0,"outcome":"rejected","reason":"insufficient_funds","code":"E010"}"#,
+                r#"{"seq":4,"type":"deposit","client":1,"tx":1,"amount":2.5,"outcome":"ignored","reason":"duplicate","code":"E001"}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_outcome_events_carry_the_run_id() {
+        let buffer = SharedBuffer::default();
+        let run_id: RunId = "2a".parse().unwrap();
+        let engine = PaymentsEngine::builder()
+            .event_sink(JsonlSink::new(buffer.clone()))
+            .run_id(run_id)
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.5));
+        engine
+            .fork()
+            .apply_transaction(Transaction::new_deposit(1, 2, 1.0));
+        engine.flush_events().unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            output.lines().last().unwrap(),
+            r#"{"seq":2,"run":"0000000000000000000000000000002a","type":"deposit","client":1,"tx":1,"amount":2.5,"outcome":"applied","available":2.5,"held":0.0,"total":2.5,"locked":false}"#
+        );
+        assert_eq!(engine.fork().run_id(), Some(run_id));
+    }
+
+    #[test]
+    fn test_input_segments_are_journaled_and_skipped_by_readers() {
+        let buffer = SharedBuffer::default();
+        let engine = PaymentsEngine::builder()
+            .event_sink(JsonlSink::new(buffer.clone()))
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.5));
+        let digest = InputDigest {
+            source: "in.csv".to_string(),
+            offset: 0,
+            len: 38,
+            blake3: "ab".repeat(32),
+        };
+        engine.record_input(digest.clone());
+        engine.flush_events().unwrap();
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let last = output.lines().last().unwrap();
+        assert_eq!(
+            last,
+            format!(
+                r#"{{"seq":3,"input":"in.csv","offset":0,"len":38,"blake3":"{}"}}"#,
+                digest.blake3
+            )
+        );
+        assert_eq!(engine.inputs(), [digest]);
+
+        #[derive(Deserialize)]
+        struct ClientLine {
+            client: u16,
+        }
+        let lines: Vec<ClientLine> = parse_journal(output.as_bytes()).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.client == 1));
+    }
+
+    #[tokio::test]
+    async fn test_channel_sink_receives_every_event() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let engine = PaymentsEngine::builder()
+            .event_sink(ChannelSink::new(sender))
+            .build();
+
+        engine
+            .handle_transaction(Transaction::new_deposit(1, 1, 1.0))
+            .await;
+        engine
+            .handle_transaction(Transaction::new_dispute(1, 1))
+            .await;
+        engine
+            .handle_transaction(Transaction::new_chargeback(1, 1))
+            .await;
+        drop(engine);
+
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event);
+        }
+        let sequences: Vec<u64> = events.iter().map(Event::sequence).collect();
+        assert_eq!(sequences, [1, 2, 3, 4, 5]);
+        assert!(matches!(
+            events[0],
+            Event::Client(ClientEvent {
+                client: 1,
+                kind: ClientEventKind::Created,
+                ..
+            })
+        ));
+        assert!(matches!(
+            events[2],
+            Event::Outcome(OutcomeEvent {
+                outcome: TransactionOutcome::Applied { balances },
+                ..
+            }) if balances.held == 1.0
+        ));
+        assert!(matches!(
+            events[3],
+            Event::Client(ClientEvent {
+                kind: ClientEventKind::Locked,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,272 @@
+use std::time::SystemTime;
+
+use crate::manifest::utc_date;
+
+/// Separates the fields of a FIX message.
+pub const SOH: u8 = 0x01;
+
+pub const BEGIN_STRING: u32 = 8;
+pub const BODY_LENGTH: u32 = 9;
+pub const CHECK_SUM: u32 = 10;
+pub const MSG_SEQ_NUM: u32 = 34;
+pub const MSG_TYPE: u32 = 35;
+pub const NEW_SEQ_NO: u32 = 36;
+pub const POSS_DUP_FLAG: u32 = 43;
+pub const SENDER_COMP_ID: u32 = 49;
+pub const SENDING_TIME: u32 = 52;
+pub const TARGET_COMP_ID: u32 = 56;
+pub const ORIG_SENDING_TIME: u32 = 122;
+pub const GAP_FILL_FLAG: u32 = 123;
+
+/// Longest body accepted, far above any message a venue sends, so a
+/// corrupt or hostile body length can't make the acceptor buffer without
+/// end.
+pub const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+/// A FIX message: its type and the fields after it, in order. The begin
+/// string, body length and checksum are added when it is encoded, and
+/// checked and left out when it is decoded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    msg_type: String,
+    fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn new(msg_type: &str) -> Self {
+        Self {
+            msg_type: msg_type.to_string(),
+            fields: vec![],
+        }
+    }
+
+    /// The message with `tag` set to `value` after its other fields.
+    pub fn with(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn msg_type(&self) -> &str {
+        &self.msg_type
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = &(u32, String)> {
+        self.fields.iter()
+    }
+
+    /// The value of the first `tag` field.
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == tag)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.get(MSG_SEQ_NUM)?.parse().ok()
+    }
+
+    pub fn is_poss_dup(&self) -> bool {
+        self.get(POSS_DUP_FLAG) == Some("Y")
+    }
+
+    /// Whether the message belongs to the session layer rather than the
+    /// application, so it is never resent.
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self.msg_type.as_str(),
+            "0" | "1" | "2" | "3" | "4" | "5" | "A"
+        )
+    }
+
+    /// The message on the wire, under `begin_string`, e.g. `FIX.4.4`.
+    pub fn encode(&self, begin_string: &str) -> Vec<u8> {
+        let mut body = vec![];
+        push_field(&mut body, MSG_TYPE, &self.msg_type);
+        for (tag, value) in &self.fields {
+            push_field(&mut body, *tag, value);
+        }
+        let mut bytes = vec![];
+        push_field(&mut bytes, BEGIN_STRING, begin_string);
+        push_field(&mut bytes, BODY_LENGTH, &body.len().to_string());
+        bytes.extend_from_slice(&body);
+        let check_sum = format!("{:03}", checksum(&bytes));
+        push_field(&mut bytes, CHECK_SUM, &check_sum);
+        bytes
+    }
+}
+
+fn push_field(bytes: &mut Vec<u8>, tag: u32, value: &str) {
+    bytes.extend_from_slice(tag.to_string().as_bytes());
+    bytes.push(b'=');
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(SOH);
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+/// What the start of a read buffer holds.
+#[derive(Debug, PartialEq)]
+pub enum Decoded {
+    /// A whole message, and the bytes it took.
+    Message(Message, usize),
+    /// The start of a message; more bytes are needed.
+    Incomplete,
+    /// Bytes that aren't a valid message, to be dropped up to the next
+    /// begin string, e.g. after a checksum mismatch.
+    Garbled(usize, String),
+}
+
+/// Decodes the message at the start of `buffer`, checking its begin string
+/// against `begin_string`, its body length and its checksum.
+pub fn decode(buffer: &[u8], begin_string: &str) -> Decoded {
+    let garbled = |reason: String| {
+        // Up to the next message, or all but what may be the start of one
+        let next = find(&buffer[1.min(buffer.len())..], b"8=FIX")
+            .map_or(buffer.len().saturating_sub(4), |at| at + 1);
+        Decoded::Garbled(next.max(1), reason)
+    };
+
+    let prefix = format!("8={}\x019=", begin_string);
+    if buffer.len() < prefix.len() {
+        return match prefix.as_bytes().starts_with(buffer) {
+            true => Decoded::Incomplete,
+            false => garbled("expected a begin string".to_string()),
+        };
+    }
+    if !buffer.starts_with(prefix.as_bytes()) {
+        return garbled(format!("expected {}", begin_string));
+    }
+    let Some(length_end) = find(&buffer[prefix.len()..], &[SOH]).map(|at| prefix.len() + at) else {
+        return match buffer.len() - prefix.len() > 10 {
+            true => garbled("body length too long".to_string()),
+            false => Decoded::Incomplete,
+        };
+    };
+    let Ok(body_length) = std::str::from_utf8(&buffer[prefix.len()..length_end])
+        .unwrap_or_default()
+        .parse::<usize>()
+    else {
+        return garbled("invalid body length".to_string());
+    };
+    if body_length > MAX_BODY_LENGTH {
+        return garbled(format!("body length {} too long", body_length));
+    }
+    let body_start = length_end + 1;
+    // The checksum field is always 10=NNN and a SOH
+    let (Some(body_end), Some(end)) = (
+        body_start.checked_add(body_length),
+        body_start.checked_add(body_length + 7),
+    ) else {
+        return garbled(format!("body length {} too long", body_length));
+    };
+    if buffer.len() < end {
+        return Decoded::Incomplete;
+    }
+    let trailer = &buffer[body_end..end];
+    if !trailer.starts_with(b"10=") || trailer[6] != SOH {
+        return garbled("body length doesn't match".to_string());
+    }
+    let expected = format!("{:03}", checksum(&buffer[..body_end]));
+    if &trailer[3..6] != expected.as_bytes() {
+        return garbled(format!(
+            "checksum {} instead of {}",
+            String::from_utf8_lossy(&trailer[3..6]),
+            expected
+        ));
+    }
+
+    let mut fields = vec![];
+    for field in buffer[body_start..body_end]
+        .split(|byte| *byte == SOH)
+        .filter(|field| !field.is_empty())
+    {
+        let field = String::from_utf8_lossy(field);
+        let parsed = field
+            .split_once('=')
+            .and_then(|(tag, value)| Some((tag.parse::<u32>().ok()?, value.to_string())));
+        match parsed {
+            Some(field) => fields.push(field),
+            None => return Decoded::Garbled(end, format!("invalid field '{}'", field)),
+        }
+    }
+    match fields.first() {
+        Some((MSG_TYPE, _)) => {
+            let (_, msg_type) = fields.remove(0);
+            Decoded::Message(Message { msg_type, fields }, end)
+        }
+        _ => Decoded::Garbled(end, "MsgType isn't the third field".to_string()),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Formats a time as a FIX UTC timestamp with milliseconds, e.g.
+/// `20240501-12:30:00.250`.
+pub fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    let day_seconds = since_epoch.as_secs() % 86_400;
+    let (year, month, day) = utc_date(time);
+    format!(
+        "{:04}{:02}{:02}-{:02}:{:02}:{:02}.{:03}",
+        year,
+        month,
+        day,
+        day_seconds / 3_600,
+        day_seconds % 3_600 / 60,
+        day_seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages_round_trip_and_garbled_ones_are_skipped() {
+        let heartbeat = Message::new("0")
+            .with(SENDER_COMP_ID, "ENGINE")
+            .with(TARGET_COMP_ID, "VENUE")
+            .with(MSG_SEQ_NUM, 2);
+        let bytes = heartbeat.encode("FIX.4.4");
+        assert_eq!(
+            String::from_utf8(bytes.clone())
+                .unwrap()
+                .replace('\x01', "|"),
+            "8=FIX.4.4|9=29|35=0|49=ENGINE|56=VENUE|34=2|10=061|"
+        );
+        assert_eq!(
+            decode(&bytes, "FIX.4.4"),
+            Decoded::Message(heartbeat.clone(), bytes.len())
+        );
+        assert_eq!(decode(&bytes[..20], "FIX.4.4"), Decoded::Incomplete);
+
+        let mut corrupted = bytes.clone();
+        corrupted[25] = b'X';
+        corrupted.extend_from_slice(&bytes);
+        match decode(&corrupted, "FIX.4.4") {
+            Decoded::Garbled(skip, _) => assert_eq!(skip, bytes.len()),
+            other => panic!("{:?}", other),
+        }
+        for length in ["18446744073709551615", "99999999"] {
+            let oversized = format!("8=FIX.4.4\x019={}\x0135=0\x01", length);
+            assert!(matches!(
+                decode(oversized.as_bytes(), "FIX.4.4"),
+                Decoded::Garbled(_, reason) if reason.contains("too long")
+            ));
+        }
+        assert_eq!(
+            timestamp(SystemTime::UNIX_EPOCH + std::time::Duration::from_millis(951_827_696_250)),
+            "20000229-12:34:56.250"
+        );
+    }
+}
@@ -0,0 +1,601 @@
+mod message;
+
+use std::error::Error;
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::currency::{RoundingMode, DEFAULT_DECIMALS};
+use crate::engine::PaymentsEngine;
+use crate::outcome::TransactionOutcome;
+use crate::transactions::{Transaction, TransactionType};
+
+use message::*;
+pub use message::{decode, timestamp, Decoded, Message};
+
+const ACCOUNT: u32 = 1;
+const BEGIN_SEQ_NO: u32 = 7;
+const END_SEQ_NO: u32 = 16;
+const EXEC_ID: u32 = 17;
+const LAST_PX: u32 = 31;
+const LAST_QTY: u32 = 32;
+const REF_SEQ_NUM: u32 = 45;
+const SIDE: u32 = 54;
+const TEXT: u32 = 58;
+const ENCRYPT_METHOD: u32 = 98;
+const HEART_BT_INT: u32 = 108;
+const TEST_REQ_ID: u32 = 112;
+const RESET_SEQ_NUM_FLAG: u32 = 141;
+const EXEC_TYPE: u32 = 150;
+const REF_MSG_TYPE: u32 = 372;
+const BUSINESS_REJECT_REF_ID: u32 = 379;
+const BUSINESS_REJECT_REASON: u32 = 380;
+
+/// Who the session is between and how its trades are numbered.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SessionConfig {
+    /// Version of the protocol, e.g. `FIX.4.4`.
+    pub begin_string: String,
+    /// The engine's CompID.
+    pub sender_comp_id: String,
+    /// The venue's CompID.
+    pub target_comp_id: String,
+    /// Id of the first trade's transaction. Execution ids are free text,
+    /// so trades are numbered in the order they are applied, and this has
+    /// to keep clear of the ids of other inputs.
+    pub first_tx: u32,
+}
+
+/// A message the engine sent, kept to be sent again on a resend request.
+struct Sent {
+    seq: u64,
+    sending_time: String,
+    message: Message,
+}
+
+/// A FIX acceptor applying a trading venue's trades to an engine.
+///
+/// Every `ExecutionReport` of a trade (`ExecType` `F`) becomes a transaction
+/// of the client in `Account`: a withdrawal of `LastQty` × `LastPx` if the
+/// client bought, a deposit if they sold. Other execution reports are order
+/// status and change nothing; trade cancels and corrections, rejected
+/// transactions and other application messages are answered with a
+/// `BusinessMessageReject`, whose `Text` starts with the rejection code of
+/// a rejected transaction, e.g. `E010 insufficient available funds`.
+///
+/// The session layer handles logon, heartbeats and test requests, and
+/// logout. Messages are applied strictly in sequence: a gap is answered
+/// with a `ResendRequest` and later messages are dropped until it is
+/// filled, duplicates are dropped, and a sequence number lower than
+/// expected without `PossDupFlag` ends the session. The venue's resend
+/// requests are served from the messages sent so far, with session-level
+/// ones replaced by a gap fill. Sequence numbers and sent messages last
+/// as long as the acceptor, across reconnects, unless a logon resets them.
+/// One connection is served at a time.
+pub struct FixAcceptor {
+    engine: PaymentsEngine,
+    config: SessionConfig,
+    next_tx: u32,
+    next_in: u64,
+    next_out: u64,
+    sent: Vec<Sent>,
+}
+
+/// How a connection ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SessionEnd {
+    /// Either side logged out, or the connection closed.
+    Disconnected,
+    /// The acceptor was cancelled and logged out.
+    Cancelled,
+}
+
+impl FixAcceptor {
+    pub fn new(engine: PaymentsEngine, config: SessionConfig) -> Self {
+        Self {
+            engine,
+            next_tx: config.first_tx,
+            config,
+            next_in: 1,
+            next_out: 1,
+            sent: vec![],
+        }
+    }
+
+    /// Accepts connections on `listener` one after another until `cancel`
+    /// fires. A connection that fails is logged and the next one accepted.
+    pub async fn run(
+        &mut self,
+        listener: TcpListener,
+        cancel: &CancellationToken,
+    ) -> io::Result<()> {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = cancel.cancelled() => return Ok(()),
+            };
+            log::info!("fix: connection from {}", peer);
+            match self.serve(stream, cancel).await {
+                Ok(SessionEnd::Cancelled) => return Ok(()),
+                Ok(SessionEnd::Disconnected) => log::info!("fix: {} disconnected", peer),
+                Err(err) => log::warn!("fix: session with {} failed: {}", peer, err),
+            }
+        }
+    }
+
+    /// Serves one connection, from the venue's logon to either side's
+    /// logout or the connection closing.
+    pub async fn serve<S: AsyncRead + AsyncWrite + Unpin>(
+        &mut self,
+        mut stream: S,
+        cancel: &CancellationToken,
+    ) -> Result<SessionEnd, Box<dyn Error>> {
+        let mut buffer = Vec::with_capacity(4096);
+        let mut heartbeat = Duration::from_secs(30);
+        let mut logged_on = false;
+        // Highest sequence number seen past a gap being resent
+        let mut resend_until: Option<u64> = None;
+        let mut last_received = Instant::now();
+        let mut last_sent = Instant::now();
+        let mut test_request_sent = false;
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            let message = loop {
+                match decode(&buffer, &self.config.begin_string) {
+                    Decoded::Message(message, length) => {
+                        buffer.drain(..length);
+                        break Some(message);
+                    }
+                    Decoded::Garbled(length, reason) => {
+                        log::warn!("fix: dropped a garbled message: {}", reason);
+                        buffer.drain(..length);
+                    }
+                    Decoded::Incomplete => break None,
+                }
+            };
+            let Some(message) = message else {
+                tokio::select! {
+                    read = stream.read_buf(&mut buffer) => {
+                        if read? == 0 {
+                            return Ok(SessionEnd::Disconnected);
+                        }
+                        last_received = Instant::now();
+                        test_request_sent = false;
+                    }
+                    _ = ticks.tick(), if logged_on => {
+                        let silent = last_received.elapsed();
+                        if silent > heartbeat * 2 {
+                            return Err("no messages from the venue within two heartbeats".into());
+                        }
+                        if silent > heartbeat + heartbeat / 5 && !test_request_sent {
+                            let request = Message::new("1").with(TEST_REQ_ID, self.next_out);
+                            self.send(&mut stream, request).await?;
+                            last_sent = Instant::now();
+                            test_request_sent = true;
+                        } else if last_sent.elapsed() >= heartbeat {
+                            self.send(&mut stream, Message::new("0")).await?;
+                            last_sent = Instant::now();
+                        }
+                    }
+                    _ = cancel.cancelled() => {
+                        if logged_on {
+                            self.send(&mut stream, Message::new("5")).await?;
+                        }
+                        return Ok(SessionEnd::Cancelled);
+                    }
+                }
+                continue;
+            };
+
+            if message.get(SENDER_COMP_ID) != Some(&self.config.target_comp_id)
+                || message.get(TARGET_COMP_ID) != Some(&self.config.sender_comp_id)
+            {
+                return Err(format!(
+                    "message from {} to {} instead of {} to {}",
+                    message.get(SENDER_COMP_ID).unwrap_or_default(),
+                    message.get(TARGET_COMP_ID).unwrap_or_default(),
+                    self.config.target_comp_id,
+                    self.config.sender_comp_id
+                )
+                .into());
+            }
+            let seq = message.seq_num().ok_or("message without MsgSeqNum")?;
+
+            if !logged_on {
+                if message.msg_type() != "A" {
+                    return Err(format!("{} message before logon", message.msg_type()).into());
+                }
+                let reset = message.get(RESET_SEQ_NUM_FLAG) == Some("Y");
+                if reset {
+                    self.next_in = 1;
+                    self.next_out = 1;
+                    self.sent.clear();
+                }
+                if seq < self.next_in {
+                    let text = format!("MsgSeqNum too low, expecting {}", self.next_in);
+                    self.send(&mut stream, Message::new("5").with(TEXT, &text))
+                        .await?;
+                    return Err(text.into());
+                }
+                heartbeat = message
+                    .get(HEART_BT_INT)
+                    .and_then(|seconds| seconds.parse().ok())
+                    .filter(|seconds| *seconds > 0)
+                    .map_or(heartbeat, Duration::from_secs);
+                let mut logon = Message::new("A")
+                    .with(ENCRYPT_METHOD, 0)
+                    .with(HEART_BT_INT, heartbeat.as_secs());
+                if reset {
+                    logon = logon.with(RESET_SEQ_NUM_FLAG, "Y");
+                }
+                self.send(&mut stream, logon).await?;
+                last_sent = Instant::now();
+                logged_on = true;
+                log::info!("fix: {} logged on", self.config.target_comp_id);
+                if seq > self.next_in {
+                    resend_until = Some(seq);
+                    self.request_resend(&mut stream).await?;
+                } else {
+                    self.next_in += 1;
+                }
+                continue;
+            }
+
+            // A sequence reset without gap fill sets the next number
+            // whatever this one's is
+            if message.msg_type() == "4" && message.get(GAP_FILL_FLAG) != Some("Y") {
+                self.reset_sequence(&message)?;
+                continue;
+            }
+            if seq < self.next_in {
+                if message.is_poss_dup() {
+                    continue;
+                }
+                let text = format!("MsgSeqNum {} too low, expecting {}", seq, self.next_in);
+                self.send(&mut stream, Message::new("5").with(TEXT, &text))
+                    .await?;
+                return Err(text.into());
+            }
+            if seq > self.next_in {
+                // A logout is answered even out of sequence
+                if message.msg_type() == "5" {
+                    self.send(&mut stream, Message::new("5")).await?;
+                    return Ok(SessionEnd::Disconnected);
+                }
+                if resend_until.is_none() {
+                    self.request_resend(&mut stream).await?;
+                }
+                resend_until = resend_until.max(Some(seq));
+                continue;
+            }
+
+            self.next_in += 1;
+            if resend_until.is_some_and(|until| self.next_in > until) {
+                resend_until = None;
+            }
+            match message.msg_type() {
+                "0" | "3" | "A" => {}
+                "1" => {
+                    let mut reply = Message::new("0");
+                    if let Some(id) = message.get(TEST_REQ_ID) {
+                        reply = reply.with(TEST_REQ_ID, id);
+                    }
+                    self.send(&mut stream, reply).await?;
+                }
+                "2" => self.resend(&mut stream, &message).await?,
+                "4" => self.reset_sequence(&message)?,
+                "5" => {
+                    self.send(&mut stream, Message::new("5")).await?;
+                    return Ok(SessionEnd::Disconnected);
+                }
+                "8" => {
+                    if let Err(text) = self.apply_execution_report(&message) {
+                        let reject = Message::new("j")
+                            .with(REF_SEQ_NUM, seq)
+                            .with(REF_MSG_TYPE, "8")
+                            .with(
+                                BUSINESS_REJECT_REF_ID,
+                                message.get(EXEC_ID).unwrap_or("N/A"),
+                            )
+                            .with(BUSINESS_REJECT_REASON, 0)
+                            .with(TEXT, text);
+                        self.send(&mut stream, reject).await?;
+                    }
+                }
+                other => {
+                    let reject = Message::new("j")
+                        .with(REF_SEQ_NUM, seq)
+                        .with(REF_MSG_TYPE, other)
+                        .with(BUSINESS_REJECT_REASON, 3)
+                        .with(TEXT, "unsupported message type");
+                    self.send(&mut stream, reject).await?;
+                }
+            }
+            last_sent = Instant::now();
+        }
+    }
+
+    /// Applies the trade of an execution report, or says why it wasn't.
+    fn apply_execution_report(&mut self, message: &Message) -> Result<(), String> {
+        match message.get(EXEC_TYPE) {
+            Some("F") => {}
+            Some("G" | "H") => return Err("trade corrections and cancels aren't applied".into()),
+            _ => return Ok(()),
+        }
+        let field = |tag: u32, name: &str| {
+            message
+                .get(tag)
+                .ok_or_else(|| format!("trade without {}", name))
+        };
+        let client = field(ACCOUNT, "Account")?
+            .parse::<u16>()
+            .map_err(|_| "Account isn't a client id".to_string())?;
+        let tx_type = match field(SIDE, "Side")? {
+            "1" => TransactionType::Withdrawal,
+            "2" => TransactionType::Deposit,
+            side => return Err(format!("Side {} isn't a buy or a sell", side)),
+        };
+        let quantity = field(LAST_QTY, "LastQty")?.parse::<f64>();
+        let price = field(LAST_PX, "LastPx")?.parse::<f64>();
+        let amount = match (quantity, price) {
+            (Ok(quantity), Ok(price)) if quantity * price >= 0.0 => {
+                RoundingMode::HalfUp.round(quantity * price, DEFAULT_DECIMALS)
+            }
+            _ => return Err("LastQty and LastPx aren't a trade's amounts".to_string()),
+        };
+        let tx_id = self.next_tx;
+        self.next_tx = tx_id
+            .checked_add(1)
+            .ok_or("transaction ids ran out, restart with a lower first id")?;
+        let tx = Transaction {
+            tx_type,
+            client_id: client,
+            tx_id,
+            amount: Some(amount),
+            reason: None,
+            dispute_reason: None,
+        };
+        match self.engine.apply_transaction(tx) {
+            TransactionOutcome::Applied { .. } | TransactionOutcome::Held { .. } => Ok(()),
+            TransactionOutcome::Rejected { reason } => Err(format!("{} {}", reason.code(), reason)),
+            TransactionOutcome::Ignored { reason } => Err(format!("{} {}", reason.code(), reason)),
+        }
+    }
+
+    fn reset_sequence(&mut self, message: &Message) -> Result<(), String> {
+        let new_seq = message
+            .get(NEW_SEQ_NO)
+            .and_then(|seq| seq.parse::<u64>().ok())
+            .ok_or("SequenceReset without NewSeqNo")?;
+        // Numbers can't go back; a gap fill's own number has been counted
+        if new_seq < self.next_in {
+            return Err(format!(
+                "SequenceReset to {} while expecting {}",
+                new_seq, self.next_in
+            ));
+        }
+        self.next_in = new_seq;
+        Ok(())
+    }
+
+    async fn request_resend<S: AsyncWrite + Unpin>(&mut self, stream: &mut S) -> io::Result<()> {
+        let request = Message::new("2")
+            .with(BEGIN_SEQ_NO, self.next_in)
+            .with(END_SEQ_NO, 0);
+        self.send(stream, request).await
+    }
+
+    /// Sends the messages a resend request asks for again: application
+    /// messages as they were, with `PossDupFlag`, and runs of session-level
+    /// ones as a gap fill.
+    async fn resend<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        request: &Message,
+    ) -> io::Result<()> {
+        let seq = |tag| {
+            request
+                .get(tag)
+                .and_then(|seq| seq.parse::<u64>().ok())
+                .unwrap_or_default()
+        };
+        let begin = seq(BEGIN_SEQ_NO).max(1);
+        let end = match seq(END_SEQ_NO) {
+            0 => self.next_out - 1,
+            end => end.min(self.next_out - 1),
+        };
+        let mut bytes = vec![];
+        let mut gap_from = None;
+        for number in begin..=end {
+            let stored = self.sent.iter().find(|sent| sent.seq == number);
+            let Some(sent) = stored else {
+                gap_from.get_or_insert(number);
+                continue;
+            };
+            if let Some(from) = gap_from.take() {
+                bytes.extend(self.gap_fill(from, number));
+            }
+            let message = header(&self.config, sent.seq, true, &sent.message)
+                .with(ORIG_SENDING_TIME, &sent.sending_time);
+            bytes.extend(with_body(message, &sent.message).encode(&self.config.begin_string));
+        }
+        if let Some(from) = gap_from {
+            bytes.extend(self.gap_fill(from, end + 1));
+        }
+        stream.write_all(&bytes).await?;
+        stream.flush().await
+    }
+
+    fn gap_fill(&self, from: u64, to: u64) -> Vec<u8> {
+        let reset = Message::new("4")
+            .with(GAP_FILL_FLAG, "Y")
+            .with(NEW_SEQ_NO, to);
+        with_body(header(&self.config, from, true, &reset), &reset)
+            .encode(&self.config.begin_string)
+    }
+
+    /// Sends `message` with the next sequence number, keeping application
+    /// messages for resends.
+    async fn send<S: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+        message: Message,
+    ) -> io::Result<()> {
+        let seq = self.next_out;
+        self.next_out += 1;
+        let header = header(&self.config, seq, false, &message);
+        let sending_time = header.get(SENDING_TIME).unwrap_or_default().to_string();
+        stream
+            .write_all(&with_body(header, &message).encode(&self.config.begin_string))
+            .await?;
+        if !message.is_admin() {
+            self.sent.push(Sent {
+                seq,
+                sending_time,
+                message,
+            });
+        }
+        stream.flush().await
+    }
+}
+
+/// The standard header of an outgoing `message`, sent now.
+fn header(config: &SessionConfig, seq: u64, poss_dup: bool, message: &Message) -> Message {
+    let header = Message::new(message.msg_type())
+        .with(SENDER_COMP_ID, &config.sender_comp_id)
+        .with(TARGET_COMP_ID, &config.target_comp_id)
+        .with(MSG_SEQ_NUM, seq)
+        .with(SENDING_TIME, timestamp(SystemTime::now()));
+    match poss_dup {
+        true => header.with(POSS_DUP_FLAG, "Y"),
+        false => header,
+    }
+}
+
+fn with_body(mut header: Message, message: &Message) -> Message {
+    for (tag, value) in message.fields() {
+        header = header.with(*tag, value);
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads messages off the venue's end until one of `msg_type`.
+    async fn expect<S: AsyncRead + Unpin>(
+        stream: &mut S,
+        buffer: &mut Vec<u8>,
+        msg_type: &str,
+    ) -> Message {
+        loop {
+            if let Decoded::Message(message, length) = decode(buffer, "FIX.4.4") {
+                buffer.drain(..length);
+                if message.msg_type() == msg_type {
+                    return message;
+                }
+                continue;
+            }
+            assert_ne!(
+                stream.read_buf(buffer).await.unwrap(),
+                0,
+                "closed before {}",
+                msg_type
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trades_are_applied_in_sequence_and_rejects_resent() {
+        let engine = PaymentsEngine::new();
+        let mut acceptor = FixAcceptor::new(
+            engine.clone(),
+            SessionConfig {
+                begin_string: "FIX.4.4".to_string(),
+                sender_comp_id: "ENGINE".to_string(),
+                target_comp_id: "VENUE".to_string(),
+                first_tx: 100,
+            },
+        );
+        let (mut venue, engine_end) = tokio::io::duplex(4096);
+        let cancel = CancellationToken::new();
+        let session = tokio::spawn(async move {
+            let end = acceptor.serve(engine_end, &cancel).await.unwrap();
+            (acceptor, end)
+        });
+
+        let send = |seq: u64, message: Message| {
+            with_body(
+                Message::new(message.msg_type())
+                    .with(SENDER_COMP_ID, "VENUE")
+                    .with(TARGET_COMP_ID, "ENGINE")
+                    .with(MSG_SEQ_NUM, seq),
+                &message,
+            )
+            .encode("FIX.4.4")
+        };
+        let trade = |side: u32, qty: &str, px: &str| {
+            Message::new("8")
+                .with(EXEC_ID, "E1")
+                .with(ACCOUNT, 7)
+                .with(SIDE, side)
+                .with(EXEC_TYPE, "F")
+                .with(LAST_QTY, qty)
+                .with(LAST_PX, px)
+        };
+        let mut buffer = vec![];
+
+        venue
+            .write_all(&send(1, Message::new("A").with(HEART_BT_INT, 30)))
+            .await
+            .unwrap();
+        expect(&mut venue, &mut buffer, "A").await;
+        venue
+            .write_all(&send(2, trade(2, "10", "2.5")))
+            .await
+            .unwrap();
+        // 3 goes missing, so 4 asks for it to be resent
+        venue.write_all(&send(4, trade(1, "1", "3"))).await.unwrap();
+        let request = expect(&mut venue, &mut buffer, "2").await;
+        assert_eq!(request.get(BEGIN_SEQ_NO), Some("3"));
+        venue
+            .write_all(&send(3, trade(1, "100", "1")))
+            .await
+            .unwrap();
+        let reject = expect(&mut venue, &mut buffer, "j").await;
+        assert_eq!(reject.get(REF_SEQ_NUM), Some("3"));
+        assert_eq!(reject.get(TEXT), Some("E010 insufficient available funds"));
+        venue.write_all(&send(4, trade(1, "1", "3"))).await.unwrap();
+
+        // The venue missed everything; only the reject comes back as is
+        venue
+            .write_all(&send(
+                5,
+                Message::new("2").with(BEGIN_SEQ_NO, 1).with(END_SEQ_NO, 0),
+            ))
+            .await
+            .unwrap();
+        let fill = expect(&mut venue, &mut buffer, "4").await;
+        assert_eq!((fill.seq_num(), fill.get(NEW_SEQ_NO)), (Some(1), Some("3")));
+        let resent = expect(&mut venue, &mut buffer, "j").await;
+        assert!(resent.is_poss_dup());
+        assert_eq!(resent.seq_num(), reject.seq_num());
+
+        venue.write_all(&send(6, Message::new("5"))).await.unwrap();
+        expect(&mut venue, &mut buffer, "5").await;
+        let (acceptor, end) = session.await.unwrap();
+        assert_eq!(end, SessionEnd::Disconnected);
+        assert_eq!(acceptor.next_in, 7);
+        let mut report = vec![];
+        engine.write_report(&mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked\n7,22.0000,0.0000,22.0000,false\n"
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::error::Error;
+
+use roxmltree::{Document, Node};
+
+use super::Importer;
+use crate::transactions::TransactionType;
+
+/// Imports a pain.001 or camt.053 message, told apart by the message
+/// element under `Document`. Elements are matched by local name, so any
+/// version of either message is read.
+///
+/// Every transfer of a pain.001 is a withdrawal of its payment's debtor
+/// account. Every booked entry of a camt.053 is a deposit if it credits
+/// the statement's account and a withdrawal if it debits it; pending
+/// entries are left out. All amounts must be in one currency.
+pub fn parse(xml: &str, importer: &mut Importer) -> Result<(), Box<dyn Error>> {
+    let document = Document::parse(xml)?;
+    let message = document
+        .root_element()
+        .children()
+        .find(Node::is_element)
+        .ok_or("empty ISO 20022 document")?;
+    let mut currency = None;
+    match message.tag_name().name() {
+        "CstmrCdtTrfInitn" => {
+            for payment in elements(message, "PmtInf") {
+                let account = account_id(child(payment, "DbtrAcct")?)?;
+                for transfer in elements(payment, "CdtTrfTxInf") {
+                    let amount = child(child(transfer, "Amt")?, "InstdAmt")?;
+                    check_currency(amount, &mut currency)?;
+                    importer.push(TransactionType::Withdrawal, account, text(amount)?)?;
+                }
+            }
+        }
+        "BkToCstmrStmt" => {
+            for statement in elements(message, "Stmt") {
+                let account = account_id(child(statement, "Acct")?)?;
+                for entry in elements(statement, "Ntry") {
+                    let status = child(entry, "Sts")?;
+                    // Statuses are a code element since version 8, plain
+                    // text before
+                    let status = match elements(status, "Cd").next() {
+                        Some(code) => text(code)?,
+                        None => text(status)?,
+                    };
+                    if status != "BOOK" {
+                        continue;
+                    }
+                    let tx_type = match text(child(entry, "CdtDbtInd")?)? {
+                        "CRDT" => TransactionType::Deposit,
+                        "DBIT" => TransactionType::Withdrawal,
+                        other => return Err(format!("unknown CdtDbtInd '{}'", other).into()),
+                    };
+                    let amount = child(entry, "Amt")?;
+                    check_currency(amount, &mut currency)?;
+                    importer.push(tx_type, account, text(amount)?)?;
+                }
+            }
+        }
+        other => return Err(format!("unsupported ISO 20022 message {}", other).into()),
+    }
+    Ok(())
+}
+
+fn elements<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Result<Node<'a, 'input>, String> {
+    node.children()
+        .find(|child| child.is_element() && child.tag_name().name() == name)
+        .ok_or_else(|| {
+            format!(
+                "{} without {} at byte {}",
+                node.tag_name().name(),
+                name,
+                node.range().start
+            )
+        })
+}
+
+fn text<'a>(node: Node<'a, '_>) -> Result<&'a str, String> {
+    node.text().map(str::trim).ok_or_else(|| {
+        format!(
+            "empty {} at byte {}",
+            node.tag_name().name(),
+            node.range().start
+        )
+    })
+}
+
+/// An account's IBAN, or its other identification.
+fn account_id<'a>(account: Node<'a, '_>) -> Result<&'a str, String> {
+    let id = child(account, "Id")?;
+    match elements(id, "IBAN").next() {
+        Some(iban) => text(iban),
+        None => text(child(child(id, "Othr")?, "Id")?),
+    }
+}
+
+fn check_currency<'a>(amount: Node<'a, '_>, currency: &mut Option<&'a str>) -> Result<(), String> {
+    let ccy = amount
+        .attribute("Ccy")
+        .ok_or_else(|| format!("amount without currency at byte {}", amount.range().start))?;
+    match currency {
+        Some(first) if *first != ccy => Err(format!(
+            "amounts in both {} and {}, import each currency separately",
+            first, ccy
+        )),
+        _ => {
+            *currency = Some(ccy);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::AccountMap;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_statement_entries_become_deposits_and_withdrawals() {
+        let accounts: AccountMap = vec![("DE89370400440532013000".to_string(), 7)]
+            .into_iter()
+            .collect();
+        let statement = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Acct><Id><IBAN>DE89370400440532013000</IBAN></Id></Acct>
+      <Ntry>
+        <Amt Ccy="EUR">120.50</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">20.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">5.25</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+        let mut importer = Importer::new(&accounts, 1000);
+        parse(statement, &mut importer).unwrap();
+        assert_eq!(
+            importer.finish(),
+            [
+                Transaction::new_deposit(7, 1000, 120.5),
+                Transaction::new_withdrawal(7, 1001, 5.25),
+            ]
+        );
+
+        let transfer = r#"<Document><CstmrCdtTrfInitn><PmtInf>
+            <DbtrAcct><Id><Othr><Id>0042</Id></Othr></Id></DbtrAcct>
+            <CdtTrfTxInf><Amt><InstdAmt Ccy="EUR">1.00</InstdAmt></Amt></CdtTrfTxInf>
+        </PmtInf></CstmrCdtTrfInitn></Document>"#;
+        let err = parse(transfer, &mut Importer::new(&accounts, 1)).unwrap_err();
+        assert_eq!(err.to_string(), "account 0042 has no client");
+    }
+}
@@ -0,0 +1,190 @@
+#[cfg(feature = "iso20022")]
+pub mod iso20022;
+pub mod ofx;
+pub mod qif;
+#[cfg(not(feature = "iso20022"))]
+mod iso20022 {
+    use std::error::Error;
+
+    pub fn parse(_: &str, _: &mut super::Importer) -> Result<(), Box<dyn Error>> {
+        Err("ISO 20022 import needs the iso20022 feature".into())
+    }
+}
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::iter::FromIterator;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::transactions::{Transaction, TransactionType};
+
+/// Bank file formats that can be imported as deposits and withdrawals.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ImportFormat {
+    /// ISO 20022 XML: pain.001 credit transfer initiations, whose transfers
+    /// are withdrawals of the debtor account, and camt.053 statements,
+    /// whose booked entries are deposits or withdrawals. Needs the
+    /// `iso20022` feature.
+    Iso20022,
+    /// OFX bank and credit card statements, SGML or XML.
+    Ofx,
+    /// QIF exports of bank, cash, credit card and other asset or liability
+    /// accounts.
+    Qif,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "iso20022" => Ok(ImportFormat::Iso20022),
+            "ofx" => Ok(ImportFormat::Ofx),
+            "qif" => Ok(ImportFormat::Qif),
+            _ => Err(format!(
+                "unknown import format '{}', expected iso20022, ofx or qif",
+                s
+            )),
+        }
+    }
+}
+
+/// The client each bank account belongs to, since bank files identify
+/// accounts by IBAN or a bank's own account number rather than by client.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AccountMap {
+    clients: HashMap<String, u16>,
+}
+
+#[derive(Deserialize)]
+struct AccountRow {
+    account: String,
+    client: u16,
+}
+
+impl AccountMap {
+    /// Reads a CSV file with `account` and `client` columns.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(File::open(path)?)
+    }
+
+    pub fn parse<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut clients = HashMap::new();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for row in reader.deserialize() {
+            let row: AccountRow = row?;
+            clients.insert(row.account, row.client);
+        }
+        Ok(Self { clients })
+    }
+
+    /// The client of `account`, failing for accounts without one so no
+    /// entry is silently left out.
+    pub fn client(&self, account: &str) -> Result<u16, String> {
+        self.clients
+            .get(account)
+            .copied()
+            .ok_or_else(|| format!("account {} has no client", account))
+    }
+}
+
+impl FromIterator<(String, u16)> for AccountMap {
+    fn from_iter<I: IntoIterator<Item = (String, u16)>>(clients: I) -> Self {
+        Self {
+            clients: clients.into_iter().collect(),
+        }
+    }
+}
+
+/// Builds the transactions of an import. Bank references are free text,
+/// so transactions are numbered in file order from a first id instead,
+/// which must keep clear of the ids of other inputs.
+pub struct Importer<'a> {
+    accounts: &'a AccountMap,
+    next_tx: u32,
+    transactions: Vec<Transaction>,
+}
+
+impl<'a> Importer<'a> {
+    pub fn new(accounts: &'a AccountMap, first_tx: u32) -> Self {
+        Self {
+            accounts,
+            next_tx: first_tx,
+            transactions: vec![],
+        }
+    }
+
+    /// Adds a deposit or withdrawal of `amount` for the client of
+    /// `account`.
+    pub fn push(
+        &mut self,
+        tx_type: TransactionType,
+        account: &str,
+        amount: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let amount = match amount.trim().parse::<f64>() {
+            Ok(amount) if amount.is_finite() && amount >= 0.0 => amount,
+            _ => return Err(format!("'{}' is not an amount", amount).into()),
+        };
+        let tx_id = self.next_tx;
+        self.next_tx = tx_id
+            .checked_add(1)
+            .ok_or("transaction ids ran out, import with a lower first id")?;
+        self.transactions.push(Transaction {
+            tx_type,
+            client_id: self.accounts.client(account)?,
+            tx_id,
+            amount: Some(amount),
+            reason: None,
+            dispute_reason: None,
+        });
+        Ok(())
+    }
+
+    /// Adds a deposit for a positive `amount` and a withdrawal for a
+    /// negative one, as statements sign their entries.
+    pub fn push_signed(&mut self, account: &str, amount: &str) -> Result<(), Box<dyn Error>> {
+        let amount = amount.trim();
+        match amount.strip_prefix('-') {
+            Some(debit) => self.push(TransactionType::Withdrawal, account, debit),
+            None => self.push(
+                TransactionType::Deposit,
+                account,
+                amount.strip_prefix('+').unwrap_or(amount),
+            ),
+        }
+    }
+
+    pub fn finish(self) -> Vec<Transaction> {
+        self.transactions
+    }
+}
+
+/// Reads the bank file at `path` as `format`, with its accounts' clients
+/// from `accounts` and transactions numbered from `first_tx`. A QIF file
+/// without an `!Account` block is taken to be the account named like the
+/// file, without its extension.
+pub fn read(
+    format: ImportFormat,
+    path: &Path,
+    accounts: &AccountMap,
+    first_tx: u32,
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let mut importer = Importer::new(accounts, first_tx);
+    match format {
+        ImportFormat::Iso20022 => iso20022::parse(&fs::read_to_string(path)?, &mut importer)?,
+        ImportFormat::Ofx => ofx::parse(&fs::read_to_string(path)?, &mut importer)?,
+        ImportFormat::Qif => {
+            let account = path.file_stem().unwrap_or_default().to_string_lossy();
+            qif::parse(&fs::read_to_string(path)?, &account, &mut importer)?
+        }
+    }
+    Ok(importer.finish())
+}
@@ -0,0 +1,66 @@
+use std::error::Error;
+
+use super::Importer;
+
+/// Imports the statement transactions of an OFX file: each `STMTTRN` is a
+/// deposit or a withdrawal of the account last named by an `ACCTID`, as
+/// its `TRNAMT` is positive or negative.
+///
+/// Reads OFX 1.x SGML, whose leaf elements aren't closed, as well as OFX
+/// 2.x XML, by tags alone. The header, bank and card statements alike, and
+/// anything but those three elements are passed over.
+pub fn parse(text: &str, importer: &mut Importer) -> Result<(), Box<dyn Error>> {
+    let mut account: Option<&str> = None;
+    // Amount of the transaction being read, if inside one
+    let mut transaction: Option<Option<&str>> = None;
+    for element in text.split('<').skip(1) {
+        let (tag, value) = element
+            .split_once('>')
+            .ok_or_else(|| format!("unterminated tag <{}", element.trim()))?;
+        let value = value.trim();
+        match (tag.trim().to_ascii_uppercase().as_str(), &mut transaction) {
+            // Transfers name their other account inside the transaction
+            ("ACCTID", None) => account = Some(value),
+            ("STMTTRN", _) => transaction = Some(None),
+            ("TRNAMT", Some(amount)) => *amount = Some(value),
+            ("/STMTTRN", Some(amount)) => {
+                let account = account.ok_or("STMTTRN before any ACCTID")?;
+                let amount = amount.ok_or("STMTTRN without TRNAMT")?;
+                importer.push_signed(account, amount)?;
+                transaction = None;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::AccountMap;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_sgml_statement_transactions_are_imported() {
+        let accounts: AccountMap = vec![("000123456".to_string(), 3)].into_iter().collect();
+        let ofx = "OFXHEADER:100\nDATA:OFXSGML\nVERSION:102\n\n\
+            <OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><CURDEF>USD\n\
+            <BANKACCTFROM><BANKID>121000248<ACCTID>000123456<ACCTTYPE>CHECKING</BANKACCTFROM>\n\
+            <BANKTRANLIST>\n\
+            <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240105<TRNAMT>250.00<FITID>A1</STMTTRN>\n\
+            <STMTTRN><TRNTYPE>XFER<TRNAMT>-40.25<FITID>A2\n\
+            <BANKACCTTO><BANKID>1<ACCTID>999</BANKACCTTO></STMTTRN>\n\
+            </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>\n";
+
+        let mut importer = Importer::new(&accounts, 1);
+        parse(ofx, &mut importer).unwrap();
+        assert_eq!(
+            importer.finish(),
+            [
+                Transaction::new_deposit(3, 1, 250.0),
+                Transaction::new_withdrawal(3, 2, 40.25),
+            ]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use std::error::Error;
+
+use super::Importer;
+
+/// Account types whose transactions are imported. Investment, category,
+/// class and memorized transaction lists are passed over.
+const ACCOUNT_TYPES: [&str; 5] = ["Bank", "Cash", "CCard", "Oth A", "Oth L"];
+
+/// Imports the transactions of a QIF file: each record is a deposit or a
+/// withdrawal as its `T` amount is positive or negative. Records belong to
+/// the account named by the last `!Account` block, or to `account` before
+/// any. Thousands separators in amounts are ignored; split lines are, too,
+/// since the total covers them.
+pub fn parse(text: &str, account: &str, importer: &mut Importer) -> Result<(), Box<dyn Error>> {
+    let mut account = account.to_string();
+    let mut in_account_block = false;
+    let mut importing = false;
+    let mut amount: Option<String> = None;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if let Some(header) = line.strip_prefix('!') {
+            match header.split_once(':') {
+                Some(("Type", kind)) => {
+                    importing = ACCOUNT_TYPES.contains(&kind.trim());
+                    in_account_block = false;
+                }
+                // Options such as !Option:AutoSwitch change nothing here
+                Some(_) => {}
+                None => in_account_block = header == "Account",
+            }
+            amount = None;
+            continue;
+        }
+        let mut chars = line.chars();
+        let (code, value) = match chars.next() {
+            Some(code) => (code, chars.as_str().trim()),
+            None => continue,
+        };
+        match code {
+            'N' if in_account_block => account = value.to_string(),
+            // Newer exports repeat the amount as U
+            'T' | 'U' if importing && !in_account_block => {
+                amount.get_or_insert_with(|| value.replace(',', ""));
+            }
+            '^' if in_account_block => in_account_block = false,
+            '^' => {
+                if let Some(amount) = amount.take() {
+                    importer
+                        .push_signed(&account, &amount)
+                        .map_err(|err| format!("line {}: {}", index + 1, err))?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::import::AccountMap;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_records_are_imported_under_their_account() {
+        let accounts: AccountMap = vec![("checking".to_string(), 1), ("Savings".to_string(), 2)]
+            .into_iter()
+            .collect();
+        let qif = "!Type:Bank\n\
+            D01/05/2024\nT1,250.00\nU1,250.00\nPSalary\n^\n\
+            D01/06/2024\nT-40.25\nSRent\n$-40.25\n^\n\
+            !Account\nNSavings\nTBank\n^\n\
+            !Type:Bank\nD01/07/2024\nT10.00\n^\n\
+            !Type:Cat\nNGroceries\nE\n^\n";
+
+        let mut importer = Importer::new(&accounts, 1);
+        parse(qif, "checking", &mut importer).unwrap();
+        assert_eq!(
+            importer.finish(),
+            [
+                Transaction::new_deposit(1, 1, 1250.0),
+                Transaction::new_withdrawal(1, 2, 40.25),
+                Transaction::new_deposit(2, 3, 10.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::engine::PaymentsEngine;
+use crate::outcome::Balances;
+use crate::transactions::TransactionStatus;
+
+/// Slack allowed when comparing balances: half of the report's precision,
+/// so rounding noise from accumulating `f64`s isn't flagged.
+const TOLERANCE: f64 = 0.000_05;
+
+/// A rule the engine's state must satisfy after any sequence of
+/// transactions, in any order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// `total` isn't `available + held`.
+    Unbalanced {
+        client: u16,
+        available: f64,
+        held: f64,
+        total: f64,
+    },
+    NegativeHeld {
+        client: u16,
+        held: f64,
+    },
+    /// The account is locked although none of its transactions was
+    /// charged back, nor did a custom type's handler lock it.
+    LockedWithoutChargeback {
+        client: u16,
+    },
+    /// In strict checks, `available` is below zero, or below minus the
+    /// client's overdraft limit.
+    NegativeAvailable {
+        client: u16,
+        available: f64,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Unbalanced {
+                client,
+                available,
+                held,
+                total,
+            } => write!(
+                f,
+                "client {}: total {} != available {} + held {}",
+                client, total, available, held
+            ),
+            Violation::NegativeHeld { client, held } => {
+                write!(f, "client {}: held {} is negative", client, held)
+            }
+            Violation::LockedWithoutChargeback { client } => {
+                write!(f, "client {}: locked without a chargeback", client)
+            }
+            Violation::NegativeAvailable { client, available } => {
+                write!(
+                    f,
+                    "client {}: available {} is below its floor",
+                    client, available
+                )
+            }
+        }
+    }
+}
+
+impl Error for Violation {}
+
+/// Checks every client of `engine` against the invariants, returning all
+/// violations found.
+///
+/// Meant for property tests of any processing path: apply arbitrary
+/// transactions however the path applies them, then check the result.
+pub fn check(engine: &PaymentsEngine) -> Result<(), Vec<Violation>> {
+    let charged_back: HashSet<u16> = engine
+        .transactions()
+        .iter()
+        .filter(|tx| tx.status() == TransactionStatus::Chargeback)
+        .map(|tx| tx.client_id())
+        .collect();
+
+    let mut violations = vec![];
+    for client in engine.clients().iter() {
+        if (client.total - (client.available + client.held)).abs() > TOLERANCE {
+            violations.push(Violation::Unbalanced {
+                client: client.id,
+                available: client.available,
+                held: client.held,
+                total: client.total,
+            });
+        }
+        if client.held < -TOLERANCE {
+            violations.push(Violation::NegativeHeld {
+                client: client.id,
+                held: client.held,
+            });
+        }
+        if client.locked && !client.frozen && !charged_back.contains(&client.id) {
+            violations.push(Violation::LockedWithoutChargeback { client: client.id });
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        violations.sort_by_key(|violation| match violation {
+            Violation::Unbalanced { client, .. }
+            | Violation::NegativeHeld { client, .. }
+            | Violation::LockedWithoutChargeback { client }
+            | Violation::NegativeAvailable { client, .. } => *client,
+        });
+        Err(violations)
+    }
+}
+
+/// Invariants the engine checks on a client's balances after every
+/// transaction it applies, set with
+/// [`EngineBuilder::check_invariants`](crate::engine::EngineBuilder::check_invariants).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InvariantChecks {
+    pub on_violation: OnViolation,
+    /// Also require `available` to stay at or above zero, or minus the
+    /// client's overdraft limit. Disputes of funds already withdrawn and
+    /// debits break this legitimately.
+    pub strict: bool,
+}
+
+/// What the engine does when a transaction breaks an invariant.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OnViolation {
+    /// Logs an error and carries on; see
+    /// [`PaymentsEngine::invariant_violation_count`].
+    Alert,
+    /// Panics, stopping processing at the transaction that broke it.
+    Abort,
+}
+
+impl FromStr for OnViolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alert" => Ok(OnViolation::Alert),
+            "abort" => Ok(OnViolation::Abort),
+            _ => Err(format!("unknown action '{}', expected alert or abort", s)),
+        }
+    }
+}
+
+/// Checks one client's balances, right after a transaction, against the
+/// invariants that don't need the rest of the engine's state. With
+/// `floor`, `available` must not be below it.
+pub fn check_balances(balances: &Balances, floor: Option<f64>) -> Vec<Violation> {
+    let mut violations = vec![];
+    if (balances.total - (balances.available + balances.held)).abs() > TOLERANCE {
+        violations.push(Violation::Unbalanced {
+            client: balances.client,
+            available: balances.available,
+            held: balances.held,
+            total: balances.total,
+        });
+    }
+    if balances.held < -TOLERANCE {
+        violations.push(Violation::NegativeHeld {
+            client: balances.client,
+            held: balances.held,
+        });
+    }
+    if floor.is_some_and(|floor| balances.available < floor - TOLERANCE) {
+        violations.push(Violation::NegativeAvailable {
+            client: balances.client,
+            available: balances.available,
+        });
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::Simulation;
+    use crate::transactions::Transaction;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_in_order_processing_keeps_the_invariants(
+            transactions in vec(any::<Transaction>(), 0..200)
+        ) {
+            let engine = PaymentsEngine::new();
+            for tx in &transactions {
+                engine.apply_transaction(*tx);
+            }
+
+            prop_assert_eq!(check(&engine), Ok(()));
+        }
+
+        #[test]
+        fn test_any_interleaving_keeps_the_invariants(
+            transactions in vec(any::<Transaction>(), 0..200),
+            seed in any::<u64>(),
+        ) {
+            let engine = Simulation::new(seed).run(&PaymentsEngine::builder(), &transactions);
+
+            prop_assert_eq!(check(&engine), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_reports_violations() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 2.0));
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 2.0));
+        engine.clients().get_mut(&1).unwrap().total = 3.0;
+        engine.clients().get_mut(&2).unwrap().locked = true;
+
+        assert_eq!(
+            check(&engine),
+            Err(vec![
+                Violation::Unbalanced {
+                    client: 1,
+                    available: 2.0,
+                    held: 0.0,
+                    total: 3.0,
+                },
+                Violation::LockedWithoutChargeback { client: 2 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_balances_are_checked_against_the_floor() {
+        let balances = Balances {
+            client: 4,
+            available: -1.0,
+            held: 3.0,
+            total: 2.0,
+            locked: false,
+        };
+
+        assert_eq!(check_balances(&balances, None), []);
+        assert_eq!(check_balances(&balances, Some(-1.0)), []);
+        assert_eq!(
+            check_balances(&balances, Some(0.0)),
+            [Violation::NegativeAvailable {
+                client: 4,
+                available: -1.0
+            }]
+        );
+    }
+}
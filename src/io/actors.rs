@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use futures::future::join_all;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::task::JoinHandle;
+
+use crate::engine::PaymentsEngine;
+use crate::transactions::Transaction;
+
+/// Dispatches between pruning the actors that went idle, so the map of
+/// mailboxes doesn't keep every client ever seen.
+const PRUNE_EVERY: u32 = 4096;
+
+/// Settings of the actor-per-client execution model, see [`ClientActors`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ActorConfig {
+    /// How long an actor waits for its client's next transaction before it
+    /// hibernates, i.e. its task ends and its mailbox is freed.
+    pub idle_timeout: Duration,
+    /// Transactions an actor's mailbox holds before dispatching to it
+    /// waits.
+    pub mailbox_capacity: usize,
+}
+
+impl Default for ActorConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(1),
+            mailbox_capacity: 1024,
+        }
+    }
+}
+
+/// Applies transactions through one actor per active client instead of a
+/// task per transaction.
+///
+/// Each client's transactions go to its actor's mailbox, and the actor, a
+/// task of its own, applies them one at a time in the order they were
+/// dispatched. An actor is spawned when its client's first transaction
+/// arrives and hibernates once it has been idle for the configured
+/// timeout; the client's next transaction wakes a new one, which starts
+/// only after the old one has applied everything it had queued. So every
+/// client's transactions are applied serially and in order, and no two
+/// tasks ever contend for the same client's entry, which a hot client
+/// makes likely with a task per transaction. Different clients still share
+/// the engine's maps, and their shard locks.
+///
+/// A full mailbox makes dispatching wait, which pushes back on the
+/// producer in place of the engine's memory watermark.
+pub struct ClientActors {
+    engine: PaymentsEngine,
+    config: ActorConfig,
+    actors: HashMap<u16, (mpsc::Sender<Transaction>, JoinHandle<()>)>,
+    dispatched: u32,
+}
+
+impl ClientActors {
+    pub fn new(engine: PaymentsEngine, config: ActorConfig) -> Self {
+        Self {
+            engine,
+            config,
+            actors: HashMap::new(),
+            dispatched: 0,
+        }
+    }
+
+    /// Queues `tx` for its client's actor, waking one if there is none.
+    pub async fn dispatch(&mut self, tx: Transaction) {
+        self.dispatched = self.dispatched.wrapping_add(1);
+        if self.dispatched.is_multiple_of(PRUNE_EVERY) {
+            self.actors.retain(|_, (_, actor)| !actor.is_finished());
+        }
+
+        let client = self.engine.remapped(tx).client_id;
+        let previous = match self.actors.remove(&client) {
+            Some((mailbox, actor)) => match mailbox.send(tx).await {
+                Ok(()) => {
+                    self.actors.insert(client, (mailbox, actor));
+                    return;
+                }
+                // Hibernating: the actor stopped taking transactions
+                Err(_) => Some(actor),
+            },
+            None => None,
+        };
+
+        let (mailbox, receiver) = mpsc::channel(self.config.mailbox_capacity.max(1));
+        // A fresh mailbox has room for one
+        let _ = mailbox.try_send(tx);
+        let engine = self.engine.clone();
+        let idle_timeout = self.config.idle_timeout;
+        let actor = tokio::spawn(async move {
+            if let Some(previous) = previous {
+                // Whatever the previous actor still had queued comes first
+                let _ = previous.await;
+            }
+            run(engine, receiver, idle_timeout).await;
+        });
+        self.actors.insert(client, (mailbox, actor));
+    }
+
+    /// Number of actors awake or still finishing their mailbox.
+    pub fn active(&self) -> usize {
+        self.actors
+            .values()
+            .filter(|(_, actor)| !actor.is_finished())
+            .count()
+    }
+
+    /// Waits until every dispatched transaction has been applied.
+    pub async fn finish(self) {
+        let actors = self.actors.into_values().map(|(mailbox, actor)| {
+            drop(mailbox);
+            actor
+        });
+        join_all(actors).await;
+    }
+}
+
+async fn run(engine: PaymentsEngine, mut mailbox: mpsc::Receiver<Transaction>, idle: Duration) {
+    loop {
+        match tokio::time::timeout(idle, mailbox.recv()).await {
+            Ok(Some(tx)) => {
+                engine.apply_transaction(tx);
+            }
+            Ok(None) => return,
+            Err(_) => break,
+        }
+    }
+    // Refuse new transactions, then apply those that made it in meanwhile
+    mailbox.close();
+    loop {
+        match mailbox.try_recv() {
+            Ok(tx) => {
+                engine.apply_transaction(tx);
+            }
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_actors_apply_each_clients_transactions_in_order() {
+        let engine = PaymentsEngine::new();
+        let mut actors = ClientActors::new(
+            engine.clone(),
+            ActorConfig {
+                idle_timeout: Duration::from_millis(1),
+                mailbox_capacity: 4,
+            },
+        );
+        // Each withdrawal only succeeds once the deposit before it is in
+        for round in 0..300u32 {
+            for client in 0..8u16 {
+                let id = (round * 8 + client as u32) * 2;
+                actors
+                    .dispatch(Transaction::new_deposit(client, id, 1.0))
+                    .await;
+                actors
+                    .dispatch(Transaction::new_withdrawal(client, id + 1, 1.0))
+                    .await;
+            }
+            if round.is_multiple_of(100) {
+                // Long enough for the actors to hibernate
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                assert_eq!(actors.active(), 0);
+            }
+        }
+        actors.finish().await;
+
+        assert_eq!(engine.rejected_count(), 0);
+        for client in 0..8u16 {
+            assert_eq!(engine.clients().get(&client).unwrap().available, 0.0);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::error::Error;
+
+use csv::ByteRecord;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+use crate::io::{csv_reader, MalformedRows};
+use crate::transactions::{CsvColumns, Transaction};
+
+/// Bytes of whole rows read per chunk before they are parsed.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Reads transactions from a CSV input on tokio's async IO.
+///
+/// Whole rows are read in chunks of about 64 KiB and each chunk is parsed
+/// with the same reused-[`ByteRecord`] path as the synchronous reader, so
+/// the only await points are the reads themselves. Rows are split on
+/// newlines, so quoted fields spanning several lines are not supported.
+/// Malformed rows are skipped unless [`MalformedRows::Reject`] is set.
+pub struct AsyncTransactionReader<R> {
+    reader: BufReader<R>,
+    columns: CsvColumns,
+    chunk: Vec<u8>,
+    record: ByteRecord,
+    lines_read: u64,
+    malformed_rows: MalformedRows,
+    skipped: u64,
+    rows: RowRange,
+    rows_read: u64,
+    done: bool,
+}
+
+/// Which data rows of an input to process, e.g. to bisect a large file.
+/// Rows are counted from 1 after the header, malformed ones included.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RowRange {
+    /// Rows to pass over before the first one processed.
+    pub skip: u64,
+    /// Rows to process at most; reading stops after them.
+    pub limit: Option<u64>,
+}
+
+impl RowRange {
+    pub(super) fn end(&self) -> Option<u64> {
+        self.limit.map(|limit| self.skip.saturating_add(limit))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncTransactionReader<R> {
+    pub async fn new(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut reader = BufReader::new(reader);
+        let mut header = vec![];
+        reader.read_until(b'\n', &mut header).await?;
+        let columns = CsvColumns::from_headers(csv_reader(header.as_slice()).byte_headers()?)?;
+
+        Ok(Self {
+            reader,
+            columns,
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            record: ByteRecord::new(),
+            lines_read: 1,
+            malformed_rows: MalformedRows::default(),
+            skipped: 0,
+            rows: RowRange::default(),
+            rows_read: 0,
+            done: false,
+        })
+    }
+
+    pub fn malformed_rows(mut self, policy: MalformedRows) -> Self {
+        self.malformed_rows = policy;
+        self
+    }
+
+    /// Only reads the given range of rows. Rows before it aren't parsed, so
+    /// malformed ones there go unreported.
+    pub fn rows(mut self, rows: RowRange) -> Self {
+        self.rows = rows;
+        self
+    }
+
+    /// Number of malformed rows skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Number of data rows passed so far, counted from the row after the
+    /// header and including skipped and malformed ones; rows read ahead
+    /// past the end of the range aren't counted.
+    pub fn rows_read(&self) -> u64 {
+        self.rows
+            .end()
+            .map_or(self.rows_read, |end| self.rows_read.min(end))
+    }
+
+    /// Appends the transactions of the next chunk to `transactions`.
+    /// Returns false once the input is exhausted.
+    pub async fn read_chunk(
+        &mut self,
+        transactions: &mut Vec<Transaction>,
+    ) -> Result<bool, Box<dyn Error>> {
+        if self.done {
+            return Ok(false);
+        }
+        self.chunk.clear();
+        while self.chunk.len() < CHUNK_SIZE {
+            if self.reader.read_until(b'\n', &mut self.chunk).await? == 0 {
+                break;
+            }
+        }
+        if self.chunk.is_empty() {
+            return Ok(false);
+        }
+
+        let first_line = self.lines_read;
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(self.chunk.as_slice());
+        loop {
+            let read = reader.read_byte_record(&mut self.record);
+            if !matches!(read, Ok(false)) {
+                self.rows_read += 1;
+                if self.rows.end().is_some_and(|end| self.rows_read > end) {
+                    self.done = true;
+                    break;
+                }
+                if self.rows_read <= self.rows.skip {
+                    continue;
+                }
+            }
+            match read {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    // Reading from a slice can't fail on IO, so this is a
+                    // malformed row
+                    let line = first_line + err.position().map_or(0, |pos| pos.line());
+                    self.malformed_rows.handle(
+                        format_args!("line {}", line),
+                        &err,
+                        &mut self.skipped,
+                    )?;
+                    continue;
+                }
+            }
+            // The csv crate skips trimming the very first record of a
+            // headerless reader, so trim explicitly
+            self.record.trim();
+            match Transaction::from_byte_record(&self.record, &self.columns) {
+                Ok(tx) => transactions.push(tx),
+                Err(err) => {
+                    let line = first_line + self.record.position().map_or(0, |pos| pos.line());
+                    self.malformed_rows.handle(
+                        format_args!("line {}", line),
+                        &err,
+                        &mut self.skipped,
+                    )?;
+                }
+            }
+        }
+        self.lines_read += self.chunk.iter().filter(|byte| **byte == b'\n').count() as u64;
+
+        Ok(!self.done)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reads_every_row_across_chunks() {
+        let mut input = String::from("type, client, tx, amount\n");
+        for tx in 0..10_000 {
+            input.push_str(&format!("deposit, 1, {}, 1.0\n", tx));
+        }
+
+        let mut reader = AsyncTransactionReader::new(input.as_bytes()).await.unwrap();
+        let mut transactions = vec![];
+        let mut chunks = 0;
+        while reader.read_chunk(&mut transactions).await.unwrap() {
+            chunks += 1;
+        }
+
+        assert!(chunks > 1);
+        assert_eq!(transactions.len(), 10_000);
+        assert_eq!(transactions[9_999].tx_id, 9_999);
+    }
+
+    #[tokio::test]
+    async fn test_errors_report_the_line() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nbogus,1,2,1.0\n";
+
+        let mut reader = AsyncTransactionReader::new(input.as_bytes())
+            .await
+            .unwrap()
+            .malformed_rows(MalformedRows::Reject);
+        let err = reader.read_chunk(&mut vec![]).await.unwrap_err();
+
+        assert_eq!(err.to_string(), "line 3: unknown transaction type 'bogus'");
+    }
+
+    #[tokio::test]
+    async fn test_malformed_rows_are_skipped_by_default() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,1.0\n\
+                     bogus,1,2,1.0\n\
+                     deposit,1\n\
+                     deposit,1,3,NaN\n\
+                     deposit,1,4,2.0\n";
+
+        let mut reader = AsyncTransactionReader::new(input.as_bytes()).await.unwrap();
+        let mut transactions = vec![];
+        while reader.read_chunk(&mut transactions).await.unwrap() {}
+
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[1].tx_id, 4);
+        assert_eq!(reader.skipped(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_row_range_reads_only_the_given_rows() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=10_000 {
+            input.push_str(&format!("deposit,1,{},1.0\n", tx));
+        }
+        // Malformed, but before the range
+        input.insert_str(22, "bogus\n");
+
+        let mut reader = AsyncTransactionReader::new(input.as_bytes())
+            .await
+            .unwrap()
+            .rows(RowRange {
+                skip: 5_001,
+                limit: Some(3_000),
+            });
+        let mut transactions = vec![];
+        while reader.read_chunk(&mut transactions).await.unwrap() {}
+
+        assert_eq!(reader.skipped(), 0);
+        assert_eq!(transactions.len(), 3_000);
+        assert_eq!(transactions[0].tx_id, 5_001);
+        assert_eq!(transactions[2_999].tx_id, 8_000);
+        assert_eq!(reader.rows_read(), 8_001);
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::sim::SimRng;
+
+/// Which failures to inject, and how often.
+///
+/// Rates are probabilities between 0 and 1, drawn per write or read call.
+#[derive(Clone, Debug, Default)]
+pub struct ChaosConfig {
+    pub seed: u64,
+    /// Chance that a write to a storage or report sink fails.
+    pub write_error_rate: f64,
+    /// Extra time every sink flush takes.
+    pub flush_delay: Duration,
+    /// Chance that a read from an input drops the connection.
+    pub read_drop_rate: f64,
+}
+
+/// Source of injected failures, shared by every reader and writer it wraps
+/// so that one seed drives the whole run.
+///
+/// Meant for verifying that retry and recovery paths work before relying
+/// on them; wrapping a reader or writer with a default config injects
+/// nothing.
+#[derive(Clone)]
+pub struct Chaos {
+    config: ChaosConfig,
+    rng: Arc<Mutex<SimRng>>,
+}
+
+impl Chaos {
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            rng: Arc::new(Mutex::new(SimRng::new(config.seed))),
+            config,
+        }
+    }
+
+    pub fn writer<W: Write>(&self, inner: W) -> ChaosWriter<W> {
+        ChaosWriter {
+            inner,
+            chaos: self.clone(),
+        }
+    }
+
+    pub fn reader<R: AsyncRead + Unpin>(&self, inner: R) -> ChaosReader<R> {
+        ChaosReader {
+            inner,
+            chaos: self.clone(),
+        }
+    }
+
+    fn strikes(&self, rate: f64) -> bool {
+        rate > 0.0 && self.rng.lock().unwrap().next_f64() < rate
+    }
+}
+
+/// Sink whose writes fail and whose flushes stall as configured.
+pub struct ChaosWriter<W> {
+    inner: W,
+    chaos: Chaos,
+}
+
+impl<W: Write> Write for ChaosWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.chaos.strikes(self.chaos.config.write_error_rate) {
+            return Err(io::Error::other("injected write failure"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.chaos.config.flush_delay.is_zero() {
+            thread::sleep(self.chaos.config.flush_delay);
+        }
+        self.inner.flush()
+    }
+}
+
+/// Input whose reads drop the connection as configured.
+pub struct ChaosReader<R> {
+    inner: R,
+    chaos: Chaos,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for ChaosReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.chaos.strikes(self.chaos.config.read_drop_rate) {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "injected connection drop",
+            )));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[test]
+    fn test_write_errors_follow_the_rate() {
+        let chaos = Chaos::new(ChaosConfig {
+            write_error_rate: 1.0,
+            ..Default::default()
+        });
+        let err = chaos.writer(vec![]).write_all(b"row").unwrap_err();
+        assert_eq!(err.to_string(), "injected write failure");
+
+        let mut writer = Chaos::new(ChaosConfig::default()).writer(vec![]);
+        writer.write_all(b"row").unwrap();
+        assert_eq!(writer.inner, b"row");
+    }
+
+    #[tokio::test]
+    async fn test_reads_drop_the_connection() {
+        let chaos = Chaos::new(ChaosConfig {
+            read_drop_rate: 1.0,
+            ..Default::default()
+        });
+        let err = chaos
+            .reader(&b"type,client,tx,amount\n"[..])
+            .read_to_end(&mut vec![])
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+/// Compression applied to an output such as the report or the event log.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Compression implied by the file extension: `.gz` for gzip and `.zst`
+    /// for zstd, none otherwise.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wraps `inner` so everything written to it is compressed.
+    pub fn writer<W: Write>(self, inner: W) -> io::Result<CompressedWriter<W>> {
+        let encoder = match self {
+            Compression::None => Encoder::Plain(inner),
+            Compression::Gzip => {
+                Encoder::Gzip(GzEncoder::new(inner, flate2::Compression::default()))
+            }
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(inner, 0)?),
+        };
+        Ok(CompressedWriter { encoder })
+    }
+
+    /// Wraps `inner` so everything read from it is decompressed.
+    pub fn reader<'a, R: Read + 'a>(self, inner: R) -> io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::None => Box::new(inner),
+            Compression::Gzip => Box::new(MultiGzDecoder::new(inner)),
+            Compression::Zstd => Box::new(zstd::Decoder::new(inner)?),
+        })
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "unknown compression '{}', expected none, gzip or zstd",
+                s
+            )),
+        }
+    }
+}
+
+/// Writer compressing into `W`.
+///
+/// The compressed stream is only complete once [`finish`](Self::finish) is
+/// called. Dropping the writer finishes it too, but any error is lost, so
+/// call `finish` where the outcome matters.
+pub struct CompressedWriter<W: Write> {
+    encoder: Encoder<W>,
+}
+
+enum Encoder<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    /// Writes the end of the compressed stream and flushes it. Further
+    /// calls do nothing.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(inner) => inner.flush(),
+            Encoder::Gzip(encoder) => encoder.try_finish(),
+            Encoder::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.encoder {
+            Encoder::Plain(inner) => inner.write(buf),
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.encoder {
+            Encoder::Plain(inner) => inner.flush(),
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl<W: Write> Drop for CompressedWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        let mut writer = compression.writer(&mut output).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        output
+    }
+
+    #[test]
+    fn test_compressed_output_round_trips() {
+        let data = "client,available,held,total,locked\n1,1.0000,0.0000,1.0000,false\n".repeat(100);
+
+        let gzip = compress(Compression::Gzip, data.as_bytes());
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&gzip[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+        assert!(gzip.len() < data.len());
+
+        let zstd = compress(Compression::Zstd, data.as_bytes());
+        assert_eq!(zstd::decode_all(&zstd[..]).unwrap(), data.as_bytes());
+
+        assert_eq!(
+            compress(Compression::None, data.as_bytes()),
+            data.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_compression_follows_the_extension() {
+        assert_eq!(
+            Compression::from_path(Path::new("accounts.csv.gz")),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_path(Path::new("events.jsonl.zst")),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::from_path(Path::new("accounts.csv")),
+            Compression::None
+        );
+    }
+}
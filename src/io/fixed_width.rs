@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use csv::ByteRecord;
+use serde::Deserialize;
+
+use crate::io::MalformedRows;
+use crate::transactions::{Transaction, TransactionType};
+
+/// Where the fields of a fixed-width record sit, as banks describe their
+/// mainframe extracts: each field's first column, counted from 1, and its
+/// width in bytes. Read from JSON, e.g.
+///
+/// ```json
+/// {
+///   "type": { "start": 1, "width": 3 },
+///   "client": { "start": 4, "width": 5 },
+///   "tx": { "start": 9, "width": 10 },
+///   "amount": { "start": 19, "width": 12 },
+///   "implied_decimals": 2,
+///   "type_codes": { "DEP": "deposit", "WDL": "withdrawal" }
+/// }
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FixedWidthLayout {
+    #[serde(rename = "type")]
+    pub tx_type: FieldSpan,
+    pub client: FieldSpan,
+    pub tx: FieldSpan,
+    #[serde(default)]
+    pub amount: Option<FieldSpan>,
+    /// Reason codes of adjustments and disputes, as in the CSV `reason`
+    /// column.
+    #[serde(default)]
+    pub reason: Option<FieldSpan>,
+    /// Digits of the amount field after an implied decimal point, e.g. 2
+    /// to read `000000012345` as 123.45. Amounts with a point of their own
+    /// are read as they are.
+    #[serde(default)]
+    pub implied_decimals: usize,
+    /// Codes the type field uses instead of the transaction type names.
+    /// Codes without an entry are read as type names.
+    #[serde(default)]
+    pub type_codes: HashMap<String, TransactionType>,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct FieldSpan {
+    pub start: usize,
+    pub width: usize,
+}
+
+impl FieldSpan {
+    /// The field's bytes in `line`, cut short or empty if the line is.
+    fn slice(self, line: &[u8]) -> &[u8] {
+        let start = (self.start - 1).min(line.len());
+        let end = (start + self.width).min(line.len());
+        &line[start..end]
+    }
+}
+
+impl FixedWidthLayout {
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let layout: FixedWidthLayout = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let spans = [
+            Some(layout.tx_type),
+            Some(layout.client),
+            Some(layout.tx),
+            layout.amount,
+            layout.reason,
+        ];
+        if spans
+            .iter()
+            .flatten()
+            .any(|span| span.start == 0 || span.width == 0)
+        {
+            return Err("fields start at column 1 and are at least 1 wide".into());
+        }
+        Ok(layout)
+    }
+
+    /// Cuts `line` into a record of the standard CSV columns, with type
+    /// codes translated and the implied decimal point put in.
+    fn record(&self, line: &[u8], record: &mut ByteRecord) {
+        record.clear();
+        let tx_type = self.tx_type.slice(line).trim_ascii();
+        match std::str::from_utf8(tx_type)
+            .ok()
+            .and_then(|code| self.type_codes.get(code))
+        {
+            Some(tx_type) => record.push_field(tx_type.as_str().as_bytes()),
+            None => record.push_field(tx_type),
+        }
+        record.push_field(self.client.slice(line));
+        record.push_field(self.tx.slice(line));
+
+        let amount = self
+            .amount
+            .map_or(&[][..], |span| span.slice(line).trim_ascii());
+        if self.implied_decimals > 0 && !amount.is_empty() && !amount.contains(&b'.') {
+            let point = amount.len().saturating_sub(self.implied_decimals);
+            let mut with_point = Vec::with_capacity(amount.len() + 2);
+            with_point.push(b'0');
+            with_point.extend_from_slice(&amount[..point]);
+            with_point.push(b'.');
+            with_point.extend(std::iter::repeat_n(
+                b'0',
+                self.implied_decimals - (amount.len() - point),
+            ));
+            with_point.extend_from_slice(&amount[point..]);
+            record.push_field(&with_point);
+        } else {
+            record.push_field(amount);
+        }
+        record.push_field(self.reason.map_or(&[][..], |span| span.slice(line)));
+    }
+}
+
+/// Reads transactions out of fixed-width records, one per line, cut into
+/// fields by a [`FixedWidthLayout`] and parsed like CSV rows. Blank lines
+/// are passed over; records that don't parse, such as header and trailer
+/// records, are malformed rows.
+pub struct FixedWidthReader<R> {
+    reader: R,
+    layout: FixedWidthLayout,
+    line: Vec<u8>,
+    record: ByteRecord,
+    lines_read: u64,
+    malformed_rows: MalformedRows,
+    skipped: u64,
+}
+
+impl<R: BufRead> FixedWidthReader<R> {
+    pub fn new(reader: R, layout: FixedWidthLayout) -> Self {
+        Self {
+            reader,
+            layout,
+            line: vec![],
+            record: ByteRecord::new(),
+            lines_read: 0,
+            malformed_rows: MalformedRows::default(),
+            skipped: 0,
+        }
+    }
+
+    pub fn malformed_rows(mut self, policy: MalformedRows) -> Self {
+        self.malformed_rows = policy;
+        self
+    }
+
+    /// Number of malformed rows skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Lines read so far, blank and malformed ones included.
+    pub fn lines_read(&self) -> u64 {
+        self.lines_read
+    }
+}
+
+impl<R: BufRead> Iterator for FixedWidthReader<R> {
+    type Item = Result<Transaction, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => self.lines_read += 1,
+                Err(err) => return Some(Err(err.into())),
+            }
+            let line = self
+                .line
+                .strip_suffix(b"\n")
+                .map_or(&self.line[..], |line| {
+                    line.strip_suffix(b"\r").unwrap_or(line)
+                });
+            if line.trim_ascii().is_empty() {
+                continue;
+            }
+            self.layout.record(line, &mut self.record);
+            let err = match Transaction::parse_csv_record(&self.record) {
+                Ok(tx) => return Some(Ok(tx)),
+                Err(err) => err,
+            };
+            if let Err(err) = self.malformed_rows.handle(
+                format_args!("line {}", self.lines_read),
+                &err,
+                &mut self.skipped,
+            ) {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_are_cut_by_the_layout() {
+        let layout: FixedWidthLayout = serde_json::from_str(
+            r#"{
+                "type": { "start": 1, "width": 3 },
+                "client": { "start": 4, "width": 5 },
+                "tx": { "start": 9, "width": 6 },
+                "amount": { "start": 15, "width": 8 },
+                "implied_decimals": 2,
+                "type_codes": { "DEP": "deposit", "WDL": "withdrawal" }
+            }"#,
+        )
+        .unwrap();
+        let input = "HDR BANK EXTRACT 2019\n\
+                     DEP00007000001 0012345\n\
+                     \n\
+                     WDL00007000002      50\r\n\
+                     DSP00007000001\n";
+
+        let mut reader = FixedWidthReader::new(input.as_bytes(), layout);
+        let transactions: Vec<Transaction> = (&mut reader).map(Result::unwrap).collect();
+        assert_eq!(
+            transactions,
+            [
+                Transaction::new_deposit(7, 1, 123.45),
+                Transaction::new_withdrawal(7, 2, 0.5),
+            ]
+        );
+        assert_eq!(reader.skipped(), 2);
+        assert_eq!(reader.lines_read(), 5);
+    }
+}
@@ -0,0 +1,65 @@
+use std::io::{self, Write};
+
+use crate::io::ReportLayout;
+use crate::processor::ClientDb;
+
+/// Writes the client report as a JSON array with one object per client,
+/// keyed by column name. Amounts keep the layout's decimal places, and
+/// each object is on its own line so the output still diffs and greps like
+/// the CSV report. The layout's header setting doesn't apply.
+pub fn write_json<W: Write>(
+    clients_db: &ClientDb,
+    layout: ReportLayout,
+    destination: W,
+) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(destination);
+    writer.write_all(b"[")?;
+    for (i, client) in clients_db.iter().enumerate() {
+        writer.write_all(if i == 0 { b"\n{" } else { b",\n{" })?;
+        let cells = layout.cells(&client);
+        for (j, (name, cell)) in layout.columns.names().zip(&cells).enumerate() {
+            if j > 0 {
+                writer.write_all(b",")?;
+            }
+            // Column names are plain identifiers and every cell is a
+            // number or a boolean, so nothing needs escaping
+            write!(writer, "\"{}\":{}", name, cell)?;
+        }
+        writer.write_all(b"}")?;
+    }
+    writer.write_all(b"\n]\n")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::io::ReportColumns;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_json_report_is_an_array_of_clients() {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 1.5));
+        let layout = ReportLayout {
+            columns: ReportColumns::Extended,
+            ..ReportLayout::default()
+        };
+
+        let mut report = vec![];
+        write_json(engine.clients(), layout, &mut report).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&report).unwrap();
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "[\n{\"client\":1,\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false,\
+             \"transactions\":1,\"disputes\":0,\"deposited\":1.5000,\"withdrawn\":0.0000,\"last_activity\":1,\"adjusted\":0.0000}\n]\n"
+        );
+        assert_eq!(value[0]["available"], 1.5);
+
+        let mut empty = vec![];
+        write_json(PaymentsEngine::new().clients(), layout, &mut empty).unwrap();
+        assert_eq!(serde_json::from_slice::<Vec<()>>(&empty).unwrap(), []);
+    }
+}
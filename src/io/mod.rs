@@ -1,47 +1,929 @@
+pub mod actors;
+mod async_reader;
+pub mod chaos;
+mod compress;
+mod fixed_width;
+mod json;
+pub mod partitioned;
+pub mod provenance;
+mod schema;
+mod sharded;
+mod sink;
+pub mod soak;
+mod table;
+pub mod tail;
+pub mod watch;
+
 use futures::future::join_all;
+use std::collections::VecDeque;
 use std::error::Error;
-use std::fs::File;
-use std::io::{self, BufReader};
-use std::sync::Arc;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
 
-use dashmap::DashMap;
+use csv::ByteRecord;
+use tokio::io::AsyncRead;
+use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::currency::Precision;
+use crate::engine::PaymentsEngine;
+use crate::io::actors::ClientActors;
+use crate::io::provenance::SegmentHasher;
+use crate::processor::{Client, ClientDb, TransactionsDb};
+use crate::screening::ScreeningHit;
+use crate::transactions::{CsvColumns, DisputeReason, Transaction};
+
+pub use async_reader::{AsyncTransactionReader, RowRange};
+pub use compress::{CompressedWriter, Compression};
+pub use fixed_width::{FieldSpan, FixedWidthLayout, FixedWidthReader};
+pub use json::write_json;
+pub use schema::{
+    ColumnSchema, ColumnType, ReportColumns, ReportLayout, ReportSchema, REPORT_SCHEMA_VERSION,
+};
+pub use sharded::{write_sharded_csv, ShardBy};
+pub use sink::{CsvReport, JsonReport, ReportSink, TableReport};
+pub use table::write_table;
+
+/// Whether an input was read to the end or stopped through its
+/// cancellation token.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ReadOutcome {
+    Completed,
+    Cancelled,
+}
+
+/// How far [`read_csv_rows`] got through its input.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ReadProgress {
+    pub outcome: ReadOutcome,
+    /// Data rows passed from the start of the input, skipped and malformed
+    /// ones included, e.g. to resume after them later.
+    pub rows: u64,
+}
+
+/// Processes a CSV file on tokio's async IO, stopping promptly once `cancel`
+/// fires. Transactions already dispatched are still applied before this
+/// returns.
+pub async fn read_csv(
+    engine: &PaymentsEngine,
+    filename: &str,
+    cancel: &CancellationToken,
+) -> Result<ReadOutcome, Box<dyn Error>> {
+    let progress = read_csv_rows(engine, filename, RowRange::default(), cancel).await?;
+    Ok(progress.outcome)
+}
+
+/// [`read_csv`] limited to a range of rows.
+pub async fn read_csv_rows(
+    engine: &PaymentsEngine,
+    filename: &str,
+    rows: RowRange,
+    cancel: &CancellationToken,
+) -> Result<ReadProgress, Box<dyn Error>> {
+    // Opening a named pipe waits for a writer, and reading its header for
+    // the writer's first row, so both have to give way to ctrl-c
+    let hasher = SegmentHasher::new(Path::new(filename), 0);
+    let opened = async {
+        let file = tokio::fs::File::open(filename).await?;
+        AsyncTransactionReader::new(hasher.reader(file)).await
+    };
+    let reader = tokio::select! {
+        biased;
+        _ = cancel.cancelled() => {
+            return Ok(ReadProgress {
+                outcome: ReadOutcome::Cancelled,
+                rows: 0,
+            });
+        }
+        reader = opened => reader?.rows(rows),
+    };
+    let progress = process_transaction_reader(engine, reader, cancel).await?;
+    engine.record_input(hasher.digest());
+    Ok(progress)
+}
+
+/// Whether `path` is a named pipe (FIFO), which can only be read once, from
+/// start to end, and has no length to split or fingerprint.
+pub fn is_fifo(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        std::fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+/// [`read_csv_rows`] for a file of fixed-width records cut into fields by
+/// `layout`. Rows are the file's lines, counted from 1.
+pub async fn read_fixed_width_rows(
+    engine: &PaymentsEngine,
+    path: &Path,
+    layout: FixedWidthLayout,
+    rows: RowRange,
+    cancel: &CancellationToken,
+) -> Result<ReadProgress, Box<dyn Error>> {
+    let hasher = SegmentHasher::new(path, 0);
+    let file = io::BufReader::new(hasher.reader(std::fs::File::open(path)?));
+    let mut reader = FixedWidthReader::new(file, layout);
+    let mut dispatcher = Dispatcher::with_capacity(engine, engine.expected_transactions());
+    let end = rows.end().unwrap_or(u64::MAX);
+
+    let mut outcome = ReadOutcome::Completed;
+    while reader.lines_read() < end {
+        if cancel.is_cancelled() {
+            outcome = ReadOutcome::Cancelled;
+            break;
+        }
+        let Some(tx) = reader.next() else {
+            break;
+        };
+        let tx = tx?;
+        // Blank and malformed lines are read past inside the reader, so the
+        // transaction may sit after the end
+        let line = reader.lines_read();
+        if line > rows.skip && line <= end {
+            dispatcher.dispatch(tx).await;
+        }
+    }
 
-use crate::processor::{self, Client};
-use crate::transactions::{Transaction, TransactionWithStatus};
+    dispatcher.finish().await;
+    engine.record_malformed_rows(reader.skipped());
+    engine.record_input(hasher.digest());
+    Ok(ReadProgress {
+        outcome,
+        rows: reader.lines_read().min(end),
+    })
+}
+
+pub async fn process_async<R: AsyncRead + Unpin>(
+    engine: &PaymentsEngine,
+    reader: R,
+    cancel: &CancellationToken,
+) -> Result<ReadOutcome, Box<dyn Error>> {
+    let reader = AsyncTransactionReader::new(reader).await?;
+    let progress = process_transaction_reader(engine, reader, cancel).await?;
+    Ok(progress.outcome)
+}
+
+async fn process_transaction_reader<R: AsyncRead + Unpin>(
+    engine: &PaymentsEngine,
+    mut reader: AsyncTransactionReader<R>,
+    cancel: &CancellationToken,
+) -> Result<ReadProgress, Box<dyn Error>> {
+    let mut dispatcher = Dispatcher::with_capacity(engine, engine.expected_transactions());
+    let mut transactions = vec![];
+
+    loop {
+        let more = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => {
+                dispatcher.finish().await;
+                engine.record_malformed_rows(reader.skipped());
+                return Ok(ReadProgress {
+                    outcome: ReadOutcome::Cancelled,
+                    rows: reader.rows_read(),
+                });
+            }
+            more = reader.read_chunk(&mut transactions) => more?,
+        };
 
-pub async fn read_csv(filename: &str) -> Result<(), Box<dyn Error>> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
-    let client_db = Arc::new(DashMap::<u16, Client>::new());
-    let transactions_db = Arc::new(DashMap::<u32, TransactionWithStatus>::new());
+        for tx in transactions.drain(..) {
+            dispatcher.dispatch(tx).await;
+        }
+        if !more {
+            break;
+        }
+    }
+
+    dispatcher.finish().await;
+    engine.record_malformed_rows(reader.skipped());
+    Ok(ReadProgress {
+        outcome: ReadOutcome::Completed,
+        rows: reader.rows_read(),
+    })
+}
+
+/// Reads every transaction of a CSV file without applying any of them, so a
+/// file with a malformed row can be rejected as a whole. Returns `None` if
+/// cancelled.
+pub async fn parse_file(
+    path: &Path,
+    cancel: &CancellationToken,
+) -> Result<Option<Vec<Transaction>>, Box<dyn Error>> {
+    parse_reader(tokio::fs::File::open(path).await?, cancel).await
+}
 
-    let mut reader = csv::ReaderBuilder::new()
+/// Like [`parse_file`], for any async input.
+pub async fn parse_reader<R: AsyncRead + Unpin>(
+    reader: R,
+    cancel: &CancellationToken,
+) -> Result<Option<Vec<Transaction>>, Box<dyn Error>> {
+    let mut reader = AsyncTransactionReader::new(reader)
+        .await?
+        .malformed_rows(MalformedRows::Reject);
+    let mut transactions = vec![];
+
+    loop {
+        let more = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => return Ok(None),
+            more = reader.read_chunk(&mut transactions) => more?,
+        };
+        if !more {
+            return Ok(Some(transactions));
+        }
+    }
+}
+
+fn csv_reader<R: io::Read>(reader: R) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
         .trim(csv::Trim::All)
-        .from_reader(reader);
+        .from_reader(reader)
+}
+
+/// What a reader does with a row that can't be parsed.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum MalformedRows {
+    /// Warn on stderr and carry on with the next row, so one bad row can't
+    /// abort a whole run.
+    #[default]
+    Skip,
+    /// Stop with an error naming the row, e.g. to reject a file as a whole.
+    Reject,
+}
+
+impl MalformedRows {
+    /// Applies the policy to the malformed row at `location`, e.g.
+    /// "line 3", counting it in `skipped` if it is skipped.
+    fn handle(
+        self,
+        location: fmt::Arguments,
+        err: &dyn fmt::Display,
+        skipped: &mut u64,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            MalformedRows::Skip => {
+                log::warn!("Skipping malformed row at {}: {}", location, err);
+                *skipped += 1;
+                Ok(())
+            }
+            MalformedRows::Reject => Err(format!("{}: {}", location, err).into()),
+        }
+    }
+}
+
+/// Reads transactions out of a CSV input through a single reused
+/// [`ByteRecord`], avoiding per-row allocations.
+///
+/// Malformed rows are skipped unless [`MalformedRows::Reject`] is set; only
+/// IO errors always end the iteration with an error.
+pub struct TransactionReader<R> {
+    reader: csv::Reader<R>,
+    columns: CsvColumns,
+    record: ByteRecord,
+    malformed_rows: MalformedRows,
+    skipped: u64,
+}
+
+impl<R: io::Read> TransactionReader<R> {
+    pub fn new(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv_reader(reader);
+        let columns = CsvColumns::from_headers(reader.byte_headers()?)?;
+
+        Ok(Self {
+            reader,
+            columns,
+            record: ByteRecord::new(),
+            malformed_rows: MalformedRows::default(),
+            skipped: 0,
+        })
+    }
+
+    pub fn malformed_rows(mut self, policy: MalformedRows) -> Self {
+        self.malformed_rows = policy;
+        self
+    }
+
+    /// Number of malformed rows skipped so far.
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+impl<R: io::Read> Iterator for TransactionReader<R> {
+    type Item = Result<Transaction, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (line, err): (u64, Box<dyn fmt::Display>) =
+                match self.reader.read_byte_record(&mut self.record) {
+                    Ok(true) => match Transaction::from_byte_record(&self.record, &self.columns) {
+                        Ok(tx) => return Some(Ok(tx)),
+                        Err(err) => (
+                            self.record.position().map_or(0, |pos| pos.line()),
+                            Box::new(err),
+                        ),
+                    },
+                    Ok(false) => return None,
+                    Err(err) if err.is_io_error() => return Some(Err(err.into())),
+                    Err(err) => (err.position().map_or(0, |pos| pos.line()), Box::new(err)),
+                };
+
+            if let Err(err) =
+                self.malformed_rows
+                    .handle(format_args!("line {}", line), &err, &mut self.skipped)
+            {
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+/// Spawns a task per transaction, holding off on new ones while the engine
+/// is past its memory watermark or its limit of transactions in flight, or
+/// hands transactions to client actors if
+/// the engine was built with them.
+struct Dispatcher<'a> {
+    engine: &'a PaymentsEngine,
+    in_flight: VecDeque<JoinHandle<()>>,
+    warned: bool,
+    actors: Option<ClientActors>,
+}
+
+impl<'a> Dispatcher<'a> {
+    fn new(engine: &'a PaymentsEngine) -> Self {
+        Self::with_capacity(engine, 0)
+    }
+
+    /// Pre-sizes the in-flight list for `transactions`, which it grows to
+    /// when neither a memory watermark nor an in-flight limit makes it
+    /// drain.
+    fn with_capacity(engine: &'a PaymentsEngine, transactions: usize) -> Self {
+        let actors = engine
+            .client_actors()
+            .map(|config| ClientActors::new(engine.clone(), config));
+        let transactions = match engine.max_in_flight() {
+            _ if actors.is_some() => 0,
+            Some(limit) => transactions.min(limit),
+            None => transactions,
+        };
+        Self {
+            engine,
+            in_flight: VecDeque::with_capacity(transactions),
+            warned: false,
+            actors,
+        }
+    }
 
-    let mut transactions: Vec<JoinHandle<()>> = vec![];
+    async fn dispatch(&mut self, tx: Transaction) {
+        if let Some(actors) = &mut self.actors {
+            return actors.dispatch(tx).await;
+        }
+        if self.engine.exceeds_memory_watermark(self.in_flight.len()) {
+            // Stop reading until everything in flight has been applied. This
+            // is what pushes back on socket and queue producers.
+            join_all(self.in_flight.drain(..)).await;
 
-    for result in reader.deserialize() {
-        let tx: Transaction = result?;
-        let client_db = client_db.clone();
-        let transactions_db = transactions_db.clone();
+            if self.engine.stores_exceed_memory_watermark() && !self.warned {
+                log::warn!(
+                    "Memory watermark exceeded by the stores alone (~{} bytes), \
+                     processing one transaction at a time",
+                    self.engine.approximate_memory_usage()
+                );
+                self.warned = true;
+            }
+        }
 
-        transactions.push(tokio::task::spawn(async move {
-            processor::handle_transaction(tx, &client_db, &transactions_db).await;
+        if let Some(limit) = self.engine.max_in_flight() {
+            // Transactions are applied roughly in order, so the oldest is
+            // the one most likely done already
+            while self.in_flight.len() >= limit {
+                if let Some(oldest) = self.in_flight.pop_front() {
+                    let _ = oldest.await;
+                }
+            }
+        }
+
+        let engine = self.engine.clone();
+        self.in_flight.push_back(tokio::task::spawn(async move {
+            engine.handle_transaction(tx).await;
         }));
     }
 
-    join_all(transactions).await;
+    async fn finish(self) {
+        join_all(self.in_flight).await;
+        if let Some(actors) = self.actors {
+            actors.finish().await;
+        }
+    }
+}
+
+/// Streams transactions from `reader` into the engine, dispatching each one
+/// to its own task as soon as it has been parsed.
+pub async fn process_csv<R: io::Read>(
+    engine: &PaymentsEngine,
+    reader: R,
+) -> Result<(), Box<dyn Error>> {
+    let mut dispatcher = Dispatcher::with_capacity(engine, engine.expected_transactions());
 
-    write_csv(&client_db);
+    let mut reader = TransactionReader::new(reader)?;
+    for result in &mut reader {
+        dispatcher.dispatch(result?).await;
+    }
+
+    dispatcher.finish().await;
+    engine.record_malformed_rows(reader.skipped());
     Ok(())
 }
 
-pub fn write_csv(clients_db: &Arc<DashMap<u16, Client>>) {
-    let mut writer = csv::Writer::from_writer(io::stdout());
-    clients_db.iter().for_each(|client| {
-        writer.serialize(*client);
-    });
-    writer.flush();
+pub async fn process_transactions(engine: &PaymentsEngine, transactions: Vec<Transaction>) {
+    let mut dispatcher = Dispatcher::with_capacity(engine, transactions.len());
+
+    for tx in transactions {
+        dispatcher.dispatch(tx).await;
+    }
+
+    dispatcher.finish().await;
+}
+
+/// Applies transactions pushed into a channel by any number of producers
+/// (socket readers, queue consumers, ...) until every sender is dropped.
+///
+/// Transactions are taken off the channel as fast as they can be dispatched,
+/// so its capacity only absorbs bursts. What holds producers off is the
+/// engine's memory watermark or in-flight limit (or full mailboxes with
+/// client actors): reading stops while one is reached, the channel then
+/// fills up and senders wait.
+pub async fn process_channel(engine: &PaymentsEngine, mut receiver: mpsc::Receiver<Transaction>) {
+    let mut dispatcher = Dispatcher::new(engine);
+
+    while let Some(tx) = receiver.recv().await {
+        dispatcher.dispatch(tx).await;
+    }
+
+    dispatcher.finish().await;
+}
+
+/// Writes the client report as CSV to any destination, e.g. stdout, a file
+/// or an upload buffer.
+///
+/// Rows are written field by field through one reused scratch buffer rather
+/// than serialized with serde, so no allocation happens per row, and output
+/// reaches `destination` in large chunks.
+pub fn write_csv<W: io::Write>(
+    clients_db: &ClientDb,
+    destination: W,
+) -> Result<(), Box<dyn Error>> {
+    write_csv_with(clients_db, ReportLayout::default(), destination)
+}
+
+/// [`write_csv`] with a choice of columns and header.
+pub fn write_csv_with<W: io::Write>(
+    clients_db: &ClientDb,
+    layout: ReportLayout,
+    destination: W,
+) -> Result<(), Box<dyn Error>> {
+    write_client_rows(clients_db.iter().map(|client| *client), layout, destination)
+}
+
+/// Writes the payouts owed to closed accounts as `client,amount` CSV, in
+/// client order: each closed account's residual available balance, where
+/// there is one at the given precision.
+pub fn write_payouts<W: io::Write>(
+    clients_db: &ClientDb,
+    precision: Precision,
+    destination: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut payouts: Vec<(u16, f64)> = clients_db
+        .iter()
+        .filter(|client| client.closed && precision.round(client.available) != 0.0)
+        .map(|client| (client.id, client.available))
+        .collect();
+    payouts.sort_unstable_by_key(|(client, _)| *client);
+
+    let mut writer = csv::Writer::from_writer(destination);
+    writer.write_record(["client", "amount"])?;
+    for (client, amount) in payouts {
+        writer.write_record([client.to_string(), precision.format(amount).to_string()])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes every dispute, resolve and chargeback as
+/// `tx,client,seq,action,amount,reason` CSV, grouped by transaction in id
+/// order and oldest first within each. `reason` is empty when the dispute
+/// gave none. Amounts are written at the given precision.
+pub fn write_dispute_report<W: io::Write>(
+    transactions_db: &TransactionsDb,
+    precision: Precision,
+    destination: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(destination);
+    writer.write_record(["tx", "client", "seq", "action", "amount", "reason"])?;
+    for (tx_id, steps) in transactions_db.dispute_histories() {
+        for step in steps {
+            writer.write_record([
+                tx_id.to_string().as_str(),
+                &step.client_id.to_string(),
+                &step.sequence.to_string(),
+                step.action.as_str(),
+                &precision.format(step.amount).to_string(),
+                step.reason.map_or("", DisputeReason::as_str),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes every transaction rejected by blocklist screening as
+/// `seq,client,tx,type,amount` CSV, in the order they were received.
+pub fn write_screening_report<W: io::Write>(
+    hits: &[ScreeningHit],
+    precision: Precision,
+    destination: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(destination);
+    writer.write_record(["seq", "client", "tx", "type", "amount"])?;
+    for hit in hits {
+        writer.write_record([
+            hit.sequence.to_string().as_str(),
+            &hit.client_id.to_string(),
+            &hit.tx_id.to_string(),
+            hit.tx_type.as_str(),
+            &hit.amount
+                .map_or_else(String::new, |amount| precision.format(amount).to_string()),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a report of just the given clients.
+pub(crate) fn write_client_rows<W: io::Write>(
+    clients: impl Iterator<Item = Client>,
+    layout: ReportLayout,
+    destination: W,
+) -> Result<(), Box<dyn Error>> {
+    let columns = layout.columns;
+    let precision = layout.precision;
+    let mut writer = csv::WriterBuilder::new()
+        .buffer_capacity(REPORT_BUFFER_CAPACITY)
+        .from_writer(destination);
+    if layout.header {
+        writer.write_record(columns.names())?;
+    }
+
+    let mut field = Vec::with_capacity(32);
+    for client in clients {
+        write_field(&mut writer, &mut field, format_args!("{}", client.id))?;
+        write_field(
+            &mut writer,
+            &mut field,
+            format_args!("{}", precision.format(client.available)),
+        )?;
+        write_field(
+            &mut writer,
+            &mut field,
+            format_args!("{}", precision.format(client.held)),
+        )?;
+        write_field(
+            &mut writer,
+            &mut field,
+            format_args!("{}", precision.format(client.total)),
+        )?;
+        writer.write_field(if client.locked { "true" } else { "false" })?;
+        if columns == ReportColumns::Extended {
+            let stats = &client.stats;
+            write_field(
+                &mut writer,
+                &mut field,
+                format_args!("{}", stats.transactions),
+            )?;
+            write_field(&mut writer, &mut field, format_args!("{}", stats.disputes))?;
+            write_field(
+                &mut writer,
+                &mut field,
+                format_args!("{}", precision.format(stats.deposited)),
+            )?;
+            write_field(
+                &mut writer,
+                &mut field,
+                format_args!("{}", precision.format(stats.withdrawn)),
+            )?;
+            write_field(
+                &mut writer,
+                &mut field,
+                format_args!("{}", stats.last_activity),
+            )?;
+            write_field(
+                &mut writer,
+                &mut field,
+                format_args!("{}", precision.format(stats.adjusted)),
+            )?;
+        }
+        writer.write_record(None::<&[u8]>)?;
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+const REPORT_BUFFER_CAPACITY: usize = 1 << 16;
+
+fn write_field<W: io::Write>(
+    writer: &mut csv::Writer<W>,
+    scratch: &mut Vec<u8>,
+    value: fmt::Arguments,
+) -> csv::Result<()> {
+    scratch.clear();
+    // Writing into a Vec can't fail
+    scratch.write_fmt(value).unwrap();
+    writer.write_field(&scratch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::currency::Currency;
+    use crate::engine::RuntimeConfig;
+    use crate::test_support::TempDir;
+
+    #[tokio::test]
+    async fn test_channel_source_applies_transactions_from_every_producer() {
+        let engine = PaymentsEngine::new();
+        let (sender, receiver) = mpsc::channel(1);
+
+        let producers: Vec<JoinHandle<()>> = (0..2)
+            .map(|client_id| {
+                let sender = sender.clone();
+                tokio::task::spawn(async move {
+                    for tx_id in 0..10 {
+                        let tx =
+                            Transaction::new_deposit(client_id, client_id as u32 * 10 + tx_id, 1.0);
+                        sender.send(tx).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(sender);
+
+        process_channel(&engine, receiver).await;
+        join_all(producers).await;
+
+        assert_eq!(engine.clients().get(&0).unwrap().available, 10.0);
+        assert_eq!(engine.clients().get(&1).unwrap().available, 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_ingestion_continues_past_the_memory_watermark() {
+        let engine = PaymentsEngine::builder().memory_watermark(0).build();
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\nwithdrawal,1,2,0.5\n";
+
+        process_csv(&engine, input.as_bytes()).await.unwrap();
+
+        assert_eq!(engine.clients().get(&1).unwrap().available, 1.5);
+    }
+
+    #[test]
+    fn test_in_flight_transactions_are_limited() {
+        let builder = PaymentsEngine::builder()
+            .max_in_flight(2)
+            .runtime(RuntimeConfig {
+                worker_threads: Some(2),
+                max_blocking_threads: Some(1),
+                event_interval: Some(7),
+                global_queue_interval: Some(3),
+            });
+        let runtime = builder.build_runtime().unwrap();
+        let engine = builder.build();
+        runtime.block_on(async {
+            let mut dispatcher = Dispatcher::new(&engine);
+            for tx_id in 0..100 {
+                dispatcher
+                    .dispatch(Transaction::new_deposit(1, tx_id, 1.0))
+                    .await;
+                assert!(dispatcher.in_flight.len() <= 2);
+            }
+            dispatcher.finish().await;
+        });
+
+        assert_eq!(engine.clients().get(&1).unwrap().available, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_rows_dont_abort_the_run() {
+        let engine = PaymentsEngine::new();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,2.0\n\
+                     deposit,1,2,-5.0\n\
+                     withdrawal,1\n\
+                     withdrawal,1,3,0.5\n";
+
+        process_csv(&engine, input.as_bytes()).await.unwrap();
+
+        assert_eq!(engine.clients().get(&1).unwrap().available, 1.5);
+        assert_eq!(engine.malformed_row_count(), 2);
+    }
+
+    #[test]
+    fn test_reject_stops_at_the_first_malformed_row() {
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,x,2,1.0\n";
+        let results: Vec<_> = TransactionReader::new(input.as_bytes())
+            .unwrap()
+            .malformed_rows(MalformedRows::Reject)
+            .collect();
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(
+            results[1].as_ref().unwrap_err().to_string(),
+            "line 3: invalid client 'x'"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_read_stops_early() {
+        let engine = PaymentsEngine::new();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\n";
+        let outcome = process_async(&engine, input.as_bytes(), &cancel)
+            .await
+            .unwrap();
+
+        assert_eq!(outcome, ReadOutcome::Cancelled);
+        assert!(engine.clients().get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_read_applies_every_transaction() {
+        let engine = PaymentsEngine::new();
+        let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,1,2,0.5\n";
+
+        let outcome = process_async(&engine, input.as_bytes(), &CancellationToken::new())
+            .await
+            .unwrap();
+
+        assert_eq!(outcome, ReadOutcome::Completed);
+        assert_eq!(engine.clients().get(&1).unwrap().available, 2.5);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_named_pipe_is_read_as_it_is_written() {
+        let dir = TempDir::new("fifo");
+        let fifo = dir.join("input.csv");
+        let made = std::process::Command::new("mkfifo")
+            .arg(&fifo)
+            .status()
+            .unwrap();
+        assert!(made.success());
+        assert!(is_fifo(&fifo));
+
+        // A writer pausing between rows, as a producer streaming into the
+        // pipe would; the read waits for it rather than seeing an early end
+        let writer = std::thread::spawn({
+            let fifo = fifo.clone();
+            move || {
+                let mut pipe = std::fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+                for row in [
+                    "type,client,tx,amount\n",
+                    "deposit,1,1,2.0\n",
+                    "withdrawal,1,2,0.5\n",
+                ] {
+                    pipe.write_all(row.as_bytes()).unwrap();
+                    pipe.flush().unwrap();
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+            }
+        });
+        let engine = PaymentsEngine::new();
+        let progress = read_csv_rows(
+            &engine,
+            fifo.to_str().unwrap(),
+            RowRange::default(),
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(progress.rows, 2);
+        assert_eq!(engine.clients().get(&1).unwrap().available, 1.5);
+    }
+
+    #[tokio::test]
+    async fn test_report_format() {
+        // A zero watermark applies the transactions one at a time, in order
+        let engine = PaymentsEngine::builder().memory_watermark(0).build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,2.0\n\
+                     deposit,1,2,1.23456\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,\n";
+        process_csv(&engine, input.as_bytes()).await.unwrap();
+
+        let mut report = vec![];
+        write_csv(engine.clients(), &mut report).unwrap();
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked\n1,1.2346,0.0000,1.2346,true\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closed_accounts_are_paid_out() {
+        let engine = PaymentsEngine::builder().memory_watermark(0).build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,2.5\n\
+                     deposit,2,2,1.0\n\
+                     deposit,3,3,4.0\n\
+                     withdrawal,3,4,4.0\n\
+                     close,1,5,\n\
+                     close,3,6,\n\
+                     deposit,1,7,9.0\n";
+        process_csv(&engine, input.as_bytes()).await.unwrap();
+
+        let mut payouts = vec![];
+        write_payouts(engine.clients(), Precision::default(), &mut payouts).unwrap();
+
+        assert_eq!(
+            String::from_utf8(payouts).unwrap(),
+            "client,amount\n1,2.5000\n"
+        );
+        assert_eq!(engine.rejected_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dispute_report_lists_each_step() {
+        let engine = PaymentsEngine::builder().memory_watermark(0).build();
+        let input = "type,client,tx,amount,reason\n\
+                     deposit,1,1,5.0,\n\
+                     deposit,2,2,3.0,\n\
+                     dispute,2,2,,duplicate\n\
+                     dispute,1,1,2.0,fraud\n\
+                     resolve,2,2,,\n\
+                     chargeback,1,1,,\n\
+                     resolve,1,1,,\n";
+        process_csv(&engine, input.as_bytes()).await.unwrap();
+
+        let mut report = vec![];
+        engine
+            .write_dispute_report(Precision::default(), &mut report)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "tx,client,seq,action,amount,reason\n\
+             1,1,4,dispute,2.0000,fraud\n\
+             1,1,6,chargeback,2.0000,fraud\n\
+             2,2,3,dispute,3.0000,duplicate\n\
+             2,2,5,resolve,3.0000,duplicate\n"
+        );
+        assert_eq!(engine.dispute_history(1).len(), 2);
+        assert_eq!(engine.rejected_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_extended_report_format() {
+        let engine = PaymentsEngine::builder().memory_watermark(0).build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,2.0\n\
+                     withdrawal,1,2,0.5\n\
+                     dispute,1,1,\n\
+                     withdrawal,1,3,5.0\n";
+        process_csv(&engine, input.as_bytes()).await.unwrap();
+
+        let mut layout = ReportLayout {
+            columns: ReportColumns::Extended,
+            ..ReportLayout::default()
+        };
+        let mut report = vec![];
+        engine.write_report_with(layout, &mut report).unwrap();
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,available,held,total,locked,transactions,disputes,deposited,withdrawn,last_activity,adjusted\n\
+             1,-0.5000,2.0000,1.5000,false,3,1,2.0000,0.5000,3,0.0000\n"
+        );
+
+        layout.header = false;
+        layout.precision.decimals = Currency::lookup("USD").unwrap().decimals();
+        let mut report = vec![];
+        engine.write_report_with(layout, &mut report).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "1,-0.50,2.00,1.50,false,3,1,2.00,0.50,3,0.00\n"
+        );
+    }
 }
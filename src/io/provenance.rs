@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, ReadBuf};
+
+/// The blake3 hash of a segment of an input: the `len` bytes from byte
+/// `offset` on that one read took in. A file read whole is a single
+/// segment from offset 0; a followed file is read in a segment per poll.
+///
+/// Ties a report to the exact bytes behind it: hashing the same range of
+/// the input again, e.g. with `b3sum`, gives the same hash.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputDigest {
+    /// Path of the input, as given.
+    pub source: String,
+    pub offset: u64,
+    pub len: u64,
+    /// The hash in hex.
+    pub blake3: String,
+}
+
+impl InputDigest {
+    /// Digest of the whole file at `path`, for inputs whose reader hashes
+    /// nothing as it goes, such as the bank file importers.
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let hasher = SegmentHasher::new(path, 0);
+        io::copy(&mut hasher.reader(File::open(path)?), &mut io::sink())?;
+        Ok(hasher.digest())
+    }
+}
+
+/// Hashes the bytes of one input segment as they are read, through
+/// [`reader`](Self::reader) or [`update`](Self::update). Clones share the
+/// running hash, so it can be read out once the reader was consumed.
+#[derive(Clone)]
+pub struct SegmentHasher {
+    source: String,
+    offset: u64,
+    state: Arc<Mutex<(blake3::Hasher, u64)>>,
+}
+
+impl SegmentHasher {
+    pub fn new(source: &Path, offset: u64) -> Self {
+        Self {
+            source: source.display().to_string(),
+            offset,
+            state: Arc::new(Mutex::new((blake3::Hasher::new(), 0))),
+        }
+    }
+
+    /// Wraps `inner`, hashing whatever is read through it.
+    pub fn reader<R>(&self, inner: R) -> HashingReader<R> {
+        HashingReader {
+            inner,
+            hasher: self.clone(),
+        }
+    }
+
+    pub fn update(&self, bytes: &[u8]) {
+        let mut state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        state.0.update(bytes);
+        state.1 += bytes.len() as u64;
+    }
+
+    /// Number of bytes hashed so far.
+    pub fn len(&self) -> u64 {
+        self.state.lock().unwrap_or_else(|err| err.into_inner()).1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The digest of the bytes hashed so far.
+    pub fn digest(&self) -> InputDigest {
+        let state = self.state.lock().unwrap_or_else(|err| err.into_inner());
+        InputDigest {
+            source: self.source.clone(),
+            offset: self.offset,
+            len: state.1,
+            blake3: state.0.finalize().to_hex().to_string(),
+        }
+    }
+}
+
+/// A reader feeding everything read through it to a [`SegmentHasher`].
+pub struct HashingReader<R> {
+    inner: R,
+    hasher: SegmentHasher,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for HashingReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let this = &mut *self;
+        let polled = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = polled {
+            this.hasher.update(&buf.filled()[filled..]);
+        }
+        polled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn test_segments_hash_the_bytes_read() {
+        // The empty input's hash from the BLAKE3 test vectors
+        let hasher = SegmentHasher::new(Path::new("in.csv"), 0);
+        assert_eq!(
+            hasher.digest().blake3,
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+
+        let input = b"type,client,tx,amount\ndeposit,1,1,2.0\n";
+        let mut read = vec![];
+        AsyncReadExt::read_to_end(&mut hasher.reader(&input[..]), &mut read)
+            .await
+            .unwrap();
+        let segment = SegmentHasher::new(Path::new("in.csv"), 22);
+        Read::read_to_end(&mut segment.reader(&input[22..]), &mut vec![]).unwrap();
+
+        let digest = hasher.digest();
+        assert_eq!(digest.len, input.len() as u64);
+        assert_eq!(digest.blake3, blake3::hash(input).to_hex().as_str());
+        assert_eq!(segment.digest().offset, 22);
+        assert_eq!(segment.digest().len, 16);
+        assert_eq!(
+            segment.digest().blake3,
+            blake3::hash(b"deposit,1,1,2.0\n").to_hex().as_str()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+use serde::Serialize;
+
+use crate::currency::Precision;
+use crate::processor::Client;
+
+/// Version of the report layout described by [`ReportSchema`]. Bumped
+/// whenever a column is renamed, removed or changes meaning; adding an
+/// optional column set doesn't change the meaning of the existing columns
+/// and doesn't bump it.
+pub const REPORT_SCHEMA_VERSION: u32 = 1;
+
+/// Columns of the client report.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ReportColumns {
+    /// `client,available,held,total,locked`
+    #[default]
+    Standard,
+    /// The standard columns followed by `transactions`, `disputes`,
+    /// `deposited`, `withdrawn`, `last_activity` and `adjusted`: the number
+    /// of applied transactions and disputes, lifetime deposit and withdrawal
+    /// amounts, the position of the last transaction that changed the
+    /// client's account in the order the engine received them, and the net
+    /// amount of manual credits and debits, non-zero for any account
+    /// adjusted by hand.
+    Extended,
+}
+
+const STANDARD_COLUMNS: [ColumnSchema; 5] = [
+    ColumnSchema::new("client", ColumnType::Integer),
+    ColumnSchema::new("available", ColumnType::Decimal),
+    ColumnSchema::new("held", ColumnType::Decimal),
+    ColumnSchema::new("total", ColumnType::Decimal),
+    ColumnSchema::new("locked", ColumnType::Boolean),
+];
+
+const EXTENDED_COLUMNS: [ColumnSchema; 6] = [
+    ColumnSchema::new("transactions", ColumnType::Integer),
+    ColumnSchema::new("disputes", ColumnType::Integer),
+    ColumnSchema::new("deposited", ColumnType::Decimal),
+    ColumnSchema::new("withdrawn", ColumnType::Decimal),
+    ColumnSchema::new("last_activity", ColumnType::Integer),
+    ColumnSchema::new("adjusted", ColumnType::Decimal),
+];
+
+impl ReportColumns {
+    pub fn columns(self) -> impl Iterator<Item = &'static ColumnSchema> {
+        let extended: &'static [ColumnSchema] = match self {
+            ReportColumns::Standard => &[],
+            ReportColumns::Extended => &EXTENDED_COLUMNS,
+        };
+        STANDARD_COLUMNS.iter().chain(extended)
+    }
+
+    pub fn names(self) -> impl Iterator<Item = &'static str> {
+        self.columns().map(|column| column.name)
+    }
+}
+
+/// Everything that decides the shape of a written report.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ReportLayout {
+    pub columns: ReportColumns,
+    /// Whether the first line names the columns.
+    pub header: bool,
+    /// Decimal places of amounts, the minor units of the report's
+    /// [`Currency`](crate::currency::Currency) if it has one, and how
+    /// amounts are rounded to them.
+    pub precision: Precision,
+}
+
+impl Default for ReportLayout {
+    fn default() -> Self {
+        Self {
+            columns: ReportColumns::Standard,
+            header: true,
+            precision: Precision::default(),
+        }
+    }
+}
+
+impl ReportLayout {
+    /// Machine-readable description of reports written with this layout.
+    pub fn schema(&self) -> ReportSchema {
+        ReportSchema {
+            schema_version: REPORT_SCHEMA_VERSION,
+            header: self.header,
+            decimals: self.precision.decimals,
+            columns: self.columns.columns().copied().collect(),
+        }
+    }
+
+    /// The client's values for the layout's columns, formatted as in the
+    /// CSV report.
+    pub(crate) fn cells(&self, client: &Client) -> Vec<String> {
+        let precision = self.precision;
+        let mut cells = vec![
+            client.id.to_string(),
+            precision.format(client.available).to_string(),
+            precision.format(client.held).to_string(),
+            precision.format(client.total).to_string(),
+            client.locked.to_string(),
+        ];
+        if self.columns == ReportColumns::Extended {
+            let stats = &client.stats;
+            cells.extend([
+                stats.transactions.to_string(),
+                stats.disputes.to_string(),
+                precision.format(stats.deposited).to_string(),
+                precision.format(stats.withdrawn).to_string(),
+                stats.last_activity.to_string(),
+                precision.format(stats.adjusted).to_string(),
+            ]);
+        }
+        cells
+    }
+}
+
+/// Description of a report's columns, for consumers to check before
+/// reading it. Serializes to JSON such as
+/// `{"schema_version":1,"header":true,"decimals":4,"columns":[{"name":"client","type":"integer"},...]}`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ReportSchema {
+    pub schema_version: u32,
+    pub header: bool,
+    /// Decimal places of every decimal column.
+    pub decimals: usize,
+    pub columns: Vec<ColumnSchema>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub struct ColumnSchema {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub column_type: ColumnType,
+}
+
+impl ColumnSchema {
+    const fn new(name: &'static str, column_type: ColumnType) -> Self {
+        Self { name, column_type }
+    }
+}
+
+/// How a column's values are written. Decimals have the schema's number of
+/// places.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnType {
+    Integer,
+    Decimal,
+    Boolean,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_describes_the_columns() {
+        let layout = ReportLayout {
+            columns: ReportColumns::Extended,
+            header: false,
+            precision: Precision {
+                decimals: 2,
+                ..Precision::default()
+            },
+        };
+        let json = serde_json::to_string(&layout.schema()).unwrap();
+
+        assert!(json.starts_with(
+            r#"{"schema_version":1,"header":false,"decimals":2,"columns":[{"name":"client","type":"integer"},{"name":"available","type":"decimal"}"#
+        ));
+        assert!(json.ends_with(r#"{"name":"adjusted","type":"decimal"}]}"#));
+        assert_eq!(ReportColumns::Standard.names().count(), 5);
+    }
+}
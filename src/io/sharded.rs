@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::io::partitioned::shard_of;
+use crate::io::{write_client_rows, Compression, ReportLayout};
+use crate::processor::{Client, ClientDb};
+
+/// How clients are assigned to the files of a sharded report.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ShardBy {
+    /// Consecutive, equally wide ranges of client ids, so shard 0 of 16
+    /// holds clients 0 to 4095.
+    Range,
+    /// Client id modulo the number of shards, as partitioned runs assign
+    /// clients to shards, spreading sequential ids evenly.
+    #[default]
+    Hash,
+}
+
+impl ShardBy {
+    /// The shard out of `shards` that `client` belongs to.
+    pub fn shard(self, client: u16, shards: usize) -> usize {
+        match self {
+            ShardBy::Range => client as usize * shards / (u16::MAX as usize + 1),
+            ShardBy::Hash => shard_of(client, shards),
+        }
+    }
+}
+
+impl FromStr for ShardBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "range" => Ok(ShardBy::Range),
+            "hash" => Ok(ShardBy::Hash),
+            _ => Err(format!("unknown sharding '{}', expected range or hash", s)),
+        }
+    }
+}
+
+/// Writes the client report as `shards` CSV files in `dir`, named
+/// `clients-00.csv` and so on, each with its own header if the layout has
+/// one, so loaders can read them in parallel. Every shard gets a file, even
+/// without clients. Files are compressed with `compression` and get its
+/// extension. Returns the files' paths, in shard order.
+pub fn write_sharded_csv(
+    clients_db: &ClientDb,
+    shards: usize,
+    by: ShardBy,
+    layout: ReportLayout,
+    compression: Compression,
+    dir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let shards = shards.max(1);
+    let mut buckets: Vec<Vec<Client>> = vec![vec![]; shards];
+    for client in clients_db.iter() {
+        buckets[by.shard(client.id, shards)].push(*client);
+    }
+
+    fs::create_dir_all(dir)?;
+    let width = (shards - 1).to_string().len().max(2);
+    let extension = match compression {
+        Compression::None => "",
+        Compression::Gzip => ".gz",
+        Compression::Zstd => ".zst",
+    };
+    let mut paths = Vec::with_capacity(shards);
+    for (shard, clients) in buckets.into_iter().enumerate() {
+        let path = dir.join(format!("clients-{:0width$}.csv{}", shard, extension));
+        let mut writer = compression.writer(File::create(&path)?)?;
+        write_client_rows(clients.into_iter(), layout, &mut writer)?;
+        writer.finish()?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::test_support::TempDir;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_every_client_lands_in_its_shard() {
+        let engine = PaymentsEngine::new();
+        for client in [1, 2, 5, 40000] {
+            engine.apply_transaction(Transaction::new_deposit(client, client as u32, 1.0));
+        }
+        let dir = TempDir::new("sharded");
+
+        let paths = engine
+            .write_sharded_report(
+                dir.path(),
+                4,
+                ShardBy::Range,
+                ReportLayout::default(),
+                Compression::None,
+            )
+            .unwrap();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "clients-00.csv",
+                "clients-01.csv",
+                "clients-02.csv",
+                "clients-03.csv"
+            ]
+        );
+        let contents: Vec<String> = paths
+            .iter()
+            .map(|path| fs::read_to_string(path).unwrap())
+            .collect();
+        assert_eq!(contents[0].lines().count(), 4);
+        assert_eq!(contents[1], "client,available,held,total,locked\n");
+        assert!(contents[2].contains("40000,1.0000"));
+
+        assert_eq!(ShardBy::Hash.shard(5, 4), 1);
+    }
+}
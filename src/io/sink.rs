@@ -0,0 +1,93 @@
+use std::error::Error;
+use std::io::Write;
+
+use crate::io::{write_csv_with, write_json, write_table, ReportLayout};
+use crate::processor::ClientDb;
+
+/// An output format for the client report, so callers such as the CLI pick
+/// a format once and write every report through it.
+pub trait ReportSink {
+    fn write_report(
+        &mut self,
+        clients_db: &ClientDb,
+        layout: ReportLayout,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// The CSV report, see [`write_csv_with`].
+pub struct CsvReport<W: Write> {
+    destination: W,
+}
+
+impl<W: Write> CsvReport<W> {
+    pub fn new(destination: W) -> Self {
+        Self { destination }
+    }
+}
+
+impl<W: Write> ReportSink for CsvReport<W> {
+    fn write_report(
+        &mut self,
+        clients_db: &ClientDb,
+        layout: ReportLayout,
+    ) -> Result<(), Box<dyn Error>> {
+        write_csv_with(clients_db, layout, &mut self.destination)
+    }
+}
+
+/// The JSON report, see [`write_json`].
+pub struct JsonReport<W: Write> {
+    destination: W,
+}
+
+impl<W: Write> JsonReport<W> {
+    pub fn new(destination: W) -> Self {
+        Self { destination }
+    }
+}
+
+impl<W: Write> ReportSink for JsonReport<W> {
+    fn write_report(
+        &mut self,
+        clients_db: &ClientDb,
+        layout: ReportLayout,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(write_json(clients_db, layout, &mut self.destination)?)
+    }
+}
+
+/// The aligned terminal table, see [`write_table`].
+pub struct TableReport<W: Write> {
+    destination: W,
+    highlight_locked: bool,
+}
+
+impl<W: Write> TableReport<W> {
+    pub fn new(destination: W) -> Self {
+        Self {
+            destination,
+            highlight_locked: false,
+        }
+    }
+
+    /// Prints locked accounts in bold red.
+    pub fn highlight_locked(mut self, highlight: bool) -> Self {
+        self.highlight_locked = highlight;
+        self
+    }
+}
+
+impl<W: Write> ReportSink for TableReport<W> {
+    fn write_report(
+        &mut self,
+        clients_db: &ClientDb,
+        layout: ReportLayout,
+    ) -> Result<(), Box<dyn Error>> {
+        Ok(write_table(
+            clients_db,
+            layout,
+            self.highlight_locked,
+            &mut self.destination,
+        )?)
+    }
+}
@@ -0,0 +1,138 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::engine::PaymentsEngine;
+use crate::io::process_transactions;
+use crate::transactions::Transaction;
+
+/// Transactions dispatched together between checks of the clock and the
+/// cancellation token.
+const BATCH_SIZE: usize = 10_000;
+
+/// How long to soak and how often to log resource usage.
+#[derive(Clone, Debug)]
+pub struct SoakOptions {
+    pub report_every: Duration,
+    /// Run until cancelled if `None`.
+    pub duration: Option<Duration>,
+}
+
+/// Totals of a finished soak run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SoakStats {
+    pub applied: u64,
+    pub reports: u64,
+}
+
+/// Feeds an endless transaction stream into `engine` until the duration is
+/// up or `cancel` fires, logging memory, map sizes and throughput to stderr
+/// every `report_every`.
+///
+/// Meant for runs of hours, to catch anything that grows without bound,
+/// such as the transaction map, before production does.
+pub async fn run_soak<I: Iterator<Item = Transaction>>(
+    engine: &PaymentsEngine,
+    mut feed: I,
+    options: &SoakOptions,
+    cancel: &CancellationToken,
+) -> SoakStats {
+    let start = Instant::now();
+    let mut stats = SoakStats::default();
+    let mut last_report = start;
+    let mut applied_at_last_report = 0;
+
+    while !cancel.is_cancelled() && options.duration.is_none_or(|d| start.elapsed() < d) {
+        let batch: Vec<Transaction> = feed.by_ref().take(BATCH_SIZE).collect();
+        if batch.is_empty() {
+            break;
+        }
+        stats.applied += batch.len() as u64;
+        process_transactions(engine, batch).await;
+
+        let now = Instant::now();
+        let interval = now - last_report;
+        if interval >= options.report_every {
+            let rate = (stats.applied - applied_at_last_report) as f64 / interval.as_secs_f64();
+            log_resources(engine, now - start, stats.applied, rate);
+            stats.reports += 1;
+            last_report = now;
+            applied_at_last_report = stats.applied;
+        }
+    }
+
+    stats
+}
+
+fn log_resources(engine: &PaymentsEngine, elapsed: Duration, applied: u64, rate: f64) {
+    let resident = resident_bytes().map_or("n/a".to_string(), |bytes| bytes.to_string());
+    log::info!(
+        "soak: elapsed={}s applied={} tx/s={:.0} clients={} transactions={} \
+         estimated_bytes={} resident_bytes={}",
+        elapsed.as_secs(),
+        applied,
+        rate,
+        engine.client_count(),
+        engine.transaction_count(),
+        engine.approximate_memory_usage(),
+        resident
+    );
+}
+
+/// Resident set size of this process, where `/proc` is available.
+fn resident_bytes() -> Option<u64> {
+    // Pages are 4 KiB on every platform this is likely to soak on
+    const PAGE_SIZE: u64 = 4096;
+
+    let statm = fs::read_to_string("/proc/self/statm").ok()?;
+    let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    Some(resident_pages * PAGE_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SyntheticFeed;
+
+    #[tokio::test]
+    async fn test_soak_runs_for_its_duration_and_reports() {
+        let engine = PaymentsEngine::new();
+        let options = SoakOptions {
+            report_every: Duration::ZERO,
+            duration: Some(Duration::from_millis(50)),
+        };
+
+        let stats = run_soak(
+            &engine,
+            SyntheticFeed::new(1, 100),
+            &options,
+            &CancellationToken::new(),
+        )
+        .await;
+
+        assert!(stats.applied >= BATCH_SIZE as u64);
+        assert!(stats.reports >= 1);
+        assert!(engine.client_count() > 0);
+    }
+
+    #[tokio::test]
+    async fn test_soak_stops_when_cancelled() {
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let options = SoakOptions {
+            report_every: Duration::from_secs(1),
+            duration: None,
+        };
+
+        let stats = run_soak(
+            &PaymentsEngine::new(),
+            SyntheticFeed::new(1, 100),
+            &options,
+            &cancel,
+        )
+        .await;
+
+        assert_eq!(stats, SoakStats::default());
+    }
+}
@@ -0,0 +1,129 @@
+use std::io::{self, Write};
+
+use crate::io::ReportLayout;
+use crate::processor::{Client, ClientDb};
+
+const LOCKED_STYLE: &str = "\x1b[1;31m";
+const RESET_STYLE: &str = "\x1b[0m";
+
+/// Writes the client report as an aligned table for reading in a terminal,
+/// sorted by client. With `highlight_locked`, rows of locked accounts are
+/// printed in bold red using ANSI escapes.
+///
+/// Unlike [`write_csv`](super::write_csv), this collects every row before
+/// writing so it can size the columns, and is meant for interactive use
+/// rather than large reports.
+pub fn write_table<W: Write>(
+    clients_db: &ClientDb,
+    layout: ReportLayout,
+    highlight_locked: bool,
+    mut destination: W,
+) -> io::Result<()> {
+    let columns = layout.columns;
+    let mut clients: Vec<Client> = clients_db.iter().map(|client| *client).collect();
+    clients.sort_unstable_by_key(|client| client.id);
+
+    let header: Vec<String> = columns.names().map(str::to_string).collect();
+    let rows: Vec<Vec<String>> = clients.iter().map(|client| layout.cells(client)).collect();
+    let mut widths: Vec<usize> = header.iter().map(|name| name.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    if layout.header {
+        write_table_line(&mut destination, &header, &widths)?;
+        destination.write_all(b"\n")?;
+        let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        write_table_line(&mut destination, &rule, &widths)?;
+        destination.write_all(b"\n")?;
+    }
+
+    for (client, row) in clients.iter().zip(&rows) {
+        let highlight = highlight_locked && client.locked;
+        if highlight {
+            destination.write_all(LOCKED_STYLE.as_bytes())?;
+        }
+        write_table_line(&mut destination, row, &widths)?;
+        if highlight {
+            // Reset before the newline so a pager doesn't carry the style over
+            destination.write_all(RESET_STYLE.as_bytes())?;
+        }
+        destination.write_all(b"\n")?;
+    }
+
+    destination.flush()
+}
+
+/// Writes one line of right-aligned cells, without the line break so the
+/// caller can close a highlight first.
+fn write_table_line<W: Write>(
+    destination: &mut W,
+    cells: &[String],
+    widths: &[usize],
+) -> io::Result<()> {
+    for (i, (cell, width)) in cells.iter().zip(widths).enumerate() {
+        if i > 0 {
+            destination.write_all(b"  ")?;
+        }
+        write!(destination, "{:>width$}", cell, width = width)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::io::ReportColumns;
+    use crate::transactions::Transaction;
+
+    fn engine() -> PaymentsEngine {
+        let engine = PaymentsEngine::new();
+        engine.apply_transaction(Transaction::new_deposit(12, 1, 1234.5));
+        engine.apply_transaction(Transaction::new_deposit(3, 2, 1.0));
+        engine.apply_transaction(Transaction::new_dispute(3, 2));
+        engine.apply_transaction(Transaction::new_chargeback(3, 2));
+        engine
+    }
+
+    #[test]
+    fn test_table_is_aligned_and_sorted_by_client() {
+        let mut table = vec![];
+        write_table(
+            engine().clients(),
+            ReportLayout::default(),
+            false,
+            &mut table,
+        )
+        .unwrap();
+        let table = String::from_utf8(table).unwrap();
+
+        assert_eq!(
+            table.lines().collect::<Vec<_>>(),
+            [
+                "client  available    held      total  locked",
+                "------  ---------  ------  ---------  ------",
+                "     3     0.0000  0.0000     0.0000    true",
+                "    12  1234.5000  0.0000  1234.5000   false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_locked_rows_are_highlighted() {
+        let layout = ReportLayout {
+            columns: ReportColumns::Extended,
+            ..ReportLayout::default()
+        };
+        let mut table = vec![];
+        write_table(engine().clients(), layout, true, &mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert!(lines[0].ends_with("adjusted"));
+        assert!(lines[2].starts_with(LOCKED_STYLE) && lines[2].ends_with(RESET_STYLE));
+        assert!(!lines[3].contains('\x1b'));
+    }
+}
@@ -0,0 +1,288 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use csv::ByteRecord;
+use tokio_util::sync::CancellationToken;
+
+use crate::engine::PaymentsEngine;
+use crate::io::provenance::SegmentHasher;
+use crate::io::{csv_reader, is_fifo, Compression, MalformedRows, ReportLayout};
+use crate::transactions::{CsvColumns, Transaction};
+
+/// Bytes of complete rows parsed and applied at a time, so catching up on
+/// a large file neither holds it in memory nor ignores cancellation.
+const BATCH_BYTES: usize = 1 << 20;
+
+/// Follows an append-only CSV file, like `tail -f`, applying rows to a
+/// single long-lived engine as they are written.
+///
+/// Every poll reads from where the last one stopped up to the last
+/// complete line, so a row still being written is left for the next poll.
+/// The bytes a poll applied are recorded as one input segment, see
+/// [`PaymentsEngine::record_input`].
+/// Rows are applied one by one in file order. Rows are split on newlines,
+/// so quoted fields spanning several lines are not supported, and
+/// malformed rows are skipped with a warning. A file that shrank is taken
+/// to have been truncated or replaced, e.g. by log rotation, and is read
+/// again from its header. The file doesn't need to exist yet.
+pub struct FileTailer {
+    engine: PaymentsEngine,
+    path: PathBuf,
+    offset: u64,
+    columns: Option<CsvColumns>,
+    report: Option<PathBuf>,
+    report_layout: ReportLayout,
+    deltas: Option<(PathBuf, u64)>,
+}
+
+impl FileTailer {
+    pub fn new(engine: PaymentsEngine, path: &Path) -> Self {
+        Self {
+            engine,
+            path: path.to_path_buf(),
+            offset: 0,
+            columns: None,
+            report: None,
+            report_layout: ReportLayout::default(),
+            deltas: None,
+        }
+    }
+
+    /// Rewrites the client report at `path` after every poll that applied
+    /// rows.
+    pub fn with_report(mut self, path: &Path) -> Self {
+        self.report = Some(path.to_path_buf());
+        self
+    }
+
+    /// Columns and header of the reports.
+    pub fn with_report_layout(mut self, layout: ReportLayout) -> Self {
+        self.report_layout = layout;
+        self
+    }
+
+    /// After every poll that applied rows, writes the clients they changed
+    /// to a new `delta-<WATERMARK>.csv` in `dir`, as watch mode does.
+    pub fn with_delta_reports(mut self, dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        self.deltas = Some((dir.to_path_buf(), 0));
+        Ok(self)
+    }
+
+    /// Byte offset of the first row not read yet.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Polls the file every `interval` until `cancel` fires.
+    pub async fn run(
+        &mut self,
+        interval: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            self.poll(cancel)?;
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = cancel.cancelled() => return Ok(()),
+            }
+        }
+    }
+
+    /// Applies the rows completed since the last poll, denies reviews
+    /// that timed out meanwhile, and writes the reports if either changed
+    /// anything. Returns the number of rows read, malformed ones included.
+    pub fn poll(&mut self, cancel: &CancellationToken) -> Result<u64, Box<dyn Error>> {
+        let denied = self.engine.expire_reviews();
+        let rows = self.read_rows(cancel)?;
+        if rows > 0 || denied > 0 {
+            self.write_reports()?;
+        }
+        Ok(rows)
+    }
+
+    fn read_rows(&mut self, cancel: &CancellationToken) -> Result<u64, Box<dyn Error>> {
+        if is_fifo(&self.path) {
+            // Opening it would block until a writer shows up
+            return Err(format!(
+                "{} is a named pipe, read it as a regular input instead",
+                self.path.display()
+            )
+            .into());
+        }
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+        if file.metadata()?.len() < self.offset {
+            log::warn!(
+                "{} shrank, reading it again from the start",
+                self.path.display()
+            );
+            self.offset = 0;
+            self.columns = None;
+        }
+
+        let segment = SegmentHasher::new(&self.path, self.offset);
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(self.offset))?;
+        let mut rows = 0;
+        let mut batch = vec![];
+        let mut batch_start = self.offset;
+        while !cancel.is_cancelled() {
+            let read = reader.read_until(b'\n', &mut batch)?;
+            let complete = read > 0 && batch.ends_with(b"\n");
+            if !complete {
+                batch.truncate(batch.len() - read);
+            }
+            if (!complete || batch.len() >= BATCH_BYTES) && !batch.is_empty() {
+                rows += self.apply(&batch, batch_start)?;
+                segment.update(&batch);
+                batch_start += batch.len() as u64;
+                self.offset = batch_start;
+                batch.clear();
+            }
+            if !complete {
+                break;
+            }
+        }
+
+        if !segment.is_empty() {
+            self.engine.record_input(segment.digest());
+        }
+        Ok(rows)
+    }
+
+    /// Applies the complete lines in `batch`, which starts at byte
+    /// `start`, reading the header first if it's still missing.
+    fn apply(&mut self, mut batch: &[u8], start: u64) -> Result<u64, Box<dyn Error>> {
+        let mut position = start;
+        let columns = match self.columns.take() {
+            Some(columns) => columns,
+            None => {
+                // Safe to unwrap, batches are made of complete lines
+                let header_end = batch.iter().position(|&byte| byte == b'\n').unwrap() + 1;
+                let (header, rest) = batch.split_at(header_end);
+                batch = rest;
+                position += header_end as u64;
+                CsvColumns::from_headers(csv_reader(header).byte_headers()?)?
+            }
+        };
+
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(batch);
+        let mut record = ByteRecord::new();
+        let mut rows = 0;
+        let mut skipped = 0;
+        let mut skip = |byte: u64, err: &dyn fmt::Display| {
+            // Skipping never fails
+            let _ = MalformedRows::Skip.handle(
+                format_args!("byte {}", position + byte),
+                err,
+                &mut skipped,
+            );
+        };
+        loop {
+            match reader.read_byte_record(&mut record) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => {
+                    rows += 1;
+                    skip(err.position().map_or(0, |pos| pos.byte()), &err);
+                    continue;
+                }
+            }
+            rows += 1;
+            // The csv crate skips trimming the very first record of a
+            // headerless reader, so trim explicitly
+            record.trim();
+            match Transaction::from_byte_record(&record, &columns) {
+                Ok(tx) => {
+                    self.engine.apply_transaction(tx);
+                }
+                Err(err) => skip(record.position().map_or(0, |pos| pos.byte()), &err),
+            }
+        }
+        self.engine.record_malformed_rows(skipped);
+        self.columns = Some(columns);
+        Ok(rows)
+    }
+
+    /// Writes the full report and the delta, each renamed into place once
+    /// complete.
+    fn write_reports(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.report {
+            let tmp = path.with_extension("tmp");
+            let mut writer = Compression::from_path(path).writer(File::create(&tmp)?)?;
+            self.engine
+                .write_report_with(self.report_layout, &mut writer)?;
+            writer.finish()?;
+            fs::rename(tmp, path)?;
+        }
+        if let Some((dir, since)) = &mut self.deltas {
+            let tmp = dir.join("delta.tmp");
+            let watermark =
+                self.engine
+                    .write_delta_report(*since, self.report_layout, File::create(&tmp)?)?;
+            // Zero-padded so the files sort in watermark order
+            fs::rename(tmp, dir.join(format!("delta-{:020}.csv", watermark)))?;
+            *since = watermark;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+    use std::io::Write;
+
+    #[test]
+    fn test_rows_are_applied_as_they_are_completed() {
+        let dir = TempDir::new("tail");
+        let input = dir.join("feed.csv");
+        let deltas = dir.join("deltas");
+        let append = |text: &str| {
+            let mut file = File::options()
+                .create(true)
+                .append(true)
+                .open(&input)
+                .unwrap();
+            file.write_all(text.as_bytes()).unwrap();
+        };
+
+        let engine = PaymentsEngine::new();
+        let mut tailer = FileTailer::new(engine.clone(), &input)
+            .with_delta_reports(&deltas)
+            .unwrap();
+        let cancel = CancellationToken::new();
+        assert_eq!(tailer.poll(&cancel).unwrap(), 0);
+
+        append("type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\nwithdrawal,1,3,");
+        assert_eq!(tailer.poll(&cancel).unwrap(), 2);
+        assert_eq!(engine.clients().get(&1).unwrap().available, 2.0);
+
+        append("0.5\nbogus,1,4,1.0\n");
+        assert_eq!(tailer.poll(&cancel).unwrap(), 2);
+        assert_eq!(tailer.poll(&cancel).unwrap(), 0);
+        assert_eq!(engine.clients().get(&1).unwrap().available, 1.5);
+        assert_eq!(engine.malformed_row_count(), 1);
+        assert_eq!(
+            fs::read_to_string(deltas.join("delta-00000000000000000003.csv")).unwrap(),
+            "client,available,held,total,locked\n1,1.5000,0.0000,1.5000,false\n"
+        );
+
+        // Rotated: the new file starts over with its own header
+        fs::write(&input, "type,client,tx,amount\ndeposit,3,5,4.0\n").unwrap();
+        assert_eq!(tailer.poll(&cancel).unwrap(), 1);
+        assert_eq!(engine.clients().get(&3).unwrap().available, 4.0);
+    }
+}
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::engine::PaymentsEngine;
+use crate::io::chaos::Chaos;
+use crate::io::provenance::SegmentHasher;
+use crate::io::{parse_reader, process_transactions, Compression, ReportLayout};
+use crate::policy::ClientLimits;
+
+const PROCESSED_DIR: &str = "processed";
+const FAILED_DIR: &str = "failed";
+
+/// Watches a drop directory for CSV files and feeds each one into a single
+/// long-lived engine.
+///
+/// A file is only picked up once its size has stayed the same across two
+/// polls, so files that are still being uploaded are left alone. Files that
+/// parse are applied as a whole and moved to `processed/`; files that don't
+/// are moved to `failed/` without touching the engine state. A file that
+/// can't be read because of an IO error is left in place and retried on the
+/// next poll, as is a report that couldn't be written. A client limits
+/// file is reloaded whenever it changes.
+pub struct DirectoryWatcher {
+    engine: PaymentsEngine,
+    dir: PathBuf,
+    report: Option<PathBuf>,
+    report_layout: ReportLayout,
+    report_stale: bool,
+    deltas: Option<DeltaReports>,
+    pending: HashMap<PathBuf, u64>,
+    chaos: Option<Chaos>,
+    client_limits: Option<LimitsFile>,
+}
+
+/// The client limits file, and when it last changed as of its last load.
+struct LimitsFile {
+    path: PathBuf,
+    modified: SystemTime,
+}
+
+/// Where delta reports go, and the watermark the last one covered.
+struct DeltaReports {
+    dir: PathBuf,
+    watermark: u64,
+}
+
+/// What became of a file that was ready to be processed.
+enum FileOutcome {
+    Handled,
+    Retry,
+    Cancelled,
+}
+
+impl DirectoryWatcher {
+    pub fn new(engine: PaymentsEngine, dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir.join(PROCESSED_DIR))?;
+        fs::create_dir_all(dir.join(FAILED_DIR))?;
+
+        Ok(Self {
+            engine,
+            dir: dir.to_path_buf(),
+            report: None,
+            report_layout: ReportLayout::default(),
+            report_stale: false,
+            deltas: None,
+            pending: HashMap::new(),
+            chaos: None,
+            client_limits: None,
+        })
+    }
+
+    /// Rewrites the client report at `path` after every processed file.
+    pub fn with_report(mut self, path: &Path) -> Self {
+        self.report = Some(path.to_path_buf());
+        self
+    }
+
+    /// Columns and header of the report written by
+    /// [`with_report`](Self::with_report).
+    pub fn with_report_layout(mut self, layout: ReportLayout) -> Self {
+        self.report_layout = layout;
+        self
+    }
+
+    /// After processing files, writes the clients they changed to a new
+    /// `delta-<WATERMARK>.csv` in `dir`, where the watermark is the number of
+    /// transactions received so far. The first delta lists every client, and
+    /// applying the deltas in watermark order rebuilds the full report.
+    pub fn with_delta_reports(mut self, dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        self.deltas = Some(DeltaReports {
+            dir: dir.to_path_buf(),
+            watermark: 0,
+        });
+        Ok(self)
+    }
+
+    /// Reloads the engine's client limits from `path` at the start of a
+    /// poll whenever the file was modified since, e.g. after a limit was
+    /// raised for a client. The engine is expected to have been built with
+    /// the file's current limits. A file that doesn't load leaves the
+    /// current limits in place, and is retried on the next poll.
+    pub fn with_client_limits(mut self, path: &Path) -> io::Result<Self> {
+        self.client_limits = Some(LimitsFile {
+            path: path.to_path_buf(),
+            modified: fs::metadata(path)?.modified()?,
+        });
+        Ok(self)
+    }
+
+    /// Injects failures into the input reads and report writes, to check
+    /// that the retry paths hold up.
+    pub fn with_chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Polls the directory every `interval` until `cancel` fires. A file
+    /// being read when that happens is left in place, unapplied.
+    pub async fn run(
+        &mut self,
+        interval: Duration,
+        cancel: &CancellationToken,
+    ) -> Result<(), Box<dyn Error>> {
+        loop {
+            self.poll(cancel).await?;
+
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = cancel.cancelled() => return Ok(()),
+            }
+        }
+    }
+
+    /// Checks the directory once, processing every file that is ready, and
+    /// denies reviews that timed out meanwhile. Returns the number of files
+    /// that were processed or failed.
+    pub async fn poll(&mut self, cancel: &CancellationToken) -> Result<usize, Box<dyn Error>> {
+        self.reload_client_limits();
+        let denied = self.engine.expire_reviews();
+
+        let mut candidates = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "csv") {
+                candidates.push(path);
+            }
+        }
+        candidates.sort();
+
+        let mut handled = 0;
+        let mut seen = HashMap::new();
+        for path in candidates {
+            let size = fs::metadata(&path)?.len();
+            if self.pending.get(&path) == Some(&size) {
+                match self.process_file(&path, cancel).await? {
+                    FileOutcome::Handled => handled += 1,
+                    FileOutcome::Retry => {
+                        seen.insert(path, size);
+                    }
+                    FileOutcome::Cancelled => break,
+                }
+            } else {
+                seen.insert(path, size);
+            }
+        }
+        self.pending = seen;
+
+        self.report_stale |= handled > 0 || denied > 0;
+        if self.report_stale {
+            if let Some(report) = &self.report {
+                match self.write_report_atomically(report) {
+                    Ok(()) => self.report_stale = false,
+                    Err(err) => log::warn!(
+                        "Failed to write report {}, retrying on the next poll: {}",
+                        report.display(),
+                        err
+                    ),
+                }
+            }
+        }
+        if let Err(err) = self.write_delta_report() {
+            log::warn!(
+                "Failed to write delta report, retrying on the next poll: {}",
+                err
+            );
+        }
+
+        Ok(handled)
+    }
+
+    fn reload_client_limits(&mut self) {
+        let Some(file) = &mut self.client_limits else {
+            return;
+        };
+        let modified = match fs::metadata(&file.path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) if modified != file.modified => modified,
+            Ok(_) => return,
+            Err(err) => {
+                log::warn!(
+                    "Failed to check {}, keeping the current client limits: {}",
+                    file.path.display(),
+                    err
+                );
+                return;
+            }
+        };
+        match ClientLimits::read(&file.path) {
+            Ok(limits) => {
+                log::info!(
+                    "Reloaded limits of {} clients from {}",
+                    limits.len(),
+                    file.path.display()
+                );
+                self.engine.set_client_limits(limits);
+                file.modified = modified;
+            }
+            Err(err) => log::warn!(
+                "Failed to load {}, keeping the current client limits: {}",
+                file.path.display(),
+                err
+            ),
+        }
+    }
+
+    async fn process_file(
+        &self,
+        path: &Path,
+        cancel: &CancellationToken,
+    ) -> Result<FileOutcome, Box<dyn Error>> {
+        let file = tokio::fs::File::open(path).await?;
+        let hasher = SegmentHasher::new(path, 0);
+        let parsed = match &self.chaos {
+            Some(chaos) => parse_reader(hasher.reader(chaos.reader(file)), cancel).await,
+            None => parse_reader(hasher.reader(file), cancel).await,
+        };
+
+        let destination = match parsed {
+            Ok(None) => return Ok(FileOutcome::Cancelled),
+            Ok(Some(transactions)) => {
+                log::debug!(
+                    "Processing {} ({} transactions)",
+                    path.display(),
+                    transactions.len()
+                );
+                process_transactions(&self.engine, transactions).await;
+                self.engine.record_input(hasher.digest());
+                PROCESSED_DIR
+            }
+            Err(err) if err.is::<io::Error>() => {
+                log::warn!(
+                    "Failed to read {}, retrying on the next poll: {}",
+                    path.display(),
+                    err
+                );
+                return Ok(FileOutcome::Retry);
+            }
+            Err(err) => {
+                log::warn!("Failed to parse {}: {}", path.display(), err);
+                FAILED_DIR
+            }
+        };
+
+        // Safe to unwrap, read_dir only yields entries with a file name
+        let file_name = path.file_name().unwrap();
+        fs::rename(path, self.dir.join(destination).join(file_name))?;
+        Ok(FileOutcome::Handled)
+    }
+
+    /// Writes the clients changed since the last delta, if anything was
+    /// received since. Like the full report, the file is only renamed into
+    /// place once complete, and a failed delta is retried with everything
+    /// it would have covered.
+    fn write_delta_report(&mut self) -> Result<(), Box<dyn Error>> {
+        let deltas = match &mut self.deltas {
+            Some(deltas) if self.engine.watermark() > deltas.watermark => deltas,
+            _ => return Ok(()),
+        };
+
+        let tmp = deltas.dir.join("delta.tmp");
+        let file = File::create(&tmp)?;
+        let watermark = match &self.chaos {
+            Some(chaos) => self.engine.write_delta_report(
+                deltas.watermark,
+                self.report_layout,
+                chaos.writer(file),
+            )?,
+            None => self
+                .engine
+                .write_delta_report(deltas.watermark, self.report_layout, file)?,
+        };
+        // Zero-padded so the files sort in watermark order
+        fs::rename(tmp, deltas.dir.join(format!("delta-{:020}.csv", watermark)))?;
+        deltas.watermark = watermark;
+        Ok(())
+    }
+
+    /// Writes the report next to `path` and renames it into place, so
+    /// readers never see a partial report. A `.gz` or `.zst` path is
+    /// compressed accordingly.
+    fn write_report_atomically(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp = path.with_extension("tmp");
+        let file = File::create(&tmp)?;
+        let compression = Compression::from_path(path);
+        match &self.chaos {
+            Some(chaos) => {
+                let mut writer = compression.writer(chaos.writer(file))?;
+                self.engine
+                    .write_report_with(self.report_layout, &mut writer)?;
+                writer.finish()?;
+            }
+            None => {
+                let mut writer = compression.writer(file)?;
+                self.engine
+                    .write_report_with(self.report_layout, &mut writer)?;
+                writer.finish()?;
+            }
+        }
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[tokio::test]
+    async fn test_file_is_processed_once_its_size_is_stable() {
+        let dir = TempDir::new("watch-stable");
+        fs::write(
+            dir.join("a.csv"),
+            "type,client,tx,amount\ndeposit,1,1,2.0\n",
+        )
+        .unwrap();
+
+        let engine = PaymentsEngine::new();
+        let mut watcher = DirectoryWatcher::new(engine.clone(), dir.path()).unwrap();
+
+        assert_eq!(watcher.poll(&CancellationToken::new()).await.unwrap(), 0);
+        assert_eq!(watcher.poll(&CancellationToken::new()).await.unwrap(), 1);
+
+        assert!(dir.join(PROCESSED_DIR).join("a.csv").exists());
+        assert!(!dir.join("a.csv").exists());
+        assert_eq!(engine.clients().get(&1).unwrap().available, 2.0);
+    }
+
+    #[tokio::test]
+    async fn test_state_carries_over_between_files() {
+        let dir = TempDir::new("watch-carry");
+        let engine = PaymentsEngine::new();
+        let mut watcher = DirectoryWatcher::new(engine.clone(), dir.path()).unwrap();
+
+        fs::write(
+            dir.join("a.csv"),
+            "type,client,tx,amount\ndeposit,1,1,2.0\n",
+        )
+        .unwrap();
+        watcher.poll(&CancellationToken::new()).await.unwrap();
+        watcher.poll(&CancellationToken::new()).await.unwrap();
+
+        fs::write(
+            dir.join("b.csv"),
+            "type,client,tx,amount\nwithdrawal,1,2,0.5\n",
+        )
+        .unwrap();
+        watcher.poll(&CancellationToken::new()).await.unwrap();
+        watcher.poll(&CancellationToken::new()).await.unwrap();
+
+        assert_eq!(engine.clients().get(&1).unwrap().available, 1.5);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_file_is_moved_to_failed_without_applying_it() {
+        let dir = TempDir::new("watch-failed");
+        fs::write(
+            dir.join("bad.csv"),
+            "type,client,tx,amount\ndeposit,1,1,2.0\nbogus,1,2,1.0\n",
+        )
+        .unwrap();
+
+        let engine = PaymentsEngine::new();
+        let mut watcher = DirectoryWatcher::new(engine.clone(), dir.path()).unwrap();
+        watcher.poll(&CancellationToken::new()).await.unwrap();
+        watcher.poll(&CancellationToken::new()).await.unwrap();
+
+        assert!(dir.join(FAILED_DIR).join("bad.csv").exists());
+        assert!(engine.clients().get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_injected_failures_are_retried() {
+        use crate::io::chaos::ChaosConfig;
+
+        let dir = TempDir::new("watch-chaos");
+        fs::write(
+            dir.join("a.csv"),
+            "type,client,tx,amount
+deposit,1,1,2.0
+",
+        )
+        .unwrap();
+        // Outside the watched files, so the report isn't picked up as input
+        fs::create_dir_all(dir.join("out")).unwrap();
+        let report = dir.join("out").join("report.csv");
+
+        let engine = PaymentsEngine::new();
+        let chaos = Chaos::new(ChaosConfig {
+            seed: 1,
+            write_error_rate: 0.5,
+            read_drop_rate: 0.5,
+            ..Default::default()
+        });
+        let mut watcher = DirectoryWatcher::new(engine.clone(), dir.path())
+            .unwrap()
+            .with_report(&report)
+            .with_chaos(chaos);
+
+        for _ in 0..50 {
+            watcher.poll(&CancellationToken::new()).await.unwrap();
+        }
+
+        assert!(dir.join(PROCESSED_DIR).join("a.csv").exists());
+        assert_eq!(engine.clients().get(&1).unwrap().available, 2.0);
+        assert!(fs::read_to_string(&report).unwrap().contains("1,2.0000"));
+    }
+
+    #[tokio::test]
+    async fn test_client_limits_are_reloaded_when_the_file_changes() {
+        let dir = TempDir::new("watch-limits");
+        fs::create_dir_all(dir.join("config")).unwrap();
+        let limits = dir.join("config").join("limits.csv");
+        fs::write(&limits, "client,max_withdrawal\n1,1.0\n").unwrap();
+
+        let engine = PaymentsEngine::builder()
+            .client_limits(ClientLimits::read(&limits).unwrap())
+            .build();
+        let mut watcher = DirectoryWatcher::new(engine.clone(), dir.path())
+            .unwrap()
+            .with_client_limits(&limits)
+            .unwrap();
+        let cancel = CancellationToken::new();
+
+        fs::write(&limits, "client,max_withdrawal\n1,5.0\n").unwrap();
+        // Some filesystems only keep modification times to the second
+        let later = SystemTime::now() + Duration::from_secs(2);
+        File::options()
+            .write(true)
+            .open(&limits)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        watcher.poll(&cancel).await.unwrap();
+
+        let max = engine
+            .policy()
+            .client_limits()
+            .get(1)
+            .unwrap()
+            .max_withdrawal;
+        assert_eq!(max, Some(5.0));
+    }
+
+    #[tokio::test]
+    async fn test_delta_reports_list_changed_clients() {
+        let dir = TempDir::new("watch-delta");
+        let deltas = dir.join("out");
+        let engine = PaymentsEngine::new();
+        let mut watcher = DirectoryWatcher::new(engine.clone(), dir.path())
+            .unwrap()
+            .with_delta_reports(&deltas)
+            .unwrap();
+        let cancel = CancellationToken::new();
+
+        fs::write(
+            dir.join("a.csv"),
+            "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,1.0\n",
+        )
+        .unwrap();
+        watcher.poll(&cancel).await.unwrap();
+        watcher.poll(&cancel).await.unwrap();
+        fs::write(
+            dir.join("b.csv"),
+            "type,client,tx,amount\nwithdrawal,2,3,0.5\n",
+        )
+        .unwrap();
+        watcher.poll(&cancel).await.unwrap();
+        watcher.poll(&cancel).await.unwrap();
+        watcher.poll(&cancel).await.unwrap();
+
+        let mut files: Vec<_> = fs::read_dir(&deltas)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].ends_with("delta-00000000000000000002.csv"));
+        assert_eq!(fs::read_to_string(&files[0]).unwrap().lines().count(), 3);
+        assert_eq!(
+            fs::read_to_string(&files[1]).unwrap(),
+            "client,available,held,total,locked\n2,0.5000,0.0000,0.5000,false\n"
+        );
+    }
+}
@@ -1,3 +1,33 @@
+pub mod accrual;
+pub mod anonymize;
+pub mod audit;
+pub mod conformance;
+pub mod currency;
+pub mod dedup;
+pub mod engine;
+pub mod erasure;
+pub mod events;
+pub mod fix;
+pub mod import;
+pub mod invariants;
 pub mod io;
+pub mod manifest;
+pub mod notify;
+pub mod outcome;
+pub mod plugin;
+pub mod policy;
 mod processor;
-mod transactions;
+pub mod remap;
+pub mod report;
+pub mod risk;
+pub mod rollup;
+pub mod router;
+pub mod screening;
+pub mod sim;
+pub mod statement;
+mod store;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod transactions;
+pub mod verify;
+pub mod webhook;
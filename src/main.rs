@@ -1,21 +1,778 @@
-use payments_engine::io;
-use std::env;
+mod cli;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{stdout, Write};
+use std::panic;
+use std::path::Path;
 use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{CommandFactory, FromArgMatches};
+use payments_engine::accrual::RateTable;
+use payments_engine::anonymize::{export, Anonymizer};
+use payments_engine::audit::{audit_input, audit_journal};
+use payments_engine::conformance::{run_suite, CaseOutcome};
+use payments_engine::dedup::TxIdIndex;
+use payments_engine::engine::{Checkpoint, InputFingerprint};
+use payments_engine::erasure::{erase_from_journal, ErasureCertificate};
+use payments_engine::fix::{FixAcceptor, SessionConfig};
+use payments_engine::import;
+use payments_engine::invariants::OnViolation;
+use payments_engine::io::provenance::InputDigest;
+use payments_engine::io::soak::{run_soak, SoakOptions};
+use payments_engine::io::{
+    self, partitioned::process_partitioned, tail::FileTailer, watch::DirectoryWatcher, Compression,
+    ReadOutcome, RowRange, TransactionReader,
+};
+use payments_engine::manifest::RunManifest;
+use payments_engine::policy::DuplicatePolicy;
+use payments_engine::rollup::{read_rollups, write_rollups};
+use payments_engine::sim::{divergent_seeds, Simulation, SyntheticFeed};
+use payments_engine::statement::{
+    read_statements, write_mt940, write_statements, Mt940Options, StatementDate, StatementFormat,
+};
+use payments_engine::transactions::Transaction;
+use payments_engine::verify::{verify_reports, verify_runs};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
 
-#[tokio::main]
-async fn main() {
-    let args: Vec<String> = env::args().collect();
+use cli::{Cli, Command, ExitStatus};
 
-    if args.len() != 2 {
-        println!("Usage: ");
-        println!("\t{} transactions.csv", args[0]);
-        process::exit(1);
+fn main() {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    cli::init_logging(cli.log_level(), cli.engine_options().run_id);
+    log::debug!("Run {}", cli.engine_options().run_id());
+    if cli.engine_options().check_invariants == Some(OnViolation::Abort) {
+        // Transactions are applied on runtime tasks, which would otherwise
+        // swallow the panic and carry on
+        let strict = cli.strict_exit;
+        let report = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            report(info);
+            process::exit(ExitStatus::code(
+                Err(ExitStatus::InvariantViolation),
+                strict,
+            ));
+        }));
     }
+    let runtime = cli
+        .engine_options()
+        .runtime()
+        .expect("Error starting runtime");
+
+    runtime.block_on(run(cli, Cli::effective_config(&matches)));
+    // A named pipe interrupted while waiting for its writer leaves a
+    // blocking read behind, which would hold up a regular shutdown forever
+    runtime.shutdown_background();
+}
+
+async fn run(cli: Cli, config: BTreeMap<String, String>) {
+    // Stop reading input promptly on ctrl-c; whatever was already read is
+    // still applied and reported
+    let cancel = CancellationToken::new();
+    tokio::spawn({
+        let cancel = cancel.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                cancel.cancel();
+            }
+        }
+    });
+
+    match cli.command {
+        Some(Command::Watch {
+            dir,
+            report,
+            delta_dir,
+            poll_ms,
+            report_options,
+            chaos,
+            engine,
+        }) => {
+            report_options
+                .write_schema()
+                .expect("Error writing report schema");
+            let limits = engine.client_limits.as_ref().map(|file| file.path.clone());
+            let engine = engine.build();
+            let mut watcher = DirectoryWatcher::new(engine.clone(), &dir)
+                .expect("Error preparing watch directory")
+                .with_report_layout(report_options.layout());
+            if let Some(limits) = limits {
+                watcher = watcher
+                    .with_client_limits(&limits)
+                    .expect("Error reading client limits file");
+            }
+            if let Some(report) = report {
+                watcher = watcher.with_report(&report);
+            }
+            if let Some(delta_dir) = delta_dir {
+                watcher = watcher
+                    .with_delta_reports(&delta_dir)
+                    .expect("Error preparing delta directory");
+            }
+            if let Some(chaos) = chaos.chaos() {
+                watcher = watcher.with_chaos(chaos);
+            }
+
+            watcher
+                .run(Duration::from_millis(poll_ms), &cancel)
+                .await
+                .expect("Error watching directory");
+
+            engine.flush_events().expect("Error writing events");
+            report_options.print(&engine).expect("Error writing report");
+        }
+        Some(Command::Tail {
+            input,
+            report,
+            delta_dir,
+            poll_ms,
+            report_options,
+            engine,
+        }) => {
+            report_options
+                .write_schema()
+                .expect("Error writing report schema");
+            let engine = engine.build();
+            let mut tailer =
+                FileTailer::new(engine.clone(), &input).with_report_layout(report_options.layout());
+            if let Some(report) = report {
+                tailer = tailer.with_report(&report);
+            }
+            if let Some(delta_dir) = delta_dir {
+                tailer = tailer
+                    .with_delta_reports(&delta_dir)
+                    .expect("Error preparing delta directory");
+            }
+
+            tailer
+                .run(Duration::from_millis(poll_ms), &cancel)
+                .await
+                .expect("Error following input file");
+
+            engine.flush_events().expect("Error writing events");
+            report_options.print(&engine).expect("Error writing report");
+        }
+        Some(Command::Simulate {
+            input,
+            seed,
+            window,
+            seeds,
+            engine,
+        }) => {
+            let transactions = File::open(&input)
+                .map_err(Into::into)
+                .and_then(TransactionReader::new)
+                .and_then(|reader| reader.collect::<Result<Vec<Transaction>, _>>())
+                .expect("Error reading CSV file");
+
+            match seeds {
+                Some(count) => {
+                    let divergent = divergent_seeds(
+                        &engine.builder(),
+                        &transactions,
+                        seed..seed.saturating_add(count),
+                        window,
+                    );
+                    for seed in &divergent {
+                        println!("seed {} diverges from in-order processing", seed);
+                    }
+                    if !divergent.is_empty() {
+                        process::exit(1);
+                    }
+                }
+                None => Simulation::new(seed)
+                    .window(window)
+                    .run(&engine.builder(), &transactions)
+                    .write_report(stdout().lock())
+                    .expect("Error writing report"),
+            }
+        }
+        Some(Command::Soak {
+            duration_secs,
+            report_every_secs,
+            clients,
+            seed,
+            engine,
+        }) => {
+            let engine = engine.build();
+            let options = SoakOptions {
+                report_every: Duration::from_secs(report_every_secs),
+                duration: duration_secs.map(Duration::from_secs),
+            };
 
+            let stats = run_soak(
+                &engine,
+                SyntheticFeed::new(seed, clients),
+                &options,
+                &cancel,
+            )
+            .await;
+            engine.flush_events().expect("Error writing events");
+            log::info!("soak: finished after {} transactions", stats.applied);
+        }
+        Some(Command::Fix {
+            listen,
+            sender_comp_id,
+            target_comp_id,
+            begin_string,
+            first_tx,
+            report_options,
+            engine,
+        }) => {
+            report_options
+                .write_schema()
+                .expect("Error writing report schema");
+            let engine = engine.build();
+            let listener = TcpListener::bind(&listen)
+                .await
+                .expect("Error listening for FIX connections");
+            let config = SessionConfig {
+                begin_string,
+                sender_comp_id,
+                target_comp_id,
+                first_tx,
+            };
+
+            FixAcceptor::new(engine.clone(), config)
+                .run(listener, &cancel)
+                .await
+                .expect("Error accepting FIX connections");
+            engine.flush_events().expect("Error writing events");
+            report_options.print(&engine).expect("Error writing report");
+        }
+        Some(Command::Verify {
+            reports,
+            input,
+            runs,
+            engine,
+        }) => {
+            let diverged = match input {
+                Some(input) => {
+                    let divergences = verify_runs(&engine.builder(), &input, runs)
+                        .await
+                        .expect("Error processing input");
+                    for divergence in &divergences {
+                        println!("run {} diverges from in-order processing", divergence.run);
+                        for diff in &divergence.diffs {
+                            println!("{}", diff);
+                        }
+                        for diff in &divergence.outcomes {
+                            println!("{}", diff);
+                        }
+                    }
+                    !divergences.is_empty()
+                }
+                None => {
+                    let diffs =
+                        verify_reports(&reports[0], &reports[1]).expect("Error reading reports");
+                    for diff in &diffs {
+                        println!("{}", diff);
+                    }
+                    !diffs.is_empty()
+                }
+            };
+            if diverged {
+                process::exit(1);
+            }
+        }
+        Some(Command::AuditBalances {
+            report,
+            journal,
+            input,
+            engine,
+        }) => {
+            let diffs = match (journal, input) {
+                (Some(journal), _) => audit_journal(&journal, &report),
+                (None, Some(input)) => audit_input(&input, &engine.builder(), &report),
+                (None, None) => unreachable!("clap requires --journal or --input"),
+            }
+            .expect("Error auditing balances");
+            for diff in &diffs {
+                println!("{}", diff);
+            }
+            if !diffs.is_empty() {
+                process::exit(1);
+            }
+        }
+        Some(Command::Statement {
+            journal,
+            client,
+            from,
+            to,
+            html,
+            mt940,
+            date,
+            statement_number,
+            amounts,
+        }) => {
+            let statements = read_statements(&journal, &client, from..=to.unwrap_or(u64::MAX))
+                .expect("Error reading journal");
+            match (mt940, amounts.currency) {
+                (true, Some(currency)) => {
+                    let options = Mt940Options {
+                        currency,
+                        rounding: amounts.rounding,
+                        date: date.unwrap_or_else(StatementDate::today),
+                        statement_number,
+                    };
+                    write_mt940(&statements, &options, stdout().lock())
+                }
+                _ => {
+                    let format = match html {
+                        true => StatementFormat::Html,
+                        false => StatementFormat::Text,
+                    };
+                    write_statements(&statements, format, amounts.precision(), stdout().lock())
+                }
+            }
+            .expect("Error writing statements");
+        }
+        Some(Command::Rollup {
+            journal,
+            period,
+            output,
+            amounts,
+        }) => {
+            let totals = read_rollups(&journal, period).expect("Error reading journal");
+            match output {
+                Some(path) => write_compressed(&path, |writer| {
+                    write_rollups(&totals, amounts.precision(), writer)
+                }),
+                None => write_rollups(&totals, amounts.precision(), stdout().lock()),
+            }
+            .expect("Error writing rollups");
+        }
+        Some(Command::Export {
+            input,
+            out,
+            key_file,
+            bucket,
+            amounts,
+            engine,
+        }) => {
+            let key = fs::read(&key_file).expect("Error reading key file");
+            let key = key.trim_ascii_end();
+            if key.is_empty() {
+                log::error!("Key file {} is empty", key_file.display());
+                process::exit(1);
+            }
+            let mut anonymizer = Anonymizer::new(key);
+            if let Some(width) = bucket {
+                anonymizer = anonymizer.bucket_amounts(width);
+            }
+            export(
+                &input,
+                &engine.builder(),
+                &anonymizer,
+                amounts.precision(),
+                &out,
+            )
+            .expect("Error exporting");
+        }
+        Some(Command::EraseClient {
+            client,
+            journal,
+            checkpoint,
+        }) => {
+            // Erase from every checkpoint before writing any, so a refusal
+            // leaves them all as they were
+            let mut checkpoints = vec![];
+            for path in checkpoint {
+                let mut saved = Checkpoint::read(&path).expect("Error reading checkpoint");
+                match saved.erase_client(client) {
+                    Ok(certificate) => checkpoints.push((path, saved, certificate)),
+                    Err(reason) => {
+                        log::error!(
+                            "{}: can't erase client {}: {}",
+                            path.display(),
+                            client,
+                            reason
+                        );
+                        process::exit(1);
+                    }
+                }
+            }
+            for path in journal {
+                match erase_from_journal(&path, client) {
+                    Ok(certificate) => print_certificate(&path, &certificate),
+                    Err(err) => {
+                        log::error!("{}", err);
+                        process::exit(1);
+                    }
+                }
+            }
+            for (path, saved, certificate) in checkpoints {
+                saved.write(&path).expect("Error writing checkpoint");
+                print_certificate(&path, &certificate);
+            }
+        }
+        Some(Command::MergeClients {
+            from,
+            into,
+            checkpoint,
+        }) => {
+            let mut checkpoints = vec![];
+            for path in checkpoint {
+                let mut saved = Checkpoint::read(&path).expect("Error reading checkpoint");
+                match saved.merge_clients(from, into) {
+                    Ok(balances) => checkpoints.push((path, saved, balances)),
+                    Err(reason) => {
+                        log::error!(
+                            "{}: can't merge client {} into {}: {}",
+                            path.display(),
+                            from,
+                            into,
+                            reason
+                        );
+                        process::exit(1);
+                    }
+                }
+            }
+            for (path, saved, balances) in checkpoints {
+                saved.write(&path).expect("Error writing checkpoint");
+                println!(
+                    "{}: client {} now has available {}, held {}, total {}{}",
+                    path.display(),
+                    into,
+                    balances.available,
+                    balances.held,
+                    balances.total,
+                    if balances.locked { ", locked" } else { "" }
+                );
+            }
+        }
+        Some(Command::Conformance { dir, engine }) => {
+            let results = run_suite(&dir, &engine.builder()).expect("Error reading suite");
+
+            let mut failures = 0;
+            for result in &results {
+                match &result.outcome {
+                    CaseOutcome::Passed => println!("PASS {}", result.name),
+                    CaseOutcome::Failed(diffs) => {
+                        failures += 1;
+                        println!("FAIL {}", result.name);
+                        for diff in diffs {
+                            println!("{}", diff);
+                        }
+                    }
+                    CaseOutcome::Error(err) => {
+                        failures += 1;
+                        println!("ERROR {}: {}", result.name, err);
+                    }
+                }
+            }
+            println!("{} passed, {} failed", results.len() - failures, failures);
+            if failures > 0 {
+                process::exit(1);
+            }
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut stdout());
+        }
+        Some(Command::Man { out_dir }) => match out_dir {
+            Some(dir) => {
+                clap_mangen::generate_to(Cli::command(), &dir).expect("Error writing man pages")
+            }
+            None => clap_mangen::Man::new(Cli::command())
+                .render(&mut stdout())
+                .expect("Error writing man page"),
+        },
+        None => {
+            let result = process_input(&cli, config, &cancel).await;
+            let code = ExitStatus::code(result, cli.strict_exit);
+            if code != 0 {
+                process::exit(code);
+            }
+        }
+    }
+}
+
+/// Processes the input file and prints the report. Failures that stop the
+/// run are reported on stderr and returned as `Err`.
+async fn process_input(
+    cli: &Cli,
+    config: BTreeMap<String, String>,
+    cancel: &CancellationToken,
+) -> Result<ExitStatus, ExitStatus> {
     // In a "real" setting, we will be fed this data through a socket.
     // Therefore, use async task here to handle that within an async task
     // A new task will be spawned when new transactions are posted.
-    io::read_csv(&args[1])
-        .await
-        .expect("Error reading CSV file");
+    // Safe to unwrap, clap prints the help when no arguments are given
+    let input = cli.input.as_deref().unwrap();
+    let needs_file = cli.partitions.is_some()
+        || cli.checkpoint.is_some()
+        || cli.resume.is_some()
+        || cli.manifest.is_some();
+    if needs_file && io::is_fifo(Path::new(input)) {
+        log::error!(
+            "{} is a named pipe, which can only be read once from start to end; \
+             --partitions, --checkpoint, --resume and --manifest need a regular file",
+            input
+        );
+        return Err(ExitStatus::InvalidInput);
+    }
+    if let Err(err) = cli.report.write_schema() {
+        log::error!("Error writing report schema: {}", err);
+        return Err(ExitStatus::OutputError);
+    }
+
+    let mut builder = cli.engine.builder().rounding(cli.report.rounding);
+    if cli.skip_other_clients {
+        builder = builder.only_clients(cli.clients.iter().copied());
+    }
+    let tx_id_index = match &cli.tx_id_index {
+        Some(path) => match TxIdIndex::read(path) {
+            Ok(index) => Some(Arc::new(index)),
+            Err(err) => {
+                log::error!(
+                    "Error reading transaction id index {}: {}",
+                    path.display(),
+                    err
+                );
+                return Err(ExitStatus::InvalidInput);
+            }
+        },
+        None => None,
+    };
+    if let Some(index) = &tx_id_index {
+        builder = builder.tx_id_index(index.clone());
+    }
+
+    let resumed = match &cli.resume {
+        Some(path) => {
+            let checkpoint = Checkpoint::read(path).and_then(|checkpoint| {
+                checkpoint.check_input(Path::new(input)).map(|_| checkpoint)
+            });
+            match checkpoint {
+                Ok(checkpoint) => Some(checkpoint),
+                Err(err) => {
+                    log::error!("Error resuming from {}: {}", path.display(), err);
+                    return Err(ExitStatus::InvalidInput);
+                }
+            }
+        }
+        None => None,
+    };
+    let fingerprinted = cli.checkpoint.is_some() || cli.manifest.is_some();
+    let fingerprint = match (fingerprinted, &resumed) {
+        (false, _) => None,
+        (true, Some(checkpoint)) => Some(checkpoint.input),
+        (true, None) => match InputFingerprint::of_file(Path::new(input)) {
+            Ok(fingerprint) => Some(fingerprint),
+            Err(err) => {
+                log::error!("Error reading CSV file: {}", err);
+                return Err(ExitStatus::InvalidInput);
+            }
+        },
+    };
+
+    let rates = match &cli.interest_rates {
+        Some(path) => match RateTable::read(path) {
+            Ok(rates) => Some(rates),
+            Err(err) => {
+                log::error!("Error reading interest rates: {}", err);
+                return Err(ExitStatus::InvalidInput);
+            }
+        },
+        None => None,
+    };
+
+    let mut manifest = cli
+        .manifest
+        .as_ref()
+        .zip(fingerprint)
+        .map(|(_, fingerprint)| RunManifest::new(Path::new(input), fingerprint, config));
+
+    let mut interrupted = false;
+    let mut rows_read = 0;
+    let read = match (cli.partitions, cli.import.zip(cli.accounts.as_ref())) {
+        (_, Some((format, accounts))) => {
+            match import::read(format, Path::new(input), accounts, cli.first_tx) {
+                Ok(transactions) => {
+                    let engine = builder.build();
+                    rows_read = transactions.len() as u64;
+                    io::process_transactions(&engine, transactions).await;
+                    // The importers parse the file whole, so it is hashed apart
+                    match InputDigest::of_file(Path::new(input)) {
+                        Ok(digest) => {
+                            engine.record_input(digest);
+                            Ok(engine)
+                        }
+                        Err(err) => Err(err.into()),
+                    }
+                }
+                Err(err) => Err(err),
+            }
+        }
+        (Some(partitions), None) => process_partitioned(&builder, Path::new(input), partitions),
+        (None, None) => {
+            let engine = builder.build();
+            let mut rows = RowRange {
+                skip: cli.skip,
+                limit: cli.limit,
+            };
+            if let Some(checkpoint) = &resumed {
+                engine.restore(checkpoint);
+                rows.skip = checkpoint.rows;
+            }
+            let progress = match &cli.fixed_width {
+                Some(layout) => {
+                    io::read_fixed_width_rows(
+                        &engine,
+                        Path::new(input),
+                        layout.clone(),
+                        rows,
+                        cancel,
+                    )
+                    .await
+                }
+                None => io::read_csv_rows(&engine, input, rows, cancel).await,
+            };
+            progress.map(|progress| {
+                if progress.outcome == ReadOutcome::Cancelled {
+                    log::warn!("Interrupted, reporting the transactions read so far");
+                    interrupted = true;
+                }
+                rows_read = progress.rows;
+                engine
+            })
+        }
+    };
+    let engine = read.map_err(|err| {
+        log::error!("Error reading CSV file: {}", err);
+        ExitStatus::InvalidInput
+    })?;
+    if let (Some(path), Some(fingerprint)) = (&cli.checkpoint, fingerprint) {
+        if let Err(err) = engine.checkpoint(fingerprint, rows_read).write(path) {
+            log::error!("Error writing checkpoint: {}", err);
+            return Err(ExitStatus::OutputError);
+        }
+    }
+    // Accrual is for the whole period, so only once the input is complete
+    // and after the checkpoint, which a resumed run must not accrue twice.
+    if let Some(rates) = rates.filter(|_| !interrupted) {
+        let posted = engine.accrue_interest(&rates);
+        log::debug!("Credited interest to {} clients", posted.len());
+    }
+    log::debug!(
+        "Read {} rows: {} malformed, {} transactions rejected, {} duplicates",
+        rows_read,
+        engine.malformed_row_count(),
+        engine.rejected_count(),
+        engine.duplicate_count()
+    );
+    if let Some(contention) = engine.contention() {
+        let hot: Vec<String> = contention
+            .hot_clients
+            .iter()
+            .map(|(client, waits)| format!("{} ({})", client, waits))
+            .collect();
+        log::info!(
+            "{} of {} transactions waited for their client's lock, {:?} in total; \
+             most often clients {}",
+            contention.contended,
+            contention.transactions,
+            contention.waited,
+            if hot.is_empty() {
+                "none".to_string()
+            } else {
+                hot.join(", ")
+            }
+        );
+    }
+    if cli.engine.duplicates == DuplicatePolicy::Reject && engine.duplicate_count() > 0 {
+        log::error!(
+            "{} transactions reused an earlier transaction id, not printing a report",
+            engine.duplicate_count()
+        );
+        return Err(ExitStatus::InvalidInput);
+    }
+    let status = if engine.invariant_violation_count() > 0 {
+        ExitStatus::InvariantViolation
+    } else if engine.malformed_row_count() > 0 {
+        ExitStatus::InvalidInput
+    } else if interrupted {
+        ExitStatus::Interrupted
+    } else if engine.rejected_count() > 0 {
+        ExitStatus::Rejected
+    } else {
+        ExitStatus::Success
+    };
+
+    if let Err(err) = engine.flush_events() {
+        log::error!("Error writing events: {}", err);
+        return Err(ExitStatus::OutputError);
+    }
+    if !cli.clients.is_empty() {
+        engine.retain_clients(&cli.clients);
+    }
+    if let Err(err) = cli.report.print(&engine) {
+        log::error!("Error writing report: {}", err);
+        return Err(ExitStatus::OutputError);
+    }
+    if let (Some(path), Some(index)) = (&cli.tx_id_index, &tx_id_index) {
+        if let Err(err) = index.write(path) {
+            log::error!("Error writing transaction id index: {}", err);
+            return Err(ExitStatus::OutputError);
+        }
+    }
+    if let Some(path) = &cli.payouts {
+        if let Err(err) = write_compressed(path, |writer| {
+            engine.write_payouts(cli.report.precision(), writer)
+        }) {
+            log::error!("Error writing payouts: {}", err);
+            return Err(ExitStatus::OutputError);
+        }
+    }
+    if let Some(path) = &cli.disputes {
+        if let Err(err) = write_compressed(path, |writer| {
+            engine.write_dispute_report(cli.report.precision(), writer)
+        }) {
+            log::error!("Error writing dispute report: {}", err);
+            return Err(ExitStatus::OutputError);
+        }
+    }
+    if let Some(path) = &cli.screening {
+        if let Err(err) = write_compressed(path, |writer| {
+            engine.write_screening_report(cli.report.precision(), writer)
+        }) {
+            log::error!("Error writing screening report: {}", err);
+            return Err(ExitStatus::OutputError);
+        }
+    }
+    if let (Some(path), Some(manifest)) = (&cli.manifest, &mut manifest) {
+        let rows_read = cli.partitions.is_none().then_some(rows_read);
+        manifest.finish(&engine, rows_read, interrupted);
+        if let Err(err) = manifest.write(path) {
+            log::error!("Error writing manifest: {}", err);
+            return Err(ExitStatus::OutputError);
+        }
+    }
+    Ok(status)
+}
+
+/// Creates the file at `path`, compressed according to its extension, and
+/// fills it with `write`.
+fn write_compressed<F>(path: &Path, write: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnOnce(&mut dyn Write) -> Result<(), Box<dyn Error>>,
+{
+    let mut writer = Compression::from_path(path).writer(File::create(path)?)?;
+    write(&mut writer)?;
+    Ok(writer.finish()?)
+}
+
+fn print_certificate(path: &Path, certificate: &ErasureCertificate) {
+    println!(
+        "{}: {}",
+        path.display(),
+        serde_json::to_string(certificate).expect("Error writing certificate")
+    );
 }
@@ -0,0 +1,186 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::engine::{InputFingerprint, PaymentsEngine, RunId};
+use crate::io::provenance::InputDigest;
+use crate::io::REPORT_SCHEMA_VERSION;
+
+/// Describes what produced a report: the engine version, the input and its
+/// fingerprint, the effective settings, when the run happened and what it
+/// read, so any report can be traced back to exactly how it was made.
+/// Written as JSON next to the report.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunManifest {
+    pub engine_version: &'static str,
+    pub report_schema_version: u32,
+    /// Id of the run, which its outcome events and log lines carry too.
+    pub run_id: Option<RunId>,
+    pub input: InputManifest,
+    /// Settings by name, e.g. `"hasher": "sip"`, including defaults and
+    /// those set through the environment.
+    pub config: BTreeMap<String, String>,
+    /// CRC-32 of `config` serialized as JSON, to compare runs at a glance.
+    pub config_crc32: String,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    /// Data rows read, malformed ones included. `None` where the reader
+    /// doesn't count them, as in partitioned runs.
+    pub rows_read: Option<u64>,
+    pub malformed_rows: u64,
+    pub rejected: u64,
+    /// Whether reading stopped early, e.g. on ctrl-c.
+    pub interrupted: bool,
+    /// blake3 digests of the input segments the report was made from,
+    /// including those read before a checkpoint the run resumed from.
+    pub inputs: Vec<InputDigest>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InputManifest {
+    pub path: String,
+    #[serde(flatten)]
+    pub fingerprint: InputFingerprint,
+}
+
+impl RunManifest {
+    /// Starts the manifest of a run beginning now.
+    pub fn new(
+        input: &Path,
+        fingerprint: InputFingerprint,
+        config: BTreeMap<String, String>,
+    ) -> Self {
+        // Serializing a map of strings can't fail
+        let config_json = serde_json::to_vec(&config).unwrap();
+        Self {
+            engine_version: env!("CARGO_PKG_VERSION"),
+            report_schema_version: REPORT_SCHEMA_VERSION,
+            run_id: None,
+            input: InputManifest {
+                path: input.display().to_string(),
+                fingerprint,
+            },
+            config,
+            config_crc32: format!("{:08x}", crc32fast::hash(&config_json)),
+            started_at: rfc3339(SystemTime::now()),
+            finished_at: None,
+            rows_read: None,
+            malformed_rows: 0,
+            rejected: 0,
+            interrupted: false,
+            inputs: vec![],
+        }
+    }
+
+    /// Records the counts of the finished run and the time it ended.
+    pub fn finish(&mut self, engine: &PaymentsEngine, rows_read: Option<u64>, interrupted: bool) {
+        self.run_id = engine.run_id();
+        self.finished_at = Some(rfc3339(SystemTime::now()));
+        self.rows_read = rows_read;
+        self.malformed_rows = engine.malformed_row_count();
+        self.rejected = engine.rejected_count();
+        self.interrupted = interrupted;
+        self.inputs = engine.inputs();
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+}
+
+/// Formats a time as an RFC 3339 UTC timestamp with millisecond precision,
+/// e.g. `2024-05-01T12:30:00.250Z`.
+fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let day_seconds = seconds % 86_400;
+    let (year, month, day) = utc_date(time);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_seconds / 3_600,
+        day_seconds % 3_600 / 60,
+        day_seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The UTC calendar date of a time, as year, month and day.
+pub(crate) fn utc_date(time: SystemTime) -> (i64, i64, i64) {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86_400;
+
+    // Civil date from days since 1970-01-01, after Howard Hinnant's
+    // days_from_civil inverse
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::Transaction;
+    use std::time::Duration;
+
+    #[test]
+    fn test_timestamps_are_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_millis(951_827_696_250)),
+            "2000-02-29T12:34:56.250Z"
+        );
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(4_107_542_399)),
+            "2100-02-28T23:59:59.000Z"
+        );
+    }
+
+    #[test]
+    fn test_manifest_records_the_run() {
+        let run_id = RunId::generate();
+        let engine = PaymentsEngine::builder().run_id(run_id).build();
+        engine.apply_transaction(Transaction::new_withdrawal(1, 1, 1.0));
+        let fingerprint = InputFingerprint { len: 10, crc32: 7 };
+        let config = BTreeMap::from([("hasher".to_string(), "sip".to_string())]);
+
+        let mut manifest = RunManifest::new(Path::new("in.csv"), fingerprint, config.clone());
+        manifest.finish(&engine, Some(1), false);
+        let json = serde_json::to_value(&manifest).unwrap();
+
+        assert_eq!(json["run_id"], run_id.to_string());
+        assert_eq!(json["input"]["path"], "in.csv");
+        assert_eq!(json["input"]["crc32"], 7);
+        assert_eq!(json["rejected"], 1);
+        assert_eq!(json["config"]["hasher"], "sip");
+        assert!(manifest.finished_at.is_some());
+        assert_eq!(
+            manifest.config_crc32,
+            RunManifest::new(Path::new("other.csv"), fingerprint, config).config_crc32
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::events::{write_event, ClientEventKind, Event, EventSink};
+use crate::outcome::TransactionOutcome;
+use crate::transactions::TransactionType;
+
+/// What an alert is about, for routing it: a transaction of a type that
+/// was applied, e.g. a chargeback, or a change to a client's account, e.g.
+/// it being locked.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AlertType {
+    Transaction(TransactionType),
+    Lifecycle(ClientEventKind),
+}
+
+impl AlertType {
+    /// The type of alert `event` makes, if any. Transactions rejected,
+    /// ignored or held for review, and input segments, make none.
+    pub fn of(event: &Event) -> Option<Self> {
+        match event {
+            Event::Outcome(event) => match event.outcome {
+                TransactionOutcome::Applied { .. } => {
+                    Some(AlertType::Transaction(event.transaction.tx_type))
+                }
+                _ => None,
+            },
+            Event::Client(event) => Some(AlertType::Lifecycle(event.kind)),
+            Event::Erasure(_) => Some(AlertType::Lifecycle(ClientEventKind::Erased)),
+            Event::Merge(_) => Some(AlertType::Lifecycle(ClientEventKind::Merged)),
+            Event::Input(_) => None,
+        }
+    }
+}
+
+impl FromStr for AlertType {
+    type Err = String;
+
+    /// Parses a transaction type, e.g. `chargeback`, or a lifecycle change:
+    /// `created`, `locked`, `closed`, `erased` or `merged`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(kind) = ClientEventKind::ALL.iter().find(|kind| kind.as_str() == s) {
+            return Ok(AlertType::Lifecycle(*kind));
+        }
+        s.parse().map(AlertType::Transaction).map_err(|_| {
+            format!(
+                "unknown alert type '{}', expected a transaction type or created, locked, \
+                 closed, erased or merged",
+                s
+            )
+        })
+    }
+}
+
+impl fmt::Display for AlertType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AlertType::Transaction(tx_type) => tx_type.as_str(),
+            AlertType::Lifecycle(kind) => kind.as_str(),
+        })
+    }
+}
+
+/// Destination of alerts, such as locked accounts and chargebacks, routed
+/// to it by type through a [`Notifier`].
+///
+/// Alerts are handed over by the [`Notifier`], an [`EventSink`], so the
+/// same constraints apply. One that delivers over the network queues the
+/// alert and sends it in the background, as
+/// [`WebhookSink`](crate::webhook::WebhookSink) does.
+pub trait NotificationSink: Send + Sync {
+    fn notify(&self, alert: AlertType, event: &Event);
+
+    /// Waits for alerts still being delivered, e.g. at the end of a run.
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Routes the events that make alerts to the notification sinks
+/// configured for their type, e.g. locked accounts to a file that paging
+/// watches and chargebacks to a webhook. A sink routed several types gets
+/// each alert once.
+#[derive(Default)]
+pub struct Notifier {
+    routes: Vec<(Vec<AlertType>, Box<dyn NotificationSink>)>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends alerts of `types` to `sink`.
+    pub fn route<S: NotificationSink + 'static>(
+        mut self,
+        types: impl IntoIterator<Item = AlertType>,
+        sink: S,
+    ) -> Self {
+        self.routes
+            .push((types.into_iter().collect(), Box::new(sink)));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+}
+
+impl EventSink for Notifier {
+    fn publish(&self, event: &Event) {
+        let Some(alert) = AlertType::of(event) else {
+            return;
+        };
+        for (types, sink) in &self.routes {
+            if types.contains(&alert) {
+                sink.notify(alert, event);
+            }
+        }
+    }
+
+    /// Flushes every sink, even after one fails, returning the first
+    /// error.
+    fn flush(&self) -> io::Result<()> {
+        let mut result = Ok(());
+        for (_, sink) in &self.routes {
+            let flushed = sink.flush();
+            result = result.and(flushed);
+        }
+        result
+    }
+}
+
+/// Appends every alert to a file as the event's line of JSON, as in the
+/// journal, written through at once so a tool tailing the file sees it.
+///
+/// Write errors are reported on stderr and the alert dropped; the next
+/// alert is tried again.
+pub struct FileNotifier {
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl FileNotifier {
+    /// Opens `path` for appending, creating it if missing.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: Mutex::new(BufWriter::new(file)),
+        })
+    }
+}
+
+impl NotificationSink for FileNotifier {
+    fn notify(&self, alert: AlertType, event: &Event) {
+        let mut writer = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        let written = write_event(&mut *writer, event)
+            .map_err(io::Error::from)
+            .and_then(|()| writer.write_all(b"\n"))
+            .and_then(|()| writer.flush());
+        if let Err(err) = written {
+            log::error!("Failed to write {} alert: {}", alert, err);
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.writer
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .flush()
+    }
+}
+
+/// Sends every alert into a channel with its type, for handlers in the
+/// same process, e.g. one freezing a client's cards when the account is
+/// locked. Unbounded like [`ChannelSink`](crate::events::ChannelSink);
+/// alerts are dropped once the receiver is gone.
+pub struct ChannelNotifier {
+    sender: UnboundedSender<(AlertType, Event)>,
+}
+
+impl ChannelNotifier {
+    pub fn new(sender: UnboundedSender<(AlertType, Event)>) -> Self {
+        Self { sender }
+    }
+}
+
+impl NotificationSink for ChannelNotifier {
+    fn notify(&self, alert: AlertType, event: &Event) {
+        let _ = self.sender.send((alert, event.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::test_support::TempDir;
+    use crate::transactions::Transaction;
+    use std::fs;
+
+    #[test]
+    fn test_alerts_are_routed_by_type() {
+        let dir = TempDir::new("alerts");
+        let path = dir.join("alerts.jsonl");
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let locked: AlertType = "locked".parse().unwrap();
+        let chargeback: AlertType = "chargeback".parse().unwrap();
+        assert_eq!(chargeback.to_string(), "chargeback");
+        assert!("unlocked".parse::<AlertType>().is_err());
+
+        let engine = PaymentsEngine::builder()
+            .event_sink(
+                Notifier::new()
+                    .route([locked], FileNotifier::open(&path).unwrap())
+                    .route([locked, chargeback], ChannelNotifier::new(sender)),
+            )
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 5.0));
+        engine.apply_transaction(Transaction::new_dispute(1, 1));
+        engine.apply_transaction(Transaction::new_chargeback(1, 1));
+        // Rejected, so no alert
+        engine.apply_transaction(Transaction::new_chargeback(1, 1));
+        engine.flush_events().unwrap();
+        drop(engine);
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "{\"seq\":4,\"client\":1,\"lifecycle\":\"locked\"}\n"
+        );
+        let mut alerts = vec![];
+        while let Ok((alert, event)) = receiver.try_recv() {
+            alerts.push((alert, event.sequence()));
+        }
+        assert_eq!(alerts, [(locked, 4), (chargeback, 5)]);
+    }
+}
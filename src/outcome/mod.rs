@@ -0,0 +1,333 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::processor::Client;
+
+/// What applying one transaction did, so callers can tell submitters
+/// precisely what happened.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TransactionOutcome {
+    /// The transaction took effect; `balances` are the client's balances
+    /// right after it.
+    Applied { balances: Balances },
+    /// The deposit or withdrawal is held for review: its amount is held
+    /// until an `approve` or `deny` settles it. `balances` are the client's
+    /// balances right after it.
+    Held { balances: Balances },
+    /// The transaction was valid input but broke a rule, and changed
+    /// nothing.
+    Rejected { reason: RejectReason },
+    /// The transaction had already been applied, and changed nothing.
+    Ignored { reason: IgnoreReason },
+}
+
+impl TransactionOutcome {
+    /// The rejection code of a rejected or ignored transaction, see
+    /// [`RejectReason::code`].
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            TransactionOutcome::Rejected { reason } => Some(reason.code()),
+            TransactionOutcome::Ignored { reason } => Some(reason.code()),
+            TransactionOutcome::Applied { .. } | TransactionOutcome::Held { .. } => None,
+        }
+    }
+}
+
+/// What applying a batch of transactions did: either every leg took
+/// effect or none did.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchOutcome {
+    /// Every leg was applied; `outcomes` are theirs, in order.
+    Applied { outcomes: Vec<TransactionOutcome> },
+    /// Leg `leg`, counting from 0, would have been rejected or ignored with
+    /// `outcome`, so no leg was applied.
+    Rejected {
+        leg: usize,
+        outcome: TransactionOutcome,
+    },
+}
+
+impl fmt::Display for BatchOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BatchOutcome::Applied { outcomes } => write!(f, "applied {} legs", outcomes.len()),
+            BatchOutcome::Rejected { leg, outcome } => match outcome {
+                TransactionOutcome::Rejected { reason } => {
+                    write!(f, "leg {} rejected: {}", leg, reason)
+                }
+                TransactionOutcome::Ignored { reason } => {
+                    write!(f, "leg {} ignored: {}", leg, reason)
+                }
+                TransactionOutcome::Applied { .. } => write!(f, "leg {} applied", leg),
+                TransactionOutcome::Held { .. } => write!(f, "leg {} held", leg),
+            },
+        }
+    }
+}
+
+/// A client's balances at one point in time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Balances {
+    pub client: u16,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+impl From<&Client> for Balances {
+    fn from(client: &Client) -> Self {
+        Self {
+            client: client.id,
+            available: client.available,
+            held: client.held,
+            total: client.total,
+            locked: client.locked,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// A deposit, withdrawal or adjustment without an amount.
+    MissingAmount,
+    InsufficientFunds,
+    /// A withdrawal that would leave less than the client's minimum
+    /// balance available.
+    BelowMinimumBalance,
+    /// A dispute, resolve or chargeback for a client without an account.
+    UnknownClient,
+    /// A dispute, resolve or chargeback referring to a transaction that
+    /// doesn't exist or wasn't retained.
+    UnknownTransaction,
+    /// A dispute, resolve or chargeback referring to another client's
+    /// transaction.
+    ForeignTransaction,
+    /// A dispute of a transaction that is disputed or charged back.
+    AlreadyDisputed,
+    /// A resolve or chargeback of a transaction that isn't disputed.
+    NotDisputed,
+    /// A dispute for more than the transaction's undisputed amount.
+    ExceedsAmount,
+    /// A resolve or chargeback for more than is disputed.
+    ExceedsDisputed,
+    /// A resolve or chargeback for more than the client holds.
+    InsufficientHeld,
+    /// Any transaction for an account that was closed.
+    AccountClosed,
+    /// A dispute of a transaction held for review.
+    PendingReview,
+    /// An approve or deny of a transaction that isn't held for review.
+    NotPending,
+    /// A dispute of a transaction that was denied on review.
+    DeniedOnReview,
+    /// A deposit or withdrawal the engine's risk scorer rejected.
+    RiskRejected,
+    /// Any transaction of a client on the engine's blocklist.
+    ClientBlocked,
+    /// A close of an account with funds held by a dispute or review.
+    FundsHeld,
+    /// A close of a locked account, which needs settling by hand.
+    AccountLocked,
+    /// An amount under the minimum set for the transaction's type.
+    BelowMinimumAmount,
+    /// An amount over the maximum set for the transaction's type.
+    AboveMaximumAmount,
+    /// A credit or debit without a reason code.
+    MissingReason,
+    /// A credit or debit to an engine that doesn't accept adjustments.
+    Unauthorized,
+    /// A deposit or withdrawal repeating an earlier one's type and id,
+    /// under [`DuplicatePolicy::Reject`](crate::policy::DuplicatePolicy),
+    /// or one that can't replace the earlier one under
+    /// [`DuplicatePolicy::LastWriteWins`](crate::policy::DuplicatePolicy).
+    DuplicateTransaction,
+    /// An amount, or a balance it would lead to, above
+    /// [`MAX_AMOUNT`](crate::transactions::MAX_AMOUNT).
+    Overflow,
+    /// A balance that would go below minus
+    /// [`MAX_AMOUNT`](crate::transactions::MAX_AMOUNT).
+    Underflow,
+    /// A transaction of a custom type the engine has no handler for.
+    UnsupportedType,
+    /// A negative or NaN amount given to an
+    /// [`Account`](crate::plugin::Account) by a custom type's handler.
+    InvalidAmount,
+}
+
+impl RejectReason {
+    /// Every reason, in order of their codes.
+    pub const ALL: [RejectReason; 28] = [
+        RejectReason::DuplicateTransaction,
+        RejectReason::MissingAmount,
+        RejectReason::MissingReason,
+        RejectReason::BelowMinimumAmount,
+        RejectReason::AboveMaximumAmount,
+        RejectReason::Overflow,
+        RejectReason::Underflow,
+        RejectReason::UnsupportedType,
+        RejectReason::InvalidAmount,
+        RejectReason::InsufficientFunds,
+        RejectReason::BelowMinimumBalance,
+        RejectReason::InsufficientHeld,
+        RejectReason::FundsHeld,
+        RejectReason::UnknownClient,
+        RejectReason::UnknownTransaction,
+        RejectReason::ForeignTransaction,
+        RejectReason::AlreadyDisputed,
+        RejectReason::NotDisputed,
+        RejectReason::ExceedsAmount,
+        RejectReason::ExceedsDisputed,
+        RejectReason::PendingReview,
+        RejectReason::NotPending,
+        RejectReason::DeniedOnReview,
+        RejectReason::AccountClosed,
+        RejectReason::AccountLocked,
+        RejectReason::Unauthorized,
+        RejectReason::RiskRejected,
+        RejectReason::ClientBlocked,
+    ];
+
+    /// A stable code for the reason, for automation to branch on instead of
+    /// the wording: `E` and three digits, grouped by what went wrong.
+    ///
+    /// - `E00x`: the transaction itself, e.g. `E001` a repeated id, `E002` a
+    ///   missing amount, `E004`/`E005` an amount outside the limits, `E006`/
+    ///   `E007` an amount or balance out of range, `E008` a custom type
+    ///   without a handler
+    /// - `E01x`: funds, e.g. `E010` insufficient funds, `E013` funds held
+    /// - `E02x`: what the transaction refers to, e.g. `E021` an unknown
+    ///   transaction
+    /// - `E03x`: the state of a dispute, e.g. `E031` not disputed
+    /// - `E04x`: reviews, e.g. `E040` pending review
+    /// - `E05x`: the state of the account, closed or locked
+    /// - `E06x`: authorization, risk scoring and screening
+    ///
+    /// A code never changes meaning; new reasons get new codes.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::DuplicateTransaction => "E001",
+            RejectReason::MissingAmount => "E002",
+            RejectReason::MissingReason => "E003",
+            RejectReason::BelowMinimumAmount => "E004",
+            RejectReason::AboveMaximumAmount => "E005",
+            RejectReason::Overflow => "E006",
+            RejectReason::Underflow => "E007",
+            RejectReason::UnsupportedType => "E008",
+            RejectReason::InvalidAmount => "E009",
+            RejectReason::InsufficientFunds => "E010",
+            RejectReason::BelowMinimumBalance => "E011",
+            RejectReason::InsufficientHeld => "E012",
+            RejectReason::FundsHeld => "E013",
+            RejectReason::UnknownClient => "E020",
+            RejectReason::UnknownTransaction => "E021",
+            RejectReason::ForeignTransaction => "E022",
+            RejectReason::AlreadyDisputed => "E030",
+            RejectReason::NotDisputed => "E031",
+            RejectReason::ExceedsAmount => "E032",
+            RejectReason::ExceedsDisputed => "E033",
+            RejectReason::PendingReview => "E040",
+            RejectReason::NotPending => "E041",
+            RejectReason::DeniedOnReview => "E042",
+            RejectReason::AccountClosed => "E050",
+            RejectReason::AccountLocked => "E051",
+            RejectReason::Unauthorized => "E060",
+            RejectReason::RiskRejected => "E061",
+            RejectReason::ClientBlocked => "E062",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RejectReason::MissingAmount => "missing amount",
+            RejectReason::InsufficientFunds => "insufficient available funds",
+            RejectReason::BelowMinimumBalance => "withdrawal would breach the minimum balance",
+            RejectReason::UnknownClient => "unknown client",
+            RejectReason::UnknownTransaction => "unknown transaction",
+            RejectReason::ForeignTransaction => "transaction belongs to another client",
+            RejectReason::AlreadyDisputed => "transaction is already disputed",
+            RejectReason::NotDisputed => "transaction is not disputed",
+            RejectReason::ExceedsAmount => "amount exceeds the transaction's undisputed amount",
+            RejectReason::ExceedsDisputed => "amount exceeds the disputed amount",
+            RejectReason::InsufficientHeld => "insufficient held funds",
+            RejectReason::PendingReview => "transaction is pending review",
+            RejectReason::NotPending => "transaction is not pending review",
+            RejectReason::DeniedOnReview => "transaction was denied on review",
+            RejectReason::RiskRejected => "rejected by risk scoring",
+            RejectReason::ClientBlocked => "client is blocked by screening",
+            RejectReason::AccountClosed => "account is closed",
+            RejectReason::FundsHeld => "account has funds held",
+            RejectReason::AccountLocked => "account is locked",
+            RejectReason::BelowMinimumAmount => "amount is below the minimum",
+            RejectReason::AboveMaximumAmount => "amount is above the maximum",
+            RejectReason::MissingReason => "adjustment without a reason code",
+            RejectReason::Unauthorized => "adjustments are not allowed",
+            RejectReason::DuplicateTransaction => "transaction id was already used",
+            RejectReason::Overflow => "amount or balance above the largest supported amount",
+            RejectReason::Underflow => "balance below the smallest supported amount",
+            RejectReason::UnsupportedType => "no handler for the transaction type",
+            RejectReason::InvalidAmount => "amount is negative or not a number",
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IgnoreReason {
+    /// A transaction of the same type and id was applied before.
+    Duplicate,
+    /// The engine only processes a set of clients that doesn't include this
+    /// one.
+    FilteredClient,
+}
+
+impl IgnoreReason {
+    /// A stable code for the reason, from the same set as
+    /// [`RejectReason::code`]: a duplicate is `E001` whether it is ignored
+    /// or rejected.
+    pub fn code(&self) -> &'static str {
+        match self {
+            IgnoreReason::Duplicate => "E001",
+            IgnoreReason::FilteredClient => "E070",
+        }
+    }
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IgnoreReason::Duplicate => f.write_str("duplicate transaction"),
+            IgnoreReason::FilteredClient => f.write_str("client not processed"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_rejection_codes_are_unique_and_stable() {
+        let codes: HashSet<&str> = RejectReason::ALL.iter().map(RejectReason::code).collect();
+        assert_eq!(codes.len(), RejectReason::ALL.len());
+        assert!(!codes.contains(IgnoreReason::FilteredClient.code()));
+        assert!(codes
+            .iter()
+            .all(|code| code.len() == 4 && code.starts_with('E')));
+        assert_eq!(RejectReason::DuplicateTransaction.code(), "E001");
+        assert_eq!(IgnoreReason::Duplicate.code(), "E001");
+        assert_eq!(RejectReason::InsufficientFunds.code(), "E010");
+        assert_eq!(
+            TransactionOutcome::Rejected {
+                reason: RejectReason::UnknownTransaction
+            }
+            .code(),
+            Some("E021")
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use std::sync::Mutex;
+
+use crate::outcome::{Balances, RejectReason};
+use crate::processor::Client;
+use crate::transactions::Transaction;
+
+/// Applies transactions of a [`CustomType`](crate::transactions::CustomType)
+/// registered by the application, set per type through
+/// [`EngineBuilder::custom_handler`](crate::engine::EngineBuilder::custom_handler).
+///
+/// The engine checks a custom transaction as it does a deposit, against a
+/// closed account and the amount limits of its type, opens the client's
+/// account if it has none, and hands the handler the transaction and the
+/// account. The handler changes the account only through [`Account`], and
+/// its changes take effect together if it returns `Ok`; returning a reason
+/// rejects the transaction and discards them. Custom transactions aren't
+/// retained, so they can't be disputed or repeated as duplicates.
+///
+/// Handlers run with the client's account locked, see
+/// [`PaymentsEngine::apply_transaction`](crate::engine::PaymentsEngine::apply_transaction)
+/// for what that asks of them. Closures of the same signature are handlers
+/// too.
+pub trait CustomHandler: Send + Sync {
+    fn apply(&self, tx: &Transaction, account: &mut Account) -> Result<(), RejectReason>;
+}
+
+impl<F> CustomHandler for F
+where
+    F: Fn(&Transaction, &mut Account) -> Result<(), RejectReason> + Send + Sync,
+{
+    fn apply(&self, tx: &Transaction, account: &mut Account) -> Result<(), RejectReason> {
+        self(tx, account)
+    }
+}
+
+/// The state a [`CustomHandler`] may change: a client's balances, moved
+/// under the same rules as built-in transactions, and its lock. Amounts
+/// must be neither negative nor NaN; the opposite move is the other
+/// method.
+pub struct Account {
+    client: Client,
+    changes: Vec<Change>,
+}
+
+/// A change a handler made through an [`Account`], kept so that it can be
+/// made again without asking the handler, as [`Replay`] does.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Change {
+    Credit(f64),
+    Debit(f64),
+    Hold(f64),
+    Release(f64),
+    Lock,
+}
+
+impl Account {
+    pub(crate) fn new(client: Client) -> Self {
+        Self {
+            client,
+            changes: vec![],
+        }
+    }
+
+    pub(crate) fn into_client(self) -> Client {
+        self.client
+    }
+
+    /// The changes made so far, in order.
+    pub(crate) fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    pub fn client(&self) -> u16 {
+        self.client.id
+    }
+
+    /// The balances with the changes made so far.
+    pub fn balances(&self) -> Balances {
+        Balances::from(&self.client)
+    }
+
+    /// Adds `amount` to the available funds.
+    pub fn credit(&mut self, amount: f64) -> Result<(), RejectReason> {
+        let amount = checked_amount(amount)?;
+        self.client.move_funds(amount, 0.0)?;
+        self.changes.push(Change::Credit(amount));
+        Ok(())
+    }
+
+    /// Takes `amount` from the available funds, which must cover it.
+    pub fn debit(&mut self, amount: f64) -> Result<(), RejectReason> {
+        let amount = checked_amount(amount)?;
+        if self.client.available < amount {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        self.client.move_funds(-amount, 0.0)?;
+        self.changes.push(Change::Debit(amount));
+        Ok(())
+    }
+
+    /// Moves `amount` of the available funds to held, as a dispute does.
+    pub fn hold(&mut self, amount: f64) -> Result<(), RejectReason> {
+        let amount = checked_amount(amount)?;
+        if self.client.available < amount {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        self.client.move_funds(-amount, amount)?;
+        self.changes.push(Change::Hold(amount));
+        Ok(())
+    }
+
+    /// Moves `amount` of the held funds back to available, as a resolve
+    /// does.
+    pub fn release(&mut self, amount: f64) -> Result<(), RejectReason> {
+        let amount = checked_amount(amount)?;
+        if self.client.held < amount {
+            return Err(RejectReason::InsufficientHeld);
+        }
+        self.client.move_funds(amount, -amount)?;
+        self.changes.push(Change::Release(amount));
+        Ok(())
+    }
+
+    /// Locks the account, as a chargeback does. Nothing unlocks it. The
+    /// lock is recorded as the handler's, so
+    /// [`invariants::check`](crate::invariants::check) doesn't expect a
+    /// chargeback behind it.
+    pub fn lock(&mut self) {
+        self.client.locked = true;
+        self.client.frozen = true;
+        self.changes.push(Change::Lock);
+    }
+}
+
+/// Makes the changes another handler made before, in the same order,
+/// e.g. to apply a batch leg as it was checked without asking the handler
+/// twice. Each change is checked again, so on a different account the
+/// replay can still be rejected.
+pub(crate) struct Replay<'a>(pub(crate) &'a [Change]);
+
+impl CustomHandler for Replay<'_> {
+    fn apply(&self, _: &Transaction, account: &mut Account) -> Result<(), RejectReason> {
+        for change in self.0 {
+            match *change {
+                Change::Credit(amount) => account.credit(amount)?,
+                Change::Debit(amount) => account.debit(amount)?,
+                Change::Hold(amount) => account.hold(amount)?,
+                Change::Release(amount) => account.release(amount)?,
+                Change::Lock => account.lock(),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Passes transactions on to `handler` and keeps the changes it made to
+/// the account of the last one it accepted.
+pub(crate) struct Recording<'a> {
+    pub(crate) handler: &'a dyn CustomHandler,
+    pub(crate) changes: Mutex<Option<Vec<Change>>>,
+}
+
+impl CustomHandler for Recording<'_> {
+    fn apply(&self, tx: &Transaction, account: &mut Account) -> Result<(), RejectReason> {
+        self.handler.apply(tx, account)?;
+        *self.changes.lock().unwrap_or_else(|err| err.into_inner()) =
+            Some(account.changes().to_vec());
+        Ok(())
+    }
+}
+
+fn checked_amount(amount: f64) -> Result<f64, RejectReason> {
+    match amount >= 0.0 {
+        true => Ok(amount),
+        false => Err(RejectReason::InvalidAmount),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::invariants;
+    use crate::outcome::TransactionOutcome;
+    use crate::transactions::{CustomType, TransactionType};
+
+    #[test]
+    fn test_custom_types_route_to_their_handler() {
+        let fee = CustomType::register("account_fee").unwrap();
+        let freeze = CustomType::register("freeze").unwrap();
+        let refund = CustomType::register("refund_unhandled").unwrap();
+        assert_eq!(CustomType::register("account_fee"), Ok(fee));
+        assert!(CustomType::register("deposit").is_err());
+        assert!(CustomType::register("Fee").is_err());
+        assert_eq!("freeze".parse(), Ok(TransactionType::Custom(freeze)));
+
+        let engine = PaymentsEngine::builder()
+            .custom_handler(fee, |tx: &Transaction, account: &mut Account| {
+                account.debit(tx.amount.ok_or(RejectReason::MissingAmount)?)
+            })
+            .custom_handler(freeze, |_: &Transaction, account: &mut Account| {
+                let available = account.balances().available;
+                account.hold(available)?;
+                account.lock();
+                Ok(())
+            })
+            .build();
+        let custom = |tx_type, tx_id, amount| Transaction {
+            tx_type: TransactionType::Custom(tx_type),
+            client_id: 1,
+            tx_id,
+            amount,
+            reason: None,
+            dispute_reason: None,
+        };
+        let rejected = |reason| TransactionOutcome::Rejected { reason };
+
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 10.0));
+        assert_eq!(
+            engine.apply_transaction(custom(fee, 2, Some(12.0))),
+            rejected(RejectReason::InsufficientFunds)
+        );
+        assert!(matches!(
+            engine.apply_transaction(custom(fee, 3, Some(2.5))),
+            TransactionOutcome::Applied { balances } if balances.available == 7.5
+        ));
+        assert_eq!(
+            engine.apply_transaction(custom(refund, 4, Some(1.0))),
+            rejected(RejectReason::UnsupportedType)
+        );
+        assert!(matches!(
+            engine.apply_transaction(custom(freeze, 5, None)),
+            TransactionOutcome::Applied { balances }
+                if balances.held == 7.5 && balances.total == 7.5 && balances.locked
+        ));
+        // Locked without a chargeback, by the handler
+        assert_eq!(invariants::check(&engine), Ok(()));
+        // Not retained, so nothing to dispute
+        assert_eq!(
+            engine.apply_transaction(Transaction::new_dispute(1, 3)),
+            rejected(RejectReason::UnknownTransaction)
+        );
+    }
+}
@@ -0,0 +1,373 @@
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use crate::currency::RoundingMode;
+use crate::outcome::RejectReason;
+use crate::transactions::{Transaction, TransactionType};
+
+/// Business rules the engine applies on top of its fixed ones, set through
+/// the [`EngineBuilder`](crate::engine::EngineBuilder).
+///
+/// The default policy is the original behaviour: withdrawals never take
+/// the available balance below zero, no balance has to stay in reserve,
+/// no withdrawal is held for review, and any amount is accepted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Policy {
+    overdraft_limit: f64,
+    client_overdraft_limits: FxHashMap<u16, f64>,
+    minimum_balance: Option<f64>,
+    client_minimum_balances: FxHashMap<u16, f64>,
+    amount_limits: AmountLimits,
+    type_amount_limits: FxHashMap<TransactionType, AmountLimits>,
+    rounding: RoundingMode,
+    review_threshold: Option<f64>,
+    review_timeout: Option<ReviewTimeout>,
+    client_limits: ClientLimits,
+    duplicates: DuplicatePolicy,
+}
+
+/// When a transaction still pending review is denied.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ReviewTimeout {
+    /// Once this much time has passed on the engine's clock since it was
+    /// held.
+    After(Duration),
+    /// Once the engine has received this many more transactions, whatever
+    /// the time, so a replay of the same input denies the same ones.
+    AfterTransactions(u64),
+}
+
+/// What happens to a deposit or withdrawal whose id and type were seen
+/// before, e.g. a row repeated by an upstream retry.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Ignores it, as the engine always did.
+    #[default]
+    Ignore,
+    /// Ignores it and logs a warning naming it.
+    Warn,
+    /// Rejects it, and the run as a whole once it is over: the CLI then
+    /// prints no report and exits with an error.
+    Reject,
+    /// Replaces the earlier transaction: its effect on the client's
+    /// balances is undone and the new one applied in its place. An earlier
+    /// transaction that is disputed, charged back, held for review or
+    /// denied, or belongs to another client, can't be replaced, and the
+    /// new one is rejected.
+    LastWriteWins,
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(DuplicatePolicy::Ignore),
+            "warn" => Ok(DuplicatePolicy::Warn),
+            "reject" => Ok(DuplicatePolicy::Reject),
+            "last-write-wins" => Ok(DuplicatePolicy::LastWriteWins),
+            _ => Err(format!(
+                "unknown duplicate policy '{}', expected ignore, warn, reject or last-write-wins",
+                s
+            )),
+        }
+    }
+}
+
+/// Per-client limits loaded from a side file, taking precedence over the
+/// per-client limits set one by one and over the global ones. A limit left
+/// empty falls back to those.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientLimits {
+    limits: FxHashMap<u16, ClientLimit>,
+}
+
+/// One client's row of a [`ClientLimits`] file.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ClientLimit {
+    /// Largest amount a single withdrawal may carry.
+    pub max_withdrawal: Option<f64>,
+    pub overdraft_limit: Option<f64>,
+    pub minimum_balance: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ClientLimitRow {
+    client: u16,
+    max_withdrawal: Option<f64>,
+    overdraft_limit: Option<f64>,
+    minimum_balance: Option<f64>,
+}
+
+impl ClientLimits {
+    /// Reads a CSV file with a `client` column and any of the
+    /// `max_withdrawal`, `overdraft_limit` and `minimum_balance` columns.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(File::open(path)?)
+    }
+
+    pub fn parse<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut limits = FxHashMap::default();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for (index, row) in reader.deserialize().enumerate() {
+            let row: ClientLimitRow = row?;
+            let limit = ClientLimit {
+                max_withdrawal: row.max_withdrawal,
+                overdraft_limit: row.overdraft_limit,
+                minimum_balance: row.minimum_balance,
+            };
+            let valid = [
+                limit.max_withdrawal,
+                limit.overdraft_limit,
+                limit.minimum_balance,
+            ]
+            .iter()
+            .flatten()
+            .all(|amount| amount.is_finite() && *amount >= 0.0);
+            if !valid {
+                return Err(
+                    format!("row {}: limits must be non-negative amounts", index + 2).into(),
+                );
+            }
+            limits.insert(row.client, limit);
+        }
+        Ok(Self { limits })
+    }
+
+    pub fn get(&self, client: u16) -> Option<&ClientLimit> {
+        self.limits.get(&client)
+    }
+
+    pub fn len(&self) -> usize {
+        self.limits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+}
+
+/// Smallest and largest amount a transaction may carry, both inclusive.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AmountLimits {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl AmountLimits {
+    fn check(&self, amount: f64) -> Result<(), RejectReason> {
+        if self.min.is_some_and(|min| amount < min) {
+            return Err(RejectReason::BelowMinimumAmount);
+        }
+        if self.max.is_some_and(|max| amount > max) {
+            return Err(RejectReason::AboveMaximumAmount);
+        }
+        Ok(())
+    }
+}
+
+impl Policy {
+    /// How far below zero a withdrawal may take the client's available
+    /// balance: the client's own limit if it has one, the global one
+    /// otherwise.
+    pub fn overdraft_limit(&self, client: u16) -> f64 {
+        self.client_limits
+            .get(client)
+            .and_then(|limit| limit.overdraft_limit)
+            .or_else(|| self.client_overdraft_limits.get(&client).copied())
+            .unwrap_or(self.overdraft_limit)
+    }
+
+    /// Available balance a withdrawal must leave the client with, e.g. a
+    /// reserve held against a margin product: the client's own minimum if
+    /// it has one, the global one otherwise.
+    pub fn minimum_balance(&self, client: u16) -> Option<f64> {
+        self.client_limits
+            .get(client)
+            .and_then(|limit| limit.minimum_balance)
+            .or_else(|| self.client_minimum_balances.get(&client).copied())
+            .or(self.minimum_balance)
+    }
+
+    /// Limits loaded for individual clients, see [`ClientLimits`].
+    pub fn client_limits(&self) -> &ClientLimits {
+        &self.client_limits
+    }
+
+    /// Limits on the amounts of transactions of `tx_type`: its own if set,
+    /// the global ones otherwise.
+    pub fn amount_limits(&self, tx_type: TransactionType) -> AmountLimits {
+        self.type_amount_limits
+            .get(&tx_type)
+            .copied()
+            .unwrap_or(self.amount_limits)
+    }
+
+    /// Amount above which a withdrawal is held for review instead of
+    /// leaving the account.
+    pub fn review_threshold(&self) -> Option<f64> {
+        self.review_threshold
+    }
+
+    /// When a transaction held for review is denied, if reviews time out.
+    pub fn review_timeout(&self) -> Option<ReviewTimeout> {
+        self.review_timeout
+    }
+
+    /// What happens to repeated deposits and withdrawals.
+    pub fn duplicates(&self) -> DuplicatePolicy {
+        self.duplicates
+    }
+
+    /// Whether `tx` is a withdrawal to hold for review.
+    pub(crate) fn needs_review(&self, tx: &Transaction) -> bool {
+        tx.tx_type == TransactionType::Withdrawal
+            && matches!(
+                (tx.amount, self.review_threshold),
+                (Some(amount), Some(threshold)) if amount > threshold
+            )
+    }
+
+    /// How amounts the engine receives or computes, such as interest, are
+    /// rounded to the four places it keeps.
+    pub fn rounding(&self) -> RoundingMode {
+        self.rounding
+    }
+
+    /// Rejects a transaction whose amount is outside the limits for its
+    /// type, or a withdrawal above the client's own maximum. Transactions
+    /// without an amount always pass.
+    pub(crate) fn check_amount(&self, tx: &Transaction) -> Result<(), RejectReason> {
+        let amount = match tx.amount {
+            Some(amount) => amount,
+            None => return Ok(()),
+        };
+        let client_max = self
+            .client_limits
+            .get(tx.client_id)
+            .and_then(|limit| limit.max_withdrawal);
+        if tx.tx_type == TransactionType::Withdrawal && client_max.is_some_and(|max| amount > max) {
+            return Err(RejectReason::AboveMaximumAmount);
+        }
+        self.amount_limits(tx.tx_type).check(amount)
+    }
+
+    pub(crate) fn set_amount_limits(&mut self, limits: AmountLimits) {
+        self.amount_limits = limits;
+    }
+
+    pub(crate) fn set_type_amount_limits(
+        &mut self,
+        tx_type: TransactionType,
+        limits: AmountLimits,
+    ) {
+        self.type_amount_limits.insert(tx_type, limits);
+    }
+
+    pub(crate) fn set_client_limits(&mut self, limits: ClientLimits) {
+        self.client_limits = limits;
+    }
+
+    pub(crate) fn set_review_threshold(&mut self, threshold: f64) {
+        self.review_threshold = Some(threshold);
+    }
+
+    pub(crate) fn set_review_timeout(&mut self, timeout: ReviewTimeout) {
+        self.review_timeout = Some(timeout);
+    }
+
+    pub(crate) fn set_duplicates(&mut self, duplicates: DuplicatePolicy) {
+        self.duplicates = duplicates;
+    }
+
+    pub(crate) fn set_rounding(&mut self, rounding: RoundingMode) {
+        self.rounding = rounding;
+    }
+
+    pub(crate) fn set_overdraft_limit(&mut self, limit: f64) {
+        self.overdraft_limit = limit;
+    }
+
+    pub(crate) fn set_client_overdraft_limit(&mut self, client: u16, limit: f64) {
+        self.client_overdraft_limits.insert(client, limit);
+    }
+
+    pub(crate) fn set_minimum_balance(&mut self, minimum: f64) {
+        self.minimum_balance = Some(minimum);
+    }
+
+    pub(crate) fn set_client_minimum_balance(&mut self, client: u16, minimum: f64) {
+        self.client_minimum_balances.insert(client, minimum);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_type_amount_limits_replace_the_global_ones() {
+        let mut policy = Policy::default();
+        policy.set_amount_limits(AmountLimits {
+            min: Some(0.01),
+            max: Some(1_000_000.0),
+        });
+        policy.set_type_amount_limits(
+            TransactionType::Withdrawal,
+            AmountLimits {
+                min: None,
+                max: Some(500.0),
+            },
+        );
+
+        assert_eq!(
+            policy.check_amount(&Transaction::new_deposit(1, 1, 0.001)),
+            Err(RejectReason::BelowMinimumAmount)
+        );
+        assert_eq!(
+            policy.check_amount(&Transaction::new_deposit(1, 1, 2_000_000.0)),
+            Err(RejectReason::AboveMaximumAmount)
+        );
+        assert_eq!(
+            policy.check_amount(&Transaction::new_withdrawal(1, 2, 0.001)),
+            Ok(())
+        );
+        assert_eq!(
+            policy.check_amount(&Transaction::new_withdrawal(1, 2, 500.5)),
+            Err(RejectReason::AboveMaximumAmount)
+        );
+        assert_eq!(policy.check_amount(&Transaction::new_dispute(1, 1)), Ok(()));
+    }
+
+    #[test]
+    fn test_client_limits_take_precedence() {
+        let file = "client,max_withdrawal,overdraft_limit\n7,250,\n12,,50\n";
+        let mut policy = Policy::default();
+        policy.set_overdraft_limit(10.0);
+        policy.set_client_overdraft_limit(7, 20.0);
+        policy.set_client_overdraft_limit(12, 20.0);
+        policy.set_client_limits(ClientLimits::parse(file.as_bytes()).unwrap());
+
+        assert_eq!(policy.overdraft_limit(7), 20.0);
+        assert_eq!(policy.overdraft_limit(12), 50.0);
+        assert_eq!(policy.overdraft_limit(1), 10.0);
+        assert_eq!(
+            policy.check_amount(&Transaction::new_withdrawal(7, 1, 250.5)),
+            Err(RejectReason::AboveMaximumAmount)
+        );
+        assert_eq!(
+            policy.check_amount(&Transaction::new_deposit(7, 2, 250.5)),
+            Ok(())
+        );
+        assert!(ClientLimits::parse("client,minimum_balance\n7,-1\n".as_bytes()).is_err());
+    }
+}
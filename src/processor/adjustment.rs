@@ -0,0 +1,73 @@
+use super::{get_or_create_client, Client, Context, TransactionHandler};
+use crate::outcome::RejectReason;
+use crate::transactions::{Transaction, TransactionType};
+
+/// Applies a manual credit or debit, which needs a reason code.
+/// Adjustments aren't retained for disputes, and a debit may leave the
+/// account overdrawn.
+pub(super) struct Adjustment;
+
+impl TransactionHandler for Adjustment {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        tx.reason.ok_or(RejectReason::MissingReason)?;
+        let mut client =
+            get_or_create_client(tx.client_id, cx.sequence, cx.client_db, cx.lifecycle);
+        let amount = match tx.tx_type {
+            TransactionType::Credit => amount,
+            _ => -amount,
+        };
+        client.move_funds(amount, 0.0)?;
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::{Balances, RejectReason, TransactionOutcome};
+    use crate::policy::Policy;
+    use crate::processor::apply_transaction;
+    use crate::processor::tests::setup;
+    use crate::transactions::{AdjustmentReason, Transaction};
+
+    #[test]
+    fn test_adjustments_need_a_reason_and_may_overdraw() {
+        let (client_db, transactions_db) = setup();
+        let apply =
+            |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default()).outcome;
+        let balances = |available| TransactionOutcome::Applied {
+            balances: Balances {
+                client: 1,
+                available,
+                held: 0.0,
+                total: available,
+                locked: false,
+            },
+        };
+
+        assert_eq!(
+            apply(Transaction::new_credit(
+                1,
+                1,
+                2.0,
+                AdjustmentReason::Goodwill
+            )),
+            balances(2.0)
+        );
+        assert_eq!(
+            apply(Transaction::new_debit(1, 2, 5.0, AdjustmentReason::Fee)),
+            balances(-3.0)
+        );
+        let mut unexplained = Transaction::new_credit(1, 3, 1.0, AdjustmentReason::Correction);
+        unexplained.reason = None;
+        assert_eq!(
+            apply(unexplained),
+            TransactionOutcome::Rejected {
+                reason: RejectReason::MissingReason
+            }
+        );
+        assert_eq!(client_db.get(&1).unwrap().stats.adjusted, -3.0);
+        assert!(transactions_db.get(&1).is_none());
+    }
+}
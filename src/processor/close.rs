@@ -0,0 +1,66 @@
+use super::{Client, Context, TransactionHandler};
+use crate::outcome::RejectReason;
+use crate::transactions::Transaction;
+
+/// Closes an unlocked account without funds held, after which it rejects
+/// every transaction.
+pub(super) struct Close;
+
+impl TransactionHandler for Close {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let mut client = cx
+            .client_db
+            .get_mut(&tx.client_id)
+            .ok_or(RejectReason::UnknownClient)?;
+        if client.locked {
+            return Err(RejectReason::AccountLocked);
+        }
+        // Compare at the report's precision, so rounding left over from
+        // a resolved dispute doesn't keep the account open
+        if (client.held * 10_000.0).round() != 0.0 {
+            return Err(RejectReason::FundsHeld);
+        }
+        client.closed = true;
+        cx.lifecycle.closed = true;
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::{RejectReason, TransactionOutcome};
+    use crate::policy::Policy;
+    use crate::processor::apply_transaction;
+    use crate::processor::tests::setup;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_closing_an_account() {
+        let (client_db, transactions_db) = setup();
+        let apply = |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default());
+        let rejected = |reason| TransactionOutcome::Rejected { reason };
+
+        assert_eq!(
+            apply(Transaction::new_close(1, 1)).outcome,
+            rejected(RejectReason::UnknownClient)
+        );
+        apply(Transaction::new_deposit(1, 2, 3.0));
+        apply(Transaction::new_dispute(1, 2));
+        assert_eq!(
+            apply(Transaction::new_close(1, 3)).outcome,
+            rejected(RejectReason::FundsHeld)
+        );
+
+        apply(Transaction::new_resolve(1, 2));
+        let closed = apply(Transaction::new_close(1, 3));
+        assert!(matches!(closed.outcome, TransactionOutcome::Applied { .. }));
+        assert!(closed.lifecycle.closed);
+        assert!(client_db.get(&1).unwrap().closed);
+        assert_eq!(
+            apply(Transaction::new_deposit(1, 4, 1.0)).outcome,
+            rejected(RejectReason::AccountClosed)
+        );
+        assert_eq!(client_db.get(&1).unwrap().available, 3.0);
+    }
+}
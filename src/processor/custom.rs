@@ -0,0 +1,81 @@
+use super::{get_or_create_client, Client, Context, TransactionHandler};
+use crate::outcome::RejectReason;
+use crate::plugin::{Account, CustomHandler};
+use crate::transactions::Transaction;
+
+/// Applies a custom type's transactions through the [`CustomHandler`] the
+/// engine has for the type, or rejects them if it has none.
+pub(super) struct Custom<'a>(pub(super) Option<&'a dyn CustomHandler>);
+
+impl TransactionHandler for Custom<'_> {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let handler = self.0.ok_or(RejectReason::UnsupportedType)?;
+        let mut client =
+            get_or_create_client(tx.client_id, cx.sequence, cx.client_db, cx.lifecycle);
+        // The handler works on a copy, so a rejection leaves nothing half done
+        let mut account = Account::new(*client);
+        handler.apply(tx, &mut account)?;
+        let updated = account.into_client();
+        cx.lifecycle.locked = updated.locked && !client.locked;
+        *client = updated;
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::{RejectReason, TransactionOutcome};
+    use crate::plugin::{Account, CustomHandler};
+    use crate::policy::Policy;
+    use crate::processor::tests::setup;
+    use crate::processor::{apply_custom, apply_transaction};
+    use crate::transactions::{CustomType, Transaction, TransactionType};
+
+    #[test]
+    fn test_rejected_custom_transactions_change_nothing() {
+        let (client_db, transactions_db) = setup();
+        let policy = Policy::default();
+        let kind = CustomType::register("sweep").unwrap();
+        let sweep = Transaction {
+            tx_type: TransactionType::Custom(kind),
+            client_id: 1,
+            tx_id: 2,
+            amount: Some(4.0),
+            reason: None,
+            dispute_reason: None,
+        };
+        // Moves the whole balance to held, then fails on the amount
+        let handler = |tx: &Transaction, account: &mut Account| {
+            let available = account.balances().available;
+            account.hold(available)?;
+            account.debit(tx.amount.unwrap())
+        };
+        let apply = |handler: Option<&dyn CustomHandler>| {
+            apply_custom(sweep, 0, handler, &client_db, &transactions_db, &policy)
+        };
+
+        apply_transaction(
+            Transaction::new_deposit(1, 1, 3.0),
+            0,
+            &client_db,
+            &transactions_db,
+            &policy,
+        );
+        assert_eq!(
+            apply(Some(&handler)).outcome,
+            TransactionOutcome::Rejected {
+                reason: RejectReason::InsufficientFunds
+            }
+        );
+        assert_eq!(
+            apply(None).outcome,
+            TransactionOutcome::Rejected {
+                reason: RejectReason::UnsupportedType
+            }
+        );
+        let client = client_db.get(&1).unwrap();
+        assert_eq!((client.available, client.held), (3.0, 0.0));
+        assert_eq!(client.stats.transactions, 1);
+    }
+}
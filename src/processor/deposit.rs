@@ -0,0 +1,109 @@
+use super::{get_or_create_client, insert_new_transaction, Client, Context, TransactionHandler};
+use crate::outcome::RejectReason;
+use crate::transactions::{Transaction, TransactionStatus};
+
+/// Credits the amount, opening the client's account with its first
+/// deposit, and retains the deposit so it can be disputed.
+pub(super) struct Deposit;
+
+impl TransactionHandler for Deposit {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        let mut client =
+            get_or_create_client(tx.client_id, cx.sequence, cx.client_db, cx.lifecycle);
+        // A deposit held for review is credited to held funds until it
+        // is approved or denied
+        let (available, held, status) = match cx.hold {
+            true => (0.0, amount, TransactionStatus::Pending),
+            false => (amount, 0.0, TransactionStatus::Good),
+        };
+        client.move_funds(available, held)?;
+        insert_new_transaction(*tx, amount, status, cx.tx_db);
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::processor::handle_transaction;
+    use crate::processor::tests::setup;
+    use crate::transactions::Transaction;
+
+    #[tokio::test]
+    async fn test_deposit() {
+        let (client_db, transactions_db) = setup();
+
+        let tx = Transaction::new_deposit(1, 1, 3.0);
+
+        handle_transaction(tx, &client_db, &transactions_db).await;
+
+        let client = client_db.get(&1).unwrap();
+
+        assert_eq!(client.available, 3.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 3.0);
+        assert!(!client.locked);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_deposits_with_different_tx_ids_succeed() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
+        let deposit2 = Transaction::new_deposit(1, 2, 2.0);
+
+        handle_transaction(deposit1, &client_db, &transactions_db).await;
+        handle_transaction(deposit2, &client_db, &transactions_db).await;
+
+        let client = client_db.get(&1).unwrap();
+
+        assert_eq!(client.available, 5.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 5.0);
+        assert!(!client.locked);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_deposits_with_different_client_ids_succeed() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit1 = Transaction::new_deposit(1, 3, 3.0);
+        let deposit2 = Transaction::new_deposit(2, 4, 2.0);
+
+        handle_transaction(deposit1, &client_db, &transactions_db).await;
+        handle_transaction(deposit2, &client_db, &transactions_db).await;
+
+        let client = client_db.get(&1).unwrap();
+
+        assert_eq!(client.available, 3.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 3.0);
+        assert!(!client.locked);
+
+        let client = client_db.get(&2).unwrap();
+
+        assert_eq!(client.available, 2.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 2.0);
+        assert!(!client.locked);
+    }
+
+    #[tokio::test]
+    async fn test_multiple_deposits_with_same_tx_ids_allows_only_first() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
+        let deposit2 = Transaction::new_deposit(1, 1, 2.0);
+
+        handle_transaction(deposit1, &client_db, &transactions_db).await;
+        handle_transaction(deposit2, &client_db, &transactions_db).await;
+
+        let client = client_db.get(&1).unwrap();
+
+        assert_eq!(client.available, 3.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 3.0);
+        assert!(!client.locked);
+    }
+}
@@ -0,0 +1,471 @@
+use super::{get_own_transaction, Client, Context, TransactionHandler, TransactionsDb};
+use crate::outcome::RejectReason;
+use crate::transactions::{
+    DisputeReason, DisputeStep, StoredTransaction, Transaction, TransactionStatus,
+};
+
+/// Holds all or part of a retained deposit or withdrawal of the client
+/// while it is disputed.
+pub(super) struct Dispute;
+
+/// Releases all or part of a dispute's held funds back to available.
+pub(super) struct Resolve;
+
+/// Takes all or part of a dispute's held funds out of the account, and
+/// locks it.
+pub(super) struct Chargeback;
+
+impl TransactionHandler for Dispute {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let mut disputed_tx = get_own_transaction(tx, cx.client_db, cx.tx_db)?;
+        let undisputed = disputed_tx.undisputed();
+        match disputed_tx.status() {
+            TransactionStatus::Good => {}
+            TransactionStatus::Pending => return Err(RejectReason::PendingReview),
+            TransactionStatus::Denied => return Err(RejectReason::DeniedOnReview),
+            // What an open dispute or a partial chargeback left may still be
+            // disputed
+            TransactionStatus::Disputed | TransactionStatus::Chargeback if undisputed > 0.0 => {}
+            TransactionStatus::Disputed | TransactionStatus::Chargeback => {
+                return Err(RejectReason::AlreadyDisputed)
+            }
+        }
+        // A dispute with an amount holds just that part
+        let disputed_amount = tx.amount.unwrap_or(undisputed);
+        if disputed_amount > undisputed {
+            return Err(RejectReason::ExceedsAmount);
+        }
+
+        let mut client = cx.client_db.get_mut(&tx.client_id).unwrap();
+        client.move_funds(-disputed_amount, disputed_amount)?;
+        let now_disputed = disputed_tx.disputed() + disputed_amount;
+        disputed_tx.set_disputed(now_disputed);
+        // Once charged back, it stays charged back
+        if disputed_tx.status() != TransactionStatus::Chargeback {
+            disputed_tx.set_status(TransactionStatus::Disputed);
+            cx.tx_db.index_status(tx.tx_id, TransactionStatus::Disputed);
+        }
+        disputed_tx.set_dispute_reason(tx.dispute_reason);
+        *cx.dispute_reason = tx.dispute_reason;
+        record_dispute_step(
+            tx,
+            cx.sequence,
+            disputed_amount,
+            tx.dispute_reason,
+            cx.tx_db,
+        );
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+impl TransactionHandler for Resolve {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let mut resolved_tx = get_own_transaction(tx, cx.client_db, cx.tx_db)?;
+        let resolved_amount = settled_amount(tx, &resolved_tx)?;
+        let mut client = cx.client_db.get_mut(&tx.client_id).unwrap();
+        if client.held < resolved_amount {
+            return Err(RejectReason::InsufficientHeld);
+        }
+        client.move_funds(resolved_amount, -resolved_amount)?;
+        let still_disputed = resolved_tx.disputed() - resolved_amount;
+        resolved_tx.set_disputed(still_disputed);
+        // Fully released, unless part of it was charged back already
+        if resolved_tx.disputed() == 0.0 && resolved_tx.status() == TransactionStatus::Disputed {
+            resolved_tx.set_status(TransactionStatus::Good);
+            cx.tx_db.index_status(tx.tx_id, TransactionStatus::Good);
+        }
+        *cx.dispute_reason = resolved_tx.dispute_reason();
+        record_dispute_step(
+            tx,
+            cx.sequence,
+            resolved_amount,
+            *cx.dispute_reason,
+            cx.tx_db,
+        );
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+impl TransactionHandler for Chargeback {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let mut chargeback_tx = get_own_transaction(tx, cx.client_db, cx.tx_db)?;
+        let chargeback_amount = settled_amount(tx, &chargeback_tx)?;
+        let mut client = cx.client_db.get_mut(&tx.client_id).unwrap();
+        if client.held < chargeback_amount {
+            return Err(RejectReason::InsufficientHeld);
+        }
+        client.move_funds(0.0, -chargeback_amount)?;
+        cx.lifecycle.locked = !client.locked;
+        client.locked = true;
+        let still_disputed = chargeback_tx.disputed() - chargeback_amount;
+        chargeback_tx.set_disputed(still_disputed);
+        let charged_back = chargeback_tx.charged_back() + chargeback_amount;
+        chargeback_tx.set_charged_back(charged_back);
+        chargeback_tx.set_status(TransactionStatus::Chargeback);
+        *cx.dispute_reason = chargeback_tx.dispute_reason();
+        cx.tx_db
+            .index_status(tx.tx_id, TransactionStatus::Chargeback);
+        record_dispute_step(
+            tx,
+            cx.sequence,
+            chargeback_amount,
+            *cx.dispute_reason,
+            cx.tx_db,
+        );
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+/// Amount a resolve or chargeback settles: the part of the dispute given
+/// in its amount, or all of what is still disputed.
+fn settled_amount(tx: &Transaction, stored: &StoredTransaction) -> Result<f64, RejectReason> {
+    let disputed = stored.disputed();
+    // A partial chargeback leaves the rest of the dispute open
+    let open = match stored.status() {
+        TransactionStatus::Good => false,
+        TransactionStatus::Disputed => true,
+        TransactionStatus::Chargeback => disputed > 0.0,
+        TransactionStatus::Pending | TransactionStatus::Denied => false,
+    };
+    if !open {
+        return Err(RejectReason::NotDisputed);
+    }
+    match tx.amount {
+        Some(amount) if amount > disputed => Err(RejectReason::ExceedsDisputed),
+        Some(amount) => Ok(amount),
+        None => Ok(disputed),
+    }
+}
+
+fn record_dispute_step(
+    tx: &Transaction,
+    sequence: u64,
+    amount: f64,
+    reason: Option<DisputeReason>,
+    tx_db: &TransactionsDb,
+) {
+    let step = DisputeStep {
+        sequence,
+        client_id: tx.client_id,
+        action: tx.tx_type,
+        amount,
+        reason,
+    };
+    tx_db.record_dispute_step(tx.tx_id, step);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::{RejectReason, TransactionOutcome};
+    use crate::policy::Policy;
+    use crate::processor::tests::setup;
+    use crate::processor::{apply_transaction, handle_transaction};
+    use crate::transactions::{DisputeReason, Transaction, TransactionStatus};
+
+    #[tokio::test]
+    async fn test_disputing_an_existing_transaction_succeeds() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit = Transaction::new_deposit(1, 1, 3.0);
+        let dispute = Transaction::new_dispute(1, 1);
+
+        handle_transaction(deposit, &client_db, &transactions_db).await;
+        handle_transaction(dispute, &client_db, &transactions_db).await;
+
+        let client = client_db.get(&1).unwrap();
+
+        assert_eq!(client.available, 0.0);
+        assert_eq!(client.held, 3.0);
+        assert_eq!(client.total, 3.0);
+        assert!(!client.locked);
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Disputed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dangling_dispute_is_ignored() {
+        let (client_db, transactions_db) = setup();
+
+        let dispute = Transaction::new_dispute(1, 1);
+
+        handle_transaction(dispute, &client_db, &transactions_db).await;
+
+        assert!(client_db.get(&1).is_none());
+        assert!(transactions_db.get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resolving_a_disputed_transaction_succeeds() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
+        let deposit2 = Transaction::new_deposit(1, 2, 1.0);
+        let dispute = Transaction::new_dispute(1, 1);
+        let resolve = Transaction::new_resolve(1, 1);
+
+        handle_transaction(deposit1, &client_db, &transactions_db).await;
+        handle_transaction(deposit2, &client_db, &transactions_db).await;
+
+        assert_eq!(client_db.get(&1).unwrap().available, 4.0);
+
+        handle_transaction(dispute, &client_db, &transactions_db).await;
+        assert_eq!(client_db.get(&1).unwrap().available, 1.0);
+        assert_eq!(client_db.get(&1).unwrap().held, 3.0);
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Disputed
+        );
+
+        handle_transaction(resolve, &client_db, &transactions_db).await;
+        assert_eq!(client_db.get(&1).unwrap().available, 4.0);
+        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Good
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dangling_resolve_is_ignored() {
+        let (client_db, transactions_db) = setup();
+
+        let dispute = Transaction::new_resolve(1, 1);
+
+        handle_transaction(dispute, &client_db, &transactions_db).await;
+
+        assert!(client_db.get(&1).is_none());
+        assert!(transactions_db.get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_chargeback_succeeds() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
+        let deposit2 = Transaction::new_deposit(1, 2, 1.0);
+        let dispute = Transaction::new_dispute(1, 1);
+        let chargeback = Transaction::new_chargeback(1, 1);
+
+        handle_transaction(deposit1, &client_db, &transactions_db).await;
+        handle_transaction(deposit2, &client_db, &transactions_db).await;
+        assert_eq!(client_db.get(&1).unwrap().available, 4.0);
+
+        handle_transaction(dispute, &client_db, &transactions_db).await;
+        assert_eq!(client_db.get(&1).unwrap().available, 1.0);
+        assert_eq!(client_db.get(&1).unwrap().held, 3.0);
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Disputed
+        );
+
+        handle_transaction(chargeback, &client_db, &transactions_db).await;
+        assert_eq!(client_db.get(&1).unwrap().available, 1.0);
+        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
+        assert!(client_db.get(&1).unwrap().locked);
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Chargeback
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chargeback_for_a_non_disputed_transaction_is_ignored() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit = Transaction::new_deposit(1, 1, 3.0);
+        let chargeback = Transaction::new_chargeback(1, 1);
+
+        handle_transaction(deposit, &client_db, &transactions_db).await;
+        assert_eq!(client_db.get(&1).unwrap().available, 3.0);
+        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
+
+        handle_transaction(chargeback, &client_db, &transactions_db).await;
+        assert_eq!(client_db.get(&1).unwrap().available, 3.0);
+        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
+        assert!(!client_db.get(&1).unwrap().locked);
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Good
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dangling_chargeback_is_ignored() {
+        let (client_db, transactions_db) = setup();
+
+        let dispute = Transaction::new_chargeback(1, 1);
+
+        handle_transaction(dispute, &client_db, &transactions_db).await;
+
+        assert!(client_db.get(&1).is_none());
+        assert!(transactions_db.get(&1).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dispute_of_another_clients_transaction_is_ignored() {
+        let (client_db, transactions_db) = setup();
+
+        handle_transaction(
+            Transaction::new_deposit(1, 1, 3.0),
+            &client_db,
+            &transactions_db,
+        )
+        .await;
+        handle_transaction(
+            Transaction::new_deposit(2, 2, 1.0),
+            &client_db,
+            &transactions_db,
+        )
+        .await;
+        handle_transaction(Transaction::new_dispute(2, 1), &client_db, &transactions_db).await;
+        handle_transaction(
+            Transaction::new_chargeback(2, 1),
+            &client_db,
+            &transactions_db,
+        )
+        .await;
+
+        let client = client_db.get(&2).unwrap();
+        assert_eq!(client.available, 1.0);
+        assert_eq!(client.held, 0.0);
+        assert!(!client.locked);
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Good
+        );
+    }
+
+    #[test]
+    fn test_dispute_reason_follows_the_dispute() {
+        let (client_db, transactions_db) = setup();
+        let apply = |tx| {
+            apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default())
+                .dispute_reason
+        };
+        let mut dispute = Transaction::new_dispute(1, 1);
+        dispute.dispute_reason = Some(DisputeReason::Fraud);
+
+        assert_eq!(apply(Transaction::new_deposit(1, 1, 3.0)), None);
+        assert_eq!(apply(dispute), Some(DisputeReason::Fraud));
+        assert_eq!(apply(dispute), None);
+        assert_eq!(
+            apply(Transaction::new_chargeback(1, 1)),
+            Some(DisputeReason::Fraud)
+        );
+        assert_eq!(
+            transactions_db.get(&1).unwrap().dispute_reason(),
+            Some(DisputeReason::Fraud)
+        );
+    }
+
+    #[test]
+    fn test_partial_disputes_hold_and_settle_part_of_the_amount() {
+        let (client_db, transactions_db) = setup();
+        let apply =
+            |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default()).outcome;
+        let part = |tx: Transaction, amount| Transaction {
+            amount: Some(amount),
+            ..tx
+        };
+        let balances = || {
+            let client = client_db.get(&1).unwrap();
+            (client.available, client.held, client.total)
+        };
+        let rejected = |reason| TransactionOutcome::Rejected { reason };
+
+        apply(Transaction::new_deposit(1, 1, 10.0));
+        assert_eq!(
+            apply(part(Transaction::new_dispute(1, 1), 12.0)),
+            rejected(RejectReason::ExceedsAmount)
+        );
+        apply(part(Transaction::new_dispute(1, 1), 4.0));
+        assert_eq!(balances(), (6.0, 4.0, 10.0));
+
+        apply(part(Transaction::new_resolve(1, 1), 1.0));
+        assert_eq!(balances(), (7.0, 3.0, 10.0));
+        assert_eq!(transactions_db.get(&1).unwrap().disputed(), 3.0);
+        assert_eq!(
+            apply(part(Transaction::new_chargeback(1, 1), 5.0)),
+            rejected(RejectReason::ExceedsDisputed)
+        );
+
+        apply(part(Transaction::new_chargeback(1, 1), 2.0));
+        assert_eq!(balances(), (7.0, 1.0, 8.0));
+        assert_eq!(
+            transactions_db.get(&1).unwrap().status(),
+            TransactionStatus::Chargeback
+        );
+        apply(Transaction::new_resolve(1, 1));
+        assert_eq!(balances(), (8.0, 0.0, 8.0));
+        assert_eq!(
+            apply(Transaction::new_resolve(1, 1)),
+            rejected(RejectReason::NotDisputed)
+        );
+        // What wasn't charged back can be disputed again, once
+        apply(Transaction::new_dispute(1, 1));
+        assert_eq!(balances(), (0.0, 8.0, 8.0));
+        assert_eq!(
+            apply(Transaction::new_dispute(1, 1)),
+            rejected(RejectReason::AlreadyDisputed)
+        );
+    }
+
+    #[test]
+    fn test_the_rest_of_a_partly_charged_back_transaction_can_be_disputed() {
+        let (client_db, transactions_db) = setup();
+        let apply =
+            |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default()).outcome;
+        let part = |tx: Transaction, amount| Transaction {
+            amount: Some(amount),
+            ..tx
+        };
+        let balances = || {
+            let client = client_db.get(&1).unwrap();
+            (client.available, client.held, client.total)
+        };
+        let rejected = |reason| TransactionOutcome::Rejected { reason };
+
+        apply(Transaction::new_deposit(1, 1, 10.0));
+        apply(part(Transaction::new_dispute(1, 1), 4.0));
+        // Beside an open dispute, up to the rest of the amount
+        assert_eq!(
+            apply(part(Transaction::new_dispute(1, 1), 7.0)),
+            rejected(RejectReason::ExceedsAmount)
+        );
+        apply(part(Transaction::new_chargeback(1, 1), 4.0));
+        assert_eq!(balances(), (6.0, 0.0, 6.0));
+        assert_eq!(
+            apply(part(Transaction::new_dispute(1, 1), 6.5)),
+            rejected(RejectReason::ExceedsAmount)
+        );
+
+        assert!(matches!(
+            apply(part(Transaction::new_dispute(1, 1), 6.0)),
+            TransactionOutcome::Applied { .. }
+        ));
+        assert_eq!(balances(), (0.0, 6.0, 6.0));
+        let stored = transactions_db.get(&1).unwrap();
+        assert_eq!(
+            (
+                stored.disputed(),
+                stored.charged_back(),
+                stored.undisputed()
+            ),
+            (6.0, 4.0, 0.0)
+        );
+        assert_eq!(stored.status(), TransactionStatus::Chargeback);
+        drop(stored);
+        assert_eq!(
+            apply(part(Transaction::new_dispute(1, 1), 0.5)),
+            rejected(RejectReason::AlreadyDisputed)
+        );
+
+        apply(Transaction::new_resolve(1, 1));
+        assert_eq!(balances(), (6.0, 0.0, 6.0));
+    }
+}
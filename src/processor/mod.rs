@@ -1,41 +1,142 @@
+mod adjustment;
+mod close;
+mod custom;
+mod deposit;
+mod dispute;
+mod review;
+mod withdrawal;
+
 use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 
+use dashmap::mapref::entry::Entry;
+use dashmap::mapref::one::RefMut;
 use dashmap::DashMap;
-use serde::{Serialize, Serializer};
-
-use crate::transactions::{Transaction, TransactionStatus, TransactionType, TransactionWithStatus};
-
-pub type TransactionsDb = Arc<DashMap<u32, TransactionWithStatus>>;
-pub type ClientDb = Arc<DashMap<u16, Client>>;
 
-#[derive(Copy, Clone, Serialize)]
+use crate::engine::EngineHasher;
+use crate::outcome::{Balances, IgnoreReason, RejectReason, TransactionOutcome};
+use crate::plugin::CustomHandler;
+use crate::policy::{DuplicatePolicy, Policy};
+use crate::store::TransactionStore;
+use crate::transactions::{
+    DisputeReason, StoredTransaction, Transaction, TransactionStatus, TransactionType, MAX_AMOUNT,
+};
+
+pub type TransactionsDb = Arc<TransactionStore>;
+pub type ClientDb = Arc<DashMap<u16, Client, EngineHasher>>;
+
+/// A client's account. Reports format it through a
+/// [`ReportLayout`](crate::io::ReportLayout), which decides the precision of
+/// its amounts.
+#[derive(Copy, Clone)]
 pub struct Client {
-    #[serde(rename = "client")]
-    id: u16,
-    #[serde(serialize_with = "change_precision")]
-    available: f64,
-    #[serde(serialize_with = "change_precision")]
-    held: f64,
-    #[serde(serialize_with = "change_precision")]
-    total: f64,
-    locked: bool,
+    pub(crate) id: u16,
+    pub(crate) available: f64,
+    pub(crate) held: f64,
+    pub(crate) total: f64,
+    pub(crate) locked: bool,
+    /// The account was locked by a custom type's handler, see
+    /// [`Account::lock`], rather than only by chargebacks.
+    pub(crate) frozen: bool,
+    /// Closed accounts reject every further transaction.
+    pub(crate) closed: bool,
+    pub(crate) stats: ClientStats,
+}
+
+/// Running totals over a client's applied transactions, for the extended
+/// report.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct ClientStats {
+    pub(crate) transactions: u32,
+    pub(crate) disputes: u32,
+    pub(crate) deposited: f64,
+    pub(crate) withdrawn: f64,
+    /// Net amount of manual credits less debits.
+    pub(crate) adjusted: f64,
+    /// Position of the last transaction that changed the client's account,
+    /// by applying or by opening it, in the order the engine received
+    /// transactions, starting at 1.
+    pub(crate) last_activity: u64,
 }
 
-fn change_precision<S>(amount: &f64, s: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-{
-    s.serialize_str(&format!("{:.4}", amount))
+impl ClientStats {
+    fn record(&mut self, tx: &Transaction, sequence: u64) {
+        self.transactions += 1;
+        match tx.tx_type {
+            TransactionType::Deposit => self.deposited += tx.amount.unwrap_or_default(),
+            TransactionType::Withdrawal => self.withdrawn += tx.amount.unwrap_or_default(),
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Credit => self.adjusted += tx.amount.unwrap_or_default(),
+            TransactionType::Debit => self.adjusted -= tx.amount.unwrap_or_default(),
+            TransactionType::Resolve
+            | TransactionType::Chargeback
+            | TransactionType::Close
+            | TransactionType::Approve
+            | TransactionType::Deny
+            | TransactionType::Custom(_) => {}
+        }
+        self.last_activity = self.last_activity.max(sequence);
+    }
 }
 
 impl Client {
-    fn new(id: u16) -> Self {
+    pub(crate) fn new(id: u16) -> Self {
         Self {
             id,
             ..Default::default()
         }
     }
+
+    /// Moves `available` and `held` funds by the given amounts, and the
+    /// total by both, unless a balance would leave the range amounts are
+    /// exact in, in which case nothing changes.
+    pub(crate) fn move_funds(&mut self, available: f64, held: f64) -> Result<(), RejectReason> {
+        // Moves between available and held cancel out exactly, leaving the
+        // total as it was
+        let total = checked_balance(self.total + (available + held))?;
+        let available = checked_balance(self.available + available)?;
+        let held = checked_balance(self.held + held)?;
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        Ok(())
+    }
+
+    /// Takes over `other`'s balances and running totals, as when two
+    /// accounts of the same customer are merged. The merged account is
+    /// locked if either was, since a chargeback's lock needs settling by
+    /// hand whichever id it ended up on. Closed accounts can't be merged.
+    pub(crate) fn absorb(&mut self, other: &Client) -> Result<(), RejectReason> {
+        if self.closed || other.closed {
+            return Err(RejectReason::AccountClosed);
+        }
+        let total = checked_balance(self.total + other.total)?;
+        let available = checked_balance(self.available + other.available)?;
+        let held = checked_balance(self.held + other.held)?;
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        self.locked |= other.locked;
+        self.frozen |= other.frozen;
+        let stats = &mut self.stats;
+        stats.transactions += other.stats.transactions;
+        stats.disputes += other.stats.disputes;
+        stats.deposited += other.stats.deposited;
+        stats.withdrawn += other.stats.withdrawn;
+        stats.adjusted += other.stats.adjusted;
+        stats.last_activity = stats.last_activity.max(other.stats.last_activity);
+        Ok(())
+    }
+}
+
+fn checked_balance(balance: f64) -> Result<f64, RejectReason> {
+    if balance > MAX_AMOUNT {
+        Err(RejectReason::Overflow)
+    } else if balance < -MAX_AMOUNT {
+        Err(RejectReason::Underflow)
+    } else {
+        Ok(balance)
+    }
 }
 
 impl Default for Client {
@@ -46,6 +147,9 @@ impl Default for Client {
             held: 0.0,
             total: 0.0,
             locked: false,
+            frozen: false,
+            closed: false,
+            stats: ClientStats::default(),
         }
     }
 }
@@ -56,373 +160,512 @@ impl Hash for Client {
     }
 }
 
-fn insert_new_transaction(tx: Transaction, tx_db: &TransactionsDb) {
+fn insert_new_transaction(
+    tx: Transaction,
+    amount: f64,
+    status: TransactionStatus,
+    tx_db: &TransactionsDb,
+) {
     if !tx_db.contains_key(&tx.tx_id) {
-        tx_db.insert(
-            tx.tx_id,
-            TransactionWithStatus {
-                tx,
-                status: TransactionStatus::Good,
-            },
-        );
+        let mut stored = StoredTransaction::new(&tx, amount);
+        stored.set_status(status);
+        tx_db.insert(tx.tx_id, stored);
     }
 }
 
-pub async fn handle_transaction(tx: Transaction, client_db: &ClientDb, tx_db: &TransactionsDb) {
-    if tx_db.contains_key(&tx.tx_id) {
-        // Transaction IDs are globally unique, ignore an incoming
-        // transaction that has the same transaction type and ID as
-        // an existing transaction
-        let existing_tx = *(tx_db.get(&tx.tx_id).unwrap());
-        if existing_tx.tx.tx_type == tx.tx_type && existing_tx.tx.tx_id == tx.tx_id {
-            return;
-        }
+/// Looks up the transaction a dispute, resolve or chargeback refers to,
+/// which must belong to the same client.
+fn get_own_transaction<'a>(
+    tx: &Transaction,
+    client_db: &ClientDb,
+    tx_db: &'a TransactionsDb,
+) -> Result<RefMut<'a, u32, StoredTransaction, EngineHasher>, RejectReason> {
+    if !client_db.contains_key(&tx.client_id) {
+        return Err(RejectReason::UnknownClient);
     }
-
-    match tx.tx_type {
-        TransactionType::Deposit => {
-            if let Some(amount) = tx.amount {
-                insert_new_transaction(tx, tx_db);
-                let mut client = client_db
-                    .entry(tx.client_id)
-                    .or_insert(Client::new(tx.client_id));
-                client.available += amount;
-                client.total += amount;
-            }
-        }
-        TransactionType::Withdrawal => {
-            if let Some(amount) = tx.amount {
-                let mut client = client_db
-                    .entry(tx.client_id)
-                    .or_insert(Client::new(tx.client_id));
-                if client.available >= amount {
-                    insert_new_transaction(tx, tx_db);
-                    client.available -= amount;
-                    client.total -= amount;
-                }
-            }
-        }
-        TransactionType::Dispute => {
-            if !client_db.contains_key(&tx.client_id) {
-                return;
-            }
-
-            if let Some(mut disputed_tx) = tx_db.get_mut(&tx.tx_id) {
-                if let TransactionStatus::Good = disputed_tx.status {
-                    let id = tx.client_id;
-                    let mut client = client_db.get_mut(&id).unwrap();
-                    client.available -= disputed_tx.tx.amount.unwrap();
-                    client.held += disputed_tx.tx.amount.unwrap();
-                    disputed_tx.status = TransactionStatus::Disputed;
-                }
-            }
-        }
-        TransactionType::Resolve => {
-            if !client_db.contains_key(&tx.client_id) {
-                return;
-            }
-
-            if let Some(mut resolved_tx) = tx_db.get_mut(&tx.tx_id) {
-                if let TransactionStatus::Disputed = resolved_tx.status {
-                    if let Some(resolved_amount) = resolved_tx.tx.amount {
-                        let id = tx.client_id;
-                        let mut client = client_db.get_mut(&id).unwrap();
-
-                        if client.held >= resolved_amount {
-                            client.available += resolved_amount;
-                            client.held -= resolved_amount;
-                            resolved_tx.status = TransactionStatus::Good;
-                        }
-                    }
-                }
-            }
-        }
-        TransactionType::Chargeback => {
-            if !client_db.contains_key(&tx.client_id) {
-                return;
-            }
-
-            if let Some(mut chargeback_tx) = tx_db.get_mut(&tx.tx_id) {
-                if let TransactionStatus::Disputed = chargeback_tx.status {
-                    if let Some(chargeback_amount) = chargeback_tx.tx.amount {
-                        let id = tx.client_id;
-                        let mut client = client_db.get_mut(&id).unwrap();
-
-                        if client.held >= chargeback_amount {
-                            client.held -= chargeback_amount;
-                            client.total -= chargeback_amount;
-                            client.locked = true;
-                            chargeback_tx.status = TransactionStatus::Chargeback;
-                        }
-                    }
-                }
-            }
-        }
+    let stored = tx_db
+        .get_mut(&tx.tx_id)
+        .ok_or(RejectReason::UnknownTransaction)?;
+    if stored.client_id() != tx.client_id {
+        return Err(RejectReason::ForeignTransaction);
     }
+    Ok(stored)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn setup() -> (ClientDb, TransactionsDb) {
-        (
-            Arc::new(DashMap::<u16, Client>::new()),
-            Arc::new(DashMap::<u32, TransactionWithStatus>::new()),
-        )
+/// Looks up the client a deposit, withdrawal or adjustment is for, opening the account
+/// if this is its first transaction.
+fn get_or_create_client<'a>(
+    client_id: u16,
+    sequence: u64,
+    client_db: &'a ClientDb,
+    lifecycle: &mut Lifecycle,
+) -> RefMut<'a, u16, Client, EngineHasher> {
+    match client_db.entry(client_id) {
+        Entry::Occupied(entry) => entry.into_ref(),
+        Entry::Vacant(entry) => {
+            lifecycle.created = true;
+            let mut client = Client::new(client_id);
+            client.stats.last_activity = sequence;
+            entry.insert(client)
+        }
     }
+}
 
-    #[tokio::test]
-    async fn test_deposit() {
-        let (client_db, transactions_db) = setup();
+/// What applying a transaction did, to the balances and to the account
+/// itself.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Processed {
+    pub outcome: TransactionOutcome,
+    pub lifecycle: Lifecycle,
+    /// Reason code of the dispute an applied dispute, resolve or chargeback
+    /// concerns, if the dispute gave one.
+    pub dispute_reason: Option<DisputeReason>,
+    /// Score the engine's risk scorer gave the transaction, if it has one.
+    pub risk_score: Option<f64>,
+    /// The transaction repeated the type and id of an earlier one, whatever
+    /// the engine's [`DuplicatePolicy`] made of it.
+    pub duplicate: bool,
+}
 
-        let tx = Transaction::new_deposit(1, 1, 3.0);
+/// Changes to the client's account as a whole caused by a transaction.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Lifecycle {
+    /// The transaction opened the account. This happens even when the
+    /// transaction itself is rejected, e.g. a first withdrawal.
+    pub created: bool,
+    /// The transaction locked a previously unlocked account.
+    pub locked: bool,
+    /// The transaction closed the account.
+    pub closed: bool,
+}
 
-        handle_transaction(tx, &client_db, &transactions_db).await;
+/// Applies `tx` as if it were the first transaction, for tests that don't
+/// look at the client's last activity.
+#[cfg(test)]
+pub async fn handle_transaction(
+    tx: Transaction,
+    client_db: &ClientDb,
+    tx_db: &TransactionsDb,
+) -> Processed {
+    apply_transaction(tx, 0, client_db, tx_db, &Policy::default())
+}
 
-        let client = client_db.get(&1).unwrap();
+/// Applies `tx` as the `sequence`th transaction the engine received, which
+/// is recorded as the client's last activity, under `policy`.
+pub fn apply_transaction(
+    tx: Transaction,
+    sequence: u64,
+    client_db: &ClientDb,
+    tx_db: &TransactionsDb,
+    policy: &Policy,
+) -> Processed {
+    let hold = policy.needs_review(&tx);
+    apply_reviewed(tx, sequence, hold, client_db, tx_db, policy)
+}
 
-        assert_eq!(client.available, 3.0);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 3.0);
-        assert!(!client.locked);
+/// Applies `tx` like [`apply_transaction`], but holds a deposit or
+/// withdrawal for review if and only if `hold` is set, e.g. on a risk
+/// scorer's verdict.
+pub fn apply_reviewed(
+    tx: Transaction,
+    sequence: u64,
+    hold: bool,
+    client_db: &ClientDb,
+    tx_db: &TransactionsDb,
+    policy: &Policy,
+) -> Processed {
+    let hold = hold
+        && matches!(
+            tx.tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        );
+    let mut lifecycle = Lifecycle::default();
+    let mut dispute_reason = None;
+    let mut cx = Context {
+        sequence,
+        hold,
+        client_db,
+        tx_db,
+        policy,
+        lifecycle: &mut lifecycle,
+        dispute_reason: &mut dispute_reason,
+    };
+
+    let existing_tx = tx_db.get(&tx.tx_id).map(|existing_tx| *existing_tx);
+    // Transaction IDs are globally unique, so an incoming transaction
+    // with the same transaction type and ID as an existing transaction
+    // repeats it
+    let duplicate = existing_tx.filter(|existing_tx| existing_tx.tx_type() == tx.tx_type);
+    let applied = match (duplicate, policy.duplicates()) {
+        (None, _) => apply_checked(tx, handler(tx.tx_type), &mut cx),
+        (Some(_), DuplicatePolicy::Ignore | DuplicatePolicy::Warn) => {
+            return Processed {
+                outcome: TransactionOutcome::Ignored {
+                    reason: IgnoreReason::Duplicate,
+                },
+                lifecycle,
+                dispute_reason: None,
+                risk_score: None,
+                duplicate: true,
+            };
+        }
+        (Some(_), DuplicatePolicy::Reject) => Err(RejectReason::DuplicateTransaction),
+        (Some(existing_tx), DuplicatePolicy::LastWriteWins) => {
+            replace_transaction(tx, existing_tx, &mut cx)
+        }
+    };
+    let outcome = match applied {
+        Ok(client) if hold => TransactionOutcome::Held {
+            balances: Balances::from(&client),
+        },
+        Ok(client) => TransactionOutcome::Applied {
+            balances: Balances::from(&client),
+        },
+        Err(reason) => TransactionOutcome::Rejected { reason },
+    };
+    Processed {
+        outcome,
+        lifecycle,
+        dispute_reason,
+        risk_score: None,
+        duplicate: duplicate.is_some(),
     }
+}
 
-    #[tokio::test]
-    async fn test_multiple_deposits_with_different_tx_ids_succeed() {
-        let (client_db, transactions_db) = setup();
-
-        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
-        let deposit2 = Transaction::new_deposit(1, 2, 2.0);
-
-        handle_transaction(deposit1, &client_db, &transactions_db).await;
-        handle_transaction(deposit2, &client_db, &transactions_db).await;
-
-        let client = client_db.get(&1).unwrap();
-
-        assert_eq!(client.available, 5.0);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 5.0);
-        assert!(!client.locked);
+/// Applies `tx`, of a custom type, through the `handler` the engine has
+/// for its type, rejecting it if there is none.
+pub fn apply_custom(
+    tx: Transaction,
+    sequence: u64,
+    handler: Option<&dyn CustomHandler>,
+    client_db: &ClientDb,
+    tx_db: &TransactionsDb,
+    policy: &Policy,
+) -> Processed {
+    let mut lifecycle = Lifecycle::default();
+    let mut dispute_reason = None;
+    let mut cx = Context {
+        sequence,
+        hold: false,
+        client_db,
+        tx_db,
+        policy,
+        lifecycle: &mut lifecycle,
+        dispute_reason: &mut dispute_reason,
+    };
+    let outcome = match apply_checked(tx, &custom::Custom(handler), &mut cx) {
+        Ok(client) => TransactionOutcome::Applied {
+            balances: Balances::from(&client),
+        },
+        Err(reason) => TransactionOutcome::Rejected { reason },
+    };
+    Processed {
+        outcome,
+        lifecycle,
+        dispute_reason: None,
+        risk_score: None,
+        duplicate: false,
     }
+}
 
-    #[tokio::test]
-    async fn test_multiple_deposits_with_different_client_ids_succeed() {
-        let (client_db, transactions_db) = setup();
-
-        let deposit1 = Transaction::new_deposit(1, 3, 3.0);
-        let deposit2 = Transaction::new_deposit(2, 4, 2.0);
-
-        handle_transaction(deposit1, &client_db, &transactions_db).await;
-        handle_transaction(deposit2, &client_db, &transactions_db).await;
-
-        let client = client_db.get(&1).unwrap();
-
-        assert_eq!(client.available, 3.0);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 3.0);
-        assert!(!client.locked);
-
-        let client = client_db.get(&2).unwrap();
-
-        assert_eq!(client.available, 2.0);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 2.0);
-        assert!(!client.locked);
+/// Applies the deposit or withdrawal `tx` in place of `existing_tx`, the
+/// earlier one it repeats: the earlier one's effect on the client's
+/// balances and running totals is undone first, and restored if `tx`
+/// isn't applied. The stored transaction keeps its place in the client's
+/// history.
+fn replace_transaction(
+    tx: Transaction,
+    existing_tx: StoredTransaction,
+    cx: &mut Context,
+) -> Result<Client, RejectReason> {
+    if existing_tx.client_id() != tx.client_id || existing_tx.status() != TransactionStatus::Good {
+        return Err(RejectReason::DuplicateTransaction);
     }
-
-    #[tokio::test]
-    async fn test_multiple_deposits_with_same_tx_ids_allows_only_first() {
-        let (client_db, transactions_db) = setup();
-
-        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
-        let deposit2 = Transaction::new_deposit(1, 1, 2.0);
-
-        handle_transaction(deposit1, &client_db, &transactions_db).await;
-        handle_transaction(deposit2, &client_db, &transactions_db).await;
-
-        let client = client_db.get(&1).unwrap();
-
-        assert_eq!(client.available, 3.0);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 3.0);
-        assert!(!client.locked);
+    let amount = existing_tx.amount();
+    let client_db = cx.client_db;
+    // Takes the earlier transaction out of the client's balances and
+    // running totals with a sign of -1, and puts it back with 1
+    let shift = |sign: f64| -> Result<(), RejectReason> {
+        let mut client = client_db
+            .get_mut(&tx.client_id)
+            .ok_or(RejectReason::UnknownClient)?;
+        match tx.tx_type {
+            TransactionType::Deposit => {
+                client.move_funds(sign * amount, 0.0)?;
+                client.stats.deposited += sign * amount;
+            }
+            _ => {
+                client.move_funds(-sign * amount, 0.0)?;
+                client.stats.withdrawn += sign * amount;
+            }
+        }
+        client.stats.transactions = match sign < 0.0 {
+            true => client.stats.transactions.saturating_sub(1),
+            false => client.stats.transactions + 1,
+        };
+        Ok(())
+    };
+    shift(-1.0)?;
+
+    match apply_checked(tx, handler(tx.tx_type), cx) {
+        Ok(client) => {
+            let status = match cx.hold {
+                true => TransactionStatus::Pending,
+                false => TransactionStatus::Good,
+            };
+            if let Some(mut stored) = cx.tx_db.get_mut(&tx.tx_id) {
+                *stored = StoredTransaction::new(&tx, tx.amount.unwrap_or_default());
+                stored.set_status(status);
+            }
+            cx.tx_db.index_status(tx.tx_id, status);
+            Ok(client)
+        }
+        Err(reason) => {
+            shift(1.0)?;
+            Err(reason)
+        }
     }
+}
 
-    #[tokio::test]
-    async fn test_deposit_and_withdrawal() {
-        let (client_db, transactions_db) = setup();
-
-        let deposit = Transaction::new_deposit(1, 1, 3.0);
-        let withdrawal = Transaction::new_withdrawal(1, 2, 1.5);
-
-        handle_transaction(deposit, &client_db, &transactions_db).await;
-        handle_transaction(withdrawal, &client_db, &transactions_db).await;
+/// What a [`TransactionHandler`] applies a transaction against, and where
+/// it reports what the transaction did besides the client's state.
+struct Context<'a> {
+    /// Position of the transaction in the order the engine received them.
+    sequence: u64,
+    /// Holds a deposit or withdrawal for review.
+    hold: bool,
+    client_db: &'a ClientDb,
+    tx_db: &'a TransactionsDb,
+    policy: &'a Policy,
+    lifecycle: &'a mut Lifecycle,
+    /// Reason code of the dispute a dispute, resolve or chargeback
+    /// concerns.
+    dispute_reason: &'a mut Option<DisputeReason>,
+}
 
-        let client = client_db.get(&1).unwrap();
+/// Applies transactions of one type, once the checks common to every
+/// type have passed. Each operation lives in a module of its own with its
+/// tests; adding one is adding a module and its type to [`handler`].
+trait TransactionHandler {
+    /// Applies `tx`, returning the client's state afterwards or why nothing
+    /// changed.
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason>;
+}
 
-        assert_eq!(client.available, 1.5);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 1.5);
-        assert!(!client.locked);
+/// The handler of transactions of `tx_type`. Custom types are routed here
+/// by the engine with their handler, see [`apply_custom`]; the one given
+/// for them here rejects them.
+fn handler(tx_type: TransactionType) -> &'static dyn TransactionHandler {
+    match tx_type {
+        TransactionType::Deposit => &deposit::Deposit,
+        TransactionType::Withdrawal => &withdrawal::Withdrawal,
+        TransactionType::Dispute => &dispute::Dispute,
+        TransactionType::Resolve => &dispute::Resolve,
+        TransactionType::Chargeback => &dispute::Chargeback,
+        TransactionType::Credit | TransactionType::Debit => &adjustment::Adjustment,
+        TransactionType::Approve | TransactionType::Deny => &review::Review,
+        TransactionType::Close => &close::Close,
+        TransactionType::Custom(_) => &custom::Custom(None),
     }
+}
 
-    #[tokio::test]
-    async fn test_withdrawing_more_than_available_fails() {
-        let (client_db, transactions_db) = setup();
-
-        let deposit = Transaction::new_deposit(1, 1, 3.0);
-        let withdrawal = Transaction::new_withdrawal(1, 2, 4.0);
-
-        handle_transaction(deposit, &client_db, &transactions_db).await;
-        handle_transaction(withdrawal, &client_db, &transactions_db).await;
+/// Applies `tx` with `handler` unless the account is closed or the amount
+/// is out of bounds. A held deposit or withdrawal moves its amount into
+/// `held`.
+fn apply_checked(
+    tx: Transaction,
+    handler: &dyn TransactionHandler,
+    cx: &mut Context,
+) -> Result<Client, RejectReason> {
+    if cx
+        .client_db
+        .get(&tx.client_id)
+        .is_some_and(|client| client.closed)
+    {
+        return Err(RejectReason::AccountClosed);
+    }
+    cx.policy.check_amount(&tx)?;
+    if tx.amount.is_some_and(|amount| amount > MAX_AMOUNT) {
+        return Err(RejectReason::Overflow);
+    }
+    handler.apply(&tx, cx)
+}
 
-        let client = client_db.get(&1).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::AdjustmentReason;
 
-        assert_eq!(client.available, 3.0);
-        assert_eq!(client.held, 0.0);
-        assert_eq!(client.total, 3.0);
-        assert!(!client.locked);
+    pub(super) fn setup() -> (ClientDb, TransactionsDb) {
+        (
+            Arc::new(DashMap::with_hasher(EngineHasher::default())),
+            Arc::new(TransactionStore::new(DashMap::with_hasher(
+                EngineHasher::default(),
+            ))),
+        )
     }
 
-    #[tokio::test]
-    async fn test_disputing_an_existing_transaction_succeeds() {
+    #[test]
+    fn test_outcomes_describe_what_happened() {
         let (client_db, transactions_db) = setup();
+        let apply =
+            |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default()).outcome;
+        let rejected = |reason| TransactionOutcome::Rejected { reason };
 
-        let deposit = Transaction::new_deposit(1, 1, 3.0);
-        let dispute = Transaction::new_dispute(1, 1);
-
-        handle_transaction(deposit, &client_db, &transactions_db).await;
-        handle_transaction(dispute, &client_db, &transactions_db).await;
-
-        let client = client_db.get(&1).unwrap();
-
-        assert_eq!(client.available, 0.0);
-        assert_eq!(client.held, 3.0);
-        assert_eq!(client.total, 3.0);
-        assert!(!client.locked);
         assert_eq!(
-            transactions_db.get(&1).unwrap().status,
-            TransactionStatus::Disputed
+            apply(Transaction::new_deposit(1, 1, 3.0)),
+            TransactionOutcome::Applied {
+                balances: Balances {
+                    client: 1,
+                    available: 3.0,
+                    held: 0.0,
+                    total: 3.0,
+                    locked: false,
+                }
+            }
+        );
+        assert_eq!(
+            apply(Transaction::new_deposit(1, 1, 3.0)),
+            TransactionOutcome::Ignored {
+                reason: IgnoreReason::Duplicate
+            }
+        );
+        assert_eq!(
+            apply(Transaction::new_withdrawal(1, 2, 5.0)),
+            rejected(RejectReason::InsufficientFunds)
+        );
+        assert_eq!(
+            apply(Transaction::new_dispute(2, 1)),
+            rejected(RejectReason::UnknownClient)
+        );
+        assert_eq!(
+            apply(Transaction::new_dispute(1, 9)),
+            rejected(RejectReason::UnknownTransaction)
+        );
+        assert_eq!(
+            apply(Transaction::new_resolve(1, 1)),
+            rejected(RejectReason::NotDisputed)
         );
-    }
-
-    #[tokio::test]
-    async fn test_dangling_dispute_is_ignored() {
-        let (client_db, transactions_db) = setup();
-
-        let dispute = Transaction::new_dispute(1, 1);
 
-        handle_transaction(dispute, &client_db, &transactions_db).await;
+        apply(Transaction::new_deposit(2, 3, 1.0));
+        assert_eq!(
+            apply(Transaction::new_dispute(2, 1)),
+            rejected(RejectReason::ForeignTransaction)
+        );
 
-        assert!(client_db.get(&1).is_none());
-        assert!(transactions_db.get(&1).is_none());
+        apply(Transaction::new_dispute(1, 1));
+        assert_eq!(
+            apply(Transaction::new_dispute(1, 1)),
+            rejected(RejectReason::AlreadyDisputed)
+        );
+        assert!(matches!(
+            apply(Transaction::new_chargeback(1, 1)),
+            TransactionOutcome::Applied { balances } if balances.locked && balances.total == 0.0
+        ));
     }
 
-    #[tokio::test]
-    async fn test_resolving_a_disputed_transaction_succeeds() {
+    #[test]
+    fn test_lifecycle_reports_created_and_locked_accounts() {
         let (client_db, transactions_db) = setup();
+        let apply = |tx| {
+            apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default()).lifecycle
+        };
+        let created = Lifecycle {
+            created: true,
+            locked: false,
+            closed: false,
+        };
 
-        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
-        let deposit2 = Transaction::new_deposit(1, 2, 1.0);
-        let dispute = Transaction::new_dispute(1, 1);
-        let resolve = Transaction::new_resolve(1, 1);
-
-        handle_transaction(deposit1, &client_db, &transactions_db).await;
-        handle_transaction(deposit2, &client_db, &transactions_db).await;
-
-        assert_eq!(client_db.get(&1).unwrap().available, 4.0);
-
-        handle_transaction(dispute, &client_db, &transactions_db).await;
-        assert_eq!(client_db.get(&1).unwrap().available, 1.0);
-        assert_eq!(client_db.get(&1).unwrap().held, 3.0);
+        assert_eq!(apply(Transaction::new_withdrawal(1, 1, 1.0)), created);
         assert_eq!(
-            transactions_db.get(&1).unwrap().status,
-            TransactionStatus::Disputed
+            apply(Transaction::new_deposit(1, 2, 3.0)),
+            Lifecycle::default()
         );
+        assert_eq!(apply(Transaction::new_dispute(2, 2)), Lifecycle::default());
+        assert_eq!(apply(Transaction::new_deposit(2, 3, 1.0)), created);
 
-        handle_transaction(resolve, &client_db, &transactions_db).await;
-        assert_eq!(client_db.get(&1).unwrap().available, 4.0);
-        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
+        apply(Transaction::new_deposit(1, 4, 1.0));
+        apply(Transaction::new_dispute(1, 2));
+        apply(Transaction::new_dispute(1, 4));
+        assert_eq!(
+            apply(Transaction::new_chargeback(1, 2)),
+            Lifecycle {
+                created: false,
+                locked: true,
+                closed: false,
+            }
+        );
         assert_eq!(
-            transactions_db.get(&1).unwrap().status,
-            TransactionStatus::Good
+            apply(Transaction::new_chargeback(1, 4)),
+            Lifecycle::default()
         );
     }
 
-    #[tokio::test]
-    async fn test_dangling_resolve_is_ignored() {
+    #[test]
+    fn test_balances_out_of_range_are_rejected() {
         let (client_db, transactions_db) = setup();
+        let apply =
+            |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &Policy::default()).outcome;
+        let rejected = |reason| TransactionOutcome::Rejected { reason };
 
-        let dispute = Transaction::new_resolve(1, 1);
-
-        handle_transaction(dispute, &client_db, &transactions_db).await;
-
+        assert_eq!(
+            apply(Transaction::new_deposit(1, 1, 1e300)),
+            rejected(RejectReason::Overflow)
+        );
         assert!(client_db.get(&1).is_none());
-        assert!(transactions_db.get(&1).is_none());
-    }
-
-    #[tokio::test]
-    async fn test_chargeback_succeeds() {
-        let (client_db, transactions_db) = setup();
-
-        let deposit1 = Transaction::new_deposit(1, 1, 3.0);
-        let deposit2 = Transaction::new_deposit(1, 2, 1.0);
-        let dispute = Transaction::new_dispute(1, 1);
-        let chargeback = Transaction::new_chargeback(1, 1);
-
-        handle_transaction(deposit1, &client_db, &transactions_db).await;
-        handle_transaction(deposit2, &client_db, &transactions_db).await;
-        assert_eq!(client_db.get(&1).unwrap().available, 4.0);
-
-        handle_transaction(dispute, &client_db, &transactions_db).await;
-        assert_eq!(client_db.get(&1).unwrap().available, 1.0);
-        assert_eq!(client_db.get(&1).unwrap().held, 3.0);
+        apply(Transaction::new_deposit(1, 2, MAX_AMOUNT - 1.0));
         assert_eq!(
-            transactions_db.get(&1).unwrap().status,
-            TransactionStatus::Disputed
+            apply(Transaction::new_deposit(1, 3, 2.0)),
+            rejected(RejectReason::Overflow)
         );
-
-        handle_transaction(chargeback, &client_db, &transactions_db).await;
-        assert_eq!(client_db.get(&1).unwrap().available, 1.0);
-        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
-        assert!(client_db.get(&1).unwrap().locked);
+        assert!(transactions_db.get(&3).is_none());
+
+        apply(Transaction::new_debit(
+            2,
+            4,
+            MAX_AMOUNT,
+            AdjustmentReason::WriteOff,
+        ));
         assert_eq!(
-            transactions_db.get(&1).unwrap().status,
-            TransactionStatus::Chargeback
+            apply(Transaction::new_debit(2, 5, 1.0, AdjustmentReason::Fee)),
+            rejected(RejectReason::Underflow)
         );
+        assert_eq!(client_db.get(&2).unwrap().total, -MAX_AMOUNT);
     }
 
-    #[tokio::test]
-    async fn test_chargeback_for_a_non_disputed_transaction_is_ignored() {
+    #[test]
+    fn test_stats_count_applied_transactions() {
         let (client_db, transactions_db) = setup();
+        let txs = [
+            Transaction::new_deposit(1, 1, 3.0),
+            Transaction::new_deposit(1, 2, 2.0),
+            Transaction::new_withdrawal(1, 3, 1.5),
+            Transaction::new_withdrawal(1, 4, 9.0),
+            Transaction::new_deposit(2, 5, 1.0),
+            Transaction::new_dispute(1, 2),
+            Transaction::new_resolve(1, 2),
+        ];
+        for (sequence, tx) in (1..).zip(txs) {
+            apply_transaction(
+                tx,
+                sequence,
+                &client_db,
+                &transactions_db,
+                &Policy::default(),
+            );
+        }
 
-        let deposit = Transaction::new_deposit(1, 1, 3.0);
-        let chargeback = Transaction::new_chargeback(1, 1);
-
-        handle_transaction(deposit, &client_db, &transactions_db).await;
-        assert_eq!(client_db.get(&1).unwrap().available, 3.0);
-        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
-
-        handle_transaction(chargeback, &client_db, &transactions_db).await;
-        assert_eq!(client_db.get(&1).unwrap().available, 3.0);
-        assert_eq!(client_db.get(&1).unwrap().held, 0.0);
-        assert!(!client_db.get(&1).unwrap().locked);
         assert_eq!(
-            transactions_db.get(&1).unwrap().status,
-            TransactionStatus::Good
+            client_db.get(&1).unwrap().stats,
+            ClientStats {
+                transactions: 5,
+                disputes: 1,
+                deposited: 5.0,
+                withdrawn: 1.5,
+                adjusted: 0.0,
+                last_activity: 7,
+            }
         );
-    }
-
-    #[tokio::test]
-    async fn test_dangling_chargeback_is_ignored() {
-        let (client_db, transactions_db) = setup();
-
-        let dispute = Transaction::new_chargeback(1, 1);
-
-        handle_transaction(dispute, &client_db, &transactions_db).await;
-
-        assert!(client_db.get(&1).is_none());
-        assert!(transactions_db.get(&1).is_none());
+        assert_eq!(client_db.get(&2).unwrap().stats.last_activity, 5);
     }
 }
@@ -0,0 +1,109 @@
+use super::{get_own_transaction, Client, Context, TransactionHandler};
+use crate::outcome::RejectReason;
+use crate::transactions::{Transaction, TransactionStatus, TransactionType};
+
+/// Settles a deposit or withdrawal held for review: approving credits a
+/// deposit or pays out a withdrawal, denying drops a deposit or returns a
+/// withdrawal's funds.
+pub(super) struct Review;
+
+impl TransactionHandler for Review {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let mut reviewed_tx = get_own_transaction(tx, cx.client_db, cx.tx_db)?;
+        if reviewed_tx.status() != TransactionStatus::Pending {
+            return Err(RejectReason::NotPending);
+        }
+        let amount = reviewed_tx.amount();
+        let mut client = cx.client_db.get_mut(&tx.client_id).unwrap();
+        let status = match (tx.tx_type, reviewed_tx.tx_type()) {
+            (TransactionType::Approve, TransactionType::Deposit) => {
+                client.move_funds(amount, -amount)?;
+                TransactionStatus::Good
+            }
+            (TransactionType::Approve, _) => {
+                client.move_funds(0.0, -amount)?;
+                TransactionStatus::Good
+            }
+            (_, TransactionType::Deposit) => {
+                client.move_funds(0.0, -amount)?;
+                client.stats.deposited -= amount;
+                TransactionStatus::Denied
+            }
+            _ => {
+                client.move_funds(amount, -amount)?;
+                client.stats.withdrawn -= amount;
+                TransactionStatus::Denied
+            }
+        };
+        reviewed_tx.set_status(status);
+        cx.tx_db.index_status(tx.tx_id, status);
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::{Balances, RejectReason, TransactionOutcome};
+    use crate::policy::Policy;
+    use crate::processor::apply_transaction;
+    use crate::processor::tests::setup;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_large_withdrawals_are_held_for_review() {
+        let (client_db, transactions_db) = setup();
+        let mut policy = Policy::default();
+        policy.set_review_threshold(100.0);
+        let apply = |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &policy).outcome;
+        let balances = |available, held, total| Balances {
+            client: 1,
+            available,
+            held,
+            total,
+            locked: false,
+        };
+        let rejected = |reason| TransactionOutcome::Rejected { reason };
+
+        apply(Transaction::new_deposit(1, 1, 500.0));
+        assert_eq!(
+            apply(Transaction::new_withdrawal(1, 2, 100.0)),
+            TransactionOutcome::Applied {
+                balances: balances(400.0, 0.0, 400.0)
+            }
+        );
+        assert_eq!(
+            apply(Transaction::new_withdrawal(1, 3, 150.0)),
+            TransactionOutcome::Held {
+                balances: balances(250.0, 150.0, 400.0)
+            }
+        );
+        assert_eq!(
+            apply(Transaction::new_dispute(1, 3)),
+            rejected(RejectReason::PendingReview)
+        );
+        assert_eq!(
+            apply(Transaction::new_approve(1, 3)),
+            TransactionOutcome::Applied {
+                balances: balances(250.0, 0.0, 250.0)
+            }
+        );
+        assert_eq!(
+            apply(Transaction::new_deny(1, 3)),
+            rejected(RejectReason::NotPending)
+        );
+
+        apply(Transaction::new_withdrawal(1, 4, 200.0));
+        assert_eq!(
+            apply(Transaction::new_deny(1, 4)),
+            TransactionOutcome::Applied {
+                balances: balances(250.0, 0.0, 250.0)
+            }
+        );
+        assert_eq!(
+            apply(Transaction::new_dispute(1, 4)),
+            rejected(RejectReason::DeniedOnReview)
+        );
+        assert_eq!(client_db.get(&1).unwrap().stats.withdrawn, 250.0);
+    }
+}
@@ -0,0 +1,132 @@
+use super::{get_or_create_client, insert_new_transaction, Client, Context, TransactionHandler};
+use crate::outcome::RejectReason;
+use crate::transactions::{Transaction, TransactionStatus};
+
+/// Takes the amount from the available funds, down to the client's
+/// overdraft limit but not below their minimum balance, and retains the
+/// withdrawal so it can be disputed.
+pub(super) struct Withdrawal;
+
+impl TransactionHandler for Withdrawal {
+    fn apply(&self, tx: &Transaction, cx: &mut Context) -> Result<Client, RejectReason> {
+        let amount = tx.amount.ok_or(RejectReason::MissingAmount)?;
+        let mut client =
+            get_or_create_client(tx.client_id, cx.sequence, cx.client_db, cx.lifecycle);
+        if client.available + cx.policy.overdraft_limit(tx.client_id) < amount {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        // A reserve outranks any overdraft: the client never goes below it
+        if let Some(minimum) = cx.policy.minimum_balance(tx.client_id) {
+            if client.available - amount < minimum {
+                return Err(RejectReason::BelowMinimumBalance);
+            }
+        }
+        // A withdrawal held for review keeps its amount held until it
+        // is approved or denied
+        let (held, status) = match cx.hold {
+            true => (amount, TransactionStatus::Pending),
+            false => (0.0, TransactionStatus::Good),
+        };
+        client.move_funds(-amount, held)?;
+        insert_new_transaction(*tx, amount, status, cx.tx_db);
+        client.stats.record(tx, cx.sequence);
+        Ok(*client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::outcome::{RejectReason, TransactionOutcome};
+    use crate::policy::Policy;
+    use crate::processor::tests::setup;
+    use crate::processor::{apply_transaction, handle_transaction};
+    use crate::transactions::Transaction;
+
+    #[tokio::test]
+    async fn test_deposit_and_withdrawal() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit = Transaction::new_deposit(1, 1, 3.0);
+        let withdrawal = Transaction::new_withdrawal(1, 2, 1.5);
+
+        handle_transaction(deposit, &client_db, &transactions_db).await;
+        handle_transaction(withdrawal, &client_db, &transactions_db).await;
+
+        let client = client_db.get(&1).unwrap();
+
+        assert_eq!(client.available, 1.5);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 1.5);
+        assert!(!client.locked);
+    }
+
+    #[tokio::test]
+    async fn test_withdrawing_more_than_available_fails() {
+        let (client_db, transactions_db) = setup();
+
+        let deposit = Transaction::new_deposit(1, 1, 3.0);
+        let withdrawal = Transaction::new_withdrawal(1, 2, 4.0);
+
+        handle_transaction(deposit, &client_db, &transactions_db).await;
+        handle_transaction(withdrawal, &client_db, &transactions_db).await;
+
+        let client = client_db.get(&1).unwrap();
+
+        assert_eq!(client.available, 3.0);
+        assert_eq!(client.held, 0.0);
+        assert_eq!(client.total, 3.0);
+        assert!(!client.locked);
+    }
+
+    #[test]
+    fn test_withdrawals_may_overdraw_up_to_the_limit() {
+        let (client_db, transactions_db) = setup();
+        let mut policy = Policy::default();
+        policy.set_overdraft_limit(5.0);
+        policy.set_client_overdraft_limit(2, 0.0);
+        let apply = |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &policy).outcome;
+        let insufficient = TransactionOutcome::Rejected {
+            reason: RejectReason::InsufficientFunds,
+        };
+
+        apply(Transaction::new_deposit(1, 1, 1.0));
+        assert!(matches!(
+            apply(Transaction::new_withdrawal(1, 2, 6.0)),
+            TransactionOutcome::Applied { balances } if balances.available == -5.0
+        ));
+        assert_eq!(apply(Transaction::new_withdrawal(1, 3, 0.5)), insufficient);
+
+        apply(Transaction::new_deposit(2, 4, 1.0));
+        assert_eq!(apply(Transaction::new_withdrawal(2, 5, 1.5)), insufficient);
+        assert_eq!(policy.overdraft_limit(3), 5.0);
+    }
+
+    #[test]
+    fn test_withdrawals_keep_the_minimum_balance() {
+        let (client_db, transactions_db) = setup();
+        let mut policy = Policy::default();
+        policy.set_overdraft_limit(5.0);
+        policy.set_minimum_balance(2.0);
+        policy.set_client_minimum_balance(2, 0.0);
+        let apply = |tx| apply_transaction(tx, 0, &client_db, &transactions_db, &policy).outcome;
+
+        apply(Transaction::new_deposit(1, 1, 10.0));
+        assert_eq!(
+            apply(Transaction::new_withdrawal(1, 2, 8.5)),
+            TransactionOutcome::Rejected {
+                reason: RejectReason::BelowMinimumBalance
+            }
+        );
+        assert!(matches!(
+            apply(Transaction::new_withdrawal(1, 3, 8.0)),
+            TransactionOutcome::Applied { balances } if balances.available == 2.0
+        ));
+
+        apply(Transaction::new_deposit(2, 4, 1.0));
+        assert!(matches!(
+            apply(Transaction::new_withdrawal(2, 5, 1.0)),
+            TransactionOutcome::Applied { balances } if balances.available == 0.0
+        ));
+        assert_eq!(policy.minimum_balance(3), Some(2.0));
+    }
+}
@@ -0,0 +1,93 @@
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::iter::FromIterator;
+use std::path::Path;
+
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+/// Legacy client ids and the ids they have now, e.g. to replay historical
+/// exports numbered the old way into the current id space. Several legacy
+/// ids may map to the same client; ids without an entry are taken to be
+/// current already.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientIdMap {
+    ids: FxHashMap<u16, u16>,
+}
+
+#[derive(Deserialize)]
+struct MappingRow {
+    old_client: u16,
+    new_client: u16,
+}
+
+impl ClientIdMap {
+    /// Reads a CSV file with `old_client` and `new_client` columns.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(File::open(path)?)
+    }
+
+    pub fn parse<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut ids = FxHashMap::default();
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for (index, row) in reader.deserialize().enumerate() {
+            let row: MappingRow = row?;
+            match ids.insert(row.old_client, row.new_client) {
+                Some(new_client) if new_client != row.new_client => {
+                    return Err(format!(
+                        "row {}: client {} is already mapped to {}",
+                        index + 2,
+                        row.old_client,
+                        new_client
+                    )
+                    .into());
+                }
+                _ => {}
+            }
+        }
+        Ok(Self { ids })
+    }
+
+    /// The current id of `client`.
+    pub fn map(&self, client: u16) -> u16 {
+        self.ids.get(&client).copied().unwrap_or(client)
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+impl FromIterator<(u16, u16)> for ClientIdMap {
+    fn from_iter<I: IntoIterator<Item = (u16, u16)>>(ids: I) -> Self {
+        Self {
+            ids: ids.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mapping_file_is_read_and_conflicts_refused() {
+        let map =
+            ClientIdMap::parse("old_client,new_client\n 1001, 1\n1002,1\n7,8\n7,8\n".as_bytes())
+                .unwrap();
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.map(1001), 1);
+        assert_eq!(map.map(1002), 1);
+        assert_eq!(map.map(8), 8);
+
+        let err = ClientIdMap::parse("old_client,new_client\n7,8\n7,9\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "row 3: client 7 is already mapped to 8");
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+
+use crate::engine::PaymentsEngine;
+
+/// A client report parsed back for comparison, with rows in client order.
+///
+/// Amounts are kept in ten-thousandths, the report's precision, so two
+/// reports compare equal whenever they print the same balances, however
+/// many decimals each was written with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Report {
+    rows: BTreeMap<u16, ReportRow>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ReportRow {
+    pub client: u16,
+    available: i64,
+    held: i64,
+    total: i64,
+    pub locked: bool,
+}
+
+impl ReportRow {
+    /// A row with the given balances, rounded to the report's precision.
+    pub fn new(client: u16, available: f64, held: f64, total: f64, locked: bool) -> Self {
+        let units = |amount: f64| (amount * 10_000.0).round() as i64;
+        Self {
+            client,
+            available: units(available),
+            held: units(held),
+            total: units(total),
+            locked,
+        }
+    }
+}
+
+impl FromIterator<ReportRow> for Report {
+    fn from_iter<I: IntoIterator<Item = ReportRow>>(rows: I) -> Self {
+        Self {
+            rows: rows.into_iter().map(|row| (row.client, row)).collect(),
+        }
+    }
+}
+
+impl Report {
+    /// Reads a report in the `client,available,held,total,locked` format
+    /// written by the engine. Columns are located by name.
+    pub fn parse<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| format!("missing '{}' column", name))
+        };
+        let columns = [
+            column("client")?,
+            column("available")?,
+            column("held")?,
+            column("total")?,
+            column("locked")?,
+        ];
+
+        let mut rows = BTreeMap::new();
+        for (index, record) in reader.records().enumerate() {
+            let record = record?;
+            // Header is line 1
+            let describe = |err: &dyn fmt::Display| format!("line {}: {}", index + 2, err);
+            let field = |column: usize| record.get(column).unwrap_or_default();
+
+            let row = ReportRow {
+                client: field(columns[0]).parse().map_err(|err| describe(&err))?,
+                available: parse_amount(field(columns[1])).map_err(|err| describe(&err))?,
+                held: parse_amount(field(columns[2])).map_err(|err| describe(&err))?,
+                total: parse_amount(field(columns[3])).map_err(|err| describe(&err))?,
+                locked: field(columns[4]).parse().map_err(|err| describe(&err))?,
+            };
+            if rows.insert(row.client, row).is_some() {
+                return Err(describe(&format!("client {} appears twice", row.client)).into());
+            }
+        }
+
+        Ok(Self { rows })
+    }
+
+    /// Snapshot of an engine's current balances.
+    pub fn from_engine(engine: &PaymentsEngine) -> Self {
+        let mut report = vec![];
+        // Writing into a Vec can't fail, and the engine always writes a
+        // report this module can read
+        engine.write_report(&mut report).unwrap();
+        Self::parse(report.as_slice()).unwrap()
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &ReportRow> {
+        self.rows.values()
+    }
+
+    /// Every difference between this report, taken as the expected one,
+    /// and `actual`, in client order.
+    pub fn diff(&self, actual: &Report) -> Vec<RowDiff> {
+        let mut diffs = vec![];
+        for (client, expected) in &self.rows {
+            match actual.rows.get(client) {
+                None => diffs.push(RowDiff::Missing(*expected)),
+                Some(actual) if actual != expected => diffs.push(RowDiff::Changed {
+                    expected: *expected,
+                    actual: *actual,
+                }),
+                Some(_) => {}
+            }
+        }
+        for (client, actual) in &actual.rows {
+            if !self.rows.contains_key(client) {
+                diffs.push(RowDiff::Unexpected(*actual));
+            }
+        }
+        diffs.sort_by_key(RowDiff::client);
+        diffs
+    }
+}
+
+fn parse_amount(field: &str) -> Result<i64, String> {
+    let amount: f64 = field
+        .parse()
+        .map_err(|_| format!("invalid amount '{}'", field))?;
+    Ok((amount * 10_000.0).round() as i64)
+}
+
+impl fmt::Display for ReportRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{},{:.4},{:.4},{:.4},{}",
+            self.client,
+            self.available as f64 / 10_000.0,
+            self.held as f64 / 10_000.0,
+            self.total as f64 / 10_000.0,
+            self.locked
+        )
+    }
+}
+
+/// One way two reports disagree about a client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RowDiff {
+    Missing(ReportRow),
+    Unexpected(ReportRow),
+    Changed {
+        expected: ReportRow,
+        actual: ReportRow,
+    },
+}
+
+impl RowDiff {
+    pub fn client(&self) -> u16 {
+        match self {
+            RowDiff::Missing(row) | RowDiff::Unexpected(row) => row.client,
+            RowDiff::Changed { expected, .. } => expected.client,
+        }
+    }
+}
+
+impl fmt::Display for RowDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowDiff::Missing(row) => write!(f, "- {}", row),
+            RowDiff::Unexpected(row) => write!(f, "+ {}", row),
+            RowDiff::Changed { expected, actual } => write!(f, "- {}\n+ {}", expected, actual),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reports_compare_at_report_precision() {
+        let expected = Report::parse(
+            "client,available,held,total,locked\n2,1.5,0,1.5,false\n1,0.0000,0.0000,0.0000,true\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        let actual = Report::parse(
+            "client, available, held, total, locked\n1, 0, 0, 0, true\n2, 1.50001, 0, 1.5, false\n"
+                .as_bytes(),
+        )
+        .unwrap();
+
+        assert_eq!(expected, actual);
+        assert!(expected.diff(&actual).is_empty());
+    }
+
+    #[test]
+    fn test_diff_lists_every_disagreement() {
+        let expected = Report::parse(
+            "client,available,held,total,locked\n1,1.0,0,1.0,false\n2,2.0,0,2.0,false\n".as_bytes(),
+        )
+        .unwrap();
+        let actual = Report::parse(
+            "client,available,held,total,locked\n2,2.0,0,2.0,true\n3,3.0,0,3.0,false\n".as_bytes(),
+        )
+        .unwrap();
+
+        let diffs: Vec<String> = expected
+            .diff(&actual)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            diffs,
+            [
+                "- 1,1.0000,0.0000,1.0000,false",
+                "- 2,2.0000,0.0000,2.0000,false\n+ 2,2.0000,0.0000,2.0000,true",
+                "+ 3,3.0000,0.0000,3.0000,false",
+            ]
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use crate::outcome::Balances;
+use crate::transactions::Transaction;
+
+/// Hook for a fraud or risk model, consulted before every deposit and
+/// withdrawal the engine applies, set through
+/// [`EngineBuilder::risk_scorer`](crate::engine::EngineBuilder::risk_scorer).
+///
+/// `balances` are the client's balances before the transaction, or `None`
+/// for a client without an account yet. Scoring is part of applying the
+/// transaction, with the constraints that puts on it described at
+/// [`PaymentsEngine::apply_transaction`](crate::engine::PaymentsEngine::apply_transaction);
+/// a model behind a network call belongs in front of the engine. Closures
+/// of the same signature are scorers too.
+pub trait RiskScorer: Send + Sync {
+    fn score(&self, tx: &Transaction, balances: Option<&Balances>) -> RiskAssessment;
+}
+
+impl<F> RiskScorer for F
+where
+    F: Fn(&Transaction, Option<&Balances>) -> RiskAssessment + Send + Sync,
+{
+    fn score(&self, tx: &Transaction, balances: Option<&Balances>) -> RiskAssessment {
+        self(tx, balances)
+    }
+}
+
+/// A scorer's verdict on one transaction. The score is the model's own,
+/// recorded with the outcome for auditing; the engine acts on the decision
+/// alone.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RiskAssessment {
+    pub score: f64,
+    pub decision: RiskDecision,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum RiskDecision {
+    /// Apply the transaction as usual.
+    Approve,
+    /// Hold the transaction for review, as a withdrawal above the review
+    /// threshold would be, until an `approve` or `deny` settles it.
+    Hold,
+    /// Reject the transaction as `risk_rejected`.
+    Reject,
+}
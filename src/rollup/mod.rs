@@ -0,0 +1,179 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::currency::Precision;
+use crate::events::{open_journal, parse_journal};
+use crate::transactions::TransactionType;
+
+/// The fields of a journal line the rollups add up.
+#[derive(Deserialize)]
+struct JournalLine {
+    seq: u64,
+    client: u16,
+    #[serde(rename = "type")]
+    tx_type: Option<TransactionType>,
+    amount: Option<f64>,
+    outcome: Option<String>,
+    total: Option<f64>,
+}
+
+/// One client's activity over one period of the journal. Periods are
+/// consecutive ranges of `length` sequence numbers, the first starting at
+/// 1, since journals carry no timestamps.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PeriodTotals {
+    /// First and last sequence number of the period, both inclusive.
+    pub from: u64,
+    pub to: u64,
+    pub client: u16,
+    /// Amounts deposited and withdrawn, including those held for review.
+    pub deposits: f64,
+    pub withdrawals: f64,
+    pub disputes_opened: u64,
+    /// Disputes resolved or charged back.
+    pub disputes_closed: u64,
+    /// Change of the client's total balance over the period, including
+    /// adjustments, chargebacks and denied reviews.
+    pub net_change: f64,
+}
+
+/// Adds up each client's applied and held transactions in the `--events`
+/// journal per period of `length` sequence numbers. Clients without such
+/// transactions in a period have no totals for it. Totals are ordered by
+/// period, then client.
+pub fn rollups<R: BufRead>(journal: R, length: u64) -> Result<Vec<PeriodTotals>, Box<dyn Error>> {
+    if length == 0 {
+        return Err("periods must span at least one event".into());
+    }
+    let mut lines: Vec<JournalLine> = parse_journal(journal)?;
+    lines.sort_by_key(|line| line.seq);
+
+    let mut periods: BTreeMap<(u64, u16), PeriodTotals> = BTreeMap::new();
+    // Each client's total balance after its last applied transaction
+    let mut totals: HashMap<u16, f64> = HashMap::new();
+    for line in lines {
+        let (Some(tx_type), Some("applied" | "held")) = (line.tx_type, line.outcome.as_deref())
+        else {
+            continue;
+        };
+        let index = line.seq.saturating_sub(1) / length;
+        let period = periods.entry((index, line.client)).or_insert(PeriodTotals {
+            from: index * length + 1,
+            to: (index + 1) * length,
+            client: line.client,
+            ..PeriodTotals::default()
+        });
+        match tx_type {
+            TransactionType::Deposit => period.deposits += line.amount.unwrap_or_default(),
+            TransactionType::Withdrawal => period.withdrawals += line.amount.unwrap_or_default(),
+            TransactionType::Dispute => period.disputes_opened += 1,
+            TransactionType::Resolve | TransactionType::Chargeback => period.disputes_closed += 1,
+            _ => {}
+        }
+        if let Some(total) = line.total {
+            let before = totals.insert(line.client, total).unwrap_or_default();
+            period.net_change += total - before;
+        }
+    }
+    Ok(periods.into_values().collect())
+}
+
+/// Adds up the journal at `path` as [`rollups`] does. The journal may be
+/// gzip or zstd compressed, according to its extension.
+pub fn read_rollups(path: &Path, length: u64) -> Result<Vec<PeriodTotals>, Box<dyn Error>> {
+    let journal = open_journal(path)?;
+    rollups(journal, length).map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+/// Writes `totals` as
+/// `from,to,client,deposits,withdrawals,disputes_opened,disputes_closed,net_change`
+/// CSV, with amounts at `precision`.
+pub fn write_rollups<W: Write>(
+    totals: &[PeriodTotals],
+    precision: Precision,
+    destination: W,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(destination);
+    writer.write_record([
+        "from",
+        "to",
+        "client",
+        "deposits",
+        "withdrawals",
+        "disputes_opened",
+        "disputes_closed",
+        "net_change",
+    ])?;
+    for period in totals {
+        writer.write_record([
+            period.from.to_string().as_str(),
+            &period.to.to_string(),
+            &period.client.to_string(),
+            &precision.format(period.deposits).to_string(),
+            &precision.format(period.withdrawals).to_string(),
+            &period.disputes_opened.to_string(),
+            &period.disputes_closed.to_string(),
+            &precision.format(period.net_change).to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JOURNAL: &str = r#"{"seq":1,"client":1,"lifecycle":"created"}
+{"seq":2,"type":"deposit","client":1,"tx":1,"amount":5.0,"outcome":"applied","available":5.0,"held":0.0,"total":5.0,"locked":false}
+{"seq":3,"type":"withdrawal","client":1,"tx":2,"amount":9.0,"outcome":"rejected","reason":"insufficient_funds"}
+{"seq":4,"type":"withdrawal","client":1,"tx":3,"amount":1.5,"outcome":"applied","available":3.5,"held":0.0,"total":3.5,"locked":false}
+{"seq":5,"type":"dispute","client":1,"tx":1,"outcome":"applied","available":-1.5,"held":5.0,"total":3.5,"locked":false}
+{"seq":6,"type":"chargeback","client":1,"tx":1,"outcome":"applied","available":-1.5,"held":0.0,"total":-1.5,"locked":true}
+{"seq":7,"type":"deposit","client":2,"tx":4,"amount":2.0,"outcome":"applied","available":2.0,"held":0.0,"total":2.0,"locked":false}
+"#;
+
+    #[test]
+    fn test_rollups_add_up_each_period() {
+        let totals = rollups(JOURNAL.as_bytes(), 4).unwrap();
+        assert_eq!(
+            totals,
+            [
+                PeriodTotals {
+                    from: 1,
+                    to: 4,
+                    client: 1,
+                    deposits: 5.0,
+                    withdrawals: 1.5,
+                    disputes_opened: 0,
+                    disputes_closed: 0,
+                    net_change: 3.5,
+                },
+                PeriodTotals {
+                    from: 5,
+                    to: 8,
+                    client: 1,
+                    deposits: 0.0,
+                    withdrawals: 0.0,
+                    disputes_opened: 1,
+                    disputes_closed: 1,
+                    net_change: -5.0,
+                },
+                PeriodTotals {
+                    from: 5,
+                    to: 8,
+                    client: 2,
+                    deposits: 2.0,
+                    withdrawals: 0.0,
+                    disputes_opened: 0,
+                    disputes_closed: 0,
+                    net_change: 2.0,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+
+use crate::engine::{EngineBuilder, PaymentsEngine};
+use crate::outcome::TransactionOutcome;
+use crate::transactions::Transaction;
+
+/// Points each instance gets on the ring unless configured otherwise.
+/// More points spread clients more evenly at the cost of a larger ring.
+pub const DEFAULT_VIRTUAL_NODES: usize = 128;
+
+/// A consistent-hashing ring assigning client ids to named instances.
+///
+/// Every instance is placed at a number of pseudo-random points, its
+/// virtual nodes, and a client belongs to the first point at or after its
+/// own hash, wrapping around. Adding or removing an instance only moves
+/// the clients of the points it gains or loses, about one in every
+/// `instances` clients, where `client % instances` would move almost all
+/// of them. Hashes are stable across runs and platforms, and the ring
+/// depends only on which instances are in it, not on the order they were
+/// added in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    points: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    /// An empty ring placing each instance at `virtual_nodes` points, at
+    /// least one.
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            points: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, instance: &str) {
+        for replica in 0..self.virtual_nodes {
+            let point = point_of(instance, replica);
+            // On the rare collision the smaller name wins, whichever
+            // instance came first
+            match self.points.get(&point) {
+                Some(owner) if owner.as_str() <= instance => {}
+                _ => {
+                    self.points.insert(point, instance.to_string());
+                }
+            }
+        }
+    }
+
+    pub fn remove(&mut self, instance: &str) {
+        self.points.retain(|_, owner| owner != instance);
+    }
+
+    /// The instance owning `client_id`, or `None` on an empty ring.
+    pub fn owner(&self, client_id: u16) -> Option<&str> {
+        let hash = splitmix64(client_id as u64);
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, owner)| owner.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
+
+/// Where an instance's `replica`th virtual node sits: FNV-1a over the name
+/// and replica, finished with splitmix64 to spread similar names apart.
+fn point_of(instance: &str, replica: usize) -> u64 {
+    let hash = instance
+        .bytes()
+        .chain((replica as u64).to_le_bytes())
+        .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+    splitmix64(hash)
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Spreads clients over several engine instances by consistent hashing,
+/// so a client population too large for one engine's maps can be split
+/// across instances that each hold only their own clients.
+///
+/// Each transaction goes to the instance owning its client, and every
+/// client lives on exactly one instance, so balances are the same as with
+/// a single engine. As with `--partitions`, instances don't see each
+/// other's transactions: a transaction id reused by clients on different
+/// instances isn't caught as a duplicate, and a dispute naming another
+/// client's transaction is refused as an unknown transaction rather than
+/// as a client mismatch.
+///
+/// The instances are built from one builder, so they share its event sink
+/// and settings. Adding or removing an instance hands the clients that
+/// change owner over with their stored transactions and pending reviews;
+/// the router is borrowed mutably for it, so no transaction is applied
+/// meanwhile.
+pub struct ShardRouter {
+    builder: EngineBuilder,
+    ring: HashRing,
+    instances: BTreeMap<String, PaymentsEngine>,
+}
+
+impl ShardRouter {
+    /// A router over one instance per name in `instances`, each placed at
+    /// `virtual_nodes` points on the ring.
+    ///
+    /// Panics if `instances` is empty.
+    pub fn new<I, S>(builder: EngineBuilder, instances: I, virtual_nodes: usize) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let names: Vec<String> = instances.into_iter().map(Into::into).collect();
+        assert!(!names.is_empty(), "a shard router needs an instance");
+        let mut ring = HashRing::new(virtual_nodes);
+        let mut engines = BTreeMap::new();
+        for name in names {
+            ring.add(&name);
+            engines.insert(name, builder.clone().build());
+        }
+        Self {
+            builder,
+            ring,
+            instances: engines,
+        }
+    }
+
+    /// Name of the instance owning `client_id`.
+    pub fn owner(&self, client_id: u16) -> &str {
+        self.ring
+            .owner(client_id)
+            .expect("a shard router always has an instance")
+    }
+
+    pub fn instance(&self, name: &str) -> Option<&PaymentsEngine> {
+        self.instances.get(name)
+    }
+
+    /// Names of the instances, in order.
+    pub fn instance_names(&self) -> impl Iterator<Item = &str> {
+        self.instances.keys().map(String::as_str)
+    }
+
+    /// Applies `transaction` on the instance owning its client.
+    pub fn apply_transaction(&self, transaction: Transaction) -> TransactionOutcome {
+        self.instances[self.owner(transaction.client_id)].apply_transaction(transaction)
+    }
+
+    /// Adds an instance and hands it the clients it now owns, returning
+    /// how many moved. An instance already in the router stays as it is.
+    pub fn add_instance(&mut self, name: &str) -> usize {
+        if self.instances.contains_key(name) {
+            return 0;
+        }
+        self.ring.add(name);
+        self.instances
+            .insert(name.to_string(), self.builder.clone().build());
+        self.rebalance()
+    }
+
+    /// Removes an instance and hands its clients to their new owners,
+    /// returning how many moved. The last instance can't be removed.
+    pub fn remove_instance(&mut self, name: &str) -> Result<usize, String> {
+        if !self.instances.contains_key(name) {
+            return Err(format!("no instance '{}'", name));
+        }
+        if self.instances.len() == 1 {
+            return Err(format!("'{}' is the last instance", name));
+        }
+        self.ring.remove(name);
+        let moved = self.rebalance();
+        self.instances.remove(name);
+        Ok(moved)
+    }
+
+    /// Moves every client not on its owner's instance there.
+    fn rebalance(&mut self) -> usize {
+        let mut moves = vec![];
+        for (name, engine) in &self.instances {
+            for client in engine.clients().iter() {
+                let owner = self.owner(*client.key());
+                if owner != name {
+                    moves.push((*client.key(), name.clone(), owner.to_string()));
+                }
+            }
+        }
+        for (client, from, into) in &moves {
+            self.instances[from].hand_over(*client, &self.instances[into]);
+        }
+        moves.len()
+    }
+
+    /// All instances merged into one engine, e.g. for reports over every
+    /// client. Balances are copied, so the instances are left as they are.
+    pub fn merged(&self) -> PaymentsEngine {
+        let engine = self.builder.clone().build();
+        for instance in self.instances.values() {
+            engine.absorb(instance.clone());
+        }
+        engine
+    }
+
+    /// Writes the balances of every client on every instance as one CSV
+    /// report.
+    pub fn write_report<W: Write>(&self, destination: W) -> Result<(), Box<dyn Error>> {
+        self.merged().write_report(destination)
+    }
+
+    /// Flushes the instances' event sink.
+    pub fn flush_events(&self) -> std::io::Result<()> {
+        for instance in self.instances.values() {
+            instance.flush_events()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_router_moves_few_clients_and_keeps_balances() {
+        let single = PaymentsEngine::new();
+        let mut router = ShardRouter::new(PaymentsEngine::builder(), ["a", "b", "c"], 64);
+        for client in 0..1000u16 {
+            let deposit = Transaction::new_deposit(client, client as u32, 10.0);
+            let withdrawal = Transaction::new_withdrawal(client, 5000 + client as u32, 2.5);
+            for tx in [deposit, withdrawal] {
+                assert_eq!(router.apply_transaction(tx), single.apply_transaction(tx));
+            }
+        }
+        for name in ["a", "b", "c"] {
+            let clients = router.instance(name).unwrap().client_count();
+            assert!((200..=500).contains(&clients), "{} has {}", name, clients);
+        }
+
+        let owners: Vec<String> = (0..1000).map(|c| router.owner(c).to_string()).collect();
+        let moved = router.add_instance("d");
+        assert!((150..=400).contains(&moved), "{} moved", moved);
+        for client in 0..1000u16 {
+            let owner = router.owner(client);
+            assert!(owner == owners[client as usize] || owner == "d");
+            assert!(router
+                .instance(owner)
+                .unwrap()
+                .clients()
+                .contains_key(&client));
+        }
+        // Disputes find deposits that moved with their client
+        let dispute = Transaction::new_dispute(999, 999);
+        assert_eq!(
+            router.apply_transaction(dispute),
+            single.apply_transaction(dispute)
+        );
+
+        router.remove_instance("a").unwrap();
+        assert!(router.remove_instance("a").is_err());
+        assert_eq!(router.instance_names().collect::<Vec<_>>(), ["b", "c", "d"]);
+
+        let rows = |report: Vec<u8>| {
+            let mut rows: Vec<String> = String::from_utf8(report)
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect();
+            rows.sort();
+            rows
+        };
+        let mut merged = vec![];
+        router.write_report(&mut merged).unwrap();
+        let mut expected = vec![];
+        single.write_report(&mut expected).unwrap();
+        assert_eq!(rows(merged), rows(expected));
+    }
+}
@@ -0,0 +1,114 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::iter::FromIterator;
+use std::path::Path;
+
+use rustc_hash::FxHashSet;
+
+use crate::transactions::TransactionType;
+
+/// Sanctions or internal blocklist screening, consulted before every
+/// transaction the engine applies, set through
+/// [`EngineBuilder::blocklist`](crate::engine::EngineBuilder::blocklist).
+///
+/// Every transaction of a blocked client is rejected and recorded as a
+/// [`ScreeningHit`]. Closures of the same signature are blocklists too, e.g.
+/// to ask a screening service's local cache.
+pub trait Blocklist: Send + Sync {
+    fn is_blocked(&self, client: u16) -> bool;
+}
+
+impl<F> Blocklist for F
+where
+    F: Fn(u16) -> bool + Send + Sync,
+{
+    fn is_blocked(&self, client: u16) -> bool {
+        self(client)
+    }
+}
+
+/// A fixed set of blocked client ids, e.g. read from a file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ClientBlocklist {
+    clients: FxHashSet<u16>,
+}
+
+impl ClientBlocklist {
+    /// Reads one client id per line. Blank lines and anything after a `#`
+    /// are ignored, so entries can carry a note of why they are listed.
+    pub fn read(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::parse(BufReader::new(File::open(path)?))
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let mut clients = FxHashSet::default();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            let entry = line.split('#').next().unwrap_or_default().trim();
+            if entry.is_empty() {
+                continue;
+            }
+            let client = entry
+                .parse()
+                .map_err(|_| format!("line {}: '{}' is not a client id", index + 1, entry))?;
+            clients.insert(client);
+        }
+        Ok(Self { clients })
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+}
+
+impl FromIterator<u16> for ClientBlocklist {
+    fn from_iter<I: IntoIterator<Item = u16>>(clients: I) -> Self {
+        Self {
+            clients: clients.into_iter().collect(),
+        }
+    }
+}
+
+impl Blocklist for ClientBlocklist {
+    fn is_blocked(&self, client: u16) -> bool {
+        self.clients.contains(&client)
+    }
+}
+
+/// A transaction rejected because its client is blocked.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ScreeningHit {
+    /// Position of the transaction among those the engine received,
+    /// starting at 1.
+    pub sequence: u64,
+    pub client_id: u16,
+    pub tx_id: u32,
+    pub tx_type: TransactionType,
+    pub amount: Option<f64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocklist_file_skips_comments() {
+        let list = "# OFAC update 2026-10-01\n7\n\n  12  # manual review\n";
+        let blocklist = ClientBlocklist::parse(list.as_bytes()).unwrap();
+
+        assert_eq!(blocklist, vec![7, 12].into_iter().collect());
+        assert!(blocklist.is_blocked(12));
+        assert!(!blocklist.is_blocked(1));
+        assert_eq!(
+            ClientBlocklist::parse("7\nseven\n".as_bytes())
+                .unwrap_err()
+                .to_string(),
+            "line 2: 'seven' is not a client id"
+        );
+    }
+}
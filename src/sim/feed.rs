@@ -0,0 +1,73 @@
+use crate::sim::SimRng;
+use crate::transactions::{Transaction, TransactionType};
+
+/// Deposits per client remembered as targets for disputes, resolves and
+/// chargebacks. Bounded so an endless feed doesn't grow with its length.
+const RECENT_DEPOSITS: usize = 16;
+
+/// Endless, reproducible stream of synthetic transactions over clients
+/// `1..=clients`.
+///
+/// Roughly half are deposits and a fifth withdrawals; the rest dispute,
+/// resolve or charge back one of the client's recent deposits, so the feed
+/// exercises every rule rather than only piling up balances. Amounts have
+/// at most four decimals, like real inputs. Transaction ids count up from 1
+/// and wrap around after `u32::MAX`.
+pub struct SyntheticFeed {
+    rng: SimRng,
+    next_tx_id: u32,
+    deposits: Vec<Vec<u32>>,
+}
+
+impl SyntheticFeed {
+    pub fn new(seed: u64, clients: u16) -> Self {
+        Self {
+            rng: SimRng::new(seed),
+            next_tx_id: 1,
+            deposits: vec![vec![]; clients.max(1) as usize],
+        }
+    }
+}
+
+impl Iterator for SyntheticFeed {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Transaction> {
+        let tx_id = self.next_tx_id;
+        self.next_tx_id = self.next_tx_id.wrapping_add(1).max(1);
+
+        let client = self.rng.below(self.deposits.len());
+        let client_id = client as u16 + 1;
+        let amount = (1 + self.rng.below(10_000_000)) as f64 / 10_000.0;
+        let recent = &mut self.deposits[client];
+
+        let (tx_type, tx_id, amount) = match self.rng.below(100) {
+            roll if roll < 50 || recent.is_empty() => {
+                if recent.len() == RECENT_DEPOSITS {
+                    recent.remove(0);
+                }
+                recent.push(tx_id);
+                (TransactionType::Deposit, tx_id, Some(amount))
+            }
+            roll if roll < 70 => (TransactionType::Withdrawal, tx_id, Some(amount)),
+            roll => {
+                let target = recent[self.rng.below(recent.len())];
+                let tx_type = match roll {
+                    70..=84 => TransactionType::Dispute,
+                    85..=92 => TransactionType::Resolve,
+                    _ => TransactionType::Chargeback,
+                };
+                (tx_type, target, None)
+            }
+        };
+
+        Some(Transaction {
+            tx_type,
+            client_id,
+            tx_id,
+            amount,
+            reason: None,
+            dispute_reason: None,
+        })
+    }
+}
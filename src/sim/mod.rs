@@ -0,0 +1,173 @@
+mod feed;
+
+pub use feed::SyntheticFeed;
+
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::engine::{EngineBuilder, EngineHasher, ManualClock, PaymentsEngine};
+use crate::report::Report;
+use crate::transactions::Transaction;
+
+/// How many transactions the simulated dispatcher keeps in flight unless
+/// told otherwise.
+const DEFAULT_WINDOW: usize = 8;
+
+/// Simulated time between two applied transactions.
+const TICK: Duration = Duration::from_millis(1);
+
+/// Replays transactions the way the task-per-transaction dispatcher may
+/// apply them, but with every choice driven by a seed.
+///
+/// The dispatcher spawns a task per transaction, so any of the transactions
+/// in flight can be applied next. The simulation keeps a window of that many
+/// transactions, in input order, and lets a seeded generator pick which one
+/// is applied next. A window of one is plain in-order processing. Maps are
+/// built with the Fx hasher so nothing else depends on per-process
+/// randomness, and the engine's clock starts at the Unix epoch and moves one
+/// millisecond per applied transaction, so a failing seed replays the exact
+/// same interleaving at the exact same times.
+#[derive(Clone, Debug)]
+pub struct Simulation {
+    seed: u64,
+    window: usize,
+}
+
+impl Simulation {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            window: DEFAULT_WINDOW,
+        }
+    }
+
+    /// Number of transactions that may be reordered among each other.
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// The order, as indices into the input, in which `len` transactions
+    /// are applied under this seed.
+    pub fn schedule(&self, len: usize) -> Vec<usize> {
+        let mut rng = SimRng::new(self.seed);
+        let mut pending = Vec::with_capacity(self.window);
+        let mut next = 0;
+        let mut order = Vec::with_capacity(len);
+
+        while order.len() < len {
+            while pending.len() < self.window && next < len {
+                pending.push(next);
+                next += 1;
+            }
+            let pick = rng.below(pending.len());
+            order.push(pending.remove(pick));
+        }
+        order
+    }
+
+    /// Applies `transactions` to a fresh engine in this seed's order.
+    pub fn run(&self, builder: &EngineBuilder, transactions: &[Transaction]) -> PaymentsEngine {
+        let clock = ManualClock::default();
+        let engine = builder
+            .clone()
+            .hasher(EngineHasher::Fx)
+            .clock(clock.clone())
+            .build();
+        for index in self.schedule(transactions.len()) {
+            engine.apply_transaction(transactions[index]);
+            clock.advance(TICK);
+        }
+        engine
+    }
+}
+
+/// Runs every seed in `seeds` and returns those whose final balances differ
+/// from applying the transactions in input order.
+pub fn divergent_seeds(
+    builder: &EngineBuilder,
+    transactions: &[Transaction],
+    seeds: Range<u64>,
+    window: usize,
+) -> Vec<u64> {
+    let expected = Report::from_engine(&Simulation::new(0).window(1).run(builder, transactions));
+
+    seeds
+        .filter(|seed| {
+            let engine = Simulation::new(*seed)
+                .window(window)
+                .run(builder, transactions);
+            Report::from_engine(&engine) != expected
+        })
+        .collect()
+}
+
+/// Small splitmix64 generator: fast, seedable and stable across platforms
+/// and releases, which is all a simulation needs.
+pub(crate) struct SimRng(u64);
+
+impl SimRng {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform-enough value in `0..n`; the modulo bias is negligible for
+    /// ranges far below 2^64.
+    pub(crate) fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    /// Value in `0..1`, built from the top 53 bits.
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_schedule() {
+        let first = Simulation::new(7).schedule(100);
+        let second = Simulation::new(7).schedule(100);
+
+        assert_eq!(first, second);
+        assert_ne!(first, Simulation::new(8).schedule(100));
+
+        let mut sorted = first.clone();
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_window_of_one_keeps_input_order() {
+        let schedule = Simulation::new(42).window(1).schedule(10);
+
+        assert_eq!(schedule, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_finds_seeds_that_reorder_a_dispute() {
+        // Disputing before the deposit is applied is ignored, so the outcome
+        // depends on the interleaving
+        let transactions = [
+            Transaction::new_deposit(1, 1, 5.0),
+            Transaction::new_dispute(1, 1),
+        ];
+
+        let divergent = divergent_seeds(&PaymentsEngine::builder(), &transactions, 0..64, 2);
+
+        assert!(!divergent.is_empty());
+        let seed = divergent[0];
+        assert_eq!(Simulation::new(seed).window(2).schedule(2), vec![1, 0]);
+    }
+}
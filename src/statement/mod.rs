@@ -0,0 +1,329 @@
+mod mt940;
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::{BufRead, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::currency::Precision;
+use crate::events::{open_journal, parse_journal};
+use crate::outcome::Balances;
+use crate::transactions::TransactionType;
+
+pub use mt940::{write_mt940, Mt940Options, StatementDate};
+
+/// The fields of a journal line a statement shows. Lifecycle lines and
+/// rejected or ignored transactions have no balances and are skipped.
+#[derive(Deserialize)]
+struct JournalLine {
+    seq: u64,
+    client: u16,
+    #[serde(rename = "type")]
+    tx_type: Option<TransactionType>,
+    tx: Option<u32>,
+    amount: Option<f64>,
+    outcome: Option<String>,
+    available: Option<f64>,
+    held: Option<f64>,
+    total: Option<f64>,
+    locked: Option<bool>,
+}
+
+/// One client's account over a range of journal sequence numbers: the
+/// balances before it, every transaction that took effect in it with the
+/// balances right after, and the balances at its end.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub client: u16,
+    pub period: RangeInclusive<u64>,
+    pub opening: Balances,
+    pub entries: Vec<StatementEntry>,
+    pub closing: Balances,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StatementEntry {
+    pub sequence: u64,
+    pub tx_type: TransactionType,
+    pub tx_id: u32,
+    /// The transaction's own amount; disputes, resolves and chargebacks of
+    /// a whole transaction have none.
+    pub amount: Option<f64>,
+    /// Whether the transaction was held for review rather than applied.
+    pub held: bool,
+    pub balances: Balances,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum StatementFormat {
+    #[default]
+    Text,
+    Html,
+}
+
+/// Builds the statements of `clients`, or of every client with activity
+/// up to the period's end if empty, from an `--events` journal. Journals
+/// carry sequence numbers rather than timestamps, so `period` is the range
+/// of sequence numbers to cover, both inclusive.
+pub fn statements<R: BufRead>(
+    journal: R,
+    clients: &[u16],
+    period: RangeInclusive<u64>,
+) -> Result<Vec<Statement>, Box<dyn Error>> {
+    let mut lines: Vec<JournalLine> = parse_journal(journal)?;
+    lines.retain(|line| {
+        line.seq <= *period.end() && (clients.is_empty() || clients.contains(&line.client))
+    });
+    lines.sort_by_key(|line| line.seq);
+
+    let mut statements = BTreeMap::new();
+    for line in lines {
+        let (Some(tx_type), Some(tx_id), Some("applied" | "held")) =
+            (line.tx_type, line.tx, line.outcome.as_deref())
+        else {
+            continue;
+        };
+        let balances = Balances {
+            client: line.client,
+            available: line.available.unwrap_or_default(),
+            held: line.held.unwrap_or_default(),
+            total: line.total.unwrap_or_default(),
+            locked: line.locked.unwrap_or_default(),
+        };
+        let statement = statements.entry(line.client).or_insert_with(|| {
+            let empty = Balances {
+                client: line.client,
+                available: 0.0,
+                held: 0.0,
+                total: 0.0,
+                locked: false,
+            };
+            Statement {
+                client: line.client,
+                period: period.clone(),
+                opening: empty,
+                entries: vec![],
+                closing: empty,
+            }
+        });
+        if line.seq < *period.start() {
+            statement.opening = balances;
+        } else {
+            statement.entries.push(StatementEntry {
+                sequence: line.seq,
+                tx_type,
+                tx_id,
+                amount: line.amount,
+                held: line.outcome.as_deref() == Some("held"),
+                balances,
+            });
+        }
+        statement.closing = balances;
+    }
+    Ok(statements.into_values().collect())
+}
+
+/// Builds statements as [`statements`] does from the journal at `path`,
+/// which may be gzip or zstd compressed, according to its extension.
+pub fn read_statements(
+    path: &Path,
+    clients: &[u16],
+    period: RangeInclusive<u64>,
+) -> Result<Vec<Statement>, Box<dyn Error>> {
+    let journal = open_journal(path)?;
+    statements(journal, clients, period)
+        .map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+/// Writes `statements` one after another, with amounts at `precision`.
+/// HTML statements form a single page.
+pub fn write_statements<W: Write>(
+    statements: &[Statement],
+    format: StatementFormat,
+    precision: Precision,
+    mut w: W,
+) -> std::io::Result<()> {
+    match format {
+        StatementFormat::Text => {
+            for (index, statement) in statements.iter().enumerate() {
+                if index > 0 {
+                    writeln!(w)?;
+                }
+                write_text(statement, precision, &mut w)?;
+            }
+        }
+        StatementFormat::Html => {
+            writeln!(w, "<!DOCTYPE html>")?;
+            writeln!(w, "<html>")?;
+            writeln!(
+                w,
+                "<head><meta charset=\"utf-8\"><title>Statements</title></head>"
+            )?;
+            writeln!(w, "<body>")?;
+            for statement in statements {
+                write_html(statement, precision, &mut w)?;
+            }
+            writeln!(w, "</body>")?;
+            writeln!(w, "</html>")?;
+        }
+    }
+    w.flush()
+}
+
+fn describe_period(period: &RangeInclusive<u64>) -> String {
+    match *period.end() {
+        u64::MAX => format!("events from {}", period.start()),
+        end => format!("events {} to {}", period.start(), end),
+    }
+}
+
+fn describe_entry(entry: &StatementEntry) -> String {
+    match entry.held {
+        true => format!("{} (held)", entry.tx_type.as_str()),
+        false => entry.tx_type.as_str().to_string(),
+    }
+}
+
+fn write_text<W: Write>(
+    statement: &Statement,
+    precision: Precision,
+    w: &mut W,
+) -> std::io::Result<()> {
+    let amount = |amount: f64| precision.format(amount).to_string();
+    let balance_line = |label: &str, balances: &Balances| {
+        format!(
+            "{:<16} available {}  held {}  total {}{}",
+            label,
+            amount(balances.available),
+            amount(balances.held),
+            amount(balances.total),
+            if balances.locked { "  (locked)" } else { "" }
+        )
+    };
+
+    writeln!(
+        w,
+        "Statement for client {}, {}",
+        statement.client,
+        describe_period(&statement.period)
+    )?;
+    writeln!(w, "{}", balance_line("Opening balance", &statement.opening))?;
+    writeln!(w)?;
+    writeln!(
+        w,
+        "{:>10}  {:<18} {:>10} {:>14} {:>14} {:>14} {:>14}",
+        "seq", "type", "tx", "amount", "available", "held", "total"
+    )?;
+    for entry in &statement.entries {
+        writeln!(
+            w,
+            "{:>10}  {:<18} {:>10} {:>14} {:>14} {:>14} {:>14}",
+            entry.sequence,
+            describe_entry(entry),
+            entry.tx_id,
+            entry.amount.map(amount).unwrap_or_default(),
+            amount(entry.balances.available),
+            amount(entry.balances.held),
+            amount(entry.balances.total),
+        )?;
+    }
+    if statement.entries.is_empty() {
+        writeln!(w, "No transactions in this period.")?;
+    }
+    writeln!(w)?;
+    writeln!(w, "{}", balance_line("Closing balance", &statement.closing))
+}
+
+fn write_html<W: Write>(
+    statement: &Statement,
+    precision: Precision,
+    w: &mut W,
+) -> std::io::Result<()> {
+    let amount = |amount: f64| precision.format(amount).to_string();
+    let balance_row = |label: &str, balances: &Balances| {
+        format!(
+            "<tr><th colspan=\"4\">{}</th><td>{}</td><td>{}</td><td>{}</td></tr>",
+            label,
+            amount(balances.available),
+            amount(balances.held),
+            amount(balances.total)
+        )
+    };
+
+    writeln!(w, "<section>")?;
+    writeln!(
+        w,
+        "<h2>Statement for client {}, {}</h2>",
+        statement.client,
+        describe_period(&statement.period)
+    )?;
+    writeln!(w, "<table>")?;
+    writeln!(
+        w,
+        "<tr><th>seq</th><th>type</th><th>tx</th><th>amount</th><th>available</th><th>held</th><th>total</th></tr>"
+    )?;
+    writeln!(w, "{}", balance_row("Opening balance", &statement.opening))?;
+    for entry in &statement.entries {
+        writeln!(
+            w,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            entry.sequence,
+            describe_entry(entry),
+            entry.tx_id,
+            entry.amount.map(amount).unwrap_or_default(),
+            amount(entry.balances.available),
+            amount(entry.balances.held),
+            amount(entry.balances.total),
+        )?;
+    }
+    writeln!(w, "{}", balance_row("Closing balance", &statement.closing))?;
+    writeln!(w, "</table>")?;
+    if statement.closing.locked {
+        writeln!(w, "<p>The account is locked.</p>")?;
+    }
+    writeln!(w, "</section>")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JOURNAL: &str = r#"{"seq":1,"client":1,"lifecycle":"created"}
+{"seq":2,"type":"deposit","client":1,"tx":1,"amount":5.0,"outcome":"applied","available":5.0,"held":0.0,"total":5.0,"locked":false}
+{"seq":3,"type":"deposit","client":2,"tx":2,"amount":1.0,"outcome":"applied","available":1.0,"held":0.0,"total":1.0,"locked":false}
+{"seq":4,"type":"withdrawal","client":1,"tx":3,"amount":9.0,"outcome":"rejected","reason":"insufficient_funds"}
+{"seq":5,"type":"withdrawal","client":1,"tx":4,"amount":1.5,"outcome":"applied","available":3.5,"held":0.0,"total":3.5,"locked":false}
+{"seq":6,"type":"dispute","client":1,"tx":1,"outcome":"applied","available":-1.5,"held":5.0,"total":3.5,"locked":false}
+{"seq":7,"type":"deposit","client":1,"tx":5,"amount":2.0,"outcome":"applied","available":0.5,"held":5.0,"total":5.5,"locked":false}
+"#;
+
+    #[test]
+    fn test_statement_covers_the_period_with_running_balances() {
+        let statements = statements(JOURNAL.as_bytes(), &[1], 3..=6).unwrap();
+        assert_eq!(statements.len(), 1);
+        let statement = &statements[0];
+
+        assert_eq!(statement.opening.total, 5.0);
+        let sequences: Vec<u64> = statement.entries.iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, [5, 6]);
+        assert_eq!(statement.entries[1].amount, None);
+        assert_eq!(statement.closing.available, -1.5);
+        assert_eq!(statement.closing.held, 5.0);
+
+        let mut text = vec![];
+        write_statements(
+            &statements,
+            StatementFormat::Text,
+            Precision::default(),
+            &mut text,
+        )
+        .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("Statement for client 1, events 3 to 6\n"));
+        assert!(text.contains("Opening balance  available 5.0000  held 0.0000  total 5.0000\n"));
+        assert!(text.contains("Closing balance  available -1.5000  held 5.0000  total 3.5000\n"));
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use super::Statement;
+use crate::currency::{Currency, Precision, RoundingMode};
+use crate::manifest::utc_date;
+use crate::transactions::TransactionType;
+
+/// A calendar date MT940 statements are dated with. Journals carry no
+/// timestamps, so one date covers every balance and entry of a statement.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StatementDate {
+    year: i64,
+    month: i64,
+    day: i64,
+}
+
+impl StatementDate {
+    /// Today's date in UTC.
+    pub fn today() -> Self {
+        let (year, month, day) = utc_date(SystemTime::now());
+        Self { year, month, day }
+    }
+}
+
+impl FromStr for StatementDate {
+    type Err = String;
+
+    /// Parses a `YYYY-MM-DD` date.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' is not a date, expected YYYY-MM-DD", s);
+        let mut parts = s.splitn(3, '-').map(|part| part.parse::<i64>());
+        let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days_in_month = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return Err(invalid()),
+        };
+        if !(1000..=9999).contains(&year) || !(1..=days_in_month).contains(&day) {
+            return Err(invalid());
+        }
+        Ok(Self { year, month, day })
+    }
+}
+
+impl fmt::Display for StatementDate {
+    /// Writes the date as MT940's `YYMMDD`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}{:02}{:02}", self.year % 100, self.month, self.day)
+    }
+}
+
+/// What an MT940 statement needs beyond the journal.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Mt940Options {
+    /// Currency of the balances and entries, which also sets their places.
+    pub currency: Currency,
+    pub rounding: RoundingMode,
+    pub date: StatementDate,
+    /// Number of the statements in the account holder's series, in `:28C:`.
+    pub statement_number: u32,
+}
+
+/// Writes `statements` as SWIFT MT940 messages, one per client, without
+/// the SWIFT envelope blocks and with CRLF line ends, as treasury systems
+/// take them from files.
+///
+/// The client id is the account in `:25:`. Opening and closing balances,
+/// `:60F:` and `:62F:`, are the client's total, and `:64:` its available
+/// funds. Every entry that changed the total gets a `:61:` line for the
+/// change, referenced by transaction id and, after `//`, by journal
+/// sequence number, and a `:86:` line naming the transaction; disputes,
+/// resolves and held withdrawals only move funds between available and
+/// held, so they have no line.
+pub fn write_mt940<W: Write>(
+    statements: &[Statement],
+    options: &Mt940Options,
+    mut w: W,
+) -> std::io::Result<()> {
+    let precision = Precision {
+        decimals: options.currency.decimals(),
+        rounding: options.rounding,
+    };
+    let balance = |tag: &str, amount: f64| {
+        let (mark, amount) = mark_and_amount(precision, amount);
+        format!(
+            ":{}:{}{}{}{}",
+            tag, mark, options.date, options.currency, amount
+        )
+    };
+
+    for statement in statements {
+        write!(w, ":20:CLIENT{}\r\n", statement.client)?;
+        write!(w, ":25:{}\r\n", statement.client)?;
+        write!(w, ":28C:{}\r\n", options.statement_number)?;
+        write!(w, "{}\r\n", balance("60F", statement.opening.total))?;
+        // Changes between rounded totals, so the lines add up to the
+        // closing balance as written
+        let mut total = precision.round(statement.opening.total);
+        for entry in &statement.entries {
+            let change = precision.round(entry.balances.total) - total;
+            total += change;
+            if precision.round(change) == 0.0 {
+                continue;
+            }
+            let (mark, amount) = mark_and_amount(precision, change);
+            let code = match entry.tx_type {
+                TransactionType::Deposit | TransactionType::Withdrawal => "TRF",
+                _ => "MSC",
+            };
+            write!(
+                w,
+                ":61:{}{}{}N{}{}//{}\r\n",
+                options.date, mark, amount, code, entry.tx_id, entry.sequence
+            )?;
+            write!(
+                w,
+                ":86:{} tx {}{}\r\n",
+                entry.tx_type.as_str(),
+                entry.tx_id,
+                if entry.held { " held for review" } else { "" }
+            )?;
+        }
+        write!(w, "{}\r\n", balance("62F", statement.closing.total))?;
+        write!(w, "{}\r\n", balance("64", statement.closing.available))?;
+        write!(w, "-\r\n")?;
+    }
+    w.flush()
+}
+
+/// The credit or debit mark of `amount` and its magnitude, written with a
+/// decimal comma as MT940 has it, e.g. `C` and `12,50`, or `D` and `100,`.
+fn mark_and_amount(precision: Precision, amount: f64) -> (char, String) {
+    let amount = precision.round(amount);
+    let mark = if amount < 0.0 { 'D' } else { 'C' };
+    let mut written = precision.format(amount.abs()).to_string().replace('.', ",");
+    if precision.decimals == 0 {
+        written.push(',');
+    }
+    (mark, written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::statements;
+
+    #[test]
+    fn test_statement_is_written_as_mt940() {
+        let journal = r#"{"seq":1,"client":4,"lifecycle":"created"}
+{"seq":2,"type":"deposit","client":4,"tx":1,"amount":5.0,"outcome":"applied","available":5.0,"held":0.0,"total":5.0,"locked":false}
+{"seq":3,"type":"withdrawal","client":4,"tx":2,"amount":1.255,"outcome":"applied","available":3.745,"held":0.0,"total":3.745,"locked":false}
+{"seq":4,"type":"dispute","client":4,"tx":1,"outcome":"applied","available":-1.255,"held":5.0,"total":3.745,"locked":false}
+{"seq":5,"type":"chargeback","client":4,"tx":1,"outcome":"applied","available":-1.255,"held":0.0,"total":-1.255,"locked":true}
+"#;
+        let statements = statements(journal.as_bytes(), &[], 3..=5).unwrap();
+        let options = Mt940Options {
+            currency: "EUR".parse().unwrap(),
+            rounding: RoundingMode::HalfUp,
+            date: "2024-02-29".parse().unwrap(),
+            statement_number: 12,
+        };
+
+        let mut mt940 = vec![];
+        write_mt940(&statements, &options, &mut mt940).unwrap();
+        assert_eq!(
+            String::from_utf8(mt940).unwrap(),
+            ":20:CLIENT4\r\n\
+             :25:4\r\n\
+             :28C:12\r\n\
+             :60F:C240229EUR5,00\r\n\
+             :61:240229D1,25NTRF2//3\r\n\
+             :86:withdrawal tx 2\r\n\
+             :61:240229D5,01NMSC1//5\r\n\
+             :86:chargeback tx 1\r\n\
+             :62F:D240229EUR1,26\r\n\
+             :64:D240229EUR1,26\r\n\
+             -\r\n"
+        );
+        assert!("2023-02-29".parse::<StatementDate>().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Hash functions per id. Seven is optimal for the 1% false positive rate
+/// the filter is sized for.
+const HASHES: u64 = 7;
+
+/// Bits per expected id for a 1% false positive rate: -ln(0.01) / ln(2)^2.
+const BITS_PER_ID: f64 = 9.6;
+
+/// Lock-free Bloom filter over transaction ids.
+///
+/// Answers "definitely never seen" or "maybe seen"; only the latter needs a
+/// lookup in the exact store. It keeps working past its expected capacity,
+/// just with a rising false positive rate.
+pub struct TxIdFilter {
+    words: Vec<AtomicU64>,
+    bits: u64,
+}
+
+impl TxIdFilter {
+    pub fn with_expected_ids(expected: usize) -> Self {
+        let bits = ((expected.max(1) as f64 * BITS_PER_ID).ceil() as u64).max(64);
+        let words = bits.div_ceil(64);
+
+        Self {
+            words: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            bits: words * 64,
+        }
+    }
+
+    pub fn insert(&self, id: u32) {
+        for bit in self.bit_positions(id) {
+            // Release pairs with the Acquire in may_contain so that a reader
+            // seeing the bits also sees what was written before them
+            self.words[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Release);
+        }
+    }
+
+    pub fn may_contain(&self, id: u32) -> bool {
+        self.bit_positions(id).all(|bit| {
+            self.words[(bit / 64) as usize].load(Ordering::Acquire) & (1 << (bit % 64)) != 0
+        })
+    }
+
+    pub fn size_in_bytes(&self) -> usize {
+        self.words.len() * 8
+    }
+
+    /// Kirsch-Mitzenmacher double hashing: position i is h1 + i * h2.
+    fn bit_positions(&self, id: u32) -> impl Iterator<Item = u64> {
+        let h1 = splitmix64(id as u64);
+        let h2 = splitmix64(h1) | 1;
+        let bits = self.bits;
+
+        (0..HASHES).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inserted_ids_are_always_found() {
+        let filter = TxIdFilter::with_expected_ids(1000);
+        for id in 0..1000 {
+            filter.insert(id);
+        }
+
+        assert!((0..1000).all(|id| filter.may_contain(id)));
+    }
+
+    #[test]
+    fn test_false_positive_rate_is_near_target() {
+        let filter = TxIdFilter::with_expected_ids(10_000);
+        for id in 0..10_000 {
+            filter.insert(id);
+        }
+
+        let false_positives = (10_000..110_000)
+            .filter(|id| filter.may_contain(*id))
+            .count();
+        assert!(
+            false_positives < 2_000,
+            "{} false positives",
+            false_positives
+        );
+    }
+}
@@ -0,0 +1,365 @@
+mod bloom;
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dashmap::mapref::one::{Ref, RefMut};
+use dashmap::DashMap;
+
+use crate::engine::EngineHasher;
+use crate::transactions::{DisputeStep, StoredTransaction, TransactionStatus};
+use bloom::TxIdFilter;
+
+/// Transactions retained for disputes, keyed by transaction id.
+///
+/// Records are stored inline in the map's tables rather than boxed, so there
+/// is no per-transaction allocation to pool in an arena.
+///
+/// Optionally fronted by a Bloom filter: most incoming ids have never been
+/// seen before, and the filter answers that without touching the map's
+/// shard locks. Anything the filter might have seen is checked against the
+/// map, so lookups stay exact.
+///
+/// Can also index transaction ids by client, in the order they were
+/// inserted, to answer history queries without scanning the map.
+/// Transactions that are disputed or charged back are always indexed by
+/// status; they are few, and finding them otherwise means a full scan.
+/// Every transaction ever disputed also keeps its dispute steps in order.
+pub struct TransactionStore {
+    map: DashMap<u32, StoredTransaction, EngineHasher>,
+    filter: Option<TxIdFilter>,
+    history: Option<DashMap<u16, Vec<u32>, EngineHasher>>,
+    flagged: DashMap<u32, TransactionStatus, EngineHasher>,
+    disputes: DashMap<u32, Vec<DisputeStep>, EngineHasher>,
+    dispute_steps: AtomicUsize,
+}
+
+impl TransactionStore {
+    pub fn new(map: DashMap<u32, StoredTransaction, EngineHasher>) -> Self {
+        let flagged = DashMap::with_hasher(map.hasher().clone());
+        let disputes = DashMap::with_hasher(map.hasher().clone());
+        Self {
+            map,
+            filter: None,
+            history: None,
+            flagged,
+            disputes,
+            dispute_steps: AtomicUsize::new(0),
+        }
+    }
+
+    /// Keeps each client's transaction ids in insertion order.
+    pub fn with_history(mut self, hasher: EngineHasher) -> Self {
+        self.history = Some(DashMap::with_hasher(hasher));
+        self
+    }
+
+    /// An empty store keeping the same per-client history, e.g. for a fork
+    /// copying transactions into it as it first needs them.
+    pub fn empty_like(&self) -> Self {
+        let store = Self::new(DashMap::with_hasher(self.map.hasher().clone()));
+        match &self.history {
+            Some(history) => store.with_history(history.hasher().clone()),
+            None => store,
+        }
+    }
+
+    /// Fronts the store with a Bloom filter sized for `expected` ids.
+    pub fn with_filter(mut self, expected: usize) -> Self {
+        self.filter = Some(TxIdFilter::with_expected_ids(expected));
+        self
+    }
+
+    fn maybe_present(&self, tx_id: u32) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.may_contain(tx_id))
+    }
+
+    pub fn contains_key(&self, tx_id: &u32) -> bool {
+        self.maybe_present(*tx_id) && self.map.contains_key(tx_id)
+    }
+
+    pub fn get(&self, tx_id: &u32) -> Option<Ref<'_, u32, StoredTransaction, EngineHasher>> {
+        if self.maybe_present(*tx_id) {
+            self.map.get(tx_id)
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&self, tx_id: &u32) -> Option<RefMut<'_, u32, StoredTransaction, EngineHasher>> {
+        if self.maybe_present(*tx_id) {
+            self.map.get_mut(tx_id)
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&self, tx_id: u32, tx: StoredTransaction) {
+        if let Some(filter) = &self.filter {
+            filter.insert(tx_id);
+        }
+        if let Some(history) = &self.history {
+            history.entry(tx.client_id()).or_default().push(tx_id);
+        }
+        self.index_status(tx_id, tx.status());
+        self.map.insert(tx_id, tx);
+    }
+
+    /// Keeps the status index in step with a status change made through
+    /// [`get_mut`](Self::get_mut).
+    pub fn index_status(&self, tx_id: u32, status: TransactionStatus) {
+        match status {
+            TransactionStatus::Good => {
+                self.flagged.remove(&tx_id);
+            }
+            _ => {
+                self.flagged.insert(tx_id, status);
+            }
+        }
+    }
+
+    /// Ids of the transactions currently disputed or charged back, in no
+    /// particular order.
+    pub fn ids_with_status(&self, status: TransactionStatus) -> Vec<u32> {
+        self.flagged
+            .iter()
+            .filter(|entry| *entry.value() == status)
+            .map(|entry| *entry.key())
+            .collect()
+    }
+
+    /// Appends a dispute, resolve or chargeback applied to `tx_id` to its
+    /// dispute history.
+    pub fn record_dispute_step(&self, tx_id: u32, step: DisputeStep) {
+        self.disputes.entry(tx_id).or_default().push(step);
+        self.dispute_steps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The dispute steps applied to `tx_id`, oldest first; empty if it was
+    /// never disputed.
+    pub fn dispute_history(&self, tx_id: u32) -> Vec<DisputeStep> {
+        self.disputes
+            .get(&tx_id)
+            .map(|steps| steps.clone())
+            .unwrap_or_default()
+    }
+
+    /// Every transaction ever disputed with its dispute steps, by
+    /// transaction id.
+    pub fn dispute_histories(&self) -> Vec<(u32, Vec<DisputeStep>)> {
+        let mut histories: Vec<_> = self
+            .disputes
+            .iter()
+            .map(|entry| (*entry.key(), entry.value().clone()))
+            .collect();
+        histories.sort_unstable_by_key(|(tx_id, _)| *tx_id);
+        histories
+    }
+
+    /// Ids of the client's transactions in insertion order, or `None` when
+    /// the store doesn't keep history.
+    pub fn client_history(&self, client_id: u16) -> Option<Vec<u32>> {
+        let history = self.history.as_ref()?;
+        Some(
+            history
+                .get(&client_id)
+                .map(|ids| ids.clone())
+                .unwrap_or_default(),
+        )
+    }
+
+    /// Moves in every transaction of `other` that isn't already here. With
+    /// history kept on both sides, each client's transactions keep their
+    /// relative order.
+    pub fn absorb(&self, other: &TransactionStore) {
+        for tx_id in other.ids() {
+            if !self.contains_key(&tx_id) {
+                if let Some(tx) = other.map.get(&tx_id) {
+                    self.insert(tx_id, *tx);
+                }
+                if let Some(steps) = other.disputes.get(&tx_id) {
+                    self.dispute_steps.fetch_add(steps.len(), Ordering::Relaxed);
+                    self.disputes.insert(tx_id, steps.clone());
+                }
+            }
+        }
+    }
+
+    /// Drops every transaction of `client_id` with its dispute steps and
+    /// history, returning how many there were. The Bloom filter can't
+    /// forget ids, so it keeps answering "maybe" for them.
+    pub fn remove_client(&self, client_id: u16) -> u64 {
+        let ids: Vec<u32> = self
+            .map
+            .iter()
+            .filter(|tx| tx.client_id() == client_id)
+            .map(|tx| *tx.key())
+            .collect();
+        for tx_id in &ids {
+            self.map.remove(tx_id);
+            self.flagged.remove(tx_id);
+            if let Some((_, steps)) = self.disputes.remove(tx_id) {
+                self.dispute_steps.fetch_sub(steps.len(), Ordering::Relaxed);
+            }
+        }
+        if let Some(history) = &self.history {
+            history.remove(&client_id);
+        }
+        ids.len() as u64
+    }
+
+    /// Moves every transaction of `client_id` with its dispute steps into
+    /// `into`, keeping their order there when both keep history, and
+    /// returns how many there were.
+    pub fn move_client(&self, client_id: u16, into: &TransactionStore) -> u64 {
+        let ids = match self.client_history(client_id) {
+            Some(ids) => ids,
+            None => self
+                .map
+                .iter()
+                .filter(|tx| tx.client_id() == client_id)
+                .map(|tx| *tx.key())
+                .collect(),
+        };
+        for tx_id in ids {
+            if let Some(tx) = self.map.get(&tx_id).map(|tx| *tx) {
+                into.insert(tx_id, tx);
+            }
+            if let Some(steps) = self.disputes.get(&tx_id) {
+                into.dispute_steps.fetch_add(steps.len(), Ordering::Relaxed);
+                into.disputes.insert(tx_id, steps.clone());
+            }
+        }
+        self.remove_client(client_id)
+    }
+
+    /// Re-points every transaction of `from` to `into`, returning how many
+    /// there were. With history kept, `from`'s transactions follow
+    /// `into`'s.
+    pub fn reassign_client(&self, from: u16, into: u16) -> u64 {
+        let mut moved = 0;
+        for mut tx in self.map.iter_mut() {
+            if tx.client_id() == from {
+                tx.set_client_id(into);
+                moved += 1;
+            }
+        }
+        if let Some(history) = &self.history {
+            if let Some((_, ids)) = history.remove(&from) {
+                history.entry(into).or_default().extend(ids);
+            }
+        }
+        moved
+    }
+
+    /// Every transaction id. When history is kept, each client's ids are in
+    /// insertion order, so inserting them in this order elsewhere rebuilds
+    /// the same history.
+    pub fn ids(&self) -> Vec<u32> {
+        match &self.history {
+            Some(history) => history.iter().flat_map(|ids| ids.clone()).collect(),
+            None => self.map.iter().map(|tx| *tx.key()).collect(),
+        }
+    }
+
+    pub fn iter(&self) -> dashmap::iter::Iter<'_, u32, StoredTransaction, EngineHasher> {
+        self.map.iter()
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.map.capacity()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Estimated bytes held by the entries, the filter and the indexes, scaling entries by the hash table's maximum load factor of 7/8.
+    pub fn approximate_memory_usage(&self) -> usize {
+        let entries =
+            self.len() * (mem::size_of::<u32>() + mem::size_of::<StoredTransaction>()) * 8 / 7;
+        let flagged =
+            self.flagged.len() * (mem::size_of::<u32>() + mem::size_of::<TransactionStatus>()) * 8
+                / 7;
+        let history = self.history.as_ref().map_or(0, |history| {
+            history.len() * (mem::size_of::<u16>() + mem::size_of::<Vec<u32>>()) * 8 / 7
+                + self.len() * mem::size_of::<u32>()
+        });
+        let disputes =
+            self.disputes.len() * (mem::size_of::<u32>() + mem::size_of::<Vec<DisputeStep>>()) * 8
+                / 7
+                + self.dispute_steps.load(Ordering::Relaxed) * mem::size_of::<DisputeStep>();
+        entries
+            + flagged
+            + history
+            + disputes
+            + self.filter.as_ref().map_or(0, TxIdFilter::size_in_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::Transaction;
+
+    #[test]
+    fn test_filtered_store_finds_inserted_transactions() {
+        let store =
+            TransactionStore::new(DashMap::with_hasher(EngineHasher::default())).with_filter(100);
+        let tx = Transaction::new_deposit(1, 42, 1.0);
+
+        assert!(!store.contains_key(&42));
+        store.insert(42, StoredTransaction::new(&tx, 1.0));
+
+        assert!(store.contains_key(&42));
+        assert_eq!(store.get(&42).unwrap().amount(), 1.0);
+        assert!(store.get_mut(&43).is_none());
+    }
+
+    #[test]
+    fn test_history_keeps_each_clients_order_when_absorbed() {
+        let store = || {
+            TransactionStore::new(DashMap::with_hasher(EngineHasher::default()))
+                .with_history(EngineHasher::default())
+        };
+        let insert = |store: &TransactionStore, tx: Transaction| {
+            store.insert(tx.tx_id, StoredTransaction::new(&tx, 1.0));
+        };
+
+        let shard = store();
+        for tx_id in [9, 3, 7] {
+            insert(&shard, Transaction::new_deposit(1, tx_id, 1.0));
+        }
+        insert(&shard, Transaction::new_deposit(2, 5, 1.0));
+
+        let merged = store();
+        merged.absorb(&shard);
+        assert_eq!(merged.client_history(1), Some(vec![9, 3, 7]));
+        assert_eq!(merged.client_history(2), Some(vec![5]));
+        assert_eq!(merged.client_history(3), Some(vec![]));
+
+        let plain = TransactionStore::new(DashMap::with_hasher(EngineHasher::default()));
+        assert_eq!(plain.client_history(1), None);
+    }
+
+    #[test]
+    fn test_status_index_follows_status_changes() {
+        let store = TransactionStore::new(DashMap::with_hasher(EngineHasher::default()));
+        let tx = Transaction::new_deposit(1, 4, 1.0);
+        store.insert(4, StoredTransaction::new(&tx, 1.0));
+        assert!(store
+            .ids_with_status(TransactionStatus::Disputed)
+            .is_empty());
+
+        store.index_status(4, TransactionStatus::Disputed);
+        assert_eq!(store.ids_with_status(TransactionStatus::Disputed), [4]);
+
+        store.index_status(4, TransactionStatus::Good);
+        assert!(store
+            .ids_with_status(TransactionStatus::Disputed)
+            .is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+//! Fixtures for testing against the engine, enabled by the `test-support`
+//! feature.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::engine::PaymentsEngine;
+use crate::sim::SyntheticFeed;
+use crate::transactions::Transaction;
+
+/// Builds a sequence of transactions, handing out transaction ids in order
+/// so scenarios read as a list of steps.
+///
+/// ```
+/// use payments_engine::test_support::Scenario;
+///
+/// let mut scenario = Scenario::new();
+/// let tx = scenario.deposit(1, 10.0);
+/// scenario.dispute(1, tx).chargeback(1, tx);
+/// let engine = scenario.run();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Scenario {
+    transactions: Vec<Transaction>,
+    next_tx_id: u32,
+}
+
+impl Scenario {
+    pub fn new() -> Self {
+        Self::starting_at(1)
+    }
+
+    /// Hands out transaction ids from `tx_id` on, e.g. to combine scenarios
+    /// without their ids colliding.
+    pub fn starting_at(tx_id: u32) -> Self {
+        Self {
+            transactions: vec![],
+            next_tx_id: tx_id,
+        }
+    }
+
+    /// Adds a deposit and returns its transaction id.
+    pub fn deposit(&mut self, client_id: u16, amount: f64) -> u32 {
+        let tx_id = self.next_id();
+        self.push(Transaction::new_deposit(client_id, tx_id, amount))
+    }
+
+    /// Adds a withdrawal and returns its transaction id.
+    pub fn withdrawal(&mut self, client_id: u16, amount: f64) -> u32 {
+        let tx_id = self.next_id();
+        self.push(Transaction::new_withdrawal(client_id, tx_id, amount))
+    }
+
+    pub fn dispute(&mut self, client_id: u16, tx_id: u32) -> &mut Self {
+        self.push(Transaction::new_dispute(client_id, tx_id));
+        self
+    }
+
+    pub fn resolve(&mut self, client_id: u16, tx_id: u32) -> &mut Self {
+        self.push(Transaction::new_resolve(client_id, tx_id));
+        self
+    }
+
+    pub fn chargeback(&mut self, client_id: u16, tx_id: u32) -> &mut Self {
+        self.push(Transaction::new_chargeback(client_id, tx_id));
+        self
+    }
+
+    /// Deposit followed by a dispute of it, leaving `amount` held.
+    pub fn disputed_deposit(&mut self, client_id: u16, amount: f64) -> u32 {
+        let tx_id = self.deposit(client_id, amount);
+        self.dispute(client_id, tx_id);
+        tx_id
+    }
+
+    /// Deposit, dispute and resolve, leaving the funds available again.
+    pub fn resolved_deposit(&mut self, client_id: u16, amount: f64) -> u32 {
+        let tx_id = self.disputed_deposit(client_id, amount);
+        self.resolve(client_id, tx_id);
+        tx_id
+    }
+
+    /// Deposit, dispute and chargeback, which removes the funds and locks
+    /// the account.
+    pub fn charged_back_deposit(&mut self, client_id: u16, amount: f64) -> u32 {
+        let tx_id = self.disputed_deposit(client_id, amount);
+        self.chargeback(client_id, tx_id);
+        tx_id
+    }
+
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    pub fn into_transactions(self) -> Vec<Transaction> {
+        self.transactions
+    }
+
+    /// Applies the scenario in order to a fresh engine.
+    pub fn run(&self) -> PaymentsEngine {
+        let engine = PaymentsEngine::new();
+        self.apply(&engine);
+        engine
+    }
+
+    /// Applies the scenario in order to `engine`.
+    pub fn apply(&self, engine: &PaymentsEngine) {
+        for tx in &self.transactions {
+            engine.apply_transaction(*tx);
+        }
+    }
+
+    fn next_id(&mut self) -> u32 {
+        let tx_id = self.next_tx_id;
+        self.next_tx_id += 1;
+        tx_id
+    }
+
+    fn push(&mut self, tx: Transaction) -> u32 {
+        self.transactions.push(tx);
+        tx.tx_id
+    }
+}
+
+/// A reproducible mix of `len` transactions over clients `1..=clients`,
+/// taken from a [`SyntheticFeed`].
+pub fn random_workload(seed: u64, clients: u16, len: usize) -> Vec<Transaction> {
+    SyntheticFeed::new(seed, clients).take(len).collect()
+}
+
+/// A fresh directory under the system temp dir, removed with everything in
+/// it when dropped.
+///
+/// Each one gets its own name, so tests running in parallel, or in several
+/// processes at once, never share files.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "payments-engine-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The path of `name` inside the directory.
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.path.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::Report;
+    use crate::transactions::TransactionType;
+
+    #[test]
+    fn test_scenario_chains() {
+        let mut scenario = Scenario::new();
+        scenario.deposit(1, 10.0);
+        scenario.charged_back_deposit(1, 4.0);
+        scenario.disputed_deposit(2, 3.0);
+        scenario.resolved_deposit(3, 1.0);
+
+        let rows: Vec<String> = Report::from_engine(&scenario.run())
+            .rows()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(
+            rows,
+            [
+                "1,10.0000,0.0000,10.0000,true",
+                "2,0.0000,3.0000,3.0000,false",
+                "3,1.0000,0.0000,1.0000,false",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_random_workload_is_reproducible() {
+        let workload = random_workload(3, 10, 1_000);
+
+        assert_eq!(workload.len(), 1_000);
+        let again = random_workload(3, 10, 1_000);
+        assert!(workload
+            .iter()
+            .zip(&again)
+            .all(|(a, b)| (a.tx_type, a.client_id, a.tx_id) == (b.tx_type, b.client_id, b.tx_id)));
+        assert!(workload
+            .iter()
+            .any(|tx| tx.tx_type == TransactionType::Chargeback));
+    }
+
+    #[test]
+    fn test_temp_dirs_are_unique_and_removed() {
+        let (a, b) = (TempDir::new("unique"), TempDir::new("unique"));
+        assert_ne!(a.path(), b.path());
+        fs::write(a.join("file"), "").unwrap();
+
+        let path = a.path().to_owned();
+        drop(a);
+        assert!(!path.exists());
+        assert!(b.path().is_dir());
+    }
+}
@@ -0,0 +1,56 @@
+use proptest::prelude::*;
+
+use crate::transactions::{Transaction, TransactionType};
+
+/// Client ids are drawn from a small range so generated transactions keep
+/// running into the same accounts.
+const CLIENTS: u16 = 8;
+/// Likewise for transaction ids, so disputes, resolves and chargebacks
+/// often refer to a transaction that exists, and ids get reused.
+const TX_IDS: u32 = 64;
+
+impl Arbitrary for TransactionType {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            4 => Just(TransactionType::Deposit),
+            2 => Just(TransactionType::Withdrawal),
+            2 => Just(TransactionType::Dispute),
+            1 => Just(TransactionType::Resolve),
+            1 => Just(TransactionType::Chargeback),
+        ]
+        .boxed()
+    }
+}
+
+/// Deposits and withdrawals get an amount of at most four decimals, as in
+/// real inputs; the other types get none.
+impl Arbitrary for Transaction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            any::<TransactionType>(),
+            1..=CLIENTS,
+            1..=TX_IDS,
+            1..=10_000_000u32,
+        )
+            .prop_map(|(tx_type, client_id, tx_id, units)| Transaction {
+                tx_type,
+                client_id,
+                tx_id,
+                amount: match tx_type {
+                    TransactionType::Deposit | TransactionType::Withdrawal => {
+                        Some(units as f64 / 10_000.0)
+                    }
+                    _ => None,
+                },
+                reason: None,
+                dispute_reason: None,
+            })
+            .boxed()
+    }
+}
@@ -0,0 +1,67 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::RwLock;
+
+use super::TransactionType;
+
+/// Names of the custom types registered so far, indexed by their id.
+/// Leaked, as types live as long as the process and there are at most 256.
+static REGISTERED: RwLock<Vec<&'static str>> = RwLock::new(Vec::new());
+
+/// A transaction type beyond the built-in ones, e.g. `fee_reversal`,
+/// registered under its name once per process with
+/// [`register`](Self::register). Registered names are read from inputs like
+/// built-in types; what a transaction of the type does is up to the
+/// [`CustomHandler`](crate::plugin::CustomHandler) an engine is given for it.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct CustomType(u8);
+
+impl CustomType {
+    /// Registers the type `name`, or returns it if it already is. Names
+    /// are lowercase letters, digits and underscores, starting with a
+    /// letter, and can't be a built-in type's. At most 256 types can be
+    /// registered.
+    pub fn register(name: &str) -> Result<Self, String> {
+        let valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+            && name
+                .bytes()
+                .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_');
+        if !valid {
+            return Err(format!(
+                "'{}' isn't a valid type name: lowercase letters, digits and underscores",
+                name
+            ));
+        }
+        if TransactionType::builtin(name.as_bytes()).is_some() {
+            return Err(format!("'{}' is a built-in transaction type", name));
+        }
+        let mut registered = REGISTERED.write().unwrap_or_else(|err| err.into_inner());
+        if let Some(id) = registered.iter().position(|known| *known == name) {
+            return Ok(Self(id as u8));
+        }
+        let id = u8::try_from(registered.len())
+            .map_err(|_| "no more than 256 custom transaction types".to_string())?;
+        registered.push(Box::leak(name.into()));
+        Ok(Self(id))
+    }
+
+    /// The registered type named `name`, if any.
+    pub fn lookup(name: &[u8]) -> Option<Self> {
+        REGISTERED
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .iter()
+            .position(|known| known.as_bytes() == name)
+            .map(|id| Self(id as u8))
+    }
+
+    pub fn name(self) -> &'static str {
+        REGISTERED.read().unwrap_or_else(|err| err.into_inner())[self.0 as usize]
+    }
+}
+
+impl fmt::Display for CustomType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
@@ -1,18 +1,198 @@
+#[cfg(any(test, feature = "proptest"))]
+mod arbitrary;
+mod custom;
+
+pub use custom::CustomType;
+
 use std::cmp::Eq;
+use std::error::Error;
+use std::fmt;
+use std::str::{self, FromStr};
 
-use serde::Deserialize;
+use csv::ByteRecord;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
-#[serde(rename_all = "lowercase")]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Closes an account without funds held. Carries no amount.
+    Close,
+    /// Manual adjustment adding to a client's available funds. Requires a
+    /// reason code.
+    Credit,
+    /// Manual adjustment taking from a client's available funds, even past
+    /// zero. Requires a reason code.
+    Debit,
+    /// Settles a deposit or withdrawal held for review: a deposit is
+    /// credited, a withdrawal's funds leave the account. Carries no amount.
+    Approve,
+    /// Drops a deposit held for review, or returns a withdrawal's funds to
+    /// the client's available balance. Carries no amount.
+    Deny,
+    /// A type registered by the application embedding the engine, applied
+    /// by the handler it gave the engine for it. Not retained, so it can't
+    /// be disputed.
+    Custom(CustomType),
+}
+
+impl TransactionType {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::builtin(bytes).or_else(|| CustomType::lookup(bytes).map(TransactionType::Custom))
+    }
+
+    fn builtin(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"deposit" => Some(TransactionType::Deposit),
+            b"withdrawal" => Some(TransactionType::Withdrawal),
+            b"dispute" => Some(TransactionType::Dispute),
+            b"resolve" => Some(TransactionType::Resolve),
+            b"chargeback" => Some(TransactionType::Chargeback),
+            b"close" => Some(TransactionType::Close),
+            b"credit" => Some(TransactionType::Credit),
+            b"debit" => Some(TransactionType::Debit),
+            b"approve" => Some(TransactionType::Approve),
+            b"deny" => Some(TransactionType::Deny),
+            _ => None,
+        }
+    }
+
+    /// Name of the type as written in inputs, e.g. `chargeback`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Close => "close",
+            TransactionType::Credit => "credit",
+            TransactionType::Debit => "debit",
+            TransactionType::Approve => "approve",
+            TransactionType::Deny => "deny",
+            TransactionType::Custom(custom) => custom.name(),
+        }
+    }
+
+    /// Whether this is a manual credit or debit rather than client
+    /// activity.
+    pub fn is_adjustment(self) -> bool {
+        matches!(self, TransactionType::Credit | TransactionType::Debit)
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(s.as_bytes()).ok_or_else(|| format!("unknown transaction type '{}'", s))
+    }
+}
+
+impl Serialize for TransactionType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Why a client disputes a transaction, from the `reason` column of a
+/// dispute row. Kept with the disputed transaction until it is disputed
+/// again.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisputeReason {
+    /// The client didn't make or authorize the transaction.
+    Fraud,
+    /// Goods or services paid for never arrived.
+    NotReceived,
+    /// The client was charged more than once.
+    Duplicate,
+    /// What arrived wasn't what was paid for.
+    NotAsDescribed,
+    /// The client doesn't recognize the transaction.
+    Unrecognized,
+    Other,
 }
 
-#[derive(Copy, Clone, Debug, Deserialize)]
+impl DisputeReason {
+    const ALL: [DisputeReason; 6] = [
+        DisputeReason::Fraud,
+        DisputeReason::NotReceived,
+        DisputeReason::Duplicate,
+        DisputeReason::NotAsDescribed,
+        DisputeReason::Unrecognized,
+        DisputeReason::Other,
+    ];
+
+    /// The code as written in inputs, e.g. `not_received`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DisputeReason::Fraud => "fraud",
+            DisputeReason::NotReceived => "not_received",
+            DisputeReason::Duplicate => "duplicate",
+            DisputeReason::NotAsDescribed => "not_as_described",
+            DisputeReason::Unrecognized => "unrecognized",
+            DisputeReason::Other => "other",
+        }
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"fraud" => Some(DisputeReason::Fraud),
+            b"not_received" => Some(DisputeReason::NotReceived),
+            b"duplicate" => Some(DisputeReason::Duplicate),
+            b"not_as_described" => Some(DisputeReason::NotAsDescribed),
+            b"unrecognized" => Some(DisputeReason::Unrecognized),
+            b"other" => Some(DisputeReason::Other),
+            _ => None,
+        }
+    }
+}
+
+/// Why a manual adjustment was made, from the `reason` column.
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdjustmentReason {
+    /// Fixes an earlier processing or booking error.
+    Correction,
+    /// Compensates the client as a courtesy.
+    Goodwill,
+    /// Charges or refunds a fee.
+    Fee,
+    /// Recovers or restores funds after a fraud investigation.
+    Fraud,
+    /// Writes off a balance that won't be collected.
+    WriteOff,
+    /// Pays interest on the balance.
+    Interest,
+}
+
+impl AdjustmentReason {
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            b"correction" => Some(AdjustmentReason::Correction),
+            b"goodwill" => Some(AdjustmentReason::Goodwill),
+            b"fee" => Some(AdjustmentReason::Fee),
+            b"fraud" => Some(AdjustmentReason::Fraud),
+            b"write_off" => Some(AdjustmentReason::WriteOff),
+            b"interest" => Some(AdjustmentReason::Interest),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub tx_type: TransactionType,
@@ -22,69 +202,715 @@ pub struct Transaction {
     pub tx_id: u32,
     #[serde(rename = "amount")]
     pub amount: Option<f64>,
+    /// Reason code of a credit or debit; ignored on other types.
+    #[serde(default)]
+    pub reason: Option<AdjustmentReason>,
+    /// Reason code of a dispute; ignored on other types.
+    #[serde(default)]
+    pub dispute_reason: Option<DisputeReason>,
 }
 
 impl Transaction {
-    #[cfg(test)]
+    /// Parses one CSV record with the fields in the standard
+    /// `type,client,tx,amount` order, optionally followed by `reason`.
+    ///
+    /// Never panics: any input, however malformed, yields a transaction or
+    /// a [`ParseError`], which makes this a suitable fuzzing entry point.
+    /// Fields are trimmed of surrounding whitespace, and amounts must be
+    /// finite and not negative.
+    pub fn parse_csv_record(record: &ByteRecord) -> Result<Self, ParseError> {
+        Self::from_byte_record(record, &CsvColumns::STANDARD)
+    }
+
+    /// Parses a record read with [`csv::Reader::read_byte_record`], so rows
+    /// can be read into one reused buffer instead of allocating a `String`
+    /// per field the way serde deserialization does.
+    pub(crate) fn from_byte_record(
+        record: &ByteRecord,
+        columns: &CsvColumns,
+    ) -> Result<Self, ParseError> {
+        if let Some(index) = columns.checksum {
+            verify_checksum(record, index)?;
+        }
+        let tx_type = field(record, columns.tx_type, "type")?;
+        let tx_type = TransactionType::from_bytes(tx_type)
+            .ok_or_else(|| ParseError::UnknownType(String::from_utf8_lossy(tx_type).into()))?;
+
+        let amount = match field(record, columns.amount.unwrap_or(usize::MAX), "amount") {
+            Err(_) => None,
+            Ok(amount) => Some(parse_amount(amount)?),
+        };
+        // The reason column holds an adjustment's or a dispute's reason
+        // code, and is ignored for other types
+        let (mut reason, mut dispute_reason) = (None, None);
+        if let Ok(code) = field(record, columns.reason.unwrap_or(usize::MAX), "reason") {
+            let unknown = || ParseError::UnknownReason(String::from_utf8_lossy(code).into());
+            match tx_type {
+                TransactionType::Credit | TransactionType::Debit => {
+                    reason = Some(AdjustmentReason::from_bytes(code).ok_or_else(unknown)?);
+                }
+                TransactionType::Dispute => {
+                    dispute_reason = Some(DisputeReason::from_bytes(code).ok_or_else(unknown)?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            tx_type,
+            client_id: parse_number(field(record, columns.client_id, "client")?, "client")?,
+            tx_id: parse_number(field(record, columns.tx_id, "tx")?, "tx")?,
+            amount,
+            reason,
+            dispute_reason,
+        })
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
     pub fn new_deposit(client_id: u16, tx_id: u32, amount: f64) -> Self {
         Self {
             tx_type: TransactionType::Deposit,
             client_id,
             tx_id,
             amount: Some(amount),
+            reason: None,
+            dispute_reason: None,
         }
     }
 
-    #[cfg(test)]
+    #[cfg(any(test, feature = "test-support"))]
     pub fn new_withdrawal(client_id: u16, tx_id: u32, amount: f64) -> Self {
         Self {
             tx_type: TransactionType::Withdrawal,
             client_id,
             tx_id,
             amount: Some(amount),
+            reason: None,
+            dispute_reason: None,
         }
     }
 
-    #[cfg(test)]
+    #[cfg(any(test, feature = "test-support"))]
     pub fn new_dispute(client_id: u16, tx_id: u32) -> Self {
         Self {
             tx_type: TransactionType::Dispute,
             client_id,
             tx_id,
             amount: None,
+            reason: None,
+            dispute_reason: None,
         }
     }
 
-    #[cfg(test)]
+    #[cfg(any(test, feature = "test-support"))]
     pub fn new_resolve(client_id: u16, tx_id: u32) -> Self {
         Self {
             tx_type: TransactionType::Resolve,
             client_id,
             tx_id,
             amount: None,
+            reason: None,
+            dispute_reason: None,
         }
     }
 
-    #[cfg(test)]
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn new_close(client_id: u16, tx_id: u32) -> Self {
+        Self {
+            tx_type: TransactionType::Close,
+            client_id,
+            tx_id,
+            amount: None,
+            reason: None,
+            dispute_reason: None,
+        }
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
     pub fn new_chargeback(client_id: u16, tx_id: u32) -> Self {
         Self {
             tx_type: TransactionType::Chargeback,
             client_id,
             tx_id,
             amount: None,
+            reason: None,
+            dispute_reason: None,
+        }
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn new_approve(client_id: u16, tx_id: u32) -> Self {
+        Self {
+            tx_type: TransactionType::Approve,
+            ..Self::new_dispute(client_id, tx_id)
+        }
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn new_deny(client_id: u16, tx_id: u32) -> Self {
+        Self {
+            tx_type: TransactionType::Deny,
+            ..Self::new_dispute(client_id, tx_id)
+        }
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn new_credit(client_id: u16, tx_id: u32, amount: f64, reason: AdjustmentReason) -> Self {
+        Self {
+            tx_type: TransactionType::Credit,
+            client_id,
+            tx_id,
+            amount: Some(amount),
+            reason: Some(reason),
+            dispute_reason: None,
+        }
+    }
+
+    #[cfg(any(test, feature = "test-support"))]
+    pub fn new_debit(client_id: u16, tx_id: u32, amount: f64, reason: AdjustmentReason) -> Self {
+        Self {
+            tx_type: TransactionType::Debit,
+            client_id,
+            tx_id,
+            amount: Some(amount),
+            reason: Some(reason),
+            dispute_reason: None,
         }
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum TransactionStatus {
     Good,
     Disputed,
     Chargeback,
+    /// A deposit or withdrawal held for review, its amount held until it
+    /// is approved or denied.
+    Pending,
+    /// A deposit denied on review, which was never credited, or a
+    /// withdrawal whose funds never left.
+    Denied,
 }
 
+/// Number of fixed-point units per unit of currency. Inputs carry at most four
+/// decimal places, so amounts are stored as ten-thousandths.
+const AMOUNT_SCALE: f64 = 10_000.0;
+
+/// Largest amount, and balance, the engine handles: beyond it an `f64` can
+/// no longer hold every ten-thousandth, so four decimal places wouldn't be
+/// exact. About 900 billion.
+pub const MAX_AMOUNT: f64 = (1u64 << 53) as f64 / AMOUNT_SCALE;
+
+/// What the engine retains of an applied deposit or withdrawal so it can be
+/// disputed later, keyed by transaction id.
+///
+/// Amounts are kept as fixed-point ten-thousandths and the type, status
+/// and dispute reason share a byte, packing a record into 27 bytes where a
+/// `Transaction` plus its status took 32. Only deposits and withdrawals are
+/// retained, so the type takes a single bit.
 #[derive(Copy, Clone, Debug)]
-pub struct TransactionWithStatus {
-    pub tx: Transaction,
+#[repr(C, packed)]
+pub struct StoredTransaction {
+    amount: i64,
+    // Part of the amount currently held by a dispute
+    disputed: i64,
+    // Part of the amount charged back, which can't be disputed again
+    charged_back: i64,
+    client_id: u16,
+    // Bit 0 set for a withdrawal, status in bits 1-3 and the reason of the
+    // last dispute in bits 4-6, zero for none
+    flags: u8,
+}
+
+impl StoredTransaction {
+    /// Packs an amount-carrying transaction, rounding the amount to four
+    /// decimal places.
+    pub fn new(tx: &Transaction, amount: f64) -> Self {
+        Self {
+            amount: (amount * AMOUNT_SCALE).round() as i64,
+            disputed: 0,
+            charged_back: 0,
+            client_id: tx.client_id,
+            flags: pack_flags(tx.tx_type, TransactionStatus::Good, None),
+        }
+    }
+
+    pub fn amount(&self) -> f64 {
+        self.amount as f64 / AMOUNT_SCALE
+    }
+
+    /// Part of the amount held by an open dispute, zero when there is none.
+    pub fn disputed(&self) -> f64 {
+        self.disputed as f64 / AMOUNT_SCALE
+    }
+
+    /// Part of the amount charged back so far, zero when there was no
+    /// chargeback.
+    pub fn charged_back(&self) -> f64 {
+        self.charged_back as f64 / AMOUNT_SCALE
+    }
+
+    /// Part of the amount a new dispute may still hold: neither held by an
+    /// open dispute nor charged back.
+    pub fn undisputed(&self) -> f64 {
+        (self.amount - self.charged_back - self.disputed) as f64 / AMOUNT_SCALE
+    }
+
+    /// Sets the part of the amount held by the dispute, rounded to four
+    /// decimal places.
+    pub fn set_disputed(&mut self, amount: f64) {
+        self.disputed = (amount * AMOUNT_SCALE).round() as i64;
+    }
+
+    /// Sets the part of the amount charged back, rounded to four decimal
+    /// places.
+    pub fn set_charged_back(&mut self, amount: f64) {
+        self.charged_back = (amount * AMOUNT_SCALE).round() as i64;
+    }
+
+    pub fn client_id(&self) -> u16 {
+        self.client_id
+    }
+
+    pub fn set_client_id(&mut self, client_id: u16) {
+        self.client_id = client_id;
+    }
+
+    pub fn tx_type(&self) -> TransactionType {
+        match self.flags & 0x01 {
+            0 => TransactionType::Deposit,
+            _ => TransactionType::Withdrawal,
+        }
+    }
+
+    pub fn status(&self) -> TransactionStatus {
+        match self.flags >> 1 & 0x07 {
+            0 => TransactionStatus::Good,
+            1 => TransactionStatus::Disputed,
+            2 => TransactionStatus::Chargeback,
+            3 => TransactionStatus::Pending,
+            _ => TransactionStatus::Denied,
+        }
+    }
+
+    pub fn set_status(&mut self, status: TransactionStatus) {
+        self.flags = pack_flags(self.tx_type(), status, self.dispute_reason());
+    }
+
+    /// Reason code given by the last dispute of this transaction, if any.
+    pub fn dispute_reason(&self) -> Option<DisputeReason> {
+        match self.flags >> 4 {
+            0 => None,
+            code => Some(DisputeReason::ALL[code as usize - 1]),
+        }
+    }
+
+    pub fn set_dispute_reason(&mut self, reason: Option<DisputeReason>) {
+        self.flags = pack_flags(self.tx_type(), self.status(), reason);
+    }
+
+    pub fn history_entry(&self, tx_id: u32) -> HistoryEntry {
+        HistoryEntry {
+            tx_id,
+            client_id: self.client_id(),
+            tx_type: self.tx_type(),
+            amount: self.amount(),
+            status: self.status(),
+            dispute_reason: self.dispute_reason(),
+        }
+    }
+}
+
+/// A retained deposit or withdrawal, with its current status, as listed
+/// by history and search queries.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    pub tx_id: u32,
+    pub client_id: u16,
+    pub tx_type: TransactionType,
+    pub amount: f64,
     pub status: TransactionStatus,
+    pub dispute_reason: Option<DisputeReason>,
+}
+
+/// One applied dispute, resolve or chargeback of a retained transaction.
+/// Together, a transaction's steps tell who disputed it, when, how much and
+/// how each dispute ended; its status only says where it stands now.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DisputeStep {
+    /// Position of the step among the transactions the engine received,
+    /// starting at 1.
+    #[serde(rename = "seq")]
+    pub sequence: u64,
+    /// Client who made the step, always the transaction's owner.
+    #[serde(rename = "client")]
+    pub client_id: u16,
+    /// `Dispute`, `Resolve` or `Chargeback`.
+    pub action: TransactionType,
+    /// Amount the step held, released or charged back.
+    pub amount: f64,
+    /// Reason code of the dispute the step belongs to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<DisputeReason>,
+}
+
+fn pack_flags(
+    tx_type: TransactionType,
+    status: TransactionStatus,
+    dispute_reason: Option<DisputeReason>,
+) -> u8 {
+    debug_assert!(matches!(
+        tx_type,
+        TransactionType::Deposit | TransactionType::Withdrawal
+    ));
+    let withdrawal = (tx_type == TransactionType::Withdrawal) as u8;
+    let status = match status {
+        TransactionStatus::Good => 0,
+        TransactionStatus::Disputed => 1,
+        TransactionStatus::Chargeback => 2,
+        TransactionStatus::Pending => 3,
+        TransactionStatus::Denied => 4,
+    };
+    let dispute_reason = dispute_reason.map_or(0, |reason| reason as u8 + 1);
+    withdrawal | status << 1 | dispute_reason << 4
+}
+
+/// Positions of the transaction fields within a CSV record, taken from the
+/// header row so the column order of the input doesn't matter.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct CsvColumns {
+    tx_type: usize,
+    client_id: usize,
+    tx_id: usize,
+    amount: Option<usize>,
+    reason: Option<usize>,
+    checksum: Option<usize>,
+}
+
+impl CsvColumns {
+    /// Layout of an input with the columns in the documented order.
+    const STANDARD: CsvColumns = CsvColumns {
+        tx_type: 0,
+        client_id: 1,
+        tx_id: 2,
+        amount: Some(3),
+        reason: Some(4),
+        checksum: None,
+    };
+
+    pub(crate) fn from_headers(headers: &ByteRecord) -> Result<Self, ParseError> {
+        let position = |name: &str| headers.iter().position(|h| h == name.as_bytes());
+        let required = |name: &'static str| position(name).ok_or(ParseError::MissingColumn(name));
+
+        Ok(Self {
+            tx_type: required("type")?,
+            client_id: required("client")?,
+            tx_id: required("tx")?,
+            amount: position("amount"),
+            reason: position("reason"),
+            checksum: position("checksum"),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    MissingColumn(&'static str),
+    MissingField(&'static str),
+    UnknownType(String),
+    UnknownReason(String),
+    InvalidNumber(&'static str, String),
+    /// The row's `checksum` field, and the CRC-32 of its other fields.
+    ChecksumMismatch(String, u32),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::MissingColumn(name) => write!(f, "missing '{}' column", name),
+            ParseError::MissingField(name) => write!(f, "missing '{}' field", name),
+            ParseError::UnknownType(value) => write!(f, "unknown transaction type '{}'", value),
+            ParseError::UnknownReason(value) => write!(f, "unknown reason code '{}'", value),
+            ParseError::InvalidNumber(name, value) => {
+                write!(f, "invalid {} '{}'", name, value)
+            }
+            ParseError::ChecksumMismatch(found, computed) => write!(
+                f,
+                "checksum '{}' doesn't match the row's CRC-32 {:08x}",
+                found, computed
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+fn field<'r>(
+    record: &'r ByteRecord,
+    index: usize,
+    name: &'static str,
+) -> Result<&'r [u8], ParseError> {
+    match record.get(index).map(<[u8]>::trim_ascii) {
+        Some(b"") | None => Err(ParseError::MissingField(name)),
+        Some(value) => Ok(value),
+    }
+}
+
+/// Checks the row's `checksum` field, at `index`, against the CRC-32 of its
+/// other fields: trimmed, in the order of the columns and joined by commas,
+/// so a row as written without quotes hashes as it reads without its
+/// checksum. The checksum is written in hex, in either case.
+fn verify_checksum(record: &ByteRecord, index: usize) -> Result<(), ParseError> {
+    let found = field(record, index, "checksum")?;
+    let mut hasher = crc32fast::Hasher::new();
+    let fields = record
+        .iter()
+        .enumerate()
+        .filter(|(position, _)| *position != index);
+    for (n, (_, value)) in fields.enumerate() {
+        if n > 0 {
+            hasher.update(b",");
+        }
+        hasher.update(value.trim_ascii());
+    }
+    let computed = hasher.finalize();
+    let found = String::from_utf8_lossy(found);
+    match u32::from_str_radix(&found, 16) {
+        Ok(checksum) if found.len() <= 8 && checksum == computed => Ok(()),
+        Ok(_) => Err(ParseError::ChecksumMismatch(found.into(), computed)),
+        Err(_) => Err(ParseError::InvalidNumber("checksum", found.into())),
+    }
+}
+
+fn parse_amount(bytes: &[u8]) -> Result<f64, ParseError> {
+    let amount: f64 = parse_number(bytes, "amount")?;
+    if amount.is_finite() && amount >= 0.0 {
+        Ok(amount)
+    } else {
+        Err(ParseError::InvalidNumber(
+            "amount",
+            String::from_utf8_lossy(bytes).into(),
+        ))
+    }
+}
+
+fn parse_number<T: FromStr>(bytes: &[u8], name: &'static str) -> Result<T, ParseError> {
+    str::from_utf8(bytes)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| ParseError::InvalidNumber(name, String::from_utf8_lossy(bytes).into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns() -> CsvColumns {
+        CsvColumns::from_headers(&ByteRecord::from(vec!["type", "client", "tx", "amount"])).unwrap()
+    }
+
+    #[test]
+    fn test_stored_transaction_is_packed() {
+        assert_eq!(std::mem::size_of::<StoredTransaction>(), 27);
+    }
+
+    #[test]
+    fn test_stored_transaction_round_trips() {
+        let tx = Transaction::new_withdrawal(7, 1, 1.2345);
+        let mut stored = StoredTransaction::new(&tx, 1.2345);
+
+        assert_eq!(stored.amount(), 1.2345);
+        assert_eq!(stored.client_id(), 7);
+        assert_eq!(stored.tx_type(), TransactionType::Withdrawal);
+        assert_eq!(stored.status(), TransactionStatus::Good);
+
+        stored.set_status(TransactionStatus::Chargeback);
+        assert_eq!(stored.tx_type(), TransactionType::Withdrawal);
+        assert_eq!(stored.status(), TransactionStatus::Chargeback);
+
+        stored.set_dispute_reason(Some(DisputeReason::Other));
+        stored.set_status(TransactionStatus::Denied);
+        assert_eq!(stored.tx_type(), TransactionType::Withdrawal);
+        assert_eq!(stored.status(), TransactionStatus::Denied);
+        assert_eq!(stored.dispute_reason(), Some(DisputeReason::Other));
+    }
+
+    #[test]
+    fn test_parse_deposit_record() {
+        let record = ByteRecord::from(vec!["deposit", "1", "2", "1.5"]);
+        let tx = Transaction::from_byte_record(&record, &columns()).unwrap();
+
+        assert_eq!(tx.tx_type, TransactionType::Deposit);
+        assert_eq!(tx.client_id, 1);
+        assert_eq!(tx.tx_id, 2);
+        assert_eq!(tx.amount, Some(1.5));
+    }
+
+    #[test]
+    fn test_parse_record_without_amount() {
+        let record = ByteRecord::from(vec!["dispute", "1", "2", ""]);
+        let tx = Transaction::from_byte_record(&record, &columns()).unwrap();
+
+        assert_eq!(tx.tx_type, TransactionType::Dispute);
+        assert_eq!(tx.amount, None);
+    }
+
+    #[test]
+    fn test_columns_follow_the_header_order() {
+        let columns =
+            CsvColumns::from_headers(&ByteRecord::from(vec!["tx", "amount", "client", "type"]))
+                .unwrap();
+        let record = ByteRecord::from(vec!["7", "2.0", "3", "withdrawal"]);
+        let tx = Transaction::from_byte_record(&record, &columns).unwrap();
+
+        assert_eq!(tx.tx_type, TransactionType::Withdrawal);
+        assert_eq!(tx.client_id, 3);
+        assert_eq!(tx.tx_id, 7);
+        assert_eq!(tx.amount, Some(2.0));
+    }
+
+    #[test]
+    fn test_invalid_records_are_errors() {
+        let columns = columns();
+
+        let record = ByteRecord::from(vec!["refund", "1", "2", "1.0"]);
+        assert_eq!(
+            Transaction::from_byte_record(&record, &columns).unwrap_err(),
+            ParseError::UnknownType("refund".to_string())
+        );
+
+        let record = ByteRecord::from(vec!["deposit", "70000", "2", "1.0"]);
+        assert_eq!(
+            Transaction::from_byte_record(&record, &columns).unwrap_err(),
+            ParseError::InvalidNumber("client", "70000".to_string())
+        );
+
+        let record = ByteRecord::from(vec!["deposit", "1", "", "1.0"]);
+        assert_eq!(
+            Transaction::from_byte_record(&record, &columns).unwrap_err(),
+            ParseError::MissingField("tx")
+        );
+    }
+
+    #[test]
+    fn test_checksum_column_is_verified() {
+        let columns = CsvColumns::from_headers(&ByteRecord::from(vec![
+            "type", "client", "tx", "amount", "checksum",
+        ]))
+        .unwrap();
+        let checksum = format!("{:08X}", crc32fast::hash(b"deposit,1,2,1.5"));
+
+        let record = ByteRecord::from(vec!["deposit", "1", "2", "1.5", checksum.as_str()]);
+        let tx = Transaction::from_byte_record(&record, &columns).unwrap();
+        assert_eq!(tx.amount, Some(1.5));
+
+        let record = ByteRecord::from(vec!["deposit", "1", "2", "7.5", checksum.as_str()]);
+        assert_eq!(
+            Transaction::from_byte_record(&record, &columns).unwrap_err(),
+            ParseError::ChecksumMismatch(checksum, crc32fast::hash(b"deposit,1,2,7.5"))
+        );
+        let record = ByteRecord::from(vec!["deposit", "1", "2", "1.5", ""]);
+        assert_eq!(
+            Transaction::from_byte_record(&record, &columns).unwrap_err(),
+            ParseError::MissingField("checksum")
+        );
+    }
+
+    #[test]
+    fn test_parse_adjustment_reason() {
+        let with_reason = CsvColumns::from_headers(&ByteRecord::from(vec![
+            "type", "client", "tx", "amount", "reason",
+        ]))
+        .unwrap();
+
+        let record = ByteRecord::from(vec!["debit", "1", "2", "1.0", "write_off"]);
+        let tx = Transaction::from_byte_record(&record, &with_reason).unwrap();
+        assert_eq!(tx.tx_type, TransactionType::Debit);
+        assert_eq!(tx.reason, Some(AdjustmentReason::WriteOff));
+
+        let record = ByteRecord::from(vec!["credit", "1", "2", "1.0", "whim"]);
+        assert_eq!(
+            Transaction::from_byte_record(&record, &with_reason).unwrap_err(),
+            ParseError::UnknownReason("whim".to_string())
+        );
+        assert_eq!(
+            Transaction::from_byte_record(
+                &ByteRecord::from(vec!["credit", "1", "2", "1.0"]),
+                &columns()
+            )
+            .unwrap()
+            .reason,
+            None
+        );
+    }
+
+    #[test]
+    fn test_dispute_reason_is_parsed_and_stored() {
+        let columns =
+            CsvColumns::from_headers(&ByteRecord::from(vec!["type", "client", "tx", "reason"]))
+                .unwrap();
+        let record = ByteRecord::from(vec!["dispute", "1", "2", "not_received"]);
+        let dispute = Transaction::from_byte_record(&record, &columns).unwrap();
+        assert_eq!(dispute.dispute_reason, Some(DisputeReason::NotReceived));
+        assert_eq!(dispute.reason, None);
+
+        let mut stored = StoredTransaction::new(&Transaction::new_withdrawal(1, 2, 1.0), 1.0);
+        stored.set_dispute_reason(dispute.dispute_reason);
+        stored.set_status(TransactionStatus::Chargeback);
+        assert_eq!(stored.tx_type(), TransactionType::Withdrawal);
+        assert_eq!(stored.status(), TransactionStatus::Chargeback);
+        assert_eq!(stored.dispute_reason(), Some(DisputeReason::NotReceived));
+        stored.set_dispute_reason(Some(DisputeReason::Other));
+        assert_eq!(stored.dispute_reason(), Some(DisputeReason::Other));
+
+        let record = ByteRecord::from(vec!["dispute", "1", "2", "goodwill"]);
+        assert_eq!(
+            Transaction::from_byte_record(&record, &columns).unwrap_err(),
+            ParseError::UnknownReason("goodwill".to_string())
+        );
+    }
+
+    #[test]
+    fn test_missing_required_column_is_an_error() {
+        let headers = ByteRecord::from(vec!["type", "client", "amount"]);
+        assert_eq!(
+            CsvColumns::from_headers(&headers).unwrap_err(),
+            ParseError::MissingColumn("tx")
+        );
+    }
+
+    #[test]
+    fn test_parse_csv_record_uses_the_standard_order() {
+        let record = ByteRecord::from(vec![" withdrawal ", " 3", "7 ", " 2.5 "]);
+        let tx = Transaction::parse_csv_record(&record).unwrap();
+
+        assert_eq!(tx.tx_type, TransactionType::Withdrawal);
+        assert_eq!(tx.client_id, 3);
+        assert_eq!(tx.tx_id, 7);
+        assert_eq!(tx.amount, Some(2.5));
+
+        let record = ByteRecord::from(vec!["resolve", "3", "7"]);
+        assert_eq!(Transaction::parse_csv_record(&record).unwrap().amount, None);
+    }
+
+    #[test]
+    fn test_non_finite_and_negative_amounts_are_errors() {
+        for amount in ["NaN", "inf", "-1.0"] {
+            let record = ByteRecord::from(vec!["deposit", "1", "2", amount]);
+            assert_eq!(
+                Transaction::parse_csv_record(&record).unwrap_err(),
+                ParseError::InvalidNumber("amount", amount.to_string())
+            );
+        }
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_parse_csv_record_never_panics(
+            fields in proptest::collection::vec(proptest::collection::vec(0u8..=255, 0..12), 0..6)
+        ) {
+            let _ = Transaction::parse_csv_record(&ByteRecord::from(fields));
+        }
+    }
 }
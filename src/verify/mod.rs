@@ -0,0 +1,241 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio_util::sync::CancellationToken;
+
+use crate::conformance::replay;
+use crate::engine::EngineBuilder;
+use crate::events::{write_event, ChannelSink, Event};
+use crate::io::{read_csv, ReadOutcome};
+use crate::report::{Report, RowDiff};
+
+/// Differences between one run and the reference.
+#[derive(Debug)]
+pub struct Divergence {
+    /// 1-based number of the run.
+    pub run: usize,
+    pub diffs: Vec<RowDiff>,
+    /// Transaction outcomes in the run's `--events` journal that the
+    /// reference's lacks or has.
+    pub outcomes: Vec<OutcomeDiff>,
+}
+
+/// A transaction's outcome, as a journal line cut down to the fields that
+/// are compared, found in only one of two journals.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutcomeDiff {
+    Missing(String),
+    Unexpected(String),
+}
+
+impl fmt::Display for OutcomeDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutcomeDiff::Missing(line) => write!(f, "- {}", line),
+            OutcomeDiff::Unexpected(line) => write!(f, "+ {}", line),
+        }
+    }
+}
+
+/// Journal fields compared between runs: which transaction, and what became
+/// of it.
+const COMPARED_FIELDS: [&str; 6] = ["type", "client", "tx", "outcome", "reason", "code"];
+
+/// Journal lines of a run's transaction outcomes, grouped by transaction.
+type Journal = BTreeMap<(u16, u32, &'static str), Vec<String>>;
+
+/// Processes `input` `runs` times through the concurrent batch path, each
+/// on a fresh engine, and compares every run's report and `--events`
+/// journal with applying the transactions in file order.
+///
+/// Guards against task scheduling silently changing results: any run whose
+/// balances or transaction outcomes differ is returned. Outcomes are
+/// compared per transaction by kind and reason only: `seq` and the balances
+/// right after each transaction depend on the order a run happened to apply
+/// a client's independent transactions in, which isn't a divergence.
+pub async fn verify_runs(
+    builder: &EngineBuilder,
+    input: &str,
+    runs: usize,
+) -> Result<Vec<Divergence>, Box<dyn Error>> {
+    let (sender, mut events) = mpsc::unbounded_channel();
+    let reference = replay(
+        Path::new(input),
+        &builder.clone().event_sink(ChannelSink::new(sender)),
+    )?;
+    let expected = Report::from_engine(&reference);
+    let expected_journal = journal(&mut events)?;
+
+    let mut divergences = vec![];
+    for run in 1..=runs {
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let engine = builder.clone().event_sink(ChannelSink::new(sender)).build();
+        if read_csv(&engine, input, &CancellationToken::new()).await? == ReadOutcome::Cancelled {
+            return Err("run was cancelled".into());
+        }
+
+        let diffs = expected.diff(&Report::from_engine(&engine));
+        let outcomes = diff_journals(&expected_journal, &journal(&mut events)?);
+        if !diffs.is_empty() || !outcomes.is_empty() {
+            divergences.push(Divergence {
+                run,
+                diffs,
+                outcomes,
+            });
+        }
+    }
+    Ok(divergences)
+}
+
+/// Writes the transaction outcomes published so far as journal lines,
+/// keeping only the [`COMPARED_FIELDS`].
+fn journal(events: &mut UnboundedReceiver<Event>) -> Result<Journal, Box<dyn Error>> {
+    let mut journal = Journal::new();
+    while let Ok(event) = events.try_recv() {
+        let Event::Outcome(outcome) = &event else {
+            continue;
+        };
+        let mut line = vec![];
+        write_event(&mut line, &event)?;
+        let mut record: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&line)?;
+        record.retain(|field, _| COMPARED_FIELDS.contains(&field.as_str()));
+        let tx = &outcome.transaction;
+        journal
+            .entry((tx.client_id, tx.tx_id, tx.tx_type.as_str()))
+            .or_default()
+            .push(serde_json::to_string(&record)?);
+    }
+    // Repeats of a transaction id, e.g. duplicates, may come in any order
+    for lines in journal.values_mut() {
+        lines.sort();
+    }
+    Ok(journal)
+}
+
+fn diff_journals(expected: &Journal, actual: &Journal) -> Vec<OutcomeDiff> {
+    let no_lines = vec![];
+    let mut diffs = vec![];
+    for key in expected
+        .keys()
+        .chain(actual.keys().filter(|key| !expected.contains_key(key)))
+    {
+        let expected = expected.get(key).unwrap_or(&no_lines);
+        let actual = actual.get(key).unwrap_or(&no_lines);
+        for line in expected.iter().filter(|line| !actual.contains(line)) {
+            diffs.push(OutcomeDiff::Missing(line.clone()));
+        }
+        for line in actual.iter().filter(|line| !expected.contains(line)) {
+            diffs.push(OutcomeDiff::Unexpected(line.clone()));
+        }
+    }
+    diffs
+}
+
+/// Compares two report files, e.g. written by two versions of the engine
+/// from the same input.
+pub fn verify_reports(first: &Path, second: &Path) -> Result<Vec<RowDiff>, Box<dyn Error>> {
+    let open = |path: &Path| -> Result<Report, Box<dyn Error>> {
+        let file = File::open(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Report::parse(file).map_err(|err| format!("{}: {}", path.display(), err).into())
+    };
+
+    Ok(open(first)?.diff(&open(second)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::test_support::TempDir;
+    use crate::transactions::Transaction;
+    use std::fs;
+
+    #[tokio::test]
+    async fn test_order_independent_input_never_diverges() {
+        let dir = TempDir::new("verify");
+        let input = dir.join("input.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,3.0\ndeposit,1,3,1.0\n",
+        )
+        .unwrap();
+
+        let divergences = verify_runs(&PaymentsEngine::builder(), input.to_str().unwrap(), 3)
+            .await
+            .unwrap();
+
+        assert!(divergences.is_empty(), "{:?}", divergences);
+    }
+
+    #[test]
+    fn test_journals_are_compared_by_outcome_ignoring_order() {
+        let outcomes = |builder: EngineBuilder, txs: &[Transaction]| {
+            let (sender, mut events) = mpsc::unbounded_channel();
+            let engine = builder.event_sink(ChannelSink::new(sender)).build();
+            for tx in txs {
+                engine.apply_transaction(*tx);
+            }
+            journal(&mut events).unwrap()
+        };
+        let deposit = Transaction::new_deposit(1, 1, 2.0);
+        let withdrawal = Transaction::new_withdrawal(1, 2, 3.0);
+
+        let expected = outcomes(PaymentsEngine::builder(), &[deposit, withdrawal]);
+        // Numbered from 2, after another client's deposit
+        let other = Transaction::new_deposit(2, 3, 1.0);
+        let shifted = outcomes(PaymentsEngine::builder(), &[other, deposit, withdrawal]);
+        let diffs = diff_journals(&expected, &shifted);
+        assert_eq!(diffs.len(), 1);
+        assert!(
+            matches!(&diffs[0], OutcomeDiff::Unexpected(line) if line.contains("\"client\":2"))
+        );
+        // Applied either way round, with other balances along the way
+        let top_up = Transaction::new_deposit(1, 4, 2.0);
+        let first = outcomes(PaymentsEngine::builder(), &[deposit, top_up, withdrawal]);
+        let reordered = outcomes(PaymentsEngine::builder(), &[top_up, deposit, withdrawal]);
+        assert!(diff_journals(&first, &reordered).is_empty());
+
+        let overdrawn = outcomes(
+            PaymentsEngine::builder().overdraft_limit(5.0),
+            &[deposit, withdrawal],
+        );
+        let diffs = diff_journals(&expected, &overdrawn);
+        assert_eq!(diffs.len(), 2);
+        assert!(
+            matches!(&diffs[0], OutcomeDiff::Missing(line) if line.contains("\"rejected\"")),
+            "{:?}",
+            diffs
+        );
+        assert!(
+            matches!(&diffs[1], OutcomeDiff::Unexpected(line) if line.contains("\"applied\"")),
+            "{:?}",
+            diffs
+        );
+        assert!(diffs.iter().all(|diff| !diff.to_string().contains("seq")));
+    }
+
+    #[test]
+    fn test_reports_are_compared_by_client() {
+        let dir = TempDir::new("verify-reports");
+        let (first, second) = (dir.join("first.csv"), dir.join("second.csv"));
+        fs::write(
+            &first,
+            "client,available,held,total,locked\n1,1.0,0,1.0,false\n2,2.0,0,2.0,false\n",
+        )
+        .unwrap();
+        fs::write(
+            &second,
+            "client,available,held,total,locked\n2,2.0,0,2.0,false\n1,1.5,0,1.5,false\n",
+        )
+        .unwrap();
+
+        let diffs = verify_reports(&first, &second).unwrap();
+
+        assert_eq!(diffs.len(), 1);
+        assert_eq!(diffs[0].client(), 1);
+    }
+}
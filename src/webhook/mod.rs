@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::events::{write_event, Event, EventSink};
+use crate::notify::{AlertType, NotificationSink};
+use crate::transactions::TransactionType;
+
+/// A plain HTTP endpoint alerts are posted to. There is no TLS client, so
+/// HTTPS receivers are reached through a local TLS-terminating proxy.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WebhookUrl {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl FromStr for WebhookUrl {
+    type Err = String;
+
+    /// Parses `http://host[:port][/path]`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = match s.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some(("https", _)) => {
+                return Err(format!(
+                    "{}: https isn't supported, post through a TLS-terminating proxy",
+                    s
+                ))
+            }
+            _ => return Err(format!("{}: expected an http:// URL", s)),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(at) => rest.split_at(at),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|_| format!("{}: invalid port '{}'", s, port))?,
+            ),
+            None => (authority, 80),
+        };
+        if host.is_empty() {
+            return Err(format!("{}: no host", s));
+        }
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for WebhookUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}:{}{}", self.host, self.port, self.path)
+    }
+}
+
+/// Where alerts go, which events are alerts, and how hard delivery is
+/// tried.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebhookConfig {
+    pub url: WebhookUrl,
+    /// Types of alerts posted when the sink is an engine's event sink, e.g.
+    /// applied chargebacks. Routed through a
+    /// [`Notifier`](crate::notify::Notifier) instead, it posts whatever it
+    /// is routed.
+    pub types: Vec<AlertType>,
+    /// Attempts per alert, the first included, before it is dead-lettered.
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubling after every failed one up
+    /// to `max_backoff`.
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    /// Limit on connecting to the receiver and on each read and write.
+    pub timeout: Duration,
+    /// How long flushing waits for alerts still being retried.
+    pub flush_timeout: Duration,
+    /// Log of every alert and delivery attempt, so alerts pending when the
+    /// process stops are retried when it starts again with the same log.
+    pub outbox: PathBuf,
+    /// Alerts given up on, with their attempts and last error, one JSON
+    /// object per line.
+    pub dead_letter: PathBuf,
+}
+
+impl WebhookConfig {
+    /// Settings for posting chargebacks to `url`, retried 8 times from one
+    /// second apart up to five minutes.
+    pub fn new(url: WebhookUrl, outbox: &Path, dead_letter: &Path) -> Self {
+        Self {
+            url,
+            types: vec![AlertType::Transaction(TransactionType::Chargeback)],
+            max_attempts: 8,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(300),
+            timeout: Duration::from_secs(10),
+            flush_timeout: Duration::from_secs(30),
+            outbox: outbox.to_path_buf(),
+            dead_letter: dead_letter.to_path_buf(),
+        }
+    }
+
+    fn backoff_after(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// A line of the outbox: an alert when first queued, an attempt at it, or
+/// its end.
+#[derive(Default, Deserialize, Serialize)]
+struct OutboxLine {
+    id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    alert: Option<Value>,
+    /// Attempts made before the log was last compacted.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    attempts: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    attempt: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    done: Option<Done>,
+}
+
+fn is_zero(attempts: &u32) -> bool {
+    *attempts == 0
+}
+
+#[derive(Copy, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Done {
+    Delivered,
+    DeadLettered,
+}
+
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    id: u64,
+    attempts: u32,
+    error: &'a str,
+    alert: &'a Value,
+}
+
+struct Delivery {
+    id: u64,
+    alert: Value,
+    attempts: u32,
+    due: Instant,
+}
+
+enum Command {
+    Alert(Value),
+    /// Answer once nothing is pending, or at the deadline.
+    Flush(Instant, Sender<()>),
+}
+
+/// Posts alerts, such as chargebacks, to a webhook as JSON, the same
+/// object the event journal has on a line.
+///
+/// Publishing only queues an alert; a background thread posts it, and
+/// retries failed deliveries with exponential backoff until one gets a
+/// 2xx response or `max_attempts` are used up, when the alert goes to the
+/// dead-letter file. A 4xx response other than 408 and 429 won't change
+/// on retrying, so it dead-letters the alert at once. Every alert and
+/// attempt is logged to the outbox first, and alerts the outbox shows
+/// pending are picked up again on opening it, so an outage of the
+/// receiver or a restart in between loses none. Alerts are posted in
+/// order, but one being retried doesn't hold back the others.
+pub struct WebhookSink {
+    types: Vec<AlertType>,
+    commands: Sender<Command>,
+    flush_timeout: Duration,
+    worker: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl WebhookSink {
+    /// Opens the outbox, compacting it down to the alerts still pending,
+    /// and starts delivering them.
+    pub fn open(config: WebhookConfig) -> Result<Self, Box<dyn Error>> {
+        let (pending, next_id) = match File::open(&config.outbox) {
+            Ok(file) => read_outbox(BufReader::new(file))
+                .map_err(|err| format!("{}: {}", config.outbox.display(), err))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (vec![], 1),
+            Err(err) => return Err(err.into()),
+        };
+
+        let tmp = config.outbox.with_extension("tmp");
+        let mut compacted = File::create(&tmp)?;
+        for delivery in &pending {
+            let line = OutboxLine {
+                id: delivery.id,
+                alert: Some(delivery.alert.clone()),
+                attempts: delivery.attempts,
+                ..OutboxLine::default()
+            };
+            writeln!(compacted, "{}", serde_json::to_string(&line)?)?;
+        }
+        compacted.sync_all()?;
+        fs::rename(&tmp, &config.outbox)?;
+        let outbox = OpenOptions::new().append(true).open(&config.outbox)?;
+        if !pending.is_empty() {
+            log::info!(
+                "webhook: {} alerts pending from {}",
+                pending.len(),
+                config.outbox.display()
+            );
+        }
+
+        let (commands, receiver) = mpsc::channel();
+        let types = config.types.clone();
+        let flush_timeout = config.flush_timeout;
+        let worker = Worker {
+            config,
+            outbox,
+            next_id,
+            pending,
+            flushes: vec![],
+        };
+        let worker = thread::Builder::new()
+            .name("webhook".to_string())
+            .spawn(move || worker.run(receiver))?;
+        Ok(Self {
+            types,
+            commands,
+            flush_timeout,
+            worker: Mutex::new(Some(worker)),
+        })
+    }
+}
+
+impl EventSink for WebhookSink {
+    fn publish(&self, event: &Event) {
+        match AlertType::of(event) {
+            Some(alert) if self.types.contains(&alert) => self.notify(alert, event),
+            _ => {}
+        }
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        NotificationSink::flush(self)
+    }
+}
+
+impl NotificationSink for WebhookSink {
+    fn notify(&self, _: AlertType, event: &Event) {
+        let mut alert = vec![];
+        if write_event(&mut alert, event).is_ok() {
+            if let Ok(alert) = serde_json::from_slice(&alert) {
+                let _ = self.commands.send(Command::Alert(alert));
+            }
+        }
+    }
+
+    /// Waits for queued alerts to be delivered or dead-lettered, up to the
+    /// flush timeout. Alerts still being retried then stay in the outbox.
+    fn flush(&self) -> io::Result<()> {
+        let (done, wait) = mpsc::channel();
+        let deadline = Instant::now() + self.flush_timeout;
+        if self.commands.send(Command::Flush(deadline, done)).is_err() {
+            // The worker is gone; only its panic could have done that
+            let worker = self.worker.lock().unwrap().take();
+            if let Some(Err(_)) = worker.map(JoinHandle::join) {
+                return Err(io::Error::other("webhook delivery thread panicked"));
+            }
+            return Ok(());
+        }
+        let _ = wait.recv();
+        Ok(())
+    }
+}
+
+/// The alerts an outbox shows pending, with their attempts so far, and
+/// the next alert id.
+fn read_outbox<R: BufRead>(outbox: R) -> Result<(Vec<Delivery>, u64), Box<dyn Error>> {
+    let mut pending: HashMap<u64, Delivery> = HashMap::new();
+    let mut next_id = 1;
+    let now = Instant::now();
+    for (index, line) in outbox.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line: OutboxLine =
+            serde_json::from_str(&line).map_err(|err| format!("line {}: {}", index + 1, err))?;
+        next_id = next_id.max(line.id + 1);
+        if let Some(alert) = line.alert {
+            pending.insert(
+                line.id,
+                Delivery {
+                    id: line.id,
+                    alert,
+                    attempts: line.attempts,
+                    due: now,
+                },
+            );
+        } else if line.done.is_some() {
+            pending.remove(&line.id);
+        } else if let (Some(attempt), Some(delivery)) = (line.attempt, pending.get_mut(&line.id)) {
+            delivery.attempts = delivery.attempts.max(attempt);
+        }
+    }
+    let mut pending: Vec<Delivery> = pending.into_values().collect();
+    pending.sort_by_key(|delivery| delivery.id);
+    Ok((pending, next_id))
+}
+
+struct Worker {
+    config: WebhookConfig,
+    outbox: File,
+    next_id: u64,
+    pending: Vec<Delivery>,
+    flushes: Vec<(Instant, Sender<()>)>,
+}
+
+impl Worker {
+    fn run(mut self, commands: Receiver<Command>) {
+        loop {
+            let wake = self
+                .pending
+                .iter()
+                .map(|delivery| delivery.due)
+                .chain(self.flushes.iter().map(|(deadline, _)| *deadline))
+                .min();
+            let command = match wake {
+                Some(wake) => commands.recv_timeout(wake.saturating_duration_since(Instant::now())),
+                None => commands.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match command {
+                Ok(Command::Alert(alert)) => self.queue(alert),
+                Ok(Command::Flush(deadline, done)) => self.flushes.push((deadline, done)),
+                Err(RecvTimeoutError::Timeout) => {}
+                // Whatever is pending stays in the outbox for next time
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            // Take every alert already queued before posting any
+            while let Ok(command) = commands.try_recv() {
+                match command {
+                    Command::Alert(alert) => self.queue(alert),
+                    Command::Flush(deadline, done) => self.flushes.push((deadline, done)),
+                }
+            }
+
+            let now = Instant::now();
+            let mut index = 0;
+            while index < self.pending.len() {
+                if self.pending[index].due <= now && self.attempt(index) {
+                    self.pending.remove(index);
+                } else {
+                    index += 1;
+                }
+            }
+
+            let now = Instant::now();
+            let idle = self.pending.is_empty();
+            self.flushes.retain(|(deadline, done)| {
+                let answered = idle || *deadline <= now;
+                if answered {
+                    let _ = done.send(());
+                }
+                !answered
+            });
+        }
+    }
+
+    fn queue(&mut self, alert: Value) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.log(&OutboxLine {
+            id,
+            alert: Some(alert.clone()),
+            ..OutboxLine::default()
+        });
+        self.pending.push(Delivery {
+            id,
+            alert,
+            attempts: 0,
+            due: Instant::now(),
+        });
+    }
+
+    /// Posts a pending alert once, and says whether it is done with.
+    fn attempt(&mut self, index: usize) -> bool {
+        let delivery = &mut self.pending[index];
+        delivery.attempts += 1;
+        let (id, attempt) = (delivery.id, delivery.attempts);
+        let result = post(&self.config, &delivery.alert);
+        let (done, error) = match result {
+            Ok(()) => (Some(Done::Delivered), None),
+            Err(Failure::Permanent(error)) => (Some(Done::DeadLettered), Some(error)),
+            Err(Failure::Transient(error)) if attempt >= self.config.max_attempts => {
+                (Some(Done::DeadLettered), Some(error))
+            }
+            Err(Failure::Transient(error)) => (None, Some(error)),
+        };
+        self.log(&OutboxLine {
+            id,
+            attempt: Some(attempt),
+            error: error.clone(),
+            ..OutboxLine::default()
+        });
+
+        match done {
+            None => {
+                let backoff = self.config.backoff_after(attempt);
+                self.pending[index].due = Instant::now() + backoff;
+                log::debug!(
+                    "webhook: alert {} failed ({}), retrying in {:?}",
+                    id,
+                    error.unwrap_or_default(),
+                    backoff
+                );
+                return false;
+            }
+            Some(Done::DeadLettered) => {
+                let error = error.unwrap_or_default();
+                log::error!(
+                    "webhook: alert {} dead-lettered after {} attempts: {}",
+                    id,
+                    attempt,
+                    error
+                );
+                let letter = DeadLetter {
+                    id,
+                    attempts: attempt,
+                    error: &error,
+                    alert: &self.pending[index].alert,
+                };
+                let written = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.config.dead_letter)
+                    .and_then(|mut file| {
+                        writeln!(file, "{}", serde_json::to_string(&letter)?)?;
+                        file.sync_data()
+                    });
+                // Left pending in the outbox rather than lost
+                if let Err(err) = written {
+                    log::error!(
+                        "webhook: failed to write {}: {}",
+                        self.config.dead_letter.display(),
+                        err
+                    );
+                    return true;
+                }
+            }
+            Some(Done::Delivered) => {}
+        }
+        self.log(&OutboxLine {
+            id,
+            done,
+            ..OutboxLine::default()
+        });
+        true
+    }
+
+    fn log(&mut self, line: &OutboxLine) {
+        let written = serde_json::to_string(line)
+            .map_err(io::Error::from)
+            .and_then(|line| writeln!(self.outbox, "{}", line))
+            .and_then(|()| self.outbox.sync_data());
+        if let Err(err) = written {
+            log::error!(
+                "webhook: failed to write {}: {}",
+                self.config.outbox.display(),
+                err
+            );
+        }
+    }
+}
+
+enum Failure {
+    /// Worth trying again, e.g. a refused connection or a 503.
+    Transient(String),
+    /// The receiver won't take the alert however often it is sent.
+    Permanent(String),
+}
+
+/// Posts `alert` to the webhook over HTTP/1.1, one connection per post.
+fn post(config: &WebhookConfig, alert: &Value) -> Result<(), Failure> {
+    let transient = |err: io::Error| Failure::Transient(err.to_string());
+    let url = &config.url;
+    let address = (url.host.as_str(), url.port)
+        .to_socket_addrs()
+        .map_err(transient)?
+        .next()
+        .ok_or_else(|| Failure::Transient(format!("{} doesn't resolve", url.host)))?;
+    let mut stream = TcpStream::connect_timeout(&address, config.timeout).map_err(transient)?;
+    stream
+        .set_read_timeout(Some(config.timeout))
+        .and_then(|()| stream.set_write_timeout(Some(config.timeout)))
+        .map_err(transient)?;
+
+    let body = alert.to_string();
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        body.len(),
+        body
+    );
+    stream.write_all(request.as_bytes()).map_err(transient)?;
+
+    let mut status_line = vec![];
+    BufReader::new(stream)
+        .read_until(b'\n', &mut status_line)
+        .map_err(transient)?;
+    let status_line = String::from_utf8_lossy(&status_line);
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse::<u16>().ok())
+        .ok_or_else(|| Failure::Transient(format!("invalid response '{}'", status_line.trim())))?;
+    match status {
+        200..=299 => Ok(()),
+        408 | 429 => Err(Failure::Transient(format!("status {}", status))),
+        400..=499 => Err(Failure::Permanent(format!("status {}", status))),
+        _ => Err(Failure::Transient(format!("status {}", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::PaymentsEngine;
+    use crate::test_support::TempDir;
+    use crate::transactions::Transaction;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    /// Answers each connection with the next status, and returns the
+    /// bodies it received.
+    fn receiver(statuses: Vec<u16>) -> (WebhookUrl, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/alerts", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut bodies = vec![];
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = vec![0; 4096];
+                let read = stream.read(&mut request).unwrap();
+                let request = String::from_utf8_lossy(&request[..read]).to_string();
+                bodies.push(request.split("\r\n\r\n").nth(1).unwrap().to_string());
+                write!(stream, "HTTP/1.1 {} X\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+            }
+            bodies
+        });
+        (url.parse().unwrap(), handle)
+    }
+
+    #[test]
+    fn test_chargebacks_are_retried_then_dead_lettered() {
+        let dir = TempDir::new("webhook");
+        let (url, received) = receiver(vec![503, 200, 400]);
+        let mut config =
+            WebhookConfig::new(url, &dir.join("outbox.jsonl"), &dir.join("dead.jsonl"));
+        config.backoff = Duration::from_millis(10);
+
+        let engine = PaymentsEngine::builder()
+            .event_sink(WebhookSink::open(config.clone()).unwrap())
+            .build();
+        engine.apply_transaction(Transaction::new_deposit(1, 1, 5.0));
+        engine.apply_transaction(Transaction::new_dispute(1, 1));
+        engine.apply_transaction(Transaction::new_chargeback(1, 1));
+        engine.flush_events().unwrap();
+        engine.apply_transaction(Transaction::new_deposit(2, 2, 1.0));
+        engine.apply_transaction(Transaction::new_dispute(2, 2));
+        engine.apply_transaction(Transaction::new_chargeback(2, 2));
+        engine.flush_events().unwrap();
+
+        let bodies = received.join().unwrap();
+        assert_eq!(bodies.len(), 3);
+        assert_eq!(bodies[0], bodies[1]);
+        let alert: Value = serde_json::from_str(&bodies[0]).unwrap();
+        assert_eq!(
+            (&alert["type"], &alert["client"]),
+            (&"chargeback".into(), &1.into())
+        );
+        let dead = fs::read_to_string(dir.join("dead.jsonl")).unwrap();
+        assert!(dead.starts_with("{\"id\":2,\"attempts\":1,\"error\":\"status 400\""));
+
+        // Nothing is left to pick up after a restart
+        drop(engine);
+        let (pending, next_id) = read_outbox(BufReader::new(
+            File::open(dir.join("outbox.jsonl")).unwrap(),
+        ))
+        .unwrap();
+        assert!(pending.is_empty());
+        assert_eq!(next_id, 3);
+    }
+}